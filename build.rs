@@ -0,0 +1,22 @@
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    // Builds from a source tarball, e.g. through nix, have no git checkout to ask.
+    let commit = Command::new("git").args(["rev-parse", "--short", "HEAD"]).output().ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=CURSETOOL_GIT_COMMIT={}", commit);
+
+    // Commits move the branch HEAD points to, whose ref may also only be in packed-refs.
+    let mut watched = vec![".git/HEAD".to_string(), ".git/refs".to_string(), ".git/packed-refs".to_string()];
+    if let Some(branch) = std::fs::read_to_string(".git/HEAD").ok().and_then(|head| Some(head.strip_prefix("ref: ")?.trim().to_string())) {
+        watched.push(format!(".git/{}", branch));
+    }
+    // Cargo reruns the script on every build for paths that don't exist.
+    for path in watched.iter().filter(|path| Path::new(path).exists()) {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
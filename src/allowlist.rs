@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use reqwest::Url;
+use reqwest::redirect;
+
+// The same limit reqwest applies by default.
+const MAX_REDIRECTS: usize = 10;
+
+/// The only hosts requests may go to, when running with `--allow-hosts`.
+#[derive(Debug)]
+pub struct HostAllowlist {
+    hosts: Vec<String>,
+}
+
+impl HostAllowlist {
+    pub fn new(hosts: &[String]) -> Self {
+        HostAllowlist { hosts: hosts.iter().map(|h| h.trim().to_lowercase()).collect() }
+    }
+
+    pub fn allows(&self, url: &Url) -> bool {
+        url.host_str().is_some_and(|host| self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host)))
+    }
+
+    pub fn check(&self, url: &Url) -> Result<()> {
+        if !self.allows(url) {
+            anyhow::bail!("Refusing to contact {}: {} is not in the allowed hosts ({})",
+                          url, url.host_str().unwrap_or("<no host>"), self.hosts.join(", "));
+        }
+        Ok(())
+    }
+}
+
+/// Follows redirects only as long as they stay on allowed hosts.
+pub fn redirect_policy(allowlist: Option<Arc<HostAllowlist>>) -> redirect::Policy {
+    let allowlist = match allowlist {
+        Some(allowlist) => allowlist,
+        None => return redirect::Policy::default(),
+    };
+    redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if let Err(e) = allowlist.check(attempt.url()) {
+            attempt.error(e.to_string())
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_only_listed_hosts() -> Result<()> {
+        let allowlist = HostAllowlist::new(&["api.curseforge.com".to_string(), " Media.ForgeCDN.net".to_string()]);
+        assert!(allowlist.allows(&Url::parse("https://api.curseforge.com/v1/mods/1")?));
+        assert!(allowlist.allows(&Url::parse("https://media.forgecdn.net/files/1/2/a.jar")?));
+        assert!(allowlist.check(&Url::parse("https://evil.example/a.jar")?).is_err());
+        assert!(!allowlist.allows(&Url::parse("https://forgecdn.net.example/a.jar")?), "Only whole hosts match");
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+//! Spreading requests over several API keys, for organizations with a quota per key.
+//!
+//! Keys come from `CURSE_API_KEY` or the `APIKEY` file, separated by commas or newlines. A key
+//! the API refuses with 401 or 403 is left alone for the rest of the run; one that's rate-limited
+//! with 429 is only waited for.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// How requests are spread over the keys.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rotation {
+    /// Use the first key until it's refused, then the next.
+    #[default]
+    Failover,
+    /// Take turns, so every key's quota is drawn on evenly.
+    RoundRobin,
+}
+
+/// Requests sent with one key, and how many of those were refused.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub requests: u32,
+    pub refused: u32,
+}
+
+struct State {
+    next: usize,
+    refused: Vec<bool>,
+    usage: Vec<KeyUsage>,
+}
+
+pub struct ApiKeys {
+    keys: Vec<String>,
+    rotation: Rotation,
+    state: Mutex<State>,
+}
+
+impl ApiKeys {
+    pub fn new(keys: Vec<String>, rotation: Rotation) -> Self {
+        let state = State { next: 0, refused: vec![false; keys.len()], usage: vec![KeyUsage::default(); keys.len()] };
+        ApiKeys { keys, rotation, state: Mutex::new(state) }
+    }
+
+    /// Splits what `CURSE_API_KEY` or `APIKEY` holds into keys.
+    pub fn parse(source: &str, rotation: Rotation) -> Self {
+        let keys = source.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|k| !k.is_empty())
+            .map(str::to_string)
+            .collect();
+        ApiKeys::new(keys, rotation)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// The key for the next request, by index and value; None once every key was refused.
+    pub fn pick(&self) -> Option<(usize, &str)> {
+        let mut state = self.state.lock().unwrap();
+        let start = match self.rotation {
+            Rotation::Failover => 0,
+            Rotation::RoundRobin => state.next,
+        };
+        let index = (0..self.keys.len()).map(|i| (start + i) % self.keys.len()).find(|&i| !state.refused[i])?;
+        state.next = index + 1;
+        state.usage[index].requests += 1;
+        Some((index, &self.keys[index]))
+    }
+
+    /// Stops using a key the API refused. Returns whether any other key is left to try.
+    pub fn refuse(&self, index: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.refused[index] = true;
+        state.usage[index].refused += 1;
+        state.refused.iter().any(|refused| !refused)
+    }
+
+    /// Names a key in logs by its position and last few characters, never the whole key.
+    pub fn label(&self, index: usize) -> String {
+        let key = &self.keys[index];
+        let tail: String = key.chars().skip(key.chars().count().saturating_sub(4)).collect();
+        format!("key {} (...{})", index + 1, tail)
+    }
+
+    pub fn usage(&self) -> BTreeMap<String, KeyUsage> {
+        let state = self.state.lock().unwrap();
+        state.usage.iter().enumerate().map(|(i, usage)| (self.label(i), usage.clone())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_past_refused_keys() {
+        let keys = ApiKeys::parse("aaaa1111, bbbb2222\ncccc3333\n", Rotation::RoundRobin);
+        assert_eq!(keys.len(), 3);
+        let picked: Vec<usize> = (0..4).map(|_| keys.pick().unwrap().0).collect();
+        assert_eq!(picked, vec![0, 1, 2, 0]);
+        assert!(keys.refuse(1));
+        let picked: Vec<usize> = (0..3).map(|_| keys.pick().unwrap().0).collect();
+        assert_eq!(picked, vec![2, 0, 2]);
+        assert_eq!(keys.usage()["key 2 (...2222)"], KeyUsage { requests: 1, refused: 1 });
+
+        let failover = ApiKeys::parse("aaaa1111,bbbb2222", Rotation::Failover);
+        assert_eq!(failover.pick().map(|(i, _)| i), Some(0));
+        assert_eq!(failover.pick().map(|(i, _)| i), Some(0), "Failover sticks to the first key");
+        assert!(failover.refuse(0));
+        assert_eq!(failover.pick(), Some((1, "bbbb2222")));
+        assert!(!failover.refuse(1), "No keys left");
+        assert_eq!(failover.pick(), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Adding up what a run is about to download, to say how long it should take and to stop
+//! updates that would pull far more than expected before they do.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::units::{ByteSize, HumanDuration};
+
+/// Files about to be downloaded, with `--max-download-bytes` as the limit on their sizes.
+pub struct DownloadBudget {
+    limit: Option<u64>,
+    planned: Mutex<Planned>,
+}
+
+#[derive(Default)]
+struct Planned {
+    files: u32,
+    bytes: u64,
+    // Files CurseForge gave no size for, which count as nothing.
+    without_size: u32,
+}
+
+/// The files to download add up to more than `--max-download-bytes`.
+#[derive(Debug)]
+pub struct OverBudget {
+    pub limit: u64,
+    pub planned: u64,
+}
+
+impl fmt::Display for OverBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The files to download add up to more than {} ({} in all), so nothing is downloaded. \
+                   Raise --max-download-bytes if that's expected", ByteSize(self.limit), ByteSize(self.planned))
+    }
+}
+
+impl std::error::Error for OverBudget {}
+
+impl DownloadBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        DownloadBudget { limit, planned: Mutex::new(Planned::default()) }
+    }
+
+    /// Counts a file that's about to be downloaded, of the size CurseForge gives if any.
+    pub fn plan(&self, size: Option<u64>) {
+        let mut planned = self.planned.lock().unwrap();
+        planned.files += 1;
+        planned.bytes += size.unwrap_or(0);
+        if size.is_none() {
+            planned.without_size += 1;
+        }
+    }
+
+    /// Fails if the planned files are over the limit. Checked before any are downloaded.
+    pub fn check(&self) -> Result<(), OverBudget> {
+        let planned = self.planned.lock().unwrap().bytes;
+        match self.limit {
+            Some(limit) if planned > limit => Err(OverBudget { limit, planned }),
+            _ => Ok(()),
+        }
+    }
+
+    /// How many planned files have no known size, making the total a lower bound.
+    pub fn unsized_files(&self) -> u32 {
+        self.planned.lock().unwrap().without_size
+    }
+
+    /// What's planned and, given how fast downloads go, about how long it takes.
+    pub fn describe(&self, bytes_per_second: Option<f64>) -> Option<String> {
+        let Planned { files, bytes, .. } = *self.planned.lock().unwrap();
+        if files == 0 {
+            return None;
+        }
+        let mut description = format!("{} {} to download, {} in total", files, if files == 1 { "file" } else { "files" }, ByteSize(bytes));
+        if let Some(rate) = bytes_per_second.filter(|&rate| rate > 0.0) {
+            let estimate = HumanDuration(Duration::from_secs_f64(bytes as f64 / rate));
+            description.push_str(&format!(", about {} at {}/s", estimate, ByteSize(rate as u64)));
+        }
+        Some(description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_once_over_the_limit() {
+        let budget = DownloadBudget::new(Some(3_000_000));
+        assert_eq!(budget.describe(None), None);
+        budget.plan(Some(2_000_000));
+        assert!(budget.check().is_ok());
+        assert_eq!(budget.describe(Some(100_000.0)).as_deref(), Some("1 file to download, 2.0MB in total, about 20s at 100.0KB/s"));
+        budget.plan(Some(1_500_000));
+        budget.plan(None);
+        assert_eq!(budget.unsized_files(), 1);
+        let over = budget.check().unwrap_err();
+        assert!(over.to_string().contains("more than 3.0MB (3.5MB in all)"), "{}", over);
+        let unlimited = DownloadBudget::new(None);
+        unlimited.plan(Some(u32::MAX as u64));
+        assert!(unlimited.check().is_ok());
+    }
+}
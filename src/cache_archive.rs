@@ -0,0 +1,90 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::database::{Cache, Query};
+use crate::output::AtomicFile;
+
+// The single file inside the archive, one JSON cache row per line.
+const ENTRY_NAME: &str = "cache.jsonl";
+
+/// Writes cache rows, optionally only those for the given URLs, to a zstd-compressed tarball.
+pub fn export(database: &dyn Cache, archive_path: &Path, only: Option<&BTreeSet<String>>) -> Result<usize> {
+    let rows = database.rows()?
+        .into_iter()
+        .filter(|row| only.is_none_or(|urls| urls.contains(row.url())))
+        .collect::<Vec<_>>();
+    let mut lines = Vec::new();
+    for row in &rows {
+        serde_json::to_writer(&mut lines, row)?;
+        lines.push(b'\n');
+    }
+
+    let file = AtomicFile::create(archive_path)?;
+    let mut builder = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(lines.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, ENTRY_NAME, lines.as_slice())?;
+    builder.into_inner()?.finish()?.commit()?;
+    Ok(rows.len())
+}
+
+/// Loads every row from an archive, keeping whichever copy of an entry is newer.
+pub fn import(database: &dyn Cache, archive_path: &Path) -> Result<usize> {
+    let file = File::open(archive_path)
+        .context(format!("While opening {:?}", archive_path))?;
+    let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+    let mut imported = 0;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.path()?.to_str() != Some(ENTRY_NAME) {
+            continue;
+        }
+        imported += import_lines(database, entry)?;
+    }
+    Ok(imported)
+}
+
+fn import_lines<R: Read>(database: &dyn Cache, reader: R) -> Result<usize> {
+    let mut imported = 0;
+    for line in BufReader::new(reader).lines() {
+        let row: Query = serde_json::from_str(&line?)
+            .context("While parsing cache archive")?;
+        if database.put_if_newer(&row)? {
+            imported += 1;
+        }
+    }
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use std::time::Duration;
+
+    #[test]
+    fn round_trips_selected_rows() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let archive_path = dir.path().join("cache.tar.zst");
+        let source = Database::for_tests()?;
+        for url in &["https://example.org/a", "https://example.org/b"] {
+            source.get_or_put(url, &Duration::from_secs(60), Box::new(|| Ok(format!("result for {}", url))))?;
+        }
+        let only: BTreeSet<String> = vec!["https://example.org/a".to_string()].into_iter().collect();
+        assert_eq!(export(&source, &archive_path, Some(&only))?, 1);
+
+        let target = Database::for_tests()?;
+        assert_eq!(import(&target, &archive_path)?, 1);
+        let cached = target.get_or_put("https://example.org/a", &Duration::from_secs(60), Box::new(|| anyhow::bail!("Should be imported")))?;
+        assert_eq!(cached, "result for https://example.org/a");
+        assert_eq!(import(&target, &archive_path)?, 0, "Rows that aren't newer are skipped");
+
+        Ok(())
+    }
+}
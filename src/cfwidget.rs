@@ -0,0 +1,119 @@
+//! Models for the unofficial CFWidget API, used to look projects up without a CurseForge API key.
+//!
+//! This is a best-effort fallback: CFWidget scrapes CurseForge on its own schedule, so its data can
+//! be stale, and it knows nothing about dependencies or categories.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::curse_api::{AddonAuthor, AddonInfo, AddonLinks, CurseModFile};
+
+/// CFWidget is run by volunteers, so requests are spaced out at least this much.
+pub const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Project {
+    pub id: u32,
+    pub title: String,
+    pub urls: ProjectUrls,
+    #[serde(default)]
+    pub files: Vec<ProjectFile>,
+    #[serde(default)]
+    pub members: Vec<Member>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProjectUrls {
+    pub curseforge: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProjectFile {
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub display: Option<String>,
+    /// Game versions and loaders, like `gameVersions` in the official API.
+    #[serde(default)]
+    pub versions: Vec<String>,
+    pub uploaded_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Member {
+    pub id: u32,
+    pub username: String,
+}
+
+impl Project {
+    pub fn slug(&self) -> Result<&str> {
+        self.urls.curseforge.trim_end_matches('/').rsplit('/').next()
+            .filter(|s| !s.is_empty())
+            .context(format!("No slug in {}", self.urls.curseforge))
+    }
+
+    pub fn addon_info(&self) -> Result<AddonInfo> {
+        Ok(AddonInfo {
+            name: self.title.clone(),
+            slug: self.slug()?.to_string(),
+            id: self.id,
+            links: AddonLinks {
+                website_url: self.urls.curseforge.clone(),
+                wiki_url: None,
+                issues_url: None,
+                source_url: None,
+            },
+            status: None,
+            is_available: None,
+            categories: vec![],
+            authors: self.members.iter()
+                .map(|m| AddonAuthor { id: m.id, name: m.username.clone(), url: None })
+                .collect(),
+            latest_files_indexes: vec![],
+            download_count: None,
+            date_released: None,
+            logo: None,
+            allow_mod_distribution: None,
+        })
+    }
+}
+
+impl ProjectFile {
+    /// CFWidget doesn't list download URLs, but CurseForge's CDN paths follow from the file ID.
+    pub fn curse_file(&self) -> CurseModFile {
+        CurseModFile {
+            id: self.id,
+            file_name: self.name.clone(),
+            display_name: self.display.clone(),
+            file_date: self.uploaded_at.clone(),
+            download_url: format!("https://media.forgecdn.net/files/{}/{}/{}", self.id / 1000, self.id % 1000, self.name),
+            game_version: self.versions.clone(),
+            dependencies: vec![],
+            file_length: None,
+            file_fingerprint: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_project_fixture() -> Result<()> {
+        let project: Project = serde_json::from_str(include_str!("../fixtures/cfwidget/project.json"))?;
+        let info = project.addon_info()?;
+        assert_eq!(info.slug, "jei");
+        assert_eq!(info.name, "Just Enough Items (JEI)");
+        assert_eq!(info.authors[0].name, "mezz");
+
+        let file = project.files[0].curse_file();
+        assert_eq!(file.download_url, "https://media.forgecdn.net/files/3043/174/jei_1.12.2-4.16.1.302.jar");
+        assert_eq!(file.game_version, vec!["1.12.2"]);
+        assert!(file.dependencies.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+use crate::model::{YamlManifest, YamlMod};
+use crate::vars;
+
+/// How a mod differs between two revisions of a manifest.
+#[derive(Debug)]
+pub enum Change<'a> {
+    Added(&'a YamlMod),
+    Removed(&'a YamlMod),
+    Updated { from: &'a YamlMod, to: &'a YamlMod },
+}
+
+/// What a changelog says about one side of a change.
+pub struct Described {
+    pub title: String,
+    pub file: String,
+    pub link: Option<String>,
+}
+
+/// Reads a manifest as it was at a git revision. Imports aren't followed, so only changes to
+/// the manifest itself show up.
+pub fn read_revision(manifest_path: &Path, revision: &str) -> Result<YamlManifest> {
+    let dir = manifest_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    let file_name = manifest_path.file_name().context(format!("{:?} is not a file", manifest_path))?;
+    let output = Command::new("git")
+        .arg("-C").arg(dir)
+        .arg("show")
+        .arg(format!("{}:./{}", revision, file_name.to_string_lossy()))
+        .output()
+        .context("While running git")?;
+    if !output.status.success() {
+        anyhow::bail!("git show failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    parse(&String::from_utf8(output.stdout)?)
+        .context(format!("While parsing {:?} at {}", manifest_path, revision))
+}
+
+pub fn parse(source: &str) -> Result<YamlManifest> {
+    let document: serde_yaml::Value = serde_yaml::from_str(source)?;
+    Ok(serde_yaml::from_value(vars::expand_manifest(document)?)?)
+}
+
+/// Changes to pins, by mod name. Settings like `side` don't make it into a changelog.
+pub fn diff<'a>(from: &'a YamlManifest, to: &'a YamlManifest) -> Vec<Change<'a>> {
+    let old: BTreeMap<&str, &YamlMod> = from.mods.iter().map(|m| (m.name.as_str(), m)).collect();
+    let new: BTreeMap<&str, &YamlMod> = to.mods.iter().map(|m| (m.name.as_str(), m)).collect();
+    let mut changes = Vec::new();
+    for (name, &to_mod) in &new {
+        match old.get(name) {
+            None => changes.push(Change::Added(to_mod)),
+            Some(&from_mod) if pins_differ(from_mod, to_mod) => changes.push(Change::Updated { from: from_mod, to: to_mod }),
+            Some(_) => (),
+        }
+    }
+    changes.extend(old.iter().filter(|(name, _)| !new.contains_key(*name)).map(|(_, &m)| Change::Removed(m)));
+    changes
+}
+
+fn pins_differ(a: &YamlMod, b: &YamlMod) -> bool {
+    let pin = |m: &YamlMod| (m.id, m.client_id, m.server_id,
+                             m.files.as_ref().map(|files| files.iter().map(|f| (f.id, f.src.clone(), f.name.clone())).collect::<Vec<_>>()));
+    pin(a) != pin(b)
+}
+
+fn link(described: &Described) -> String {
+    match described.link {
+        Some(ref link) => format!("[{}]({})", described.file, link),
+        None => described.file.clone(),
+    }
+}
+
+/// Renders the changes as Markdown, looking up what each pin points at with `describe`.
+pub fn render<F>(changes: &[Change], describe: F) -> Result<String> where F: Fn(&YamlMod) -> Result<Described> {
+    let mut added = String::new();
+    let mut updated = String::new();
+    let mut removed = String::new();
+    for change in changes {
+        match change {
+            Change::Added(m) => {
+                let d = describe(m).context(format!("While describing {}", m.name))?;
+                writeln!(added, "- {}: {}", d.title, link(&d))?;
+            }
+            Change::Updated { from, to } => {
+                let old = describe(from).context(format!("While describing the old {}", from.name))?;
+                let new = describe(to).context(format!("While describing {}", to.name))?;
+                writeln!(updated, "- {}: {} -> {}", new.title, old.file, link(&new))?;
+            }
+            Change::Removed(m) => {
+                let title = describe(m).map(|d| d.title).unwrap_or_else(|_| m.name.clone());
+                writeln!(removed, "- {}", title)?;
+            }
+        }
+    }
+    let mut changelog = String::new();
+    for (heading, section) in [("Added", added), ("Updated", updated), ("Removed", removed)] {
+        if !section.is_empty() {
+            write!(changelog, "{}## {}\n\n{}", if changelog.is_empty() { "" } else { "\n" }, heading, section)?;
+        }
+    }
+    if changelog.is_empty() {
+        changelog.push_str("No mods changed.\n");
+    }
+    Ok(changelog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_added_updated_and_removed_pins() -> Result<()> {
+        let from = parse("version: 1.12.2\nmods:\n- name: jei\n  files:\n  - id: 1\n- name: gone\n- name: same\n  side: client\n")?;
+        let to = parse("version: 1.12.2\nmods:\n- name: jei\n  files:\n  - id: 2\n- name: new\n- name: same\n")?;
+        let changes = diff(&from, &to);
+        assert_eq!(changes.len(), 3, "Only pins count, not sides: {:?}", changes);
+
+        let changelog = render(&changes, |m| Ok(Described {
+            title: m.name.to_uppercase(),
+            file: format!("{}-{}.jar", m.name, m.files.as_ref().and_then(|f| f[0].id).unwrap_or(0)),
+            link: Some(format!("https://example.org/{}", m.name)),
+        }))?;
+        assert_eq!(changelog, "## Added\n\n- NEW: [new-0.jar](https://example.org/new)\n\n\
+                               ## Updated\n\n- JEI: jei-1.jar -> [jei-2.jar](https://example.org/jei)\n\n\
+                               ## Removed\n\n- GONE\n");
+        Ok(())
+    }
+}
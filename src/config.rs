@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::api_keys::Rotation;
+use crate::database::CacheBackend;
+use crate::lint::LintConfig;
+use crate::model;
+use crate::naming::{self, FilenameRule};
+use crate::pinning::TlsRoots;
+use crate::policy::Policy;
+use crate::units::HumanDuration;
+
+const DEFAULT_CONFIG_FILE: &str = "cursetool.yaml";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub cache_ttl: CacheTtl,
+    /// Cache entries older than this are deleted on startup; 0 keeps them forever.
+    pub cache_retention: HumanDuration,
+    /// Where cached responses are kept.
+    pub cache_backend: CacheBackend,
+    pub policy: Policy,
+    /// Replaces the whole User-Agent header, for organizations with their own conventions.
+    pub user_agent: Option<String>,
+    /// Where CurseForge can reach whoever runs this instance; added to the default User-Agent.
+    pub contact_url: Option<String>,
+    /// Public key pins per host, as `sha256/<base64 of the SubjectPublicKeyInfo hash>`.
+    pub tls_pins: BTreeMap<String, Vec<String>>,
+    /// Which certificate authorities to trust. Overridden by `--tls-roots`.
+    pub tls_roots: TlsRoots,
+    /// If set, requests to any other host fail, including redirects. Overridden by `--allow-hosts`.
+    pub allow_hosts: Option<Vec<String>>,
+    /// How requests are spread over several API keys, when `CURSE_API_KEY` or `APIKEY` lists more than one.
+    pub api_key_rotation: Rotation,
+    /// Without an API key, look projects up through the unofficial CFWidget API instead of failing.
+    pub cfwidget_fallback: bool,
+    /// How many levels deep manifests may import one another, to stop runaway import trees early.
+    pub max_import_depth: usize,
+    /// Regex replacements made to filenames before they're made safe. Replaces the default of dropping parentheses.
+    pub filename_rules: Vec<FilenameRule>,
+    /// How strictly `lint` treats each of its rules.
+    pub lint: LintConfig,
+}
+
+/// How long cached API responses stay valid per kind of endpoint, as seconds or e.g. `36h`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheTtl {
+    pub addon_info: HumanDuration,
+    pub file_listing: HumanDuration,
+    pub file: HumanDuration,
+    pub search: HumanDuration,
+    /// Slug searches that found nothing.
+    pub not_found: HumanDuration,
+}
+
+impl Default for CacheTtl {
+    fn default() -> Self {
+        CacheTtl {
+            // Project metadata hardly ever changes.
+            addon_info: HumanDuration(Duration::from_secs(86400 * 7)),
+            // New files are what we're usually looking for.
+            file_listing: HumanDuration(Duration::from_secs(3600 * 6)),
+            // A specific file's metadata is effectively immutable.
+            file: HumanDuration(Duration::from_secs(86400 * 30)),
+            search: HumanDuration(Duration::from_secs(3600)),
+            // Short, as the slug may be fixed or the project published any moment.
+            not_found: HumanDuration(Duration::from_secs(600)),
+        }
+    }
+}
+
+impl CacheTtl {
+    pub fn addon_info(&self) -> Duration {
+        self.addon_info.0
+    }
+
+    pub fn file_listing(&self) -> Duration {
+        self.file_listing.0
+    }
+
+    pub fn file(&self) -> Duration {
+        self.file.0
+    }
+
+    pub fn search(&self) -> Duration {
+        self.search.0
+    }
+
+    pub fn not_found(&self) -> Duration {
+        self.not_found.0
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            cache_ttl: CacheTtl::default(),
+            cache_retention: HumanDuration(Duration::from_secs(86400 * 90)),
+            cache_backend: CacheBackend::default(),
+            policy: Policy::default(),
+            user_agent: None,
+            contact_url: None,
+            tls_pins: BTreeMap::new(),
+            tls_roots: TlsRoots::default(),
+            allow_hosts: None,
+            api_key_rotation: Rotation::default(),
+            cfwidget_fallback: false,
+            max_import_depth: model::DEFAULT_MAX_IMPORT_DEPTH,
+            filename_rules: naming::default_filename_rules(),
+            lint: LintConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn cache_retention(&self) -> Duration {
+        self.cache_retention.0
+    }
+
+    pub fn user_agent(&self) -> String {
+        if let Some(ref user_agent) = self.user_agent {
+            return user_agent.clone();
+        }
+        let base = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        match self.contact_url {
+            Some(ref contact) => format!("{} (+{})", base, contact),
+            None => base,
+        }
+    }
+
+    /// Loads the given config file, or `cursetool.yaml` from the working directory if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load_from_file(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::load_from_file(Path::new(DEFAULT_CONFIG_FILE)),
+            None => Ok(Config::default()),
+        }
+    }
+
+    fn load_from_file(path: &Path) -> Result<Self> {
+        log::info!("Using config file {:?}", path);
+        let file = File::open(path)
+            .context(format!("While opening {:?}", path))?;
+        serde_yaml::from_reader(file)
+            .context(format!("While parsing config from {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_config_keeps_defaults() -> Result<()> {
+        let config: Config = serde_yaml::from_str("cache_ttl:\n  search: 60\n")?;
+        assert_eq!(config.cache_ttl.search(), Duration::from_secs(60));
+        assert_eq!(config.cache_ttl.addon_info, CacheTtl::default().addon_info);
+        assert_eq!(config.cache_backend, CacheBackend::Sqlite);
+        let config: Config = serde_yaml::from_str("cache_backend: json-dir\n")?;
+        assert_eq!(config.cache_backend, CacheBackend::JsonDir);
+        let config: Config = serde_yaml::from_str("cache_ttl:\n  file_listing: 36h\ncache_retention: 30d\n")?;
+        assert_eq!(config.cache_ttl.file_listing(), Duration::from_secs(129600));
+        assert_eq!(config.cache_retention(), Duration::from_secs(86400 * 30));
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_agent_includes_contact() -> Result<()> {
+        let config: Config = serde_yaml::from_str("contact_url: https://example.org/pack\n")?;
+        assert_eq!(config.user_agent(), format!("cursetool-rs/{} (+https://example.org/pack)", env!("CARGO_PKG_VERSION")));
+        let config: Config = serde_yaml::from_str("user_agent: ExampleCorp-Builder/1.0\ncontact_url: ignored\n")?;
+        assert_eq!(config.user_agent(), "ExampleCorp-Builder/1.0");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! Endpoints of the CurseForge Core API, and models for their version 1 responses.
+//!
+//! Fields the API may leave out or null are optional, so cached responses
+//! from older and newer API revisions both keep parsing.
+
+use serde::{Deserialize, Serialize};
+
+/// The endpoints we use, so that their paths are spelled out in one place.
+#[derive(Clone, Copy, Debug)]
+pub enum Endpoint {
+    Mod { project_id: u32 },
+    Files { project_id: u32 },
+    File { project_id: u32, file_id: u32 },
+    Search,
+    Fingerprints { game_id: u32 },
+}
+
+impl Endpoint {
+    pub fn path(&self) -> String {
+        match self {
+            Endpoint::Mod { project_id } => format!("/v1/mods/{}", project_id),
+            Endpoint::Files { project_id } => format!("/v1/mods/{}/files", project_id),
+            Endpoint::File { project_id, file_id } => format!("/v1/mods/{}/files/{}", project_id, file_id),
+            Endpoint::Search => "/v1/mods/search".to_string(),
+            Endpoint::Fingerprints { game_id } => format!("/v1/fingerprints/{}", game_id),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CurseWrapper<T> {
+    pub data: T,
+    pub pagination: Option<Pagination>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Pagination {
+    pub index: u32,
+    #[serde(rename = "pageSize")]
+    pub page_size: u32,
+    #[serde(rename = "resultCount")]
+    pub result_count: u32,
+    #[serde(rename = "totalCount")]
+    pub total_count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AddonLinks {
+    #[serde(rename = "websiteUrl")]
+    pub website_url: String,
+    #[serde(rename = "wikiUrl", default)]
+    pub wiki_url: Option<String>,
+    #[serde(rename = "issuesUrl", default)]
+    pub issues_url: Option<String>,
+    #[serde(rename = "sourceUrl", default)]
+    pub source_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AddonInfo {
+    pub name: String,
+    pub slug: String,
+    pub id: u32,
+    pub links: AddonLinks,
+    /// Moderation status, 4 being approved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u32>,
+    #[serde(rename = "isAvailable")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_available: Option<bool>,
+    #[serde(default)]
+    pub categories: Vec<AddonCategory>,
+    #[serde(default)]
+    pub authors: Vec<AddonAuthor>,
+    #[serde(rename = "latestFilesIndexes", default)]
+    pub latest_files_indexes: Vec<FileIndex>,
+    #[serde(rename = "downloadCount", default, skip_serializing_if = "Option::is_none")]
+    pub download_count: Option<f64>,
+    #[serde(rename = "dateReleased", default, skip_serializing_if = "Option::is_none")]
+    pub date_released: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo: Option<AddonLogo>,
+    /// Whether the author lets others distribute the files, e.g. in a server tarball.
+    #[serde(rename = "allowModDistribution", default, skip_serializing_if = "Option::is_none")]
+    pub allow_mod_distribution: Option<bool>,
+}
+
+/// The project's icon, as shown next to its name on the site.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AddonLogo {
+    #[serde(rename = "thumbnailUrl")]
+    pub thumbnail_url: String,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AddonCategory {
+    pub id: u32,
+    pub name: String,
+    pub slug: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AddonAuthor {
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// The newest file of a project for one game version and release type.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileIndex {
+    #[serde(rename = "gameVersion")]
+    pub game_version: String,
+    #[serde(rename = "fileId")]
+    pub file_id: u32,
+    pub filename: String,
+    #[serde(rename = "releaseType")]
+    pub release_type: u32,
+    #[serde(rename = "gameVersionTypeId", default)]
+    pub game_version_type_id: Option<u32>,
+    #[serde(rename = "modLoader", default)]
+    pub mod_loader: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CurseModFile {
+    pub id: u32,
+    #[serde(rename = "fileName")]
+    pub file_name: String,
+    /// What the project page calls the file, which usually includes the mod's version.
+    #[serde(rename = "displayName", default)]
+    pub display_name: Option<String>,
+    #[serde(rename = "fileDate")]
+    pub file_date: String,
+    #[serde(rename = "downloadUrl")]
+    pub download_url: String,
+    #[serde(rename = "gameVersions")]
+    pub game_version: Vec<String>,
+    #[serde(default)]
+    pub dependencies: Vec<CurseFileDependency>,
+    #[serde(rename = "fileLength", default)]
+    pub file_length: Option<u64>,
+    /// See `fingerprint::curse_fingerprint`.
+    #[serde(rename = "fileFingerprint", default)]
+    pub file_fingerprint: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CurseFileDependency {
+    #[serde(rename = "modId")]
+    pub mod_id: u32,
+    #[serde(rename = "relationType")]
+    pub relation_type: u32,
+}
+
+/// The result of looking files up by fingerprint.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FingerprintMatches {
+    #[serde(rename = "exactMatches", default)]
+    pub exact_matches: Vec<FingerprintMatch>,
+    #[serde(rename = "unmatchedFingerprints", default)]
+    pub unmatched_fingerprints: Option<Vec<u32>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FingerprintMatch {
+    /// The project ID.
+    pub id: u32,
+    pub file: FingerprintFile,
+}
+
+/// Just enough of a file to pin it; unlike file listings, these may lack a download URL.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FingerprintFile {
+    pub id: u32,
+    #[serde(rename = "fileName")]
+    pub file_name: String,
+    #[serde(rename = "fileFingerprint")]
+    pub file_fingerprint: u32,
+}
+
+impl CurseFileDependency {
+    pub const EMBEDDED_LIBRARY: u32 = 1;
+    pub const REQUIRED_DEPENDENCY: u32 = 3;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn builds_endpoint_paths() {
+        assert_eq!(Endpoint::File { project_id: 238222, file_id: 3043174 }.path(), "/v1/mods/238222/files/3043174");
+        assert_eq!(Endpoint::Fingerprints { game_id: 432 }.path(), "/v1/fingerprints/432");
+    }
+
+    #[test]
+    fn parses_mod_fixture() -> Result<()> {
+        let info: CurseWrapper<AddonInfo> = serde_json::from_str(include_str!("../fixtures/curse/mod.json"))?;
+        let info = info.data;
+        assert_eq!(info.slug, "hunger-overhaul");
+        assert_eq!(info.status, Some(4));
+        assert_eq!(info.links.wiki_url.as_deref(), Some(""));
+        assert_eq!(info.links.source_url, None);
+        assert_eq!(info.categories[0].slug, "mc-food");
+        assert_eq!(info.authors[0].name, "progwml6");
+        assert_eq!(info.latest_files_indexes.len(), 2);
+        assert_eq!(info.latest_files_indexes[1].mod_loader, None);
+        assert_eq!(info.logo.map(|l| l.thumbnail_url).as_deref(),
+                   Some("https://media.forgecdn.net/avatars/thumbnails/29/69/256/256/635596230345639462.png"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_file_listing_fixture() -> Result<()> {
+        let files: CurseWrapper<Vec<CurseModFile>> = serde_json::from_str(include_str!("../fixtures/curse/files.json"))?;
+        assert_eq!(files.pagination.map(|p| p.result_count), Some(1));
+        let file = &files.data[0];
+        assert_eq!(file.game_version, vec!["1.12.2"]);
+        assert_eq!(file.dependencies.iter()
+                       .filter(|d| d.relation_type == CurseFileDependency::REQUIRED_DEPENDENCY)
+                       .map(|d| d.mod_id)
+                       .collect::<Vec<_>>(), vec![228525]);
+
+        Ok(())
+    }
+}
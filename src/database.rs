@@ -1,13 +1,21 @@
+use std::collections::HashSet;
 use std::fs::create_dir_all;
+use std::path::PathBuf;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use rusqlite::{Connection, params};
-use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::{Condvar, Mutex};
+
+use crate::json_cache::JsonDirCache;
 
 const DB_NAME: &str = "cache.db";
+// VACUUM once at least this fraction of the file is free pages.
+const VACUUM_FREE_FRACTION: f64 = 0.25;
 
-#[derive(Debug)]
+/// A cache row, as exported to archives.
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Query {
     url: String,
     // Primary key
@@ -15,8 +23,112 @@ pub struct Query {
     downloaded: SystemTime,  // This is stored as seconds since epoch
 }
 
+impl Query {
+    pub fn new(url: String, result: String, downloaded: SystemTime) -> Self {
+        Query { url, result, downloaded }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn result(&self) -> &str {
+        &self.result
+    }
+
+    pub fn downloaded(&self) -> SystemTime {
+        self.downloaded
+    }
+}
+
+/// Where cached responses are kept. Everything goes through this, so backends can be swapped.
+pub trait Cache: Send + Sync {
+    /// The cached result for a URL, if it was stored within `lifetime`.
+    fn get_fresh(&self, url: &str, lifetime: &Duration) -> Result<Option<String>>;
+
+    fn put(&self, url: &str, result: &str) -> Result<()>;
+
+    /// Stores a row unless the cache already has a copy at least as recent. Returns whether it did.
+    fn put_if_newer(&self, query: &Query) -> Result<bool>;
+
+    /// The cached result if it's fresh enough, or else whatever `fetch` returns, which is then cached.
+    fn get_or_put(&self, url: &str, lifetime: &Duration, fetch: Box<dyn FnOnce() -> Result<String> + '_>) -> Result<String>;
+
+    fn invalidate(&self, url: &str) -> Result<()>;
+
+    /// Deletes every entry whose URL matches the pattern, in which `*` matches anything.
+    /// Returns how many were deleted.
+    fn invalidate_matching(&self, pattern: &str) -> Result<usize>;
+
+    fn rows(&self) -> Result<Vec<Query>>;
+
+    /// Deletes entries downloaded longer than `retention` ago, returning how many.
+    fn remove_older_than(&self, retention: Duration) -> Result<usize>;
+}
+
+/// Which `Cache` to keep responses in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheBackend {
+    #[default]
+    Sqlite,
+    /// One JSON file per entry, for filesystems where sqlite's locking doesn't work, such as some NFS mounts.
+    JsonDir,
+}
+
+/// Opens the configured cache, dropping entries downloaded longer than `retention` ago. Zero keeps everything.
+pub fn open(backend: CacheBackend, retention: Duration) -> Result<Box<dyn Cache>> {
+    let cache: Box<dyn Cache> = match backend {
+        CacheBackend::Sqlite => Box::new(Database::from_filesystem()?),
+        CacheBackend::JsonDir => Box::new(JsonDirCache::open(Database::cache_dir()?.join("json"))?),
+    };
+    if !retention.is_zero() {
+        let removed = cache.remove_older_than(retention)
+            .context("While cleaning up the cache")?;
+        if removed > 0 {
+            log::info!("Removed {} cache entries older than {} days", removed, retention.as_secs() / 86400);
+        }
+    }
+    Ok(cache)
+}
+
+/// The URLs being fetched for a cache right now. Misses for the same URL wait for the first
+/// fetch rather than repeating it, while different URLs are fetched side by side.
+#[derive(Default)]
+pub struct InFlight {
+    fetching: Mutex<HashSet<String>>,
+    done: Condvar,
+}
+
+/// Held while fetching a URL; dropping it lets whoever waits for the URL go ahead.
+pub struct Claim<'a> {
+    in_flight: &'a InFlight,
+    url: String,
+}
+
+impl InFlight {
+    /// Waits until nobody else is fetching `url`, then claims it.
+    pub fn claim(&self, url: &str) -> Claim<'_> {
+        let mut fetching = self.fetching.lock().unwrap();
+        while fetching.contains(url) {
+            fetching = self.done.wait(fetching).unwrap();
+        }
+        fetching.insert(url.to_string());
+        Claim { in_flight: self, url: url.to_string() }
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetching.lock().unwrap().remove(&self.url);
+        self.in_flight.done.notify_all();
+    }
+}
+
+/// The default cache, a single sqlite database.
 pub struct Database {
     lock: Mutex<Connection>,
+    in_flight: InFlight,
 }
 
 fn setup(conn: &Connection) -> Result<()> {
@@ -30,11 +142,8 @@ fn setup(conn: &Connection) -> Result<()> {
 }
 
 impl Database {
-    pub fn from_filesystem() -> Result<Self> {
-        let mut db_path = directories::ProjectDirs::from("brage.info", "erisia", "cursetool-rs")
-            .context("While acquiring cache directory")?
-            .cache_dir()
-            .to_path_buf();
+    fn from_filesystem() -> Result<Self> {
+        let mut db_path = Self::cache_dir()?;
         log::info!("Using database path {:?}", db_path);
         create_dir_all(&db_path)
             .context(format!("While creating {:?}", &db_path))?;
@@ -42,7 +151,14 @@ impl Database {
         let conn = Connection::open(&db_path)
             .context(format!("While opening {:?}", &db_path))?;
         setup(&conn)?;
-        Ok(Database { lock: Mutex::new(conn) })
+        Ok(Database { lock: Mutex::new(conn), in_flight: InFlight::default() })
+    }
+
+    pub fn cache_dir() -> Result<PathBuf> {
+        Ok(directories::ProjectDirs::from("brage.info", "erisia", "cursetool-rs")
+            .context("While acquiring cache directory")?
+            .cache_dir()
+            .to_path_buf())
     }
 
     #[cfg(test)]
@@ -50,37 +166,189 @@ impl Database {
         log::info!("Using in-memory database");
         let conn = Connection::open_in_memory()?;
         setup(&conn)?;
-        Ok(Database { lock: Mutex::new(conn) })
-    }
-
-    pub fn get_or_put<F>(&self, url: &str, lifetime: &Duration, downloader: F) -> Result<String>
-        where F: FnOnce() -> Result<String> {
-        let cached_result = {
-            let conn = self.lock.lock().unwrap();
-            let mut extract = conn.prepare_cached("SELECT result FROM curse_queries WHERE url = ? AND downloaded > ?")?;
-            // We accept previously fetched data that's no older than valid_from.
-            let valid_from = SystemTime::now() - *lifetime;
-            // And convert that to seconds-since-epoch for use in SELECT.
-            let limit_secs = valid_from.duration_since(UNIX_EPOCH)?.as_secs();
-
-            let mut result = extract.query(params![url, limit_secs as i64])
-                .context("Searching cache")?;
-
-            result.next()?.map(|row| row.get(0))
-        };
-
-        if let Some(result) = cached_result {
-            // Cache hit.
-            Ok(result?)
-        } else {
-            // Cache miss. Recompute and insert.
-            let conn = self.lock.lock().unwrap();
-            let downloaded_at = SystemTime::now();
-            let result = downloader()?;
-            let mut update = conn.prepare_cached("INSERT OR REPLACE INTO curse_queries(url, result, downloaded) VALUES(?, ?, ?)")
-                .context("Updating cache")?;
-            update.execute(params![url, result, downloaded_at.duration_since(UNIX_EPOCH)?.as_secs() as i64])?;
-            Ok(result)
+        Ok(Database { lock: Mutex::new(conn), in_flight: InFlight::default() })
+    }
+}
+
+impl Cache for Database {
+    fn remove_older_than(&self, retention: Duration) -> Result<usize> {
+        // Retention reaching back past the epoch keeps everything.
+        let Some(limit) = SystemTime::now().checked_sub(retention) else { return Ok(0) };
+        let conn = self.lock.lock().unwrap();
+        let limit_secs = limit.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let removed = conn.execute("DELETE FROM curse_queries WHERE downloaded < ?", params![limit_secs as i64])?;
+        let page_count: i64 = conn.query_row("PRAGMA page_count", params![], |row| row.get(0))?;
+        let free_pages: i64 = conn.query_row("PRAGMA freelist_count", params![], |row| row.get(0))?;
+        if page_count > 0 && free_pages as f64 / page_count as f64 >= VACUUM_FREE_FRACTION {
+            log::info!("Compacting cache, {} of {} pages are free", free_pages, page_count);
+            conn.execute("VACUUM", params![])?;
         }
+        Ok(removed)
+    }
+
+    fn invalidate(&self, url: &str) -> Result<()> {
+        let conn = self.lock.lock().unwrap();
+        conn.execute("DELETE FROM curse_queries WHERE url = ?", params![url])
+            .context(format!("Invalidating cache entry for {}", url))?;
+        Ok(())
+    }
+
+    fn invalidate_matching(&self, pattern: &str) -> Result<usize> {
+        let like = pattern.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_").replace('*', "%");
+        let conn = self.lock.lock().unwrap();
+        let removed = conn.execute("DELETE FROM curse_queries WHERE url LIKE ? ESCAPE '\\'", params![like])
+            .context(format!("Invalidating cache entries matching {}", pattern))?;
+        Ok(removed)
+    }
+
+    fn rows(&self) -> Result<Vec<Query>> {
+        let conn = self.lock.lock().unwrap();
+        let mut select = conn.prepare("SELECT url, result, downloaded FROM curse_queries")?;
+        let rows = select.query_map(params![], |row| {
+            let downloaded: i64 = row.get(2)?;
+            Ok(Query {
+                url: row.get(0)?,
+                result: row.get(1)?,
+                downloaded: UNIX_EPOCH + Duration::from_secs(downloaded as u64),
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn put_if_newer(&self, query: &Query) -> Result<bool> {
+        let conn = self.lock.lock().unwrap();
+        let downloaded = query.downloaded.duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let changed = conn.execute(
+            "INSERT INTO curse_queries(url, result, downloaded) VALUES(?1, ?2, ?3)
+             ON CONFLICT(url) DO UPDATE SET result = excluded.result, downloaded = excluded.downloaded
+             WHERE excluded.downloaded > curse_queries.downloaded",
+            params![query.url, query.result, downloaded])?;
+        Ok(changed > 0)
+    }
+
+    fn get_fresh(&self, url: &str, lifetime: &Duration) -> Result<Option<String>> {
+        let conn = self.lock.lock().unwrap();
+        // Lifetimes reaching past the epoch, such as a huge `cache_ttl`, never expire.
+        let limit_secs = SystemTime::now().checked_sub(*lifetime)
+            .and_then(|limit| limit.duration_since(UNIX_EPOCH).ok())
+            .map_or(-1, |since| since.as_secs() as i64);
+        let mut select = conn.prepare_cached("SELECT result FROM curse_queries WHERE url = ? AND downloaded > ?")?;
+        let mut rows = select.query(params![url, limit_secs]).context("Searching cache")?;
+        Ok(match rows.next()? {
+            Some(row) => Some(row.get(0)?),
+            None => None,
+        })
+    }
+
+    fn put(&self, url: &str, result: &str) -> Result<()> {
+        let conn = self.lock.lock().unwrap();
+        let downloaded = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        conn.execute("INSERT OR REPLACE INTO curse_queries(url, result, downloaded) VALUES(?, ?, ?)",
+                     params![url, result, downloaded])
+            .context(format!("Updating cache entry for {}", url))?;
+        Ok(())
+    }
+
+    fn get_or_put(&self, url: &str, lifetime: &Duration, downloader: Box<dyn FnOnce() -> Result<String> + '_>) -> Result<String> {
+        if let Some(result) = self.get_fresh(url, lifetime)? {
+            return Ok(result);
+        }
+        // The connection isn't held while fetching, so other requests go ahead meanwhile.
+        let _claim = self.in_flight.claim(url);
+        // Whoever fetched it while we waited may have stored it by now.
+        if let Some(result) = self.get_fresh(url, lifetime)? {
+            return Ok(result);
+        }
+        let downloaded_at = SystemTime::now();
+        let result = downloader()?;
+        let conn = self.lock.lock().unwrap();
+        let mut update = conn.prepare_cached("INSERT OR REPLACE INTO curse_queries(url, result, downloaded) VALUES(?, ?, ?)")
+            .context("Updating cache")?;
+        update.execute(params![url, result, downloaded_at.duration_since(UNIX_EPOCH)?.as_secs() as i64])?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Checks that a cache fetches different URLs side by side, and the same URL only once.
+    pub(crate) fn check_fetches_in_flight(cache: &dyn Cache) -> Result<()> {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::time::Instant;
+
+        let active = AtomicUsize::new(0);
+        let overlapped = AtomicBool::new(false);
+        let fetches = AtomicUsize::new(0);
+        let (active, overlapped, fetches) = (&active, &overlapped, &fetches);
+        let fetch = &|url: &'static str, wait: Duration| cache.get_or_put(url, &Duration::from_secs(60), Box::new(move || {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            active.fetch_add(1, Ordering::SeqCst);
+            // The other fetch can only start meanwhile if this one doesn't hold it up.
+            let started = Instant::now();
+            while started.elapsed() < wait && !overlapped.load(Ordering::SeqCst) {
+                if active.load(Ordering::SeqCst) == 2 {
+                    overlapped.store(true, Ordering::SeqCst);
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            active.fetch_sub(1, Ordering::SeqCst);
+            Ok(url.to_string())
+        }));
+        std::thread::scope(|scope| -> Result<()> {
+            let a = scope.spawn(|| fetch("https://example.org/a", Duration::from_secs(5)));
+            let b = scope.spawn(|| fetch("https://example.org/b", Duration::from_secs(5)));
+            assert_eq!(a.join().unwrap()?, "https://example.org/a");
+            assert_eq!(b.join().unwrap()?, "https://example.org/b");
+            Ok(())
+        })?;
+        assert!(overlapped.load(Ordering::SeqCst), "Both fetches should have been in flight at once");
+
+        fetches.store(0, Ordering::SeqCst);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..2).map(|_| scope.spawn(|| fetch("https://example.org/c", Duration::from_millis(50)))).collect();
+            handles.into_iter().for_each(|h| assert!(h.join().unwrap().is_ok()));
+        });
+        assert_eq!(fetches.load(Ordering::SeqCst), 1, "A URL being fetched is waited for, not fetched again");
+        Ok(())
+    }
+
+    #[test]
+    fn removes_only_expired_entries() -> Result<()> {
+        let database = Database::for_tests()?;
+        database.get_or_put("https://example.org/new", &Duration::from_secs(60), Box::new(|| Ok("new".to_string())))?;
+        {
+            let conn = database.lock.lock().unwrap();
+            conn.execute("INSERT INTO curse_queries(url, result, downloaded) VALUES('https://example.org/old', 'old', 0)", params![])?;
+        }
+        assert_eq!(database.get_fresh("https://example.org/old", &Duration::MAX)?.as_deref(), Some("old"),
+                   "Lifetimes past the epoch never expire");
+        assert_eq!(database.remove_older_than(Duration::from_secs(86400 * 90))?, 1);
+        assert_eq!(database.remove_older_than(Duration::MAX)?, 0, "Retention past the epoch removes nothing");
+        let cached = database.get_or_put("https://example.org/new", &Duration::from_secs(60), Box::new(|| anyhow::bail!("Should be cached")))?;
+        assert_eq!(cached, "new");
+
+        Ok(())
+    }
+
+    #[test]
+    fn fetches_different_urls_at_once() -> Result<()> {
+        check_fetches_in_flight(&Database::for_tests()?)
+    }
+
+    #[test]
+    fn invalidates_matching_urls() -> Result<()> {
+        let database = Database::for_tests()?;
+        for url in &["https://api.curseforge.com/v1/mods/1", "https://api.curseforge.com/v1/mods/1/files",
+                     "https://api.curseforge.com/v1/mods_1", "https://media.forgecdn.net/files/1/2/a.jar"] {
+            database.get_or_put(url, &Duration::from_secs(60), Box::new(|| Ok("{}".to_string())))?;
+        }
+        assert_eq!(database.invalidate_matching("https://api.curseforge.com/v1/mods/1*")?, 2);
+        assert_eq!(database.invalidate_matching("*forgecdn*")?, 1);
+        assert_eq!(database.invalidate_matching("*mods/1_files")?, 0, "Underscores should only match themselves");
+        assert_eq!(database.rows()?.len(), 1);
+
+        Ok(())
     }
 }
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// When to stop starting network work and cut off what's in flight, from `--deadline`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub fn after(duration: Option<Duration>) -> Self {
+        // Deadlines too far off to represent are no deadline at all.
+        Deadline(duration.and_then(|d| Instant::now().checked_add(d)))
+    }
+
+    /// How long work may still take, or None without a deadline.
+    pub fn remaining(&self) -> Result<Option<Duration>, DeadlineExceeded> {
+        match self.0 {
+            Some(at) => match at.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => Ok(Some(left)),
+                _ => Err(DeadlineExceeded),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DeadlineExceeded;
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Deadline reached")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Whether an error came from running out of time, rather than from the work itself.
+pub fn is_exceeded(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<DeadlineExceeded>()
+        || cause.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_timeout())
+        || cause.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::TimedOut))
+}
+
+/// Fails writes once the deadline has passed, so a download trickling in can't outlast it.
+pub struct DeadlineWriter<W> {
+    pub inner: W,
+    pub deadline: Deadline,
+}
+
+impl<W: Write> Write for DeadlineWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.deadline.remaining().map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))?;
+        self.inner.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cuts_off_work_once_passed() {
+        assert!(matches!(Deadline::after(None).remaining(), Ok(None)));
+        assert!(matches!(Deadline::after(Some(Duration::from_secs(60))).remaining(), Ok(Some(_))));
+
+        let passed = Deadline::after(Some(Duration::ZERO));
+        assert!(passed.remaining().is_err());
+        let mut writer = DeadlineWriter { inner: Vec::new(), deadline: passed };
+        let error = anyhow::Error::from(writer.write(b"jar").unwrap_err());
+        assert!(is_exceeded(&error), "Late writes should count as running out of time");
+        assert!(!is_exceeded(&anyhow::anyhow!("404 Not Found")));
+    }
+}
@@ -1,32 +1,158 @@
-use std::sync::Mutex;
-use std::time::Duration;
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use lazy_static::lazy_static;
-use reqwest::{Url, header};
-use reqwest::blocking::{Client, RequestBuilder};
-use sha2::{Digest, Sha256};
+use reqwest::{StatusCode, Url, header};
+use reqwest::blocking::{Client, ClientBuilder, Request, RequestBuilder, Response};
 
-use crate::database::Database;
-use crate::model::{AddonInfo, CurseModFile, CurseModFileInfo, CurseWrapper, Pagination};
+use crate::allowlist::{self, HostAllowlist};
+use crate::cfwidget;
+use crate::config::{CacheTtl, Config};
+use crate::database::Cache;
+use crate::deadline::{Deadline, DeadlineWriter};
+use crate::faults::FaultInjector;
+use crate::game_version::VersionFilter;
+use crate::hashes::{self, HashAlgorithm, MultiHasher};
+use crate::interrupt;
+use crate::jarjar;
+use crate::modmeta::{self, ModMetadata};
+use crate::naming;
+use crate::output::AtomicFile;
+use crate::pinning;
+use crate::slugs::{self, Disambiguator};
+use crate::api_keys::{ApiKeys, KeyUsage};
+use crate::quota::{self, EndpointUsage, HandshakeCounter, QuotaCounter};
+use crate::throttle::{Throttle, ThrottledWriter};
+use crate::curse_api::{AddonInfo, CurseModFile, CurseWrapper, Endpoint, FileIndex, FingerprintMatches, Pagination};
+use crate::model::CurseModFileInfo;
 
-static DEFAULT_TIMEOUT: Duration = Duration::from_secs(86400);
 static INFINITE_TIMEOUT: Duration = Duration::from_secs(86400 * 365);
+static CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
 lazy_static! {
     static ref BASE_URL: Url = Url::parse("https://api.curseforge.com").unwrap();
+    static ref CFWIDGET_URL: Url = Url::parse("https://api.cfwidget.com").unwrap();
 }
 // TODO: Implement with tokio.
 //static MAX_CONCURRENT_QUERIES: u32 = 2;
 
 pub struct Downloader<'app> {
-    cache_timeout: Duration,
+    cache_ttl: CacheTtl,
+    // Entries expiring within this margin are treated as already expired.
+    refresh_margin: Duration,
+    request_interval: Duration,
     client: Client,
-    database: &'app Database,
+    // Downloads from the CDN must not carry the API key.
+    cdn_client: Client,
+    database: &'app dyn Cache,
     rate_limiter: Mutex<()>,
+    // Every URL looked up, whether cached or not.
+    touched: Mutex<BTreeSet<String>>,
+    download_throttle: Throttle,
+    // When the first download started, and how many bytes all of them have brought in since.
+    transferred: Mutex<Option<(Instant, u64)>>,
+    missing_api_key: Option<String>,
+    api_keys: ApiKeys,
+    // Whether to look projects up through CFWidget, for lack of an API key.
+    use_cfwidget: bool,
+    quota: QuotaCounter,
+    allowlist: Option<Arc<HostAllowlist>>,
+    // Where to look for copies of files before downloading them again.
+    local_jar_dirs: Vec<PathBuf>,
+    handshakes: Arc<HandshakeCounter>,
+    faults: Option<FaultInjector>,
+    deadline: Deadline,
+    disambiguator: Box<dyn Disambiguator>,
+    // Whether to fetch everything again, replacing what's cached.
+    bypass_cache: bool,
+}
+
+#[derive(Debug)]
+struct NoSearchResults;
+
+impl fmt::Display for NoSearchResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("No search results")
+    }
+}
+
+impl std::error::Error for NoSearchResults {}
+
+/// Slug searches ask for this many results at a time, and stop looking for an exact match
+/// after `MAX_SEARCH_PAGES` pages.
+const SEARCH_PAGE_SIZE: u32 = 50;
+const MAX_SEARCH_PAGES: u32 = 4;
+
+/// Rate-limited requests are sent again this many times, waiting as `Retry-After` says or
+/// backing off, before the 429 is given up on.
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How long to wait before sending a rate-limited request again: what `Retry-After` says, in
+/// seconds, or else twice as long as the time before, starting at a second.
+fn retry_delay(retry_after: Option<&str>, retries: u32) -> Duration {
+    retry_after.and_then(|s| s.trim().parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_else(|| Duration::from_secs(1 << retries.min(6)))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Empty search results are cached separately, for less time than actual results.
+fn reject_empty_search(body: &str) -> Result<()> {
+    match serde_json::from_str::<CurseWrapper<Vec<serde_json::Value>>>(body) {
+        Ok(result) if result.data.is_empty() => Err(NoSearchResults.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The API sometimes reports errors with a 200 status, which must not end up in the cache.
+fn check_payload(body: &str) -> Result<()> {
+    let json: serde_json::Value = match serde_json::from_str(body) {
+        Ok(json) => json,
+        // Not ours to judge; whoever parses it will complain.
+        Err(_) => return Ok(()),
+    };
+    let error = json.get("error").or_else(|| json.get("errorMessage")).or_else(|| json.get("errorCode"));
+    match error {
+        Some(error) if json.get("data").is_none() => anyhow::bail!("{}", error),
+        _ => Ok(()),
+    }
+}
+
+/// A response, and where it came from.
+struct ApiResponse {
+    url: Url,
+    body: String,
+}
+
+/// Everything we cache about a downloaded file, including what's inside the jar.
+fn inspect_download(download_url: &Url, buf: Vec<u8>, hashes: BTreeMap<HashAlgorithm, String>, size: u64) -> CurseModFileInfo {
+    let embedded_jars = jarjar::list_embedded_jars(&buf).unwrap_or_else(|e| {
+        log::debug!("Could not inspect {} as a jar: {}", download_url.as_str(), e);
+        vec![]
+    });
+    let metadata = modmeta::read_mod_metadata(&buf).unwrap_or_else(|e| {
+        log::debug!("Could not read mod metadata from {}: {:#}", download_url.as_str(), e);
+        ModMetadata::default()
+    });
+    CurseModFileInfo { hashes, size, download_url: download_url.to_string(), embedded_jars, metadata, etag: None }
+}
+
+/// Whether what the CDN says about a file still fits what was cached. Either may lack an ETag;
+/// a missing length is no evidence either way.
+fn still_matches(cached: &CurseModFileInfo, length: Option<u64>, etag: Option<&str>) -> bool {
+    length.is_none_or(|length| length == cached.size)
+        && !matches!((cached.etag.as_deref(), etag), (Some(cached), Some(current)) if cached != current)
 }
 
 impl<'app> Downloader<'app> {
-    pub(crate) fn request_mod_file_info(&self, download_url: &str) -> Result<CurseModFileInfo> {
+    /// Where a file is downloaded from, and cached by.
+    fn file_url(download_url: &str) -> Result<Url> {
         let mut download_url = Url::parse(download_url)?;
         // Edge URL don't work, for whatever reason.
         if let Some(host) = download_url.host_str() {
@@ -36,49 +162,225 @@ impl<'app> Downloader<'app> {
         } else {
             anyhow::bail!("download_url missing host part!");
         }
-        // We can generally assume files don't change.
-        let json = self.database.get_or_put(&download_url.as_str(), &INFINITE_TIMEOUT, || {
-            let mut buf: Vec<u8> = vec![];
-            let mut body = reqwest::blocking::get(download_url.clone())?;
-            let content_type = body.headers().get("content-type")
-                .context("Reading content-type")?;
-            if content_type == "application/xml" {
-                anyhow::bail!("Miscomputed URL! {} returned XML", download_url.as_str());
+        Ok(download_url)
+    }
+
+    /// Whether `request_mod_file_info` would answer from the cache, without downloading.
+    pub(crate) fn is_file_cached(&self, download_url: &str) -> Result<bool> {
+        if self.bypass_cache {
+            return Ok(false);
+        }
+        Ok(self.database.get_fresh(Self::file_url(download_url)?.as_str(), &INFINITE_TIMEOUT)?.is_some())
+    }
+
+    /// The combined rate of this run's downloads so far, in bytes per second. Before any, it's
+    /// taken to be `--max-download-rate`, if set.
+    pub fn bandwidth(&self) -> Option<f64> {
+        let measured = (*self.transferred.lock().unwrap()).and_then(|(started, bytes)| {
+            let elapsed = started.elapsed().as_secs_f64();
+            (bytes > 0 && elapsed > 0.0).then(|| bytes as f64 / elapsed)
+        });
+        measured.or_else(|| self.download_throttle.limit().map(|rate| rate as f64))
+    }
+
+    pub(crate) fn request_mod_file_info(&self, download_url: &str, algorithms: &[HashAlgorithm]) -> Result<CurseModFileInfo> {
+        let download_url = Self::file_url(download_url)?;
+        let mod_info = self.fetch_mod_file_info(&download_url)?;
+        if algorithms.iter().all(|a| mod_info.hashes.contains_key(a)) {
+            return Ok(mod_info);
+        }
+        // Cached by an older version that computed fewer hashes.
+        if let Some(mod_info) = self.hash_local_copy(&download_url, &mod_info)? {
+            return Ok(mod_info);
+        }
+        log::debug!("Cached info for {} lacks some of {:?}, downloading again", download_url.as_str(), algorithms);
+        self.database.invalidate(download_url.as_str())?;
+        self.fetch_mod_file_info(&download_url)
+    }
+
+    /// Recomputes the hashes of a cached file from a copy in one of the `--local-jar-dirs`, if one has
+    /// the same name and size and matches the hashes we already know.
+    fn hash_local_copy(&self, download_url: &Url, cached: &CurseModFileInfo) -> Result<Option<CurseModFileInfo>> {
+        let filename = urlencoding::decode(download_url.path().rsplit('/').next().unwrap_or_default())?;
+        for dir in &self.local_jar_dirs {
+            let path = dir.join(&filename);
+            if std::fs::metadata(&path).map(|m| m.len()).ok() != Some(cached.size) {
+                continue;
+            }
+            let mut hasher = MultiHasher::new();
+            std::io::copy(&mut File::open(&path)?, &mut hasher).context(format!("While hashing {:?}", path))?;
+            let (buf, hashes) = hasher.finish();
+            if cached.hashes.iter().any(|(algorithm, hash)| hashes.get(algorithm) != Some(hash)) {
+                log::warn!("{:?} has the name and size of {}, but different contents", path, download_url.as_str());
+                continue;
             }
-            let size = body.copy_to(&mut buf)?;
-            let md5 = format!("{:x}", md5::compute(&buf));
-            let sha256 = format!("{:x}", Sha256::digest(&buf));
-            let mod_info = CurseModFileInfo { md5, sha256, size, download_url: download_url.to_string() };
+            log::debug!("Hashing {:?} instead of downloading {}", path, download_url.as_str());
+            let mod_info = CurseModFileInfo { etag: cached.etag.clone(), ..inspect_download(download_url, buf, hashes, cached.size) };
+            let json = serde_json::to_string(&mod_info)?;
+            self.database.invalidate(download_url.as_str())?;
+            self.database.get_or_put(download_url.as_str(), &INFINITE_TIMEOUT, Box::new(|| Ok(json)))?;
+            return Ok(Some(mod_info));
+        }
+        Ok(None)
+    }
+
+    fn fetch_mod_file_info(&self, download_url: &Url) -> Result<CurseModFileInfo> {
+        // We can generally assume files don't change.
+        let files_lifetime = if self.bypass_cache { Duration::ZERO } else { INFINITE_TIMEOUT };
+        let fetched = Cell::new(false);
+        let json = self.database.get_or_put(download_url.as_str(), &files_lifetime, Box::new(|| {
+            fetched.set(true);
+            let mod_info = match (self.download(download_url), alternate_cdn_url(download_url)) {
+                (Ok(mod_info), _) => mod_info,
+                // The CDN hosts are occasionally out of sync, so a file missing from one may be on the other.
+                (Err(e), Some(alternate)) => {
+                    log::warn!("{} failed ({:#}), trying {}", download_url.as_str(), e, alternate.host_str().unwrap_or_default());
+                    let mod_info = self.download(&alternate)
+                        .context(format!("Also tried {}", alternate.as_str()))
+                        .context(e)?;
+                    log::info!("{} was served by {}", download_url.as_str(), alternate.host_str().unwrap_or_default());
+                    mod_info
+                }
+                (Err(e), None) => return Err(e),
+            };
             Ok(serde_json::to_string(&mod_info)?)
-        })?;
+        }))?;
+        self.touched.lock().unwrap().insert(download_url.to_string());
+        // Not API quota, but downloads are what make uncached runs slow.
+        self.quota.record("cdn".to_string(), fetched.get());
         Ok(serde_json::from_str(&json)?)
     }
 }
 
 impl<'app> Downloader<'app> {
-    pub(crate) fn request_mod_files(&self, project_id: u32, game_version: &str) -> Result<Vec<CurseModFile>> {
+    fn download(&self, download_url: &Url) -> Result<CurseModFileInfo> {
+        self.check_allowed(download_url)?;
+        self.inject_fault(download_url)?;
+        let mut hasher = MultiHasher::new();
+        self.transferred.lock().unwrap().get_or_insert_with(|| (Instant::now(), 0));
+        let mut request = self.cdn_client.get(download_url.clone());
+        if let Some(left) = self.deadline.remaining()? {
+            request = request.timeout(left);
+        }
+        let body = request.send().map_err(pinning::explain)?;
+        log::trace!("{} answered {} with headers {:?}", download_url.as_str(), body.status(), body.headers());
+        let mut body = body.error_for_status()?;
+        let content_type = body.headers().get("content-type")
+            .context("Reading content-type")?;
+        let etag = body.headers().get(header::ETAG).and_then(|e| e.to_str().ok()).map(str::to_string);
+        if content_type == "application/xml" {
+            anyhow::bail!("Miscomputed URL! {} returned XML", download_url.as_str());
+        }
+        let size = body.copy_to(&mut DeadlineWriter {
+            inner: ThrottledWriter { inner: &mut hasher, throttle: &self.download_throttle },
+            deadline: self.deadline,
+        })?;
+        if let Some((_, total)) = self.transferred.lock().unwrap().as_mut() {
+            *total += size;
+        }
+        let (buf, hashes) = hasher.finish();
+        log::debug!("{} served {}", download_url.host_str().unwrap_or_default(), download_url.path());
+        Ok(CurseModFileInfo { etag, ..inspect_download(download_url, buf, hashes, size) })
+    }
+
+    /// Downloads a file afresh and hashes it as `nix-prefetch-url` would, giving its sha256 in
+    /// Nix's base32. Nothing is read from or written to the cache.
+    pub(crate) fn prefetch(&self, url: &str) -> Result<String> {
+        let url = Url::parse(url).context(format!("Parsing download URL {}", url))?;
+        let downloaded = self.download(&url)?;
+        self.quota.record("cdn".to_string(), true);
+        hashes::hex_to_nix_base32(&downloaded.hashes[&HashAlgorithm::Sha256])
+    }
+
+    /// Saves a project icon as it is; icons aren't cached, as `--icon-dir` keeps them anyway.
+    pub(crate) fn download_icon(&self, icon_url: &str, path: &Path) -> Result<()> {
+        let url = Url::parse(icon_url).context(format!("Parsing icon URL {}", icon_url))?;
+        self.check_allowed(&url)?;
+        self.inject_fault(&url)?;
+        let mut request = self.cdn_client.get(url);
+        if let Some(left) = self.deadline.remaining()? {
+            request = request.timeout(left);
+        }
+        let mut body = request.send().map_err(pinning::explain)?.error_for_status()?;
+        let mut out = AtomicFile::create(path)?;
+        body.copy_to(&mut out)?;
+        self.quota.record("cdn".to_string(), true);
+        out.commit()
+    }
+
+    /// Asks the CDN whether a cached file is still the one that was downloaded, without downloading
+    /// it. Files that are gone or changed size or ETag don't match.
+    pub(crate) fn revalidate_file(&self, cached: &CurseModFileInfo) -> Result<bool> {
+        let url = Url::parse(&cached.download_url)?;
+        self.check_allowed(&url)?;
+        self.inject_fault(&url)?;
+        let mut request = self.cdn_client.head(url.clone());
+        if let Some(left) = self.deadline.remaining()? {
+            request = request.timeout(left);
+        }
+        let response = request.send().map_err(pinning::explain)?;
+        self.quota.record("cdn".to_string(), true);
+        if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE) {
+            log::warn!("{} is no longer on the CDN", url.as_str());
+            return Ok(false);
+        }
+        let response = response.error_for_status()?;
+        let length = response.headers().get(header::CONTENT_LENGTH)
+            .and_then(|l| l.to_str().ok()).and_then(|l| l.parse().ok());
+        let etag = response.headers().get(header::ETAG).and_then(|e| e.to_str().ok());
+        let matches = still_matches(cached, length, etag);
+        if !matches {
+            log::warn!("{} changed since it was cached: {} bytes, now {:?}; ETag {:?}, now {:?}",
+                       url.as_str(), cached.size, length, cached.etag, etag);
+        }
+        Ok(matches)
+    }
+}
+
+/// Where the page after this one starts, if there's one. Stops at an empty page too, for
+/// responses whose total count is off.
+fn next_page(page: &Pagination) -> Option<u32> {
+    let next = page.index + page.result_count;
+    (page.result_count > 0 && next < page.total_count).then_some(next)
+}
+
+/// The same file on the other forgecdn host, if it's on one of them.
+fn alternate_cdn_url(url: &Url) -> Option<Url> {
+    let alternate = match url.host_str()? {
+        "media.forgecdn.net" => "edge.forgecdn.net",
+        "edge.forgecdn.net" => "media.forgecdn.net",
+        _ => return None,
+    };
+    let mut alternate_url = url.clone();
+    alternate_url.set_host(Some(alternate)).ok()?;
+    Some(alternate_url)
+}
+
+impl<'app> Downloader<'app> {
+    pub(crate) fn request_mod_files(&self, project_id: u32, filter: &VersionFilter) -> Result<Vec<CurseModFile>> {
+        if self.use_cfwidget {
+            return self.request_cfwidget_files(project_id, filter);
+        }
         let mut files = Vec::new();
         let mut current_index = 0;
         loop {
-            let url = BASE_URL
-                .join(&format!("/v1/mods/{}/files?gameVersion={}&pageSize=50&index={}", project_id, game_version, current_index))?;
-            let data = self.get(url.clone())
+            interrupt::check()?;
+            let query = format!("?{}&pageSize=50&index={}", filter.query(), current_index);
+            let response = self.request_api(Endpoint::Files { project_id }, &query, |url| self.get(url, &self.cache_ttl.file_listing()))
                 .context(format!("Fetching files for project id {} at index {}", project_id, current_index))?;
             // Mutable to allow moving elements to the files vector
-            let mut result: CurseWrapper<Vec<CurseModFile>> = serde_json::from_str(&data)
-                .context(format!("Parsing files list as JSON for project id {}", project_id))?;
+            let mut result: CurseWrapper<Vec<CurseModFile>> = serde_json::from_str(&response.body).context(format!("Parsing files list as JSON for project id {}", project_id))?;
             files.append(&mut result.data);
             let page_info: Pagination = result.pagination.context(format!("No pagination in file listing for project id {}!", project_id))?;
-            current_index += 50;
-            if page_info.result_count == 0 {
-                break;
+            if page_info.total_count > page_info.page_size {
+                log::info!("Project {}: listed {} of {} files", project_id, files.len(), page_info.total_count);
+            }
+            match next_page(&page_info) {
+                Some(index) => current_index = index,
+                None => break,
             }
         }
         // The URLs returned are not properly URL-encoded.
-        // Specifically, the filename path needs to be encoded.
-        //
-        // Breaking the URL spec, curseforge requires + to be encoded.
-        // This means we need to do the job 'manually'.
         files
             .into_iter()
             .map(Downloader::encode_url)
@@ -86,112 +388,397 @@ impl<'app> Downloader<'app> {
     }
 
     pub(crate) fn request_mod_file(&self, project_id: u32, file_id: u32) -> Result<CurseModFile> {
-        let url = BASE_URL
-            .join(&format!("/v1/mods/{}/files/{}", project_id, file_id))?;
-        let data = self.get(url.clone())
+        if self.use_cfwidget {
+            let project = self.request_cfwidget_project(&project_id.to_string())?;
+            let file = project.files.iter().find(|f| f.id == file_id)
+                .context(format!("CFWidget doesn't list file id {} in project {}", file_id, project_id))?;
+            return Downloader::encode_url(file.curse_file());
+        }
+        let response = self.request_api(Endpoint::File { project_id, file_id }, "", |url| self.get(url, &self.cache_ttl.file()))
             .context(format!("Fetching file id {} in project {}", file_id, project_id))?;
-        let result: CurseWrapper<CurseModFile> = serde_json::from_str(&data)
-            .context(format!("Parsing file id {} in project {}", file_id, project_id))?;
+        let result: CurseWrapper<CurseModFile> = serde_json::from_str(&response.body).context(format!("Parsing file id {} in project {}", file_id, project_id))?;
         Downloader::encode_url(result.data)
     }
 
     fn encode_url(file: CurseModFile) -> Result<CurseModFile> {
-        let url = Url::parse(&file.download_url).unwrap();
-        let filename = url.path_segments()
-            .unwrap()
-            .last()
-            .unwrap();
-        // Sometimes the download URL is already encoded, and sometimes not.
-        // This encoder gives working output for the filename.
-        let encoded_filename = urlencoding::encode(
-            &urlencoding::decode(filename).unwrap());
-        // We now need to construct a new url, *not* re-encoding it.
-        let mut base_url = url.clone();
-        base_url.path_segments_mut().unwrap()
-            .pop();
-        let fixed_url = Url::parse(
-            &format!("{}/{}", base_url.as_str(), &encoded_filename)).unwrap();
         Ok(CurseModFile {
-            download_url: fixed_url.to_string(),
+            download_url: naming::normalize_download_url(&file.download_url)?,
             ..file
         })
     }
 }
 
 impl<'app> Downloader<'app> {
-    pub fn new(database: &'app Database) -> Self {
-        let api_key = get_api_key().unwrap();
-        let mut headers = header::HeaderMap::new();
-        headers.insert("x-api-key", header::HeaderValue::from_str(&api_key).expect("Could not set API key as a header!"));
-        Downloader {
-            cache_timeout: DEFAULT_TIMEOUT,
-            client: Client::builder()
-                .default_headers(headers)
-                .build().unwrap(),
+    pub fn new(database: &'app dyn Cache, config: &Config) -> Result<Self> {
+        // Offline modes and cached data don't need a key, so only complain once a request needs one.
+        let (api_keys, missing_api_key) = match get_api_key() {
+            Ok(source) => (ApiKeys::parse(&source, config.api_key_rotation), None),
+            Err(e) => (ApiKeys::new(vec![], config.api_key_rotation), Some(format!("{:#}", e))),
+        };
+        if api_keys.len() > 1 {
+            log::info!("Using {} API keys, rotating by {:?}", api_keys.len(), config.api_key_rotation);
+        }
+        let use_cfwidget = missing_api_key.is_some() && config.cfwidget_fallback;
+        if use_cfwidget {
+            log::warn!("No API key, so projects are looked up through CFWidget. This is best-effort: \
+                        it may be out of date, is slow, and can't check dependencies.");
+        }
+        let user_agent = config.user_agent();
+        log::info!("Using User-Agent {:?}", user_agent);
+        if let Some(ref hosts) = config.allow_hosts {
+            log::info!("Only allowing requests to {}", hosts.join(", "));
+        }
+        let allowlist = config.allow_hosts.as_deref().map(|hosts| Arc::new(HostAllowlist::new(hosts)));
+        let handshakes = Arc::new(HandshakeCounter::default());
+        let client_builder = || -> Result<ClientBuilder> {
+            Ok(Client::builder()
+                .user_agent(user_agent.as_str())
+                .redirect(allowlist::redirect_policy(allowlist.clone()))
+                // Keep connections around between downloads, which can be spaced out by API lookups.
+                .pool_idle_timeout(CONNECTION_IDLE_TIMEOUT)
+                .tcp_keepalive(CONNECTION_IDLE_TIMEOUT)
+                .use_preconfigured_tls(pinning::tls_config(&config.tls_pins, config.tls_roots, handshakes.clone())?))
+        };
+        Ok(Downloader {
+            cache_ttl: config.cache_ttl.clone(),
+            refresh_margin: Duration::from_secs(0),
+            request_interval: Duration::from_secs(0),
+            client: client_builder()?.build()?,
+            cdn_client: client_builder()?.build()?,
             database,
             rate_limiter: Mutex::new(()),
+            touched: Mutex::new(BTreeSet::new()),
+            download_throttle: Throttle::new(0),
+            transferred: Mutex::new(None),
+            missing_api_key,
+            api_keys,
+            use_cfwidget,
+            quota: QuotaCounter::default(),
+            allowlist,
+            local_jar_dirs: vec![],
+            handshakes,
+            faults: None,
+            deadline: Deadline::default(),
+            disambiguator: Box::new(slugs::Strict),
+            bypass_cache: false,
+        })
+    }
+
+    pub fn refresh_margin(self, refresh_margin: Duration) -> Self {
+        Downloader { refresh_margin, ..self }
+    }
+
+    pub fn request_interval(self, request_interval: Duration) -> Self {
+        Downloader { request_interval, ..self }
+    }
+
+    pub fn max_download_rate(self, bytes_per_second: u64) -> Self {
+        Downloader { download_throttle: Throttle::new(bytes_per_second), ..self }
+    }
+
+    pub fn local_jar_dirs(self, local_jar_dirs: Vec<PathBuf>) -> Self {
+        Downloader { local_jar_dirs, ..self }
+    }
+
+    pub fn fault_injector(self, faults: Option<FaultInjector>) -> Self {
+        if let Some(ref faults) = faults {
+            log::warn!("Injecting faults into {}", faults.describe());
+        }
+        Downloader { faults, ..self }
+    }
+
+    pub fn deadline(self, deadline: Deadline) -> Self {
+        Downloader { deadline, ..self }
+    }
+
+    /// Whether to ask on the terminal when a slug search has no clear winner, instead of failing.
+    pub fn interactive(self, interactive: bool) -> Self {
+        let disambiguator: Box<dyn Disambiguator> = if interactive {
+            Box::<slugs::Prompt>::default()
+        } else {
+            Box::new(slugs::Strict)
+        };
+        Downloader { disambiguator, ..self }
+    }
+
+    /// Whether to ignore cached responses and files, fetching each again. What's fetched still
+    /// replaces the cached copy.
+    pub fn bypass_cache(self, bypass_cache: bool) -> Self {
+        if bypass_cache {
+            log::info!("Bypassing the cache");
         }
+        Downloader { bypass_cache, ..self }
     }
 
-    fn get_with_builder<F>(&self, url: Url, f: F) -> Result<String> where F: FnOnce(RequestBuilder) -> RequestBuilder {
+    /// How long a cached copy stays usable, given how long it would otherwise.
+    fn cache_lifetime(&self, ttl: &Duration) -> Duration {
+        if self.bypass_cache { Duration::ZERO } else { ttl.saturating_sub(self.refresh_margin) }
+    }
+
+    /// Run before every actual network request, so cached responses never fail.
+    fn inject_fault(&self, url: &Url) -> Result<()> {
+        match self.faults {
+            Some(ref faults) => faults.check(url.as_str()),
+            None => Ok(()),
+        }
+    }
+
+    /// Fetches through the cache. Responses `check` rejects fail the request and stay out of the cache.
+    fn get_with_builder<F, C>(&self, url: Url, ttl: &Duration, f: F, check: C) -> Result<String>
+        where F: FnOnce(RequestBuilder) -> RequestBuilder, C: FnOnce(&str) -> Result<()> {
         let request = f(self.client.get(url)).build()?;
         let url: String = request.url().as_str().into();
-        self.database.get_or_put(&url, &self.cache_timeout, || {
+        let ttl = self.cache_lifetime(ttl);
+        let endpoint = quota::endpoint_of(request.url());
+        self.touched.lock().unwrap().insert(url.clone());
+        let fallback = request.url().host_str() == CFWIDGET_URL.host_str();
+        let fetched = Cell::new(false);
+        let result = self.database.get_or_put(&url, &ttl, Box::new(|| {
+            fetched.set(true);
+            match self.missing_api_key {
+                Some(ref e) if !fallback => anyhow::bail!("{}", e),
+                _ => (),
+            }
+            self.check_allowed(request.url())?;
+            self.inject_fault(request.url())?;
             let _guard = self.rate_limiter.lock().unwrap();
+            // Waiting for the lock may well have taken a while.
+            let mut request = request;
+            if let Some(left) = self.deadline.remaining()? {
+                *request.timeout_mut() = Some(left);
+            }
             log::debug!("Fetching {}", url);
-            Ok(self.client.execute(request)?.text()?)
-        })
+            log::trace!("GET {} with headers {:?}", url, request.headers());
+            let response = if fallback {
+                self.client.execute(request).map_err(pinning::explain)?
+            } else {
+                self.execute_with_key(request)?
+            };
+            let status = response.status();
+            log::trace!("{} answered {} with headers {:?}", url, status, response.headers());
+            let failed = response.error_for_status_ref().err();
+            let result = response.text()?;
+            log::trace!("{} answered {} with body {}", url, status, result);
+            if let Some(e) = failed {
+                return Err(e.into());
+            }
+            // Returning an error keeps it out of the cache.
+            check_payload(&result).context(format!("{} returned an error", url))?;
+            check(&result)?;
+            // Sleeping with the lock held spaces out requests from every thread.
+            std::thread::sleep(if fallback { self.request_interval.max(cfwidget::REQUEST_INTERVAL) } else { self.request_interval });
+            Ok(result)
+        }));
+        self.quota.record(endpoint, fetched.get());
+        result
+    }
+
+    /// Sends an API request with the next key, moving on to other keys while the API refuses them,
+    /// and waiting out rate limits, which only mean the key is used too much right now. The last
+    /// key's refusal, or a rate limit that doesn't lift, is returned as it is.
+    fn execute_with_key(&self, request: Request) -> Result<Response> {
+        let mut retries = 0;
+        loop {
+            let (index, key) = self.api_keys.pick().context("Every API key was refused")?;
+            let mut attempt = request.try_clone().context("Request can't be sent again")?;
+            attempt.headers_mut().insert("x-api-key", header::HeaderValue::from_str(key).context("Could not set API key as a header!")?);
+            let response = self.client.execute(attempt).map_err(pinning::explain)?;
+            let status = response.status();
+            if status == StatusCode::TOO_MANY_REQUESTS && retries < MAX_RATE_LIMIT_RETRIES {
+                let retry_after = response.headers().get(header::RETRY_AFTER).and_then(|v| v.to_str().ok());
+                let delay = retry_delay(retry_after, retries);
+                if self.deadline.remaining()?.is_some_and(|left| left <= delay) {
+                    return Ok(response);
+                }
+                log::warn!("The API rate-limited {}, trying again in {}s", self.api_keys.label(index), delay.as_secs());
+                std::thread::sleep(delay);
+                retries += 1;
+                continue;
+            }
+            if !matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) || !self.api_keys.refuse(index) {
+                return Ok(response);
+            }
+            log::warn!("The API refused {} with {}, switching to another key", self.api_keys.label(index), status);
+        }
+    }
+
+    /// Requests sent with each API key, when there's more than one.
+    pub fn key_usage(&self) -> BTreeMap<String, KeyUsage> {
+        if self.api_keys.len() > 1 { self.api_keys.usage() } else { BTreeMap::new() }
+    }
+
+    /// Only actual requests are checked; cached responses for other hosts are fine.
+    fn check_allowed(&self, url: &Url) -> Result<()> {
+        match self.allowlist {
+            Some(ref allowlist) => allowlist.check(url),
+            None => Ok(()),
+        }
+    }
+
+    /// The cache keys of everything requested so far.
+    pub fn touched_urls(&self) -> BTreeSet<String> {
+        self.touched.lock().unwrap().clone()
+    }
+
+    /// TLS handshakes so far, per host.
+    pub fn handshakes(&self) -> BTreeMap<String, u32> {
+        self.handshakes.snapshot()
     }
 
-    fn get(&self, url: Url) -> Result<String> {
-        self.get_with_builder(url, |b| b)
+    /// Network requests and cache hits so far, per endpoint.
+    pub fn usage(&self) -> BTreeMap<String, EndpointUsage> {
+        self.quota.snapshot()
+    }
+
+    fn get(&self, url: Url, ttl: &Duration) -> Result<String> {
+        self.get_with_builder(url, ttl, |b| b, |_| Ok(()))
+    }
+
+    /// Requests an endpoint with `f`, naming the URL in any error.
+    fn request_api<F>(&self, endpoint: Endpoint, query: &str, f: F) -> Result<ApiResponse> where F: Fn(Url) -> Result<String> {
+        let url = BASE_URL.join(&format!("{}{}", endpoint.path(), query))?;
+        match f(url.clone()) {
+            Ok(body) => Ok(ApiResponse { url, body }),
+            Err(e) => Err(e.context(format!("From {:?}", url.as_str()))),
+        }
     }
 
     pub(crate) fn request_addon_info(&self, project_id: u32) -> Result<AddonInfo> {
-        let url = BASE_URL
-            .join(&format!("/v1/mods/{}", project_id))?;
-        let data = self.get_with_builder(url.clone(), |b| b)
-                .context(format!("Fetching addon info for project id {}", project_id))
-                .context(format!("From {:?}", url.as_str()))?;
-        serde_json::from_str::<CurseWrapper<AddonInfo>>(&data)
-                .context(format!("Parsing addon info as JSON for project id {}. Data: {}", project_id, data))
-                .context(format!("From {}", url.as_str()))
-                .map(|d| d.data)
+        self.request_addon_info_within(project_id, &self.cache_ttl.addon_info())
+    }
+
+    /// The project's newest file per version and loader. It comes with the addon info, but
+    /// changes with every upload, so it's kept only as long as a file listing.
+    pub(crate) fn request_file_indexes(&self, project_id: u32) -> Result<Vec<FileIndex>> {
+        Ok(self.request_addon_info_within(project_id, &self.cache_ttl.file_listing())?.latest_files_indexes)
+    }
+
+    fn request_addon_info_within(&self, project_id: u32, ttl: &Duration) -> Result<AddonInfo> {
+        if self.use_cfwidget {
+            return self.request_cfwidget_project(&project_id.to_string())?.addon_info();
+        }
+        let response = self.request_api(Endpoint::Mod { project_id }, "", |url| self.get(url, ttl))
+                .context(format!("Fetching addon info for project id {}", project_id))?;
+        let result: CurseWrapper<AddonInfo> = serde_json::from_str(&response.body).context(format!("Parsing addon info as JSON for project id {}. Data: {}", project_id, response.body))
+            .context(format!("From {}", response.url.as_str()))?;
+        Ok(result.data)
+    }
+
+    /// Looks files up by their fingerprints. Not cached, as this is only used for one-off imports.
+    pub(crate) fn request_fingerprint_matches(&self, fingerprints: &[u32]) -> Result<FingerprintMatches> {
+        if let Some(ref e) = self.missing_api_key {
+            anyhow::bail!("{}", e);
+        }
+        let response = self.request_api(Endpoint::Fingerprints { game_id: 432 }, "", |url| {
+            self.check_allowed(&url)?;
+            self.inject_fault(&url)?;
+            let data = {
+                let _guard = self.rate_limiter.lock().unwrap();
+                log::debug!("Looking up {} fingerprints", fingerprints.len());
+                let mut request = self.client.post(url.clone())
+                    .json(&serde_json::json!({ "fingerprints": fingerprints }));
+                if let Some(left) = self.deadline.remaining()? {
+                    request = request.timeout(left);
+                }
+                let data = self.execute_with_key(request.build()?)?.error_for_status()?.text()?;
+                std::thread::sleep(self.request_interval);
+                data
+            };
+            self.quota.record(quota::endpoint_of(&url), true);
+            check_payload(&data).context(format!("{} returned an error", url))?;
+            Ok(data)
+        })?;
+        let result: CurseWrapper<FingerprintMatches> = serde_json::from_str(&response.body).context("Parsing fingerprint matches as JSON")?;
+        Ok(result.data)
     }
 
     pub(crate) fn search_id_with_slug(&self, slug: &str) -> Result<u32> {
         log::debug!("{}", format!("Searching ID for slug {}", slug));
+        if self.use_cfwidget {
+            return Ok(self.request_cfwidget_project(&format!("minecraft/mc-mods/{}", slug))?.id);
+        }
         let game_id = 432;
         let class_id = 6;
-        let url = BASE_URL
-            .join(&format!("/v1/mods/search?gameId={}&classId={}&slug={}", game_id, class_id, slug))?;
-        let data = self.get_with_builder(url.clone(), |b| b)
-            .context(format!("Searching mods for project with slug {}", slug))
-            .context(format!("From {:?}", url.as_str()))?;
-        let result: CurseWrapper<Vec<AddonInfo>> = serde_json::from_str(&data)
-            .context(format!("Parsing search results as JSON for slug {}. Data: {}", slug, data))
-            .context(format!("From {}", url.as_str()))?;
-        result.data.get(0).map(|a| a.id)
-            .context(format!("No mods found with slug {}", slug))
-            .context(format!("Response: {}", data))
+        let query = format!("?gameId={}&classId={}&slug={}", game_id, class_id, slug);
+        // Misspelled slugs are remembered for a short while on their own, so they fail fast
+        // without keeping a newly published project from being found for long.
+        let not_found = format!("{}#not-found", BASE_URL.join(&format!("{}{}", Endpoint::Search.path(), query))?);
+        if self.database.get_fresh(&not_found, &self.cache_ttl.not_found())?.is_some() {
+            anyhow::bail!("No mods found with slug {} (as of less than {} ago, see cache_ttl.not_found)",
+                          slug, self.cache_ttl.not_found);
+        }
+        let mut results = Vec::new();
+        let mut index = 0;
+        for _ in 0..MAX_SEARCH_PAGES {
+            let paged = format!("{}&pageSize={}&index={}", query, SEARCH_PAGE_SIZE, index);
+            // Only an empty first page means the slug is missing; later ones just mean there's no more.
+            let response = self.request_api(Endpoint::Search, &paged, |url| {
+                self.get_with_builder(url, &self.cache_ttl.search(), |b| b,
+                                      |body| if index == 0 { reject_empty_search(body) } else { Ok(()) })
+            });
+            let response = match response {
+                Err(e) if e.chain().any(|cause| cause.is::<NoSearchResults>()) => {
+                    self.database.put(&not_found, "")?;
+                    anyhow::bail!("No mods found with slug {}", slug);
+                }
+                response => response.context(format!("Searching mods for project with slug {}", slug))?,
+            };
+            let mut result: CurseWrapper<Vec<AddonInfo>> = serde_json::from_str(&response.body).context(format!("Parsing search results as JSON for slug {}. Data: {}", slug, response.body))
+                .context(format!("From {}", response.url.as_str()))?;
+            results.append(&mut result.data);
+            if results.iter().any(|r| slugs::same(&r.slug, slug)) {
+                break;
+            }
+            match result.pagination.as_ref().and_then(next_page) {
+                Some(next) => {
+                    log::debug!("No exact match for slug {} in the first {} results, looking further", slug, results.len());
+                    index = next;
+                }
+                None => break,
+            }
+        }
+        self.database.invalidate(&not_found)?;
+        slugs::pick(slug, &results, self.disambiguator.as_ref())
+    }
+}
+
+/// Best-effort lookups for running without an API key. One CFWidget response has everything
+/// we can get about a project, so it's cached once and shared by all of these.
+impl<'app> Downloader<'app> {
+    fn request_cfwidget_project(&self, path: &str) -> Result<cfwidget::Project> {
+        let url = CFWIDGET_URL.join(path)?;
+        let data = self.get(url.clone(), &self.cache_ttl.file_listing())
+            .context(format!("Fetching {} from CFWidget", url.as_str()))?;
+        serde_json::from_str(&data)
+            .context(format!("Parsing CFWidget project from {}", url.as_str()))
+    }
+
+    fn request_cfwidget_files(&self, project_id: u32, filter: &VersionFilter) -> Result<Vec<CurseModFile>> {
+        let project = self.request_cfwidget_project(&project_id.to_string())?;
+        project.files.iter()
+            .filter(|f| filter.game_version.as_ref().is_none_or(|v| f.versions.contains(v)))
+            .map(|f| Downloader::encode_url(f.curse_file()))
+            .collect()
     }
 }
 
 fn get_api_key() -> Result<String> {
     std::env::var("CURSE_API_KEY").map_err(anyhow::Error::from)
         .or(std::fs::read_to_string("APIKEY"))
-        .context("Get an API key at https://console.curseforge.com/, then save it in a file name APIKEY or set the CURSE_API_KEY env var.")
+        .context("Get an API key at https://console.curseforge.com/, then save it in a file name APIKEY or set the CURSE_API_KEY env var. \
+                  Or set cfwidget_fallback in the config to look projects up without one, on a best-effort basis.")
         .map(|s| s.trim().to_string())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::database::Database;
 
     fn with_downloader<F, X>(f: F) -> Result<X>
         where F: FnOnce(Downloader) -> Result<X> {
         let database = Database::for_tests().unwrap();
-        f(Downloader::new(&database))
+        f(Downloader::new(&database, &Config::default())?)
     }
 
     #[test]
@@ -204,4 +791,128 @@ mod tests {
         assert!(result.links.website_url.contains("hunger-overhaul"));
     }
 
+    #[test]
+    fn waits_out_rate_limits_without_dropping_keys() -> Result<()> {
+        use std::io::{Read, Write};
+
+        assert_eq!(retry_delay(Some("3"), 0), Duration::from_secs(3));
+        assert_eq!(retry_delay(None, 2), Duration::from_secs(4));
+        assert_eq!(retry_delay(Some("Wed, 21 Oct 2015 07:28:00 GMT"), 0), Duration::from_secs(1), "Dates fall back to backing off");
+        assert_eq!(retry_delay(Some("86400"), 0), MAX_RETRY_DELAY);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/v1/mods/1", listener.local_addr()?);
+        let server = std::thread::spawn(move || -> std::io::Result<Vec<String>> {
+            let mut keys = Vec::new();
+            for response in ["429 Too Many Requests\r\nRetry-After: 0", "403 Forbidden", "200 OK"] {
+                let (mut stream, _) = listener.accept()?;
+                let mut request = [0; 4096];
+                let read = stream.read(&mut request)?;
+                let request = String::from_utf8_lossy(&request[..read]).to_lowercase();
+                keys.extend(request.lines().filter_map(|l| l.strip_prefix("x-api-key: ")).map(str::to_string));
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}", response)?;
+            }
+            Ok(keys)
+        });
+        with_downloader(|d| {
+            let d = Downloader { api_keys: ApiKeys::parse("aaaa1111,bbbb2222", crate::api_keys::Rotation::Failover), ..d };
+            let response = d.execute_with_key(d.client.get(&url).build()?)?;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(server.join().unwrap()?, vec!["aaaa1111", "aaaa1111", "bbbb2222"],
+                       "The rate-limited key is tried again, the forbidden one isn't");
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn remembers_missing_slugs_briefly() -> Result<()> {
+        let database = Database::for_tests()?;
+        let downloader = Downloader::new(&database, &Config::default())?;
+        let search = "https://api.curseforge.com/v1/mods/search?gameId=432&classId=6&slug=";
+        database.put(&format!("{}jie#not-found", search), "")?;
+        let error = downloader.search_id_with_slug("jie").unwrap_err();
+        assert!(error.to_string().contains("as of less than 10m ago"), "{:#}", error);
+
+        database.put(&format!("{}jei&pageSize=50&index=0", search), r#"{"data": [{"name": "JEI", "slug": "jei", "id": 238222, "links": {"websiteUrl": ""}}]}"#)?;
+        assert_eq!(downloader.search_id_with_slug("jei")?, 238222);
+        assert!(reject_empty_search(r#"{"data": []}"#).is_err());
+        assert!(reject_empty_search(r#"{"data": [{"id": 1}]}"#).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn pages_through_search_results() -> Result<()> {
+        let database = Database::for_tests()?;
+        let downloader = Downloader::new(&database, &Config::default())?;
+        let search = "https://api.curseforge.com/v1/mods/search?gameId=432&classId=6&slug=create";
+        let page = |index: u32, slug: &str, id: u32| format!(
+            r#"{{"data": [{{"name": "{0}", "slug": "{0}", "id": {1}, "links": {{"websiteUrl": ""}}}}],
+                "pagination": {{"index": {2}, "pageSize": 50, "resultCount": 1, "totalCount": 2}}}}"#, slug, id, index);
+        database.put(&format!("{}&pageSize=50&index=0", search), &page(0, "create-addon", 1))?;
+        database.put(&format!("{}&pageSize=50&index=1", search), &page(1, "create", 328085))?;
+        assert_eq!(downloader.search_id_with_slug("create")?, 328085, "The exact match is on the second page");
+        Ok(())
+    }
+
+    #[test]
+    fn notices_reuploaded_files() {
+        let cached = CurseModFileInfo {
+            hashes: BTreeMap::new(),
+            size: 1234,
+            download_url: "https://media.forgecdn.net/files/1/2/a.jar".to_string(),
+            embedded_jars: vec![],
+            metadata: ModMetadata::default(),
+            etag: Some("\"abc\"".to_string()),
+        };
+        assert!(still_matches(&cached, Some(1234), Some("\"abc\"")));
+        assert!(still_matches(&cached, None, None), "Missing headers are no evidence of a change");
+        assert!(!still_matches(&cached, Some(1235), Some("\"abc\"")));
+        assert!(!still_matches(&cached, Some(1234), Some("\"def\"")));
+        assert!(still_matches(&CurseModFileInfo { etag: None, ..cached }, Some(1234), Some("\"def\"")),
+                "Entries cached without an ETag can only be checked by size");
+    }
+
+    #[test]
+    fn stops_paging_at_the_total_count() {
+        let page = |index, result_count, total_count| Pagination { index, page_size: 50, result_count, total_count };
+        assert_eq!(next_page(&page(0, 50, 120)), Some(50));
+        assert_eq!(next_page(&page(100, 20, 120)), None, "No request for the empty page after the last");
+        assert_eq!(next_page(&page(0, 50, 50)), None);
+        assert_eq!(next_page(&page(50, 0, 120)), None, "An empty page ends the listing whatever the count says");
+    }
+
+    #[test]
+    fn alternates_between_cdn_hosts() -> Result<()> {
+        let media = Url::parse("https://media.forgecdn.net/files/1/2/a.jar")?;
+        let edge = alternate_cdn_url(&media).context("media has an alternate")?;
+        assert_eq!(edge.as_str(), "https://edge.forgecdn.net/files/1/2/a.jar");
+        assert_eq!(alternate_cdn_url(&edge).as_ref(), Some(&media));
+        assert_eq!(alternate_cdn_url(&Url::parse("https://maven.minecraftforge.net/a.jar")?), None);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_error_payloads() {
+        assert!(check_payload(r#"{"error": "Service unavailable"}"#).is_err());
+        assert!(check_payload(r#"{"errorCode": 404, "errorMessage": "Not found"}"#).is_err());
+        assert!(check_payload(r#"{"data": {"id": 1, "name": "error"}}"#).is_ok());
+        assert!(check_payload("not json").is_ok());
+    }
+
+    #[test]
+    fn hashes_local_copies_instead_of_downloading() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("Some Mod-1.0.jar"), b"not really a jar")?;
+        let url = "https://media.forgecdn.net/files/1/2/Some%20Mod-1.0.jar";
+        let database = Database::for_tests()?;
+        let cached = r#"{"md5":"c4e762e76de7329365ade67908e20621","size":16,"download_url":"https://media.forgecdn.net/files/1/2/Some%20Mod-1.0.jar"}"#;
+        database.get_or_put(url, &INFINITE_TIMEOUT, Box::new(|| Ok(cached.to_string())))?;
+
+        let downloader = Downloader::new(&database, &Config::default())?.local_jar_dirs(vec![dir.path().to_path_buf()]);
+        let info = downloader.request_mod_file_info(url, &[HashAlgorithm::Md5, HashAlgorithm::Sha256])?;
+        assert_eq!(info.hashes[&HashAlgorithm::Sha256].len(), 64);
+        assert!(downloader.usage().get("cdn").is_none_or(|u| u.requests == 0), "Nothing should be downloaded");
+
+        Ok(())
+    }
 }
@@ -0,0 +1,250 @@
+//! Writing edits back to a yaml manifest without losing what serde_yaml drops: comments, blank
+//! lines, the `# yaml-language-server` header and keys the manifest structs don't know.
+//!
+//! As with `fmt`, the file is handled piece by piece. Keys and sequence items whose values didn't
+//! change keep their lines as written; changed ones are edited the same way one level down where
+//! they're written as blocks, and reserialized otherwise.
+
+use anyhow::Result;
+use serde_yaml::{Mapping, Value};
+
+use crate::formatting::{indent_of, is_comment, opens_block, reindent, to_yaml};
+use crate::model::YamlManifest;
+
+/// Lines belonging to a key or sequence item, with the blank and comment lines above it.
+struct Piece<'a> {
+    leading: Vec<&'a str>,
+    lines: Vec<&'a str>,
+}
+
+impl Piece<'_> {
+    fn verbatim(&self) -> Vec<String> {
+        self.leading.iter().chain(&self.lines).map(|l| l.to_string()).collect()
+    }
+}
+
+fn is_trivia(line: &str) -> bool {
+    line.trim().is_empty() || is_comment(line) || line.trim_end() == "---"
+}
+
+/// The indentation of a block's first real line. None if a later one is indented less.
+fn block_indent(lines: &[&str]) -> Option<usize> {
+    let mut real = lines.iter().filter(|l| !is_trivia(l)).map(|l| indent_of(l));
+    let indent = real.next()?;
+    real.all(|i| i >= indent).then_some(indent)
+}
+
+/// Splits a block into pieces starting wherever `starts` says, along with the trivia after the
+/// last one. None if the block doesn't start with a piece.
+fn split<'a, F>(lines: &[&'a str], starts: F) -> Option<(Vec<Piece<'a>>, Vec<&'a str>)> where F: Fn(&str) -> bool {
+    let mut pieces: Vec<Piece> = Vec::new();
+    let mut trivia = Vec::new();
+    for &line in lines {
+        if is_trivia(line) {
+            trivia.push(line);
+        } else if starts(line) {
+            pieces.push(Piece { leading: std::mem::take(&mut trivia), lines: vec![line] });
+        } else {
+            let piece = pieces.last_mut()?;
+            piece.lines.append(&mut trivia);
+            piece.lines.push(line);
+        }
+    }
+    Some((pieces, trivia))
+}
+
+fn indented(yaml: &str, indent: usize) -> Vec<String> {
+    yaml.lines().map(|l| format!("{}{}", " ".repeat(indent), l)).collect()
+}
+
+fn serialize_entry(key: &Value, value: &Value, indent: usize) -> Option<Vec<String>> {
+    let mut entry = Mapping::new();
+    entry.insert(key.clone(), value.clone());
+    Some(indented(&to_yaml(&entry).ok()?, indent))
+}
+
+/// Entries are matched by name, so that one can be renamed in place; anything else by value.
+fn same_item(a: &Value, b: &Value) -> bool {
+    match (a.get("name"), b.get("name")) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Rewrites a block mapping holding `old` so that it holds `new`. Keys neither of them has are
+/// left alone, as they're ones the manifest structs don't know.
+fn splice_mapping(lines: &[&str], old: &Mapping, new: &Mapping) -> Option<Vec<String>> {
+    let indent = block_indent(lines)?;
+    let (pieces, trailing) = split(lines, |l| indent_of(l) == indent && !l.trim_start().starts_with('-'))?;
+    let keys = pieces.iter()
+        .map(|piece| {
+            let entry: Mapping = serde_yaml::from_str(&reindent(&piece.lines, indent, 0)).ok()?;
+            match entry.len() {
+                1 => entry.into_iter().next().map(|(key, _)| key),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<Value>>>()?;
+    // New keys go after the nearest key before them that's already written, or first. Those
+    // the file leaves out and that didn't change, like defaults, stay left out.
+    let added_after = |after: Option<&Value>| -> Option<Vec<String>> {
+        let mut lines = Vec::new();
+        let mut previous = None;
+        for (key, value) in new {
+            if keys.contains(key) {
+                previous = Some(key);
+            } else if previous == after && old.get(key) != Some(value) {
+                lines.extend(serialize_entry(key, value, indent)?);
+            }
+        }
+        Some(lines)
+    };
+
+    let mut spliced = added_after(None)?;
+    for (piece, key) in pieces.iter().zip(&keys) {
+        match (old.get(key), new.get(key)) {
+            (old_value, new_value) if old_value == new_value => spliced.extend(piece.verbatim()),
+            (_, None) => (),
+            (old_value, Some(new_value)) => {
+                spliced.extend(piece.leading.iter().map(|l| l.to_string()));
+                let nested = match (old_value, new_value) {
+                    _ if !opens_block(piece.lines[0]) || piece.lines.len() < 2 => None,
+                    (Some(Value::Mapping(old)), Value::Mapping(new)) => splice_mapping(&piece.lines[1..], old, new),
+                    (Some(Value::Sequence(old)), Value::Sequence(new)) => splice_sequence(&piece.lines[1..], old, new),
+                    _ => None,
+                };
+                match nested {
+                    Some(nested) => {
+                        spliced.push(piece.lines[0].to_string());
+                        spliced.extend(nested);
+                    }
+                    None => spliced.extend(serialize_entry(key, new_value, indent)?),
+                }
+            }
+        }
+        spliced.extend(added_after(Some(key))?);
+    }
+    spliced.extend(trailing.iter().map(|l| l.to_string()));
+    Some(spliced)
+}
+
+/// Rewrites a block sequence holding `old` so that it holds `new`. Items that are kept stay in
+/// place, removed ones go along with their comments, and new ones are written where they are in `new`.
+fn splice_sequence(lines: &[&str], old: &[Value], new: &[Value]) -> Option<Vec<String>> {
+    let indent = block_indent(lines)?;
+    let (items, trailing) = split(lines, |l| indent_of(l) == indent && l.trim_start().starts_with('-'))?;
+    if items.len() != old.len() {
+        return None;
+    }
+    let mut spliced = Vec::new();
+    let mut next = 0;
+    for new_item in new {
+        // An item that matches none of the new ones was changed in place, e.g. renamed.
+        let matching = (next..old.len()).find(|&i| same_item(&old[i], new_item))
+            .or_else(|| (next < old.len() && !new.iter().any(|n| same_item(&old[next], n))).then_some(next));
+        match matching {
+            Some(i) => {
+                next = i + 1;
+                spliced.extend(splice_item(&items[i], indent, &old[i], new_item)?);
+            }
+            None => spliced.extend(indented(&to_yaml(&[new_item]).ok()?, indent)),
+        }
+    }
+    spliced.extend(trailing.iter().map(|l| l.to_string()));
+    Some(spliced)
+}
+
+fn splice_item(item: &Piece, indent: usize, old: &Value, new: &Value) -> Option<Vec<String>> {
+    if old == new {
+        return Some(item.verbatim());
+    }
+    let mut spliced: Vec<String> = item.leading.iter().map(|l| l.to_string()).collect();
+    // `- name: jei` is edited as a mapping a column or more to the right of the dash.
+    let after_dash = &item.lines[0][indent + 1..];
+    let gap = after_dash.len() - after_dash.trim_start().len();
+    let mapping = match (old, new) {
+        (Value::Mapping(old), Value::Mapping(new)) if gap > 0 && !after_dash.trim().is_empty() => {
+            let first = format!("{}{}", " ".repeat(indent + 1), after_dash);
+            let lines: Vec<&str> = std::iter::once(first.as_str()).chain(item.lines[1..].iter().copied()).collect();
+            splice_mapping(&lines, old, new)
+        }
+        _ => None,
+    };
+    match mapping {
+        Some(mut lines) => {
+            let first = lines.iter_mut().find(|l| !is_trivia(l))?;
+            *first = format!("{}-{}", " ".repeat(indent), &first[indent + 1..]);
+            spliced.extend(lines);
+        }
+        None => spliced.extend(indented(&to_yaml(&[new]).ok()?, indent)),
+    }
+    Some(spliced)
+}
+
+/// Rewrites `source`, the text of `original`, so that it reads as `edited`. None if the file is
+/// laid out in a way that can't be edited in place.
+pub fn apply_edits(source: &str, original: &YamlManifest, edited: &YamlManifest) -> Result<Option<String>> {
+    let lines: Vec<&str> = source.lines().collect();
+    let spliced = match (serde_yaml::to_value(original)?, serde_yaml::to_value(edited)?) {
+        (Value::Mapping(old), Value::Mapping(new)) => splice_mapping(&lines, &old, &new),
+        _ => None,
+    };
+    let spliced = match spliced {
+        Some(lines) => lines.iter().map(|l| format!("{}\n", l)).collect::<String>(),
+        None => return Ok(None),
+    };
+    // Anything unusual enough to be misread still fails safe.
+    let reparsed: Option<YamlManifest> = serde_yaml::from_str(&spliced).ok();
+    match reparsed {
+        Some(reparsed) if serde_json::to_value(&reparsed)? == serde_json::to_value(edited)? => Ok(Some(spliced)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::{YamlMod, YamlModFile};
+
+    fn edit<F>(source: &str, change: F) -> Result<Option<String>> where F: FnOnce(&mut YamlManifest) {
+        let original: YamlManifest = serde_yaml::from_str(source)?;
+        let mut edited = original.clone();
+        change(&mut edited);
+        apply_edits(source, &original, &edited)
+    }
+
+    #[test]
+    fn keeps_what_an_edit_doesnt_touch() -> Result<()> {
+        let source = "# yaml-language-server: $schema=manifest.schema.json\nversion: 1.12.2\nmods:\n\
+                      # Recipes\n- name: jei\n  homepage: https://example.org  # Not a manifest key\n  files:\n  - maturity: beta  # Releases lag behind\n\
+                      \n- name: waila\n";
+        let edited = edit(source, |m| m.mods[0].pin(42))?;
+        assert_eq!(edited.as_deref(), Some("# yaml-language-server: $schema=manifest.schema.json\nversion: 1.12.2\nmods:\n\
+                                            # Recipes\n- name: jei\n  homepage: https://example.org  # Not a manifest key\n  files:\n  - id: 42\n    maturity: beta  # Releases lag behind\n\
+                                            \n- name: waila\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn adds_removes_and_renames_entries() -> Result<()> {
+        let source = "version: 1.12.2\nmods:\n  # Gone soon\n  - name: old\n  # By project ID\n  - name: '238222'\n  - name: waila\n";
+        let edited = edit(source, |m| {
+            m.mods.remove(0);
+            m.mods.push(YamlMod::with_files("ae2", 1, YamlModFile::with_id(2)));
+        })?;
+        assert_eq!(edited.as_deref(), Some("version: 1.12.2\nmods:\n  # By project ID\n  - name: '238222'\n  - name: waila\n  - name: ae2\n    id: 1\n    files:\n      - id: 2\n"));
+        let edited = edit(source, |m| m.mods[1] = YamlMod::with_id("jei", 238222))?;
+        assert_eq!(edited.as_deref(), Some("version: 1.12.2\nmods:\n  # Gone soon\n  - name: old\n  # By project ID\n  - name: jei\n    id: 238222\n  - name: waila\n"),
+                   "Renamed entries stay where they are");
+        Ok(())
+    }
+
+    #[test]
+    fn reserializes_flow_style_values() -> Result<()> {
+        let source = "version: 1.12.2  # The pack's\nmods: [{name: jei}]\n";
+        let edited = edit(source, |m| m.mods[0].id = Some(238222))?;
+        assert_eq!(edited.as_deref(), Some("version: 1.12.2  # The pack's\nmods:\n  - name: jei\n    id: 238222\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+
+/// Randomly fails network requests, for testing how wrappers cope with flaky runs.
+///
+/// Whether an attempt fails depends only on the seed, the URL and how often that URL was tried
+/// before, so a run fails the same way every time no matter how threads are scheduled.
+#[derive(Debug)]
+pub struct FaultInjector {
+    probability: f64,
+    seed: u64,
+    attempts: Mutex<HashMap<String, u32>>,
+}
+
+impl FaultInjector {
+    pub fn check(&self, url: &str) -> Result<()> {
+        let attempt = {
+            let mut attempts = self.attempts.lock().unwrap();
+            let attempt = attempts.entry(url.to_string()).or_insert(0);
+            *attempt += 1;
+            *attempt
+        };
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.seed.to_le_bytes());
+        hasher.update(&attempt.to_le_bytes());
+        hasher.update(url.as_bytes());
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hasher.finalize().as_bytes()[..8]);
+        if (u64::from_le_bytes(bytes) as f64) < self.probability * u64::MAX as f64 {
+            anyhow::bail!("Injected fault for {} (attempt {})", url, attempt);
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{}% of requests, seed {}", self.probability * 100.0, self.seed)
+    }
+}
+
+/// Parses `p=<probability>[,seed=<number>]`, e.g. `p=0.1,seed=42`.
+impl FromStr for FaultInjector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut probability = None;
+        let mut seed = 0;
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = part.split_once('=')
+                .context(format!("Expected key=value, got {:?}", part))?;
+            match key {
+                "p" => probability = Some(value.parse::<f64>().context(format!("Invalid probability {:?}", value))?),
+                "seed" => seed = value.parse().context(format!("Invalid seed {:?}", value))?,
+                _ => anyhow::bail!("Unknown fault injection setting {:?}; expected p or seed", key),
+            }
+        }
+        let probability = probability.context("Missing p=<probability>")?;
+        if !(0.0..=1.0).contains(&probability) {
+            anyhow::bail!("Probability {} is not between 0 and 1", probability);
+        }
+        Ok(FaultInjector { probability, seed, attempts: Mutex::new(HashMap::new()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_a_reproducible_fraction() -> Result<()> {
+        let outcomes = |spec: &str| -> Result<Vec<bool>> {
+            let injector: FaultInjector = spec.parse()?;
+            Ok((0..1000).map(|i| injector.check(&format!("https://example.org/{}", i % 500)).is_err()).collect())
+        };
+        let failures = outcomes("p=0.1,seed=7")?;
+        let failed = failures.iter().filter(|f| **f).count();
+        assert!((60..140).contains(&failed), "{} of 1000 failed", failed);
+        assert_eq!(outcomes("p=0.1,seed=7")?, failures, "The same seed should fail the same requests");
+        assert_ne!(outcomes("p=0.1,seed=8")?, failures);
+        assert_ne!(failures[..500], failures[500..], "Retries should get a fresh chance");
+
+        assert!(outcomes("p=0")?.iter().all(|f| !f));
+        assert!(outcomes("p=1")?.iter().all(|f| *f));
+        assert!("p=2".parse::<FaultInjector>().is_err());
+        assert!("seed=1".parse::<FaultInjector>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! CurseForge's file fingerprints: MurmurHash2 with seed 1, over the file with all
+//! whitespace bytes removed. The API can look files up by these.
+
+const M: u32 = 0x5bd1e995;
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, 9 | 10 | 13 | 32)
+}
+
+fn murmur2(data: &[u8], seed: u32) -> u32 {
+    let mut h = seed ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
+
+pub fn curse_fingerprint(file: &[u8]) -> u32 {
+    let normalized: Vec<u8> = file.iter().copied().filter(|b| !is_whitespace(*b)).collect();
+    murmur2(&normalized, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_whitespace() {
+        // Reference values from the canonical MurmurHash2 implementation.
+        assert_eq!(murmur2(b"", 1), 0x5bd15e36);
+        assert_eq!(murmur2(b"hello", 1), 0xa631918e);
+        assert_eq!(curse_fingerprint(b"he llo\r\n"), curse_fingerprint(b"hello"));
+    }
+}
@@ -0,0 +1,192 @@
+//! Canonical formatting for yaml manifests, as done by `fmt`.
+//!
+//! serde_yaml drops comments, so the manifest is reformatted piece by piece instead: every
+//! top-level key and every mod entry is reserialized on its own, and comments on the lines
+//! above one move with it. Pieces with comments inside them are only reindented, not reserialized.
+
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+
+use crate::model::{YamlManifest, YamlMod};
+
+/// Top-level keys in the order they're written in; unknown keys go last.
+const KEY_ORDER: &[&str] = &["version", "loader", "game_version_type_id", "vars", "imports", "mods"];
+/// How far `fmt` indents nested blocks, matching what serde_yaml writes.
+const INDENT: usize = 2;
+
+/// A top-level key or a mod entry, with the comment lines above it.
+struct Piece<'a> {
+    comments: Vec<&'a str>,
+    lines: Vec<&'a str>,
+    /// Contains comments of its own, so it can't be reserialized without losing them.
+    commented: bool,
+}
+
+impl<'a> Piece<'a> {
+    fn new(comments: Vec<&'a str>, first_line: &'a str) -> Self {
+        Piece { comments, commented: has_inline_comment(first_line), lines: vec![first_line] }
+    }
+
+    fn push(&mut self, comments: &mut Vec<&'a str>, line: &'a str) {
+        self.commented |= !comments.is_empty() || has_inline_comment(line);
+        self.lines.append(comments);
+        self.lines.push(line);
+    }
+
+    fn text(&self) -> String {
+        self.lines.iter().map(|l| format!("{}\n", l)).collect()
+    }
+}
+
+pub(crate) fn is_comment(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+/// Conservative, since `#` only starts a comment after whitespace and outside quotes.
+fn has_inline_comment(line: &str) -> bool {
+    line.contains(" #")
+}
+
+/// Whether the key's value is on the lines below it, rather than after the colon.
+pub(crate) fn opens_block(key_line: &str) -> bool {
+    key_line.split(" #").next().unwrap_or_default().trim_end().ends_with(':')
+}
+
+pub(crate) fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Splits lines into the pieces starting wherever `starts` says. Comments right above a piece
+/// belong to it; those at the very end are returned separately.
+fn split_pieces<'a, F>(lines: &[&'a str], starts: F) -> (Vec<Piece<'a>>, Vec<&'a str>) where F: Fn(&str) -> bool {
+    let mut pieces: Vec<Piece> = Vec::new();
+    let mut comments = Vec::new();
+    for &line in lines {
+        if line.trim().is_empty() {
+            continue;
+        } else if is_comment(line) {
+            comments.push(line);
+        } else if starts(line) || pieces.is_empty() {
+            pieces.push(Piece::new(std::mem::take(&mut comments), line));
+        } else {
+            pieces.last_mut().unwrap().push(&mut comments, line);
+        }
+    }
+    (pieces, comments)
+}
+
+/// Moves lines from one indentation to another, leaving any that are less indented alone.
+pub(crate) fn reindent(lines: &[&str], from: usize, to: usize) -> String {
+    lines.iter()
+        .map(|line| match line.get(..from) {
+            Some(prefix) if prefix.trim().is_empty() => format!("{}{}\n", " ".repeat(to), &line[from..]),
+            _ => format!("{}\n", line),
+        })
+        .collect()
+}
+
+pub(crate) fn to_yaml<T>(value: &T) -> Result<String> where T: serde::Serialize + ?Sized {
+    let yaml = serde_yaml::to_string(value)?;
+    let yaml = yaml.strip_prefix("---\n").unwrap_or(&yaml);
+    Ok(if yaml.ends_with('\n') { yaml.to_string() } else { format!("{}\n", yaml) })
+}
+
+fn comments_at(comments: &[&str], indent: usize) -> String {
+    comments.iter().map(|c| format!("{}{}\n", " ".repeat(indent), c.trim_start())).collect()
+}
+
+/// Sorts mods by name, orders keys the way the manifest structs declare them and normalizes
+/// indentation, keeping comments. Fails rather than return a manifest that means something else.
+pub fn format_manifest(source: &str) -> Result<String> {
+    let manifest: YamlManifest = serde_yaml::from_str(source).context("While parsing the manifest")?;
+    let lines: Vec<&str> = source.lines().filter(|l| l.trim_end() != "---").collect();
+    let (mut sections, trailing) = split_pieces(&lines, |l| indent_of(l) == 0 && !l.starts_with('-'));
+    let key_of = |s: &Piece| s.lines[0].split(':').next().unwrap_or_default().trim().to_string();
+    sections.sort_by_key(|s| KEY_ORDER.iter().position(|k| *k == key_of(s)).unwrap_or(KEY_ORDER.len()));
+
+    let mut formatted = String::from("---\n");
+    // Mods written flow-style, as in `mods: [...]`, are left in the order they're in.
+    let mut sorted = true;
+    for section in &sections {
+        formatted.push_str(&comments_at(&section.comments, 0));
+        let is_mods = key_of(section) == "mods";
+        if is_mods && opens_block(section.lines[0]) && section.lines.len() > 1 {
+            formatted.push_str(&format!("{}\n", section.lines[0].trim_end()));
+            formatted.push_str(&format_mods(&section.lines[1..])?);
+        } else if is_mods {
+            sorted = false;
+            formatted.push_str(&section.text());
+        } else if section.commented {
+            formatted.push_str(&section.text());
+        } else {
+            let value: Value = serde_yaml::from_str(&section.text())?;
+            formatted.push_str(&to_yaml(&value)?);
+        }
+    }
+    formatted.push_str(&comments_at(&trailing, 0));
+
+    let mut expected = manifest;
+    if sorted {
+        expected.mods.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    let reparsed: YamlManifest = serde_yaml::from_str(&formatted)
+        .context("Formatting broke the manifest; please report this")?;
+    if serde_json::to_value(&reparsed)? != serde_json::to_value(&expected)? {
+        anyhow::bail!("Formatting would change the manifest's contents; please report this");
+    }
+    Ok(formatted)
+}
+
+fn format_mods(lines: &[&str]) -> Result<String> {
+    let item_indent = lines.iter().find(|l| !is_comment(l) && !l.trim().is_empty()).map(|l| indent_of(l)).unwrap_or_default();
+    let (items, trailing) = split_pieces(lines, |l| indent_of(l) == item_indent && l.trim_start().starts_with('-'));
+    let mut formatted = items.iter().map(|item| {
+        let text = reindent(&item.lines, item_indent, 0);
+        let original: Vec<Value> = serde_yaml::from_str(&text)?;
+        let parsed: Vec<YamlMod> = serde_yaml::from_str(&text)?;
+        let yaml_mod = parsed.into_iter().next().context("Empty mod entry")?;
+        // Keys the structs don't know would disappear, so those entries are kept as they are too.
+        let known = serde_json::to_value(vec![&yaml_mod])? == serde_json::to_value(&original)?;
+        let body = if item.commented || !known {
+            reindent(&item.lines, item_indent, INDENT)
+        } else {
+            to_yaml(&vec![&yaml_mod])?.lines().map(|l| format!("{}{}\n", " ".repeat(INDENT), l)).collect()
+        };
+        Ok((yaml_mod.name, comments_at(&item.comments, INDENT) + &body))
+    }).collect::<Result<Vec<(String, String)>>>()?;
+    formatted.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut result: String = formatted.into_iter().map(|(_, text)| text).collect();
+    result.push_str(&comments_at(&trailing, INDENT));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_mods_and_normalizes_keys() -> Result<()> {
+        let source = "mods:\n- files:\n  - id: 2\n  name: b\n  id: 1\n- name: a\nversion: 1.12.2\n";
+        let formatted = format_manifest(source)?;
+        assert_eq!(formatted, "---\nversion: 1.12.2\nmods:\n  - name: a\n  - name: b\n    id: 1\n    files:\n      - id: 2\n");
+        assert_eq!(format_manifest(&formatted)?, formatted, "Formatting should be idempotent");
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_flow_style_mods_alone() -> Result<()> {
+        let source = "mods: [{name: b}, {name: a}]\nversion: 1.12.2\n";
+        assert_eq!(format_manifest(source)?, "---\nversion: 1.12.2\nmods: [{name: b}, {name: a}]\n");
+        let source = "version: 1.12.2\nmods:  # Sorted\n- name: b\n- name: a\n";
+        assert_eq!(format_manifest(source)?, "---\nversion: 1.12.2\nmods:  # Sorted\n  - name: a\n  - name: b\n");
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_comments_with_their_entries() -> Result<()> {
+        let source = "# The pack\nversion: 1.12.2\nmods:\n  # Needed by everything\n  - name: zz-lib\n    id: 3\n  - name: jei\n    # Newer ones crash\n    files:\n        - id: 1\n  - id: 2\n    name: b  # Was c\n";
+        let formatted = format_manifest(source)?;
+        assert_eq!(formatted, "---\n# The pack\nversion: 1.12.2\nmods:\n  - id: 2\n    name: b  # Was c\n  - name: jei\n    # Newer ones crash\n    files:\n        - id: 1\n  # Needed by everything\n  - name: zz-lib\n    id: 3\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+//! Turning a manifest's Minecraft version into a CurseForge file filter. Releases are matched
+//! by name, but CurseForge files its pre-releases under e.g. `1.20.5-Snapshot` and weekly
+//! snapshots only under the version group given by `gameVersionTypeId`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::curse_api::FileIndex;
+
+#[derive(Debug, PartialEq)]
+pub enum Kind {
+    Release,
+    /// A pre-release or release candidate of the given release.
+    PreRelease(String),
+    /// A weekly snapshot such as `24w14a`.
+    Snapshot,
+}
+
+pub fn kind(version: &str) -> Kind {
+    let lower = version.to_lowercase();
+    if is_weekly_snapshot(&lower) {
+        return Kind::Snapshot;
+    }
+    // `1.20.5-pre1`, `1.20.5-rc1`, and the older `1.14 Pre-Release 2`.
+    for marker in ["-pre", "-rc", " pre-release", "-snapshot"] {
+        if let Some(index) = lower.find(marker) {
+            return Kind::PreRelease(version[..index].to_string());
+        }
+    }
+    Kind::Release
+}
+
+fn is_weekly_snapshot(version: &str) -> bool {
+    match version.split_once('w') {
+        Some((year, rest)) => year.len() == 2 && year.chars().all(|c| c.is_ascii_digit())
+            && rest.len() >= 3 && rest[..2].chars().all(|c| c.is_ascii_digit())
+            && rest[2..].chars().all(|c| c.is_ascii_lowercase()),
+        None => false,
+    }
+}
+
+/// The query parameters used to list a project's files for one Minecraft version.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionFilter {
+    pub game_version: Option<String>,
+    pub game_version_type_id: Option<u32>,
+    /// CurseForge's ID for the manifest's loader. Only used to pick from the project's index,
+    /// as the listing isn't filtered by loader.
+    pub mod_loader: Option<u32>,
+}
+
+impl VersionFilter {
+    pub fn new(version: &str, game_version_type_id: Option<u32>) -> Result<Self> {
+        let game_version = match kind(version) {
+            Kind::Release => Some(version.to_string()),
+            Kind::PreRelease(release) => Some(format!("{}-Snapshot", release)),
+            Kind::Snapshot if game_version_type_id.is_some() => None,
+            Kind::Snapshot => anyhow::bail!(
+                "CurseForge doesn't list files under snapshot names like {}; set game_version_type_id in the manifest \
+                 to the ID of the version group it belongs to", version),
+        };
+        Ok(VersionFilter { game_version, game_version_type_id, mod_loader: None })
+    }
+
+    /// Narrows the filter to a loader, by the lowercased name it has in manifests.
+    pub fn for_loader(self, loader: Option<&str>) -> Self {
+        let mod_loader = loader.and_then(|name| (1..=6).find(|&id| loader_name(id).is_some_and(|n| n.eq_ignore_ascii_case(name))));
+        VersionFilter { mod_loader, ..self }
+    }
+
+    /// The newest file for the version among a project's `latestFilesIndexes`, which list the
+    /// newest file per version, loader and release type. File IDs go up with every upload.
+    /// Only releases are matched, as the index files snapshots under their own names. With a
+    /// loader, entries for other loaders, or for no loader in particular, are passed over.
+    pub fn newest_indexed(&self, indexes: &[FileIndex]) -> Option<u32> {
+        let game_version = self.game_version.as_ref()?;
+        indexes.iter()
+            .filter(|index| &index.game_version == game_version)
+            .filter(|index| self.mod_loader.is_none() || index.mod_loader == self.mod_loader)
+            .map(|index| index.file_id)
+            .max()
+    }
+
+    pub fn query(&self) -> String {
+        let mut query = Vec::new();
+        if let Some(ref version) = self.game_version {
+            query.push(format!("gameVersion={}", urlencoding::encode(version)));
+        }
+        if let Some(type_id) = self.game_version_type_id {
+            query.push(format!("gameVersionTypeId={}", type_id));
+        }
+        query.join("&")
+    }
+}
+
+impl std::fmt::Display for VersionFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (&self.game_version, self.game_version_type_id) {
+            (Some(version), _) => write!(f, "{}", version),
+            (None, Some(type_id)) => write!(f, "version type {}", type_id),
+            (None, None) => write!(f, "any version"),
+        }
+    }
+}
+
+/// CurseForge's names for its `modLoader` IDs.
+fn loader_name(id: u32) -> Option<&'static str> {
+    match id {
+        1 => Some("Forge"),
+        2 => Some("Cauldron"),
+        3 => Some("LiteLoader"),
+        4 => Some("Fabric"),
+        5 => Some("Quilt"),
+        6 => Some("NeoForge"),
+        _ => None,
+    }
+}
+
+/// A project has no files for the version being resolved. Lists the versions and loaders it
+/// does have files for, going by the project's newest file per version.
+#[derive(Debug)]
+pub struct NoFilesForVersion {
+    pub name: String,
+    pub wanted: String,
+    /// Game versions to loader names; None if the project couldn't be looked up.
+    pub available: Option<BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl NoFilesForVersion {
+    pub fn new(name: &str, filter: &VersionFilter, indexes: Option<&[FileIndex]>) -> Self {
+        let available = indexes.map(|indexes| {
+            let mut available: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+            for index in indexes {
+                let loaders = available.entry(index.game_version.clone()).or_default();
+                loaders.extend(index.mod_loader.and_then(loader_name).map(str::to_string));
+            }
+            available
+        });
+        NoFilesForVersion { name: name.to_string(), wanted: filter.to_string(), available }
+    }
+}
+
+impl fmt::Display for NoFilesForVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} has no files for {}", self.name, self.wanted)?;
+        match self.available {
+            None => write!(f, "; couldn't look up which versions it has"),
+            Some(ref available) if available.is_empty() => write!(f, "; it lists no files at all"),
+            Some(ref available) => {
+                let versions: Vec<String> = available.iter().rev().map(|(version, loaders)| match loaders.len() {
+                    0 => version.clone(),
+                    _ => format!("{} ({})", version, loaders.iter().cloned().collect::<Vec<_>>().join(", ")),
+                }).collect();
+                write!(f, "; it has files for {}", versions.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for NoFilesForVersion {}
+
+/// One report for every mod without files for the version, instead of one error at a time.
+pub fn report_missing_files(missing: &[&NoFilesForVersion]) -> String {
+    let lines: Vec<String> = missing.iter().map(|m| m.to_string()).collect();
+    format!("{} mods have no files for the manifest's version:\n  {}", missing.len(), lines.join("\n  "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_snapshots_and_prereleases() -> Result<()> {
+        assert_eq!(kind("1.20.4"), Kind::Release);
+        assert_eq!(kind("1.20.5-pre1"), Kind::PreRelease("1.20.5".to_string()));
+        assert_eq!(kind("1.14 Pre-Release 2"), Kind::PreRelease("1.14".to_string()));
+        assert_eq!(kind("24w14a"), Kind::Snapshot);
+
+        assert_eq!(VersionFilter::new("1.20.4", None)?.query(), "gameVersion=1.20.4");
+        assert_eq!(VersionFilter::new("1.20.5-rc1", None)?.query(), "gameVersion=1.20.5-Snapshot");
+        assert!(VersionFilter::new("24w14a", None).is_err(), "Weekly snapshots need a version type");
+        assert_eq!(VersionFilter::new("24w14a", Some(77784))?.query(), "gameVersionTypeId=77784");
+        Ok(())
+    }
+
+    #[test]
+    fn lists_what_a_project_has_files_for() -> Result<()> {
+        let index = |game_version: &str, mod_loader: Option<u32>| FileIndex {
+            game_version: game_version.to_string(),
+            file_id: 1,
+            filename: "mod.jar".to_string(),
+            release_type: 1,
+            game_version_type_id: None,
+            mod_loader,
+        };
+        let filter = VersionFilter::new("1.20.1", None)?;
+        let indexes = [index("1.18.2", Some(1)), index("1.19.2", Some(4)), index("1.19.2", Some(1)), index("1.19.2", None)];
+        let missing = NoFilesForVersion::new("jei", &filter, Some(&indexes));
+        assert_eq!(missing.to_string(), "jei has no files for 1.20.1; it has files for 1.19.2 (Fabric, Forge), 1.18.2 (Forge)");
+        let unknown = NoFilesForVersion::new("gone", &filter, None);
+        assert_eq!(report_missing_files(&[&missing, &unknown]).lines().count(), 3);
+        assert_eq!(NoFilesForVersion::new("empty", &filter, Some(&[])).to_string(), "empty has no files for 1.20.1; it lists no files at all");
+        Ok(())
+    }
+
+    #[test]
+    fn finds_the_newest_indexed_file() -> Result<()> {
+        let index = |game_version: &str, file_id: u32, mod_loader: Option<u32>| FileIndex {
+            game_version: game_version.to_string(),
+            file_id,
+            filename: "mod.jar".to_string(),
+            release_type: 1,
+            game_version_type_id: None,
+            mod_loader,
+        };
+        let indexes = [index("1.20.1", 10, Some(1)), index("1.20.1", 12, Some(1)), index("1.20.1", 14, Some(4)),
+                       index("1.20.1", 16, None), index("1.19.2", 20, Some(1))];
+        assert_eq!(VersionFilter::new("1.20.1", None)?.newest_indexed(&indexes), Some(16));
+        assert_eq!(VersionFilter::new("1.20.1", None)?.for_loader(Some("forge")).newest_indexed(&indexes), Some(12));
+        assert_eq!(VersionFilter::new("1.20.1", None)?.for_loader(Some("fabric")).newest_indexed(&indexes), Some(14));
+        assert_eq!(VersionFilter::new("1.20.1", None)?.for_loader(Some("neoforge")).newest_indexed(&indexes), None);
+        assert_eq!(VersionFilter::new("1.18.2", None)?.newest_indexed(&indexes), None);
+        assert_eq!(VersionFilter::new("24w14a", Some(77784))?.newest_indexed(&indexes), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Md5,
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+pub const DEFAULT_HASHES: &[HashAlgorithm] = &[HashAlgorithm::Md5, HashAlgorithm::Sha256];
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "md5" => Ok(HashAlgorithm::Md5),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            other => anyhow::bail!("Unknown hash algorithm {}, expected one of md5, sha256, sha512, blake3", other),
+        }
+    }
+}
+
+/// A set of hash algorithms, parsed from a comma-separated list such as `md5,sha256`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashList(pub Vec<HashAlgorithm>);
+
+impl Default for HashList {
+    fn default() -> Self {
+        HashList(DEFAULT_HASHES.to_vec())
+    }
+}
+
+impl FromStr for HashList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut algorithms = s.split(',').map(HashAlgorithm::from_str).collect::<Result<Vec<_>>>()?;
+        algorithms.sort();
+        algorithms.dedup();
+        Ok(HashList(algorithms))
+    }
+}
+
+const NIX_BASE32_ALPHABET: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+/// Encodes a digest the way Nix prints hashes, e.g. in `nix-prefetch-url`'s output. This isn't
+/// RFC 4648 base32: the alphabet leaves out e, o, u and t, and bytes are read from the end.
+pub fn nix_base32(digest: &[u8]) -> String {
+    let length = (digest.len() * 8).saturating_sub(1) / 5 + 1;
+    (0..length).rev().map(|n| {
+        let (byte, bit) = (n * 5 / 8, n * 5 % 8);
+        let low = digest[byte] >> bit;
+        let high = digest.get(byte + 1).map_or(0, |next| next.checked_shl(8 - bit as u32).unwrap_or(0));
+        NIX_BASE32_ALPHABET[((low | high) & 0x1f) as usize] as char
+    }).collect()
+}
+
+/// Re-encodes a hex digest, as we record them, in Nix's base32.
+pub fn hex_to_nix_base32(hex: &str) -> Result<String> {
+    // Checked up front, as slicing by byte would split any multibyte character.
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        anyhow::bail!("{} isn't a hex digest", hex);
+    }
+    let digest = (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()?;
+    Ok(nix_base32(&digest))
+}
+
+/// The same hashes, with sha256 in Nix's base32 instead of hex.
+pub fn with_base32_sha256(hashes: &BTreeMap<HashAlgorithm, String>) -> Result<BTreeMap<HashAlgorithm, String>> {
+    let mut hashes = hashes.clone();
+    if let Some(sha256) = hashes.get_mut(&HashAlgorithm::Sha256) {
+        *sha256 = hex_to_nix_base32(sha256)?;
+    }
+    Ok(hashes)
+}
+
+/// Computes every supported hash in a single pass over the data, while keeping a copy of it.
+///
+/// We always compute all of them, as the result gets cached forever and the download
+/// is far more expensive than the hashing.
+pub struct MultiHasher {
+    buffer: Vec<u8>,
+    md5: md5::Context,
+    sha256: Sha256,
+    sha512: Sha512,
+    blake3: blake3::Hasher,
+}
+
+impl MultiHasher {
+    pub fn new() -> Self {
+        MultiHasher {
+            buffer: Vec::new(),
+            md5: md5::Context::new(),
+            sha256: Sha256::new(),
+            sha512: Sha512::new(),
+            blake3: blake3::Hasher::new(),
+        }
+    }
+
+    pub fn finish(self) -> (Vec<u8>, BTreeMap<HashAlgorithm, String>) {
+        let mut hashes = BTreeMap::new();
+        hashes.insert(HashAlgorithm::Md5, format!("{:x}", self.md5.compute()));
+        hashes.insert(HashAlgorithm::Sha256, format!("{:x}", self.sha256.finalize()));
+        hashes.insert(HashAlgorithm::Sha512, format!("{:x}", self.sha512.finalize()));
+        hashes.insert(HashAlgorithm::Blake3, self.blake3.finalize().to_hex().to_string());
+        (self.buffer, hashes)
+    }
+}
+
+impl Write for MultiHasher {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        self.md5.consume(data);
+        self.sha256.update(data);
+        self.sha512.update(data);
+        self.blake3.update(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_known_values() -> Result<()> {
+        let mut hasher = MultiHasher::new();
+        hasher.write_all(b"ab")?;
+        hasher.write_all(b"c")?;
+        let (buffer, hashes) = hasher.finish();
+
+        assert_eq!(buffer, b"abc");
+        assert_eq!(hashes[&HashAlgorithm::Md5], "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hashes[&HashAlgorithm::Sha256], "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert!(hashes[&HashAlgorithm::Sha512].starts_with("ddaf35a193617aba"));
+        assert_eq!(hashes[&HashAlgorithm::Blake3], "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+
+        Ok(())
+    }
+
+    #[test]
+    fn encodes_like_nix() -> Result<()> {
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(hex_to_nix_base32(sha256)?, "1b8m03r63zqhnjf7l5wnldhh7c134ap5vpj0850ymkq1iyzicy5s");
+        assert_eq!(nix_base32(&[0xff]), "7z");
+        assert!(hex_to_nix_base32("abc").is_err());
+        assert!(hex_to_nix_base32("zz").is_err());
+        assert!(hex_to_nix_base32("aé").is_err());
+        let hashes = vec![(HashAlgorithm::Md5, "aa".to_string()), (HashAlgorithm::Sha256, "ff".to_string())].into_iter().collect();
+        let converted = with_base32_sha256(&hashes)?;
+        assert_eq!((converted[&HashAlgorithm::Md5].as_str(), converted[&HashAlgorithm::Sha256].as_str()), ("aa", "7z"));
+        Ok(())
+    }
+
+    #[test]
+    fn parses_hash_lists() -> Result<()> {
+        assert_eq!("sha512, md5,sha512".parse::<HashList>()?.0, vec![HashAlgorithm::Md5, HashAlgorithm::Sha512]);
+        assert!("crc32".parse::<HashList>().is_err());
+
+        Ok(())
+    }
+}
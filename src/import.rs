@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use reqwest::Url;
+
+/// Turns one line of an import list into a slug.
+///
+/// Lines may be bare slugs or CurseForge project URLs, e.g.
+/// `https://www.curseforge.com/minecraft/mc-mods/jei/files/3043174`.
+/// Blank lines and lines starting with `#` are skipped.
+pub fn parse_list_entry(line: &str) -> Result<Option<String>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    match Url::parse(line) {
+        Ok(url) => slug_from_project_url(&url)
+            .context(format!("{} is not a CurseForge project URL", line))
+            .map(Some),
+        Err(_) => Ok(Some(line.to_string())),
+    }
+}
+
+/// Splits a CurseForge file page URL, as copied from the browser, into the project slug and file ID,
+/// e.g. `https://www.curseforge.com/minecraft/mc-mods/jei/files/3043174` -> (`jei`, 3043174).
+pub fn parse_file_page_url(url: &str) -> Result<(String, u32)> {
+    let parsed = Url::parse(url).context(format!("{} is not a URL", url))?;
+    let slug = slug_from_project_url(&parsed).context(format!("{} is not a CurseForge project URL", url))?;
+    let mut segments = parsed.path_segments().context("URL has no path")?.skip_while(|s| *s != "files");
+    let file_id = segments.nth(1)
+        .and_then(|id| id.parse().ok())
+        .context(format!("{} doesn't point at a file; expected .../files/<file id>", url))?;
+    Ok((slug, file_id))
+}
+
+fn slug_from_project_url(url: &Url) -> Option<String> {
+    let mut segments = url.path_segments()?;
+    segments.position(|s| s == "mc-mods")?;
+    segments.next().filter(|s| !s.is_empty()).map(|s| s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_slugs_and_urls() -> Result<()> {
+        assert_eq!(parse_list_entry("  jei \n")?, Some("jei".to_string()));
+        assert_eq!(parse_list_entry("https://www.curseforge.com/minecraft/mc-mods/iron-chests")?, Some("iron-chests".to_string()));
+        assert_eq!(parse_list_entry("https://www.curseforge.com/minecraft/mc-mods/jei/files/3043174")?, Some("jei".to_string()));
+        assert_eq!(parse_list_entry("# a comment")?, None);
+        assert_eq!(parse_list_entry("")?, None);
+        assert!(parse_list_entry("https://example.com/not-a-mod").is_err(), "Foreign URLs should be rejected");
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_file_page_urls() -> Result<()> {
+        assert_eq!(parse_file_page_url("https://www.curseforge.com/minecraft/mc-mods/jei/files/3043174")?,
+                   ("jei".to_string(), 3043174));
+        assert_eq!(parse_file_page_url("https://www.curseforge.com/minecraft/mc-mods/jei/files/3043174?page=2")?,
+                   ("jei".to_string(), 3043174));
+        assert!(parse_file_page_url("https://www.curseforge.com/minecraft/mc-mods/jei").is_err(), "Project pages have no file");
+        assert!(parse_file_page_url("https://www.curseforge.com/minecraft/mc-mods/jei/files/all").is_err());
+
+        Ok(())
+    }
+}
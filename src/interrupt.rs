@@ -0,0 +1,64 @@
+//! Winding a run down on Ctrl+C, rather than dying halfway through it.
+//!
+//! The first Ctrl+C stops mods from being started; requests in flight finish, so the cache keeps
+//! what they fetched, and whatever resolved by then is still written out, lockfile included. The
+//! run then fails, listing the mods it left out. A second Ctrl+C quits at once.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Traps SIGINT for the rest of the run.
+#[cfg(unix)]
+pub fn install() {
+    extern "C" fn handle(_signal: libc::c_int) {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            unsafe { libc::_exit(130) }
+        }
+        // Only async-signal-safe calls in here, so no logging.
+        let message = b"\nInterrupted, finishing what's in flight. Press Ctrl+C again to quit at once.\n";
+        unsafe { libc::write(libc::STDERR_FILENO, message.as_ptr() as *const libc::c_void, message.len()) };
+    }
+    unsafe { libc::signal(libc::SIGINT, handle as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fails once the run was interrupted, for work about to start.
+pub fn check() -> Result<(), Interrupted> {
+    if interrupted() { Err(Interrupted) } else { Ok(()) }
+}
+
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Whether an error came from the run being interrupted, rather than from the work itself.
+pub fn is_interrupted(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<Interrupted>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_interruptions_from_failures() {
+        let error = anyhow::Error::from(Interrupted).context("[jei] Resolving mod");
+        assert!(is_interrupted(&error));
+        assert!(!is_interrupted(&anyhow::anyhow!("404 Not Found")));
+    }
+}
@@ -0,0 +1,158 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use serde_json::json;
+
+use crate::hashes::HashAlgorithm;
+use crate::model::NixMod;
+use crate::naming;
+use crate::output::AtomicFile;
+
+const COLUMNS: &[&str] = &["slug", "title", "id", "file_id", "filename", "version", "side", "size", "sha256", "page"];
+
+/// The mod's own version, as its file is named: everything from the first part starting with a
+/// digit, e.g. `1.12.2-4.16.1.302` for `jei_1.12.2-4.16.1.302.jar`. Empty if nothing looks like one.
+fn file_version(filename: &str) -> &str {
+    let stem = filename.strip_suffix(".jar").or_else(|| filename.strip_suffix(".zip")).unwrap_or(filename);
+    let start = stem.char_indices()
+        .find(|&(i, c)| c.is_ascii_digit() && (i == 0 || stem[..i].ends_with(['-', '_', ' ', '+', 'v'])))
+        .map_or(stem.len(), |(i, _)| i);
+    &stem[start..]
+}
+
+/// Writes a spreadsheet-friendly inventory of the resolved mods, one row per mod.
+pub fn write_csv(path: &Path, mods: &[NixMod]) -> Result<()> {
+    let mut writer = AtomicFile::create(path)?;
+    writeln!(writer, "{}", COLUMNS.join(","))?;
+    for nix_mod in mods {
+        let side = json!(nix_mod.side);
+        let row = [
+            nix_mod.slug.as_str(),
+            &nix_mod.title,
+            &nix_mod.id.to_string(),
+            &nix_mod.file_id.to_string(),
+            &nix_mod.filename,
+            file_version(&nix_mod.filename),
+            side.as_str().unwrap_or_default(),
+            &nix_mod.size.to_string(),
+            nix_mod.hashes.get(&HashAlgorithm::Sha256).map(String::as_str).unwrap_or_default(),
+            &nix_mod.page,
+        ];
+        writeln!(writer, "{}", row.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","))?;
+    }
+    writer.commit()
+}
+
+/// Writes a Markdown table of the resolved mods, linking each to its project page.
+pub fn write_markdown(path: &Path, version: &str, mods: &[NixMod]) -> Result<()> {
+    let mut writer = AtomicFile::create(path)?;
+    writeln!(writer, "# Mods for Minecraft {}\n", version)?;
+    writeln!(writer, "| Mod | File | Side |\n| --- | --- | --- |")?;
+    for nix_mod in mods {
+        let title = markdown_cell(&nix_mod.title);
+        let linked = if nix_mod.page.is_empty() { title } else { format!("[{}]({})", title, nix_mod.page) };
+        let side = json!(nix_mod.side);
+        writeln!(writer, "| {} | {} | {} |", linked, markdown_cell(&nix_mod.filename), side.as_str().unwrap_or_default())?;
+    }
+    writer.commit()
+}
+
+/// Writes an input file for `aria2c -i`, which checks each download against its sha256.
+/// aria2c has no use for the size, so it goes in a comment.
+pub fn write_aria2_input(path: &Path, mods: &[NixMod]) -> Result<()> {
+    let mut writer = AtomicFile::create(path)?;
+    for nix_mod in mods {
+        writeln!(writer, "# {}: {} bytes", nix_mod.slug, nix_mod.size)?;
+        writeln!(writer, "{}\n  out={}", nix_mod.src, naming::safe_filename(&nix_mod.filename))?;
+        if let Some(sha256) = nix_mod.hashes.get(&HashAlgorithm::Sha256) {
+            writeln!(writer, "  checksum=sha-256={}", sha256)?;
+        }
+    }
+    writer.commit()
+}
+
+/// Writes the same as `write_aria2_input`, as a JSON list for mirroring scripts.
+pub fn write_fetchlist_json(path: &Path, mods: &[NixMod]) -> Result<()> {
+    let entries: Vec<_> = mods.iter().map(|nix_mod| json!({
+        "url": nix_mod.src,
+        "sha256": nix_mod.hashes.get(&HashAlgorithm::Sha256),
+        "size": nix_mod.size,
+        "filename": naming::safe_filename(&nix_mod.filename),
+    })).collect();
+    let mut writer = AtomicFile::create(path)?;
+    serde_json::to_writer_pretty(&mut writer, &entries)?;
+    writeln!(writer)?;
+    writer.commit()
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+/// Quotes a field if it would otherwise break the row, per RFC 4180.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_awkward_fields() -> Result<()> {
+        assert_eq!(csv_field("jei"), "jei");
+        assert_eq!(csv_field("Tinkers' Construct, Reforged"), "\"Tinkers' Construct, Reforged\"");
+        assert_eq!(csv_field("The \"Best\" Mod"), "\"The \"\"Best\"\" Mod\"");
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("mods.csv");
+        let nix_mod = NixMod::new("jei", "Just Enough Items", 238222)
+            .file_id(3043174)
+            .file("jei_1.12.2-4.16.1.302.jar", "https://media.forgecdn.net/files/3043/174/jei_1.12.2-4.16.1.302.jar", 1234)
+            .hash(HashAlgorithm::Sha256, "def");
+        let waystones = NixMod::new("waystones", "Waystones", 245755)
+            .file("Waystones-1.12.2-4.1.0.jar", "https://media.forgecdn.net/files/1/2/Waystones-1.12.2-4.1.0.jar", 1);
+        write_csv(&path, &[nix_mod, waystones])?;
+        let written = std::fs::read_to_string(&path)?;
+        assert_eq!(written.lines().nth(1),
+                   Some("jei,Just Enough Items,238222,3043174,jei_1.12.2-4.16.1.302.jar,1.12.2-4.16.1.302,both,1234,def,"));
+        assert_eq!(written.lines().nth(2).and_then(|l| l.split(',').nth(5)), Some("1.12.2-4.1.0"), "Each mod has its own version");
+        assert_eq!(file_version("appliedenergistics2-rv6-stable-7.jar"), "6-stable-7");
+        assert_eq!(file_version("jei.jar"), "");
+
+        let markdown_path = dir.path().join("modlist.md");
+        let odd = NixMod::new("odd", "Either | Or", 1).file("odd.jar", "https://example.org/odd.jar", 1);
+        write_markdown(&markdown_path, "1.12.2", &[odd])?;
+        assert_eq!(std::fs::read_to_string(&markdown_path)?.lines().last(), Some("| Either \\| Or | odd.jar | both |"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn lists_files_to_fetch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let nix_mod = NixMod::new("jei", "Just Enough Items", 238222)
+            .file("jei 1.12.2.jar", "https://media.forgecdn.net/files/3043/174/jei%201.12.2.jar", 1234)
+            .hash(HashAlgorithm::Sha256, "def");
+        let aria2_path = dir.path().join("fetchlist.txt");
+        write_aria2_input(&aria2_path, std::slice::from_ref(&nix_mod))?;
+        assert_eq!(std::fs::read_to_string(&aria2_path)?,
+                   "# jei: 1234 bytes\nhttps://media.forgecdn.net/files/3043/174/jei%201.12.2.jar\n  out=jei 1.12.2.jar\n  checksum=sha-256=def\n");
+
+        let json_path = dir.path().join("fetchlist.json");
+        write_fetchlist_json(&json_path, &[nix_mod])?;
+        let written: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&json_path)?)?;
+        assert_eq!(written, json!([{
+            "url": "https://media.forgecdn.net/files/3043/174/jei%201.12.2.jar",
+            "sha256": "def",
+            "size": 1234,
+            "filename": "jei 1.12.2.jar",
+        }]));
+        Ok(())
+    }
+}
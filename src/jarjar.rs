@@ -0,0 +1,106 @@
+use std::io::{Cursor, Read, Seek};
+
+use anyhow::Result;
+use zip::ZipArchive;
+
+use crate::model::NixMod;
+
+// Forge's JarJar and Fabric's nested jars, respectively.
+const EMBEDDED_JAR_DIRS: &[&str] = &["META-INF/jarjar/", "META-INF/jars/"];
+
+/// A manifest entry whose content is already shipped inside another entry.
+#[derive(Debug, PartialEq)]
+pub struct EmbeddedDuplicate {
+    pub container: String,
+    pub library: String,
+}
+
+pub fn list_embedded_jars(jar: &[u8]) -> Result<Vec<String>> {
+    list_embedded_jars_from(Cursor::new(jar))
+}
+
+fn list_embedded_jars_from<R>(reader: R) -> Result<Vec<String>> where R: Read + Seek {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut embedded = Vec::new();
+    for i in 0..archive.len() {
+        let name = archive.by_index(i)?.name().to_string();
+        for dir in EMBEDDED_JAR_DIRS {
+            if let Some(file) = name.strip_prefix(dir) {
+                if file.ends_with(".jar") && !file.contains('/') {
+                    embedded.push(file.to_string());
+                }
+            }
+        }
+    }
+    Ok(embedded)
+}
+
+/// Reduces a jar filename to its versionless, lowercase stem,
+/// e.g. `Cloth-Config-Fabric-6.2.57.jar` -> `cloth-config-fabric`.
+pub fn library_stem(filename: &str) -> String {
+    let name = filename.to_lowercase();
+    let name = name.trim_end_matches(".jar");
+    let mut stem = Vec::new();
+    for part in name.split(['-', '_']) {
+        if part.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+            break;
+        }
+        stem.push(part);
+    }
+    stem.join("-")
+}
+
+/// Finds manifest entries that are also embedded in another entry, either according to the
+/// CurseForge dependency metadata or by inspecting the jar itself.
+pub fn find_embedded_duplicates(mods: &[NixMod]) -> Vec<EmbeddedDuplicate> {
+    let mut duplicates = Vec::new();
+    for container in mods {
+        let embedded_stems: Vec<String> = container.embedded_jars.iter().map(|j| library_stem(j)).collect();
+        for library in mods {
+            if library.id == container.id {
+                continue;
+            }
+            if container.embedded_ids.contains(&library.id)
+                || embedded_stems.contains(&library_stem(&library.filename)) {
+                duplicates.push(EmbeddedDuplicate {
+                    container: container.slug.clone(),
+                    library: library.slug.clone(),
+                });
+            }
+        }
+    }
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    #[test]
+    fn lists_jarjar_and_nested_jars() -> Result<()> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for name in &["META-INF/MANIFEST.MF", "META-INF/jarjar/kotlinforforge-4.3.0.jar",
+                      "META-INF/jars/cloth-config-fabric-6.2.57.jar", "assets/icon.png"] {
+            writer.start_file(*name, FileOptions::default())?;
+            writer.write_all(b"")?;
+        }
+        let jar = writer.finish()?.into_inner();
+
+        let embedded = list_embedded_jars(&jar)?;
+        assert_eq!(embedded, vec!["kotlinforforge-4.3.0.jar", "cloth-config-fabric-6.2.57.jar"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn strips_versions_from_stems() {
+        assert_eq!(library_stem("Cloth-Config-Fabric-6.2.57.jar"), "cloth-config-fabric");
+        assert_eq!(library_stem("jei_1.12.2-4.16.1.302.jar"), "jei");
+        assert_eq!(library_stem("Botania r1.10-363.jar"), "botania r1.10");
+    }
+}
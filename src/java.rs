@@ -0,0 +1,83 @@
+//! Which Java a pack's server needs, so provisioning can pick the JRE without being told.
+
+use std::cmp::Ordering;
+
+use crate::game_version::{self, Kind};
+use crate::versions;
+
+/// The Java major versions a pack runs on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JavaRequirement {
+    pub major: u32,
+    /// The newest major version that works, when newer ones are known not to.
+    pub newest: Option<u32>,
+}
+
+/// The first release needing each Java version, newest first.
+const RELEASES: &[(&str, u32)] = &[("1.20.5", 21), ("1.18", 17), ("1.17", 16)];
+/// The first weekly snapshot needing each Java version, as (year, week), newest first.
+const SNAPSHOTS: &[((u32, u32), u32)] = &[((24, 14), 21), ((21, 44), 17), ((21, 19), 16)];
+
+/// What Minecraft needs, except that Forge before 1.17 only runs on Java 8 itself.
+pub fn requirement(minecraft_version: &str, loader: Option<&str>) -> JavaRequirement {
+    let major = match game_version::kind(minecraft_version) {
+        Kind::Snapshot => snapshot_week(minecraft_version)
+            .and_then(|week| SNAPSHOTS.iter().find(|(first, _)| week >= *first))
+            .map_or(8, |&(_, major)| major),
+        Kind::PreRelease(release) => release_requirement(&release),
+        Kind::Release => release_requirement(minecraft_version),
+    };
+    let newest = match loader {
+        Some("forge") if major == 8 => Some(8),
+        _ => None,
+    };
+    JavaRequirement { major, newest }
+}
+
+fn release_requirement(release: &str) -> u32 {
+    RELEASES.iter()
+        .find(|(first, _)| versions::compare(release, first) != Ordering::Less)
+        .map_or(8, |&(_, major)| major)
+}
+
+/// The year and week of a snapshot such as `24w14a`.
+fn snapshot_week(snapshot: &str) -> Option<(u32, u32)> {
+    let (year, rest) = snapshot.split_once('w')?;
+    Some((year.parse().ok()?, rest.get(..2)?.parse().ok()?))
+}
+
+impl std::fmt::Display for JavaRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "    \"java\" = {{\n        \"major\" = {};", self.major)?;
+        if let Some(newest) = self.newest {
+            writeln!(f, "        \"newest\" = {};", newest)?;
+        }
+        write!(f, "    }};")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_java_from_the_game_version() {
+        let major = |version: &str| requirement(version, None).major;
+        assert_eq!(major("1.12.2"), 8);
+        assert_eq!(major("1.16.5"), 8);
+        assert_eq!(major("1.17.1"), 16);
+        assert_eq!(major("1.18"), 17);
+        assert_eq!(major("1.20.4"), 17);
+        assert_eq!(major("1.20.5"), 21);
+        assert_eq!(major("1.21.1"), 21);
+        assert_eq!(major("1.20.5-pre1"), 21);
+        assert_eq!(major("24w14a"), 21);
+        assert_eq!(major("21w20a"), 16);
+
+        assert_eq!(requirement("1.12.2", Some("forge")), JavaRequirement { major: 8, newest: Some(8) });
+        assert_eq!(requirement("1.16.5", Some("fabric")).newest, None, "Fabric runs on newer Java");
+        assert_eq!(requirement("1.20.1", Some("forge")).newest, None);
+        assert_eq!(requirement("1.12.2", Some("forge")).to_string(),
+                   "    \"java\" = {\n        \"major\" = 8;\n        \"newest\" = 8;\n    };");
+    }
+}
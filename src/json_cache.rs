@@ -0,0 +1,183 @@
+//! A cache kept as one JSON file per entry, for where sqlite can't be used, such as NFS mounts
+//! without working locks. Files are replaced by renaming, so readers never see half an entry.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::database::{Cache, InFlight, Query};
+use crate::wildcard;
+
+pub struct JsonDirCache {
+    dir: PathBuf,
+    // Held around checking an entry and replacing it, so an older copy never overwrites a newer one.
+    lock: Mutex<()>,
+    // Fetches are claimed by URL instead, so different URLs are fetched side by side.
+    in_flight: InFlight,
+}
+
+impl JsonDirCache {
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        log::info!("Using JSON cache directory {:?}", dir);
+        fs::create_dir_all(&dir)
+            .context(format!("While creating {:?}", dir))?;
+        Ok(JsonDirCache { dir, lock: Mutex::new(()), in_flight: InFlight::default() })
+    }
+
+    /// Named by a hash, as URLs don't make for portable filenames.
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{:x}.json", Sha256::digest(url.as_bytes())))
+    }
+
+    fn read(&self, path: &Path) -> Result<Option<Query>> {
+        match fs::read(path) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data).context(format!("While parsing {:?}", path))?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(format!("While reading {:?}", path)),
+        }
+    }
+
+    fn write(&self, query: &Query) -> Result<()> {
+        let path = self.path(query.url());
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_vec(query)?)
+            .context(format!("While writing {:?}", tmp_path))?;
+        fs::rename(&tmp_path, &path)
+            .context(format!("While renaming {:?} to {:?}", tmp_path, path))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).context(format!("While removing {:?}", path)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every entry, with the file it's kept in.
+    fn entries(&self) -> Result<Vec<(PathBuf, Query)>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir).context(format!("While listing {:?}", self.dir))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                if let Some(query) = self.read(&path)? {
+                    entries.push((path, query));
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl Cache for JsonDirCache {
+    fn get_fresh(&self, url: &str, lifetime: &Duration) -> Result<Option<String>> {
+        // Lifetimes reaching past the epoch, such as a huge `cache_ttl`, never expire.
+        let valid_from = SystemTime::now().checked_sub(*lifetime);
+        Ok(self.read(&self.path(url))?
+            .filter(|query| query.url() == url && valid_from.is_none_or(|from| query.downloaded() > from))
+            .map(|query| query.result().to_string()))
+    }
+
+    fn put(&self, url: &str, result: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        self.write(&Query::new(url.to_string(), result.to_string(), SystemTime::now()))
+            .context(format!("Updating cache entry for {}", url))
+    }
+
+    fn put_if_newer(&self, query: &Query) -> Result<bool> {
+        let _guard = self.lock.lock().unwrap();
+        if self.read(&self.path(query.url()))?.is_some_and(|cached| cached.downloaded() >= query.downloaded()) {
+            return Ok(false);
+        }
+        self.write(query)?;
+        Ok(true)
+    }
+
+    fn get_or_put(&self, url: &str, lifetime: &Duration, fetch: Box<dyn FnOnce() -> Result<String> + '_>) -> Result<String> {
+        if let Some(result) = self.get_fresh(url, lifetime)? {
+            return Ok(result);
+        }
+        let _claim = self.in_flight.claim(url);
+        // Whoever held the claim before may have just fetched it.
+        if let Some(result) = self.get_fresh(url, lifetime)? {
+            return Ok(result);
+        }
+        let downloaded = SystemTime::now();
+        let result = fetch()?;
+        self.put_if_newer(&Query::new(url.to_string(), result.clone(), downloaded))?;
+        Ok(result)
+    }
+
+    fn invalidate(&self, url: &str) -> Result<()> {
+        self.remove(&self.path(url))
+            .context(format!("Invalidating cache entry for {}", url))
+    }
+
+    fn invalidate_matching(&self, pattern: &str) -> Result<usize> {
+        let mut removed = 0;
+        for (path, query) in self.entries()? {
+            if wildcard::matches(pattern, query.url()) {
+                self.remove(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn rows(&self) -> Result<Vec<Query>> {
+        Ok(self.entries()?.into_iter().map(|(_, query)| query).collect())
+    }
+
+    fn remove_older_than(&self, retention: Duration) -> Result<usize> {
+        let Some(limit) = SystemTime::now().checked_sub(retention) else { return Ok(0) };
+        let mut removed = 0;
+        for (path, query) in self.entries()? {
+            if query.downloaded() < limit {
+                self.remove(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_entries_as_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = JsonDirCache::open(dir.path().join("json"))?;
+        for url in &["https://api.curseforge.com/v1/mods/1", "https://api.curseforge.com/v1/mods/1/files",
+                     "https://media.forgecdn.net/files/1/2/a.jar"] {
+            cache.get_or_put(url, &Duration::from_secs(60), Box::new(|| Ok(format!("result for {}", url))))?;
+        }
+        let cached = cache.get_or_put("https://api.curseforge.com/v1/mods/1", &Duration::from_secs(60),
+                                      Box::new(|| anyhow::bail!("Should be cached")))?;
+        assert_eq!(cached, "result for https://api.curseforge.com/v1/mods/1");
+        assert_eq!(cache.get_fresh("https://api.curseforge.com/v1/mods/1", &Duration::ZERO)?, None);
+
+        let old = Query::new("https://example.org/old".to_string(), "old".to_string(), SystemTime::UNIX_EPOCH);
+        assert!(cache.put_if_newer(&old)?);
+        assert!(!cache.put_if_newer(&old)?, "Only newer rows replace what's there");
+        assert_eq!(cache.get_fresh("https://example.org/old", &Duration::MAX)?.as_deref(), Some("old"),
+                   "Lifetimes past the epoch never expire");
+        assert_eq!(cache.remove_older_than(Duration::MAX)?, 0);
+        assert_eq!(cache.remove_older_than(Duration::from_secs(86400 * 90))?, 1);
+
+        assert_eq!(cache.invalidate_matching("https://api.curseforge.com/v1/mods/1*")?, 2);
+        assert_eq!(cache.rows()?.len(), 1);
+        assert_eq!(fs::read_dir(dir.path().join("json"))?.count(), 1, "No temporary files are left behind");
+        Ok(())
+    }
+
+    #[test]
+    fn fetches_different_urls_at_once() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        crate::database::tests::check_fetches_in_flight(&JsonDirCache::open(dir.path().join("json"))?)
+    }
+}
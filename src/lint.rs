@@ -0,0 +1,276 @@
+//! Checking a yaml manifest for likely mistakes before anything is resolved, such as a mod listed
+//! twice or a misspelled field, which loading it would silently ignore. How seriously each rule
+//! is taken is set in the `lint` config section.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use schemars::schema::{RootSchema, Schema};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::model::{YamlManifest, YamlMod};
+use crate::policy::Action;
+use crate::slugs;
+use crate::vars;
+use crate::wildcard;
+
+/// Mods that do nothing on a server, so without `side: client` they only bloat server packs, or
+/// crash them.
+const CLIENT_ONLY: &[&str] = &[
+    "betterf3", "continuity", "controlling", "dynamic-fps", "embeddium", "entityculling", "iris",
+    "lambdynamiclights", "mouse-tweaks", "not-enough-animations", "oculus", "ok-zoomer", "optifine",
+    "rubidium", "sodium", "sound-physics-remastered", "zoomify",
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    DuplicateName,
+    IdSlugMismatch,
+    MissingSide,
+    FloatingInRelease,
+    UnknownField,
+}
+
+impl Rule {
+    /// The rule's key in the `lint` config section.
+    fn name(self) -> &'static str {
+        match self {
+            Rule::DuplicateName => "duplicate_name",
+            Rule::IdSlugMismatch => "id_slug_mismatch",
+            Rule::MissingSide => "missing_side",
+            Rule::FloatingInRelease => "floating_in_release",
+            Rule::UnknownField => "unknown_field",
+        }
+    }
+}
+
+/// How strictly each rule is treated, and where it applies.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LintConfig {
+    pub duplicate_name: Action,
+    pub id_slug_mismatch: Action,
+    pub missing_side: Action,
+    pub floating_in_release: Action,
+    pub unknown_field: Action,
+    /// Branches releases are cut from, where every mod should be pinned. `*` matches anything.
+    pub release_branches: Vec<String>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            duplicate_name: Action::Error,
+            id_slug_mismatch: Action::Warn,
+            missing_side: Action::Warn,
+            floating_in_release: Action::Error,
+            unknown_field: Action::Error,
+            release_branches: vec!["release".to_string(), "release/*".to_string(), "release-*".to_string()],
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn action(&self, rule: Rule) -> Action {
+        match rule {
+            Rule::DuplicateName => self.duplicate_name,
+            Rule::IdSlugMismatch => self.id_slug_mismatch,
+            Rule::MissingSide => self.missing_side,
+            Rule::FloatingInRelease => self.floating_in_release,
+            Rule::UnknownField => self.unknown_field,
+        }
+    }
+
+    pub fn is_release_branch(&self, branch: &str) -> bool {
+        self.release_branches.iter().any(|pattern| wildcard::matches(pattern, branch))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn new<T>(rule: Rule, message: T) -> Self where T: Into<String> {
+        Finding { rule, message: message.into() }
+    }
+}
+
+/// The git branch the manifest is checked out on; None outside git or on a detached HEAD.
+pub fn current_branch(manifest_path: &Path) -> Option<String> {
+    let dir = manifest_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    let output = Command::new("git")
+        .arg("-C").arg(dir)
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let branch = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    Some(branch).filter(|b| !b.is_empty() && b != "HEAD")
+}
+
+/// The fields a definition in the manifest schema allows.
+fn known_fields<'a>(schema: &'a RootSchema, definition: Option<&str>) -> Vec<&'a str> {
+    let object = match definition {
+        None => schema.schema.object.as_ref(),
+        Some(name) => match schema.definitions.get(name) {
+            Some(Schema::Object(object)) => object.object.as_ref(),
+            _ => None,
+        },
+    };
+    object.map(|o| o.properties.keys().map(String::as_str).collect()).unwrap_or_default()
+}
+
+fn unknown_fields(value: &Value, known: &[&str], what: &str, findings: &mut Vec<Finding>) {
+    for key in value.as_mapping().into_iter().flat_map(|m| m.iter().map(|(key, _)| key)) {
+        let key = key.as_str().unwrap_or_default();
+        if !known.contains(&key) {
+            findings.push(Finding::new(Rule::UnknownField, format!("{} has field {:?}, which isn't one cursetool knows", what, key)));
+        }
+    }
+}
+
+/// Everything wrong with a manifest file that can be told without looking anything up. Imports
+/// aren't followed; each manifest is linted on its own. `branch` is the git branch it's on.
+pub fn lint(document: &Value, config: &LintConfig, branch: Option<&str>) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    let schema = YamlManifest::schema();
+    unknown_fields(document, &known_fields(&schema, None), "The manifest", &mut findings);
+    let mod_fields = known_fields(&schema, Some("YamlMod"));
+    let file_fields = known_fields(&schema, Some("YamlModFile"));
+    for entry in document.get("mods").and_then(Value::as_sequence).into_iter().flatten() {
+        let name = entry.get("name").and_then(Value::as_str).unwrap_or("(unnamed)");
+        unknown_fields(entry, &mod_fields, name, &mut findings);
+        for file in entry.get("files").and_then(Value::as_sequence).into_iter().flatten() {
+            unknown_fields(file, &file_fields, &format!("A file of {}", name), &mut findings);
+        }
+    }
+
+    let manifest = expand(document)?;
+    let mut seen = HashMap::new();
+    for yaml_mod in &manifest.mods {
+        if let Some(first) = seen.insert(slugs::normalize(&yaml_mod.name), &yaml_mod.name) {
+            let spelled = if first == &yaml_mod.name { String::new() } else { format!(", also as {}", first) };
+            findings.push(Finding::new(Rule::DuplicateName, format!("{} is listed more than once{}", yaml_mod.name, spelled)));
+        }
+    }
+    for yaml_mod in manifest.mods.iter().filter(|m| m.side.is_none() && m.client_id.is_none()) {
+        if CLIENT_ONLY.contains(&slugs::normalize(&yaml_mod.name).as_str()) {
+            findings.push(Finding::new(Rule::MissingSide, format!("{} only runs on the client, but has no `side: client`", yaml_mod.name)));
+        }
+    }
+    if let Some(branch) = branch.filter(|b| config.is_release_branch(b)) {
+        for yaml_mod in manifest.mods.iter().filter(|m| m.is_floating()) {
+            findings.push(Finding::new(Rule::FloatingInRelease,
+                                       format!("{} has no pinned file, but {} is a release branch", yaml_mod.name, branch)));
+        }
+    }
+    Ok(findings)
+}
+
+/// The manifest a document loads as, with its variables expanded. Imports aren't followed.
+pub fn expand(document: &Value) -> Result<YamlManifest> {
+    serde_yaml::from_value(vars::expand_manifest(document.clone())?).context("It isn't a manifest cursetool can load")
+}
+
+/// The mods named for the project their `id` is, which `id_slug_mismatch` can check once each
+/// project's slug is looked up. Split projects are named for their entry, not either project.
+pub fn mods_with_ids(manifest: &YamlManifest) -> Vec<(&YamlMod, u32)> {
+    manifest.mods.iter()
+        .filter(|m| m.client_id.is_none())
+        .filter_map(|m| Some((m, m.id?)))
+        .collect()
+}
+
+/// A finding if the project `id` is, going by its slug, isn't the mod the entry is named for.
+pub fn id_slug_mismatch(yaml_mod: &YamlMod, id: u32, slug: &str) -> Option<Finding> {
+    (!slugs::same(slug, &yaml_mod.name))
+        .then(|| Finding::new(Rule::IdSlugMismatch, format!("{} has id {}, which is {}", yaml_mod.name, id, slug)))
+}
+
+/// Logs every finding by its rule's severity, failing if any is configured as an error.
+pub fn report(findings: Vec<Finding>, config: &LintConfig) -> Result<()> {
+    let mut errors = Vec::new();
+    for finding in findings {
+        let line = format!("{} [{}]", finding.message, finding.rule.name());
+        match config.action(finding.rule) {
+            Action::Ignore => log::debug!("Ignoring {}", line),
+            Action::Warn => log::warn!("{}", line),
+            Action::Error => errors.push(line),
+        }
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("{} problems are configured as errors:\n  {}", errors.len(), errors.join("\n  "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_each_rule() -> Result<()> {
+        let document: Value = serde_yaml::from_str("\
+version: 1.20.1
+loadr: forge
+vars:
+  chests: iron-chests
+mods:
+- name: jei
+  files:
+  - id: 1
+    sha: abc
+- name: JEI
+- name: sodium
+- name: iris
+  side: client
+  files:
+  - id: 2
+- name: waystones
+  sides: both
+- name: ${chests}
+  id: 228756
+  files:
+  - id: 3
+- name: ae2
+  id: 238222
+  files:
+  - id: 4
+")?;
+        let config = LintConfig::default();
+        let findings = lint(&document, &config, Some("release/1.2"))?;
+        let messages: Vec<(Rule, &str)> = findings.iter().map(|f| (f.rule, f.message.as_str())).collect();
+        assert_eq!(messages, vec![
+            (Rule::UnknownField, "The manifest has field \"loadr\", which isn't one cursetool knows"),
+            (Rule::UnknownField, "A file of jei has field \"sha\", which isn't one cursetool knows"),
+            (Rule::UnknownField, "waystones has field \"sides\", which isn't one cursetool knows"),
+            (Rule::DuplicateName, "JEI is listed more than once, also as jei"),
+            (Rule::MissingSide, "sodium only runs on the client, but has no `side: client`"),
+            (Rule::FloatingInRelease, "JEI has no pinned file, but release/1.2 is a release branch"),
+            (Rule::FloatingInRelease, "sodium has no pinned file, but release/1.2 is a release branch"),
+            (Rule::FloatingInRelease, "waystones has no pinned file, but release/1.2 is a release branch"),
+        ]);
+        assert!(report(findings, &config).is_err());
+
+        let slugs: HashMap<u32, &str> = HashMap::from([(228756, "iron-chests"), (238222, "jei")]);
+        let manifest = expand(&document)?;
+        let mismatched: Vec<Finding> = mods_with_ids(&manifest).into_iter()
+            .filter_map(|(yaml_mod, id)| id_slug_mismatch(yaml_mod, id, slugs[&id]))
+            .collect();
+        assert_eq!(mismatched, vec![Finding::new(Rule::IdSlugMismatch, "ae2 has id 238222, which is jei")],
+                   "Names are compared with their variables expanded");
+
+        let findings = lint(&document, &config, Some("main"))?;
+        assert!(findings.iter().all(|f| f.rule != Rule::FloatingInRelease), "Floating is fine outside releases");
+        let lenient: LintConfig = serde_yaml::from_str("unknown_field: ignore\nduplicate_name: warn\n")?;
+        assert!(report(findings, &lenient).is_ok(), "Every rule can be turned down");
+        Ok(())
+    }
+}
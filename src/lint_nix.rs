@@ -0,0 +1,124 @@
+//! Checking a generated nix manifest for the mistakes hand edits make, such as a broken URL or
+//! a hash pasted into the wrong attribute. Duplicate slugs are already refused by `nix::parse`.
+
+use reqwest::Url;
+use serde_json::{Map, Value};
+
+use crate::hashes::HashAlgorithm;
+
+const SIDES: &[&str] = &["both", "client", "server"];
+const ALGORITHMS: &[HashAlgorithm] = &[HashAlgorithm::Md5, HashAlgorithm::Sha256, HashAlgorithm::Sha512, HashAlgorithm::Blake3];
+
+/// How long a digest is in hex, and in Nix's base32 where `--base32-sha256` writes that.
+fn digest_lengths(algorithm: HashAlgorithm) -> &'static [usize] {
+    match algorithm {
+        HashAlgorithm::Md5 => &[32],
+        HashAlgorithm::Sha256 => &[64, 52],
+        HashAlgorithm::Sha512 => &[128],
+        HashAlgorithm::Blake3 => &[64],
+    }
+}
+
+fn is_digest(hash: &str, algorithm: HashAlgorithm) -> bool {
+    let alphabet = if hash.len() == 52 { "0123456789abcdfghijklmnpqrsvwxyz" } else { "0123456789abcdef" };
+    digest_lengths(algorithm).contains(&hash.len()) && hash.chars().all(|c| alphabet.contains(c))
+}
+
+fn is_download_url(src: &str) -> bool {
+    Url::parse(src).is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+}
+
+/// Every problem with a manifest parsed by `nix::parse`, one line each.
+pub fn lint(manifest: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    if !manifest["version"].is_string() {
+        problems.push("The manifest has no version".to_string());
+    }
+    let mods = match manifest["mods"].as_object() {
+        Some(mods) => mods,
+        None => {
+            problems.push("The manifest has no mods attribute set".to_string());
+            return problems;
+        }
+    };
+    for (slug, entry) in mods {
+        match entry.as_object() {
+            Some(entry) => lint_mod(slug, entry, mods, &mut problems),
+            None => problems.push(format!("{} isn't an attribute set", slug)),
+        }
+    }
+    problems
+}
+
+fn lint_mod(slug: &str, entry: &Map<String, Value>, mods: &Map<String, Value>, problems: &mut Vec<String>) {
+    for field in &["title", "filename", "encoded", "src"] {
+        if entry.get(*field).and_then(Value::as_str).is_none_or(str::is_empty) {
+            problems.push(format!("{} has no {}", slug, field));
+        }
+    }
+    if entry.get("name").and_then(Value::as_str) != Some(slug) {
+        problems.push(format!("{} has name {}, not its own slug", slug, entry.get("name").unwrap_or(&Value::Null)));
+    }
+    if entry.get("size").and_then(Value::as_u64).is_none_or(|size| size == 0) {
+        problems.push(format!("{} has no size", slug));
+    }
+    match entry.get("side").and_then(Value::as_str) {
+        Some(side) if SIDES.contains(&side) => (),
+        side => problems.push(format!("{} has side {}, expected one of {}", slug, side.unwrap_or("(none)"), SIDES.join(", "))),
+    }
+    if let Some(src) = entry.get("src").and_then(Value::as_str) {
+        if !src.is_empty() && !is_download_url(src) {
+            problems.push(format!("{} has src {:?}, which isn't an http(s) URL", slug, src));
+        }
+    }
+    let mut hashed = false;
+    for &algorithm in ALGORITHMS {
+        match entry.get(algorithm.name()) {
+            None => (),
+            Some(Value::String(hash)) if is_digest(hash, algorithm) => hashed = true,
+            Some(hash) => problems.push(format!("{} has {} {}, which isn't a {} digest", slug, algorithm, hash, algorithm)),
+        }
+    }
+    if !hashed {
+        problems.push(format!("{} has no hashes", slug));
+    }
+    for dep in entry.get("deps").and_then(Value::as_array).into_iter().flatten() {
+        if !dep.as_str().is_some_and(|dep| mods.contains_key(dep)) {
+            problems.push(format!("{} depends on {}, which isn't in the manifest", slug, dep));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::NixMod;
+    use crate::nix;
+
+    #[test]
+    fn reports_broken_entries() -> anyhow::Result<()> {
+        let jei = NixMod::new("jei", "JEI", 238222)
+            .file("jei.jar", "https://media.forgecdn.net/files/3043/174/jei.jar", 1234)
+            .hash(HashAlgorithm::Md5, "900150983cd24fb0d6963f7d28e17f72");
+        let render = |mods: &str| format!("{{\n    \"version\" = \"1.12.2\";\n    \"mods\" = {{\n{}\n    }};\n}}", mods);
+        assert!(lint(&nix::parse(&render(&jei.to_string()))?).is_empty());
+
+        let broken = NixMod {
+            side: crate::model::Side::Client,
+            deps: vec!["gone".to_string()],
+            ..NixMod::new("broken", "Broken", 1)
+                .file("broken.jar", "media.forgecdn.net/broken.jar", 0)
+                .hash(HashAlgorithm::Sha256, "abc")
+        }.to_string().replace("\"client\"", "\"clients\"");
+        let problems = lint(&nix::parse(&render(&broken))?);
+        assert_eq!(problems, vec![
+            "broken has no size",
+            "broken has side clients, expected one of both, client, server",
+            "broken has src \"media.forgecdn.net/broken.jar\", which isn't an http(s) URL",
+            "broken has sha256 \"abc\", which isn't a sha256 digest",
+            "broken has no hashes",
+            "broken depends on \"gone\", which isn't in the manifest",
+        ]);
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::hashes::HashAlgorithm;
+use crate::nix;
+
+/// The installer for the pack's mod loader, as fetched from the loader's maven.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoaderInfo {
+    pub name: String,
+    pub version: String,
+    pub src: String,
+    pub size: u64,
+    #[serde(flatten)]
+    pub hashes: BTreeMap<HashAlgorithm, String>,
+}
+
+/// Splits a loader such as `forge-14.23.5.2860` into its name and version.
+pub fn parse_loader(loader: &str) -> Result<(String, String)> {
+    let (name, version) = loader.split_once('-')
+        .context(format!("Loader {} has no version, expected e.g. forge-14.23.5.2860", loader))?;
+    Ok((name.to_lowercase(), version.to_string()))
+}
+
+pub fn installer_url(minecraft_version: &str, name: &str, version: &str) -> Result<String> {
+    match name {
+        "forge" => Ok(format!(
+            "https://maven.minecraftforge.net/net/minecraftforge/forge/{mc}-{v}/forge-{mc}-{v}-installer.jar",
+            mc = minecraft_version, v = version)),
+        // NeoForge versions already encode the Minecraft version.
+        "neoforge" => Ok(format!(
+            "https://maven.neoforged.net/releases/net/neoforged/neoforge/{v}/neoforge-{v}-installer.jar",
+            v = version)),
+        other => anyhow::bail!("Don't know where to find installers for loader {}", other),
+    }
+}
+
+impl std::fmt::Display for LoaderInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f,
+r#"    "loader" = {{
+        "name" = {name};
+        "version" = {version};
+        "src" = {src};
+{hashes}
+        "size" = {size};
+    }};"#,
+            name = nix::string(&self.name),
+            version = nix::string(&self.version),
+            src = nix::string(&self.src),
+            hashes = nix::hash_lines(&self.hashes),
+            size = self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_installer_urls() -> Result<()> {
+        let (name, version) = parse_loader("Forge-14.23.5.2860")?;
+        assert_eq!(installer_url("1.12.2", &name, &version)?,
+                   "https://maven.minecraftforge.net/net/minecraftforge/forge/1.12.2-14.23.5.2860/forge-1.12.2-14.23.5.2860-installer.jar");
+        let (name, version) = parse_loader("neoforge-20.4.237")?;
+        assert_eq!(installer_url("1.20.4", &name, &version)?,
+                   "https://maven.neoforged.net/releases/net/neoforged/neoforge/20.4.237/neoforge-20.4.237-installer.jar");
+        assert!(parse_loader("forge").is_err(), "Versionless loaders can't be resolved");
+        assert!(installer_url("1.20.1", "fabric", "0.15.0").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::hashes::HashAlgorithm;
+use crate::loader::LoaderInfo;
+use crate::model::{CurseModFileInfo, NixMod, YamlManifest, YamlMod};
+use crate::naming::{self, FilenameRule};
+use crate::nix;
+use crate::output::AtomicFile;
+
+/// The fully resolved state of a manifest, in machine-readable form.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Lockfile {
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loader: Option<LoaderInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inputs: Option<LockInputs>,
+    pub mods: Vec<NixMod>,
+}
+
+/// What a lock was resolved from, so the next run can tell which mods need resolving again.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LockInputs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loader: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_version_type_id: Option<u32>,
+    pub hashes: Vec<HashAlgorithm>,
+    /// Whether mods were resolved with `--rich-metadata`, which records more about each.
+    #[serde(default)]
+    pub rich_metadata: bool,
+    /// The `filename_rules` files were named by. Locks from before they were recorded used the default.
+    #[serde(default = "naming::default_filename_rules")]
+    pub filename_rules: Vec<FilenameRule>,
+    /// Each mod's manifest entry, by name.
+    pub mods: BTreeMap<String, serde_json::Value>,
+}
+
+impl LockInputs {
+    pub fn new(yaml_manifest: &YamlManifest, hashes: &[HashAlgorithm]) -> Result<Self> {
+        Ok(LockInputs {
+            loader: yaml_manifest.loader.clone(),
+            game_version_type_id: yaml_manifest.game_version_type_id,
+            hashes: hashes.to_vec(),
+            rich_metadata: false,
+            filename_rules: naming::default_filename_rules(),
+            // Holding a mod back keeps the file it has, so it mustn't count as a change.
+            mods: yaml_manifest.mods.iter()
+                .map(|m| Ok((m.name.clone(), serde_json::to_value(YamlMod { hold: None, ..m.clone() })?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    fn same_target(&self, other: &LockInputs) -> bool {
+        self.loader == other.loader && self.game_version_type_id == other.game_version_type_id && self.hashes == other.hashes
+            && self.rich_metadata == other.rich_metadata && self.filename_rules == other.filename_rules
+    }
+}
+
+impl Lockfile {
+    /// Reads the lock an earlier run wrote, if there is one.
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let source = std::fs::read_to_string(path)
+            .context(format!("While reading {:?}", path))?;
+        Ok(Some(serde_json::from_str(&source).context(format!("While parsing {:?}", path))?))
+    }
+
+    /// The mods whose manifest entries are the same as when this lock was written, for the same
+    /// game version, loader, hashes and filename rules. Locks from before inputs were recorded
+    /// carry nothing.
+    pub fn unchanged_mods(self, version: &str, inputs: &LockInputs) -> BTreeMap<String, NixMod> {
+        let previous = match self.inputs {
+            Some(previous) if self.version == version && previous.same_target(inputs) => previous,
+            _ => return BTreeMap::new(),
+        };
+        self.mods.into_iter()
+            .filter(|m| previous.mods.get(&m.slug).is_some_and(|entry| inputs.mods.get(&m.slug) == Some(entry)))
+            .map(|m| (m.slug.clone(), m))
+            .collect()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut file = AtomicFile::create(path)?;
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.commit()
+    }
+
+    /// The resolved mods that got an older file of the same project than this lock has, as when
+    /// an author deletes their newest file. File IDs only go up, so a lower one is an older upload.
+    pub fn downgrades<'a>(&self, version: &str, resolved: impl IntoIterator<Item = &'a NixMod>) -> Vec<Downgrade> {
+        if self.version != version {
+            return Vec::new();
+        }
+        resolved.into_iter().filter_map(|nix_mod| {
+            let locked = self.mods.iter().find(|m| m.slug == nix_mod.slug && m.id == nix_mod.id)?;
+            (nix_mod.file_id != 0 && nix_mod.file_id < locked.file_id).then(|| Downgrade {
+                name: nix_mod.slug.clone(),
+                locked_file_id: locked.file_id,
+                locked_filename: locked.filename.clone(),
+                resolved_file_id: nix_mod.file_id,
+                resolved_filename: nix_mod.filename.clone(),
+            })
+        }).collect()
+    }
+}
+
+/// A mod that resolved to an older file than the lock has.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Downgrade {
+    pub name: String,
+    pub locked_file_id: u32,
+    pub locked_filename: String,
+    pub resolved_file_id: u32,
+    pub resolved_filename: String,
+}
+
+impl std::fmt::Display for Downgrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {} ({}) -> {} ({})", self.name, self.locked_filename, self.locked_file_id,
+               self.resolved_filename, self.resolved_file_id)
+    }
+}
+
+/// What an earlier run recorded about each file, by download URL, so `--verify-existing` can
+/// skip downloading files whose pins haven't changed.
+///
+/// Reads a lockfile if the path ends in `.json`, and a nix manifest otherwise. Nix manifests
+/// don't record embedded jars or mod metadata, so those come back empty.
+pub fn recorded_files(path: &Path) -> Result<HashMap<String, CurseModFileInfo>> {
+    if !path.exists() {
+        log::info!("{:?} doesn't exist yet, so every file will be downloaded", path);
+        return Ok(HashMap::new());
+    }
+    let source = std::fs::read_to_string(path)
+        .context(format!("While reading {:?}", path))?;
+    if path.extension().is_some_and(|e| e == "json") {
+        let lockfile: Lockfile = serde_json::from_str(&source)
+            .context(format!("While parsing {:?}", path))?;
+        return Ok(lockfile.mods.into_iter().map(|m| (m.src.clone(), CurseModFileInfo {
+            hashes: m.hashes,
+            size: m.size,
+            download_url: m.src,
+            embedded_jars: m.embedded_jars,
+            metadata: m.metadata,
+            etag: None,
+        })).collect());
+    }
+    let manifest = nix::parse(&source)
+        .context(format!("While parsing {:?}", path))?;
+    let mods = manifest["mods"].as_object().context(format!("{:?} has no mods attribute set", path))?;
+    let mut recorded = HashMap::new();
+    for (name, entry) in mods {
+        let (src, size) = match (entry["src"].as_str(), entry["size"].as_u64()) {
+            (Some(src), Some(size)) => (src, size),
+            _ => {
+                log::warn!("Ignoring recorded entry for {}, as it lacks a src or size", name);
+                continue;
+            }
+        };
+        let hashes = entry.as_object().into_iter().flatten()
+            .filter_map(|(key, value)| Some((key.parse::<HashAlgorithm>().ok()?, value.as_str()?.to_string())))
+            .collect();
+        recorded.insert(src.to_string(), CurseModFileInfo {
+            hashes,
+            size,
+            download_url: src.to_string(),
+            embedded_jars: Vec::new(),
+            metadata: Default::default(),
+            etag: None,
+        });
+    }
+    Ok(recorded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carries_forward_only_unchanged_entries() -> Result<()> {
+        let manifest = |source: &str| -> Result<YamlManifest> { Ok(serde_yaml::from_str(source)?) };
+        let before = manifest("version: 1.12.2\nmods:\n- name: jei\n  files:\n  - id: 1\n- name: ae2\n  files:\n  - id: 2\n")?;
+        let lock = || -> Result<Lockfile> {
+            Ok(Lockfile {
+                version: "1.12.2".to_string(),
+                loader: None,
+                inputs: Some(LockInputs::new(&before, &[HashAlgorithm::Sha256])?),
+                mods: vec![NixMod::new("jei", "JEI", 238222), NixMod::new("ae2", "AE2", 223794)],
+            })
+        };
+        let after = manifest("version: 1.12.2\nmods:\n- name: jei\n  files:\n  - id: 1\n- name: ae2\n  files:\n  - id: 3\n")?;
+        let unchanged = lock()?.unchanged_mods("1.12.2", &LockInputs::new(&after, &[HashAlgorithm::Sha256])?);
+        assert_eq!(unchanged.keys().collect::<Vec<_>>(), vec!["jei"]);
+        assert!(lock()?.unchanged_mods("1.12.2", &LockInputs::new(&after, &[HashAlgorithm::Md5])?).is_empty(),
+                "Different hashes need every file downloaded again");
+        assert!(lock()?.unchanged_mods("1.16.5", &LockInputs::new(&after, &[HashAlgorithm::Sha256])?).is_empty());
+        let renamed = LockInputs { filename_rules: vec![], ..LockInputs::new(&after, &[HashAlgorithm::Sha256])? };
+        assert!(lock()?.unchanged_mods("1.12.2", &renamed).is_empty(), "Different filename rules name every file anew");
+        let held = manifest("version: 1.12.2\nmods:\n- name: jei\n  hold: true\n  files:\n  - id: 1\n")?;
+        assert!(held.mods[0].is_held());
+        assert_eq!(lock()?.unchanged_mods("1.12.2", &LockInputs::new(&held, &[HashAlgorithm::Sha256])?).keys().collect::<Vec<_>>(),
+                   vec!["jei"], "Holding a mod back keeps its file");
+        Ok(())
+    }
+
+    #[test]
+    fn finds_mods_resolved_to_older_files() {
+        let file = |slug: &str, id: u32, file_id: u32| NixMod {
+            file_id,
+            ..NixMod::new(slug, slug, id).file(&format!("{}-{}.jar", slug, file_id), "https://example.org/a.jar", 1)
+        };
+        let lock = Lockfile {
+            version: "1.20.1".to_string(),
+            loader: None,
+            inputs: None,
+            mods: vec![file("jei", 238222, 5000), file("ae2", 223794, 4000), file("forked", 1, 9000)],
+        };
+        let resolved = vec![file("jei", 238222, 4900), file("ae2", 223794, 4100), file("forked", 2, 10)];
+        let downgrades = lock.downgrades("1.20.1", &resolved);
+        assert_eq!(downgrades.len(), 1, "Newer files and other projects aren't downgrades");
+        assert_eq!(downgrades[0].to_string(), "jei: jei-5000.jar (5000) -> jei-4900.jar (4900)");
+        assert!(lock.downgrades("1.19.2", &resolved).is_empty(), "Other versions have other files");
+    }
+
+    #[test]
+    fn reads_recorded_hashes_from_nix() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("out.nix");
+        std::fs::write(&path, r#"{
+  "version" = "1.12.2";
+  "mods" = {
+    jei = {
+        "title" = "Just Enough Items (JEI)";
+        "name" = "jei";
+        "src" = "https://media.forgecdn.net/files/3043/174/jei_1.12.2-4.16.1.302.jar";
+        "size" = 1234;
+        "md5" = "d41d8cd98f00b204e9800998ecf8427e";
+        "sha256" = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    };
+    broken = {
+        "title" = "No file";
+    };
+  };
+}
+"#)?;
+        let recorded = recorded_files(&path)?;
+        assert_eq!(recorded.len(), 1, "Entries without a file should be skipped");
+        let jei = &recorded["https://media.forgecdn.net/files/3043/174/jei_1.12.2-4.16.1.302.jar"];
+        assert_eq!(jei.size, 1234);
+        assert_eq!(jei.hashes.keys().collect::<Vec<_>>(), vec![&HashAlgorithm::Md5, &HashAlgorithm::Sha256]);
+        assert!(recorded_files(&dir.path().join("missing.nix"))?.is_empty());
+        Ok(())
+    }
+}
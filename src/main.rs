@@ -1,28 +1,101 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use console::style;
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::LevelFilter;
 use rayon::prelude::*;
 
-
-use simplelog::*;
-
+use curse_api::*;
 use model::*;
 use options::Mode;
 
-use crate::database::Database;
+use crate::budget::DownloadBudget;
+use crate::config::Config;
+use crate::database::Cache;
+use crate::deadline::Deadline;
 use crate::downloader::Downloader;
-use crate::options::{Commandline, parse_commandline};
+use crate::game_version::{NoFilesForVersion, VersionFilter};
+use crate::hashes::HashAlgorithm;
+use crate::java::JavaRequirement;
+use crate::lint::{Finding, LintConfig};
+use crate::loader::LoaderInfo;
+use crate::lockfile::{Downgrade, LockInputs, Lockfile};
+use crate::mirror::Mirror;
+use crate::naming::CompiledRules;
+use crate::policy::{Action, ErrorClass, PolicyEnforcer};
+use crate::metrics::Metrics;
+use crate::progress::ProgressObserver;
+use crate::provenance::{EntryProvenance, Provenance};
+use crate::output::AtomicFile;
+use crate::options::{CacheCommand, Commandline, ExportCommand, YamlOptions, parse_commandline};
+use crate::remove::ModDependencies;
+use crate::status::StatusFile;
+use crate::summary::Summary;
 
+mod allowlist;
+mod api_keys;
+mod budget;
+mod cache_archive;
+mod cfwidget;
+mod changelog;
+mod config;
+mod curse_api;
 mod database;
+mod deadline;
+mod editing;
+mod faults;
+mod fingerprint;
+mod formatting;
 
 mod options;
+mod output;
+mod outdated;
 mod model;
 mod downloader;
+mod game_version;
+mod hashes;
+mod import;
+mod interrupt;
+mod inventory;
+mod json_cache;
+mod jarjar;
+mod lint;
+mod lint_nix;
+mod java;
+mod loader;
+mod lockfile;
+mod metrics;
+mod mirror;
+mod modmeta;
+mod modrinth;
+mod naming;
+mod nix;
+mod pinning;
+mod policy;
+mod progress;
+mod provenance;
+mod quota;
+mod remove;
+mod server_check;
+mod slugs;
+mod server_import;
+mod status;
+mod summary;
+mod throttle;
+mod trace;
+mod units;
+mod vars;
+mod versions;
+mod wildcard;
+
 
+const KNOWN_LOADERS: &[&str] = &["forge", "neoforge", "fabric", "quilt"];
 
 fn print_phase<T>(current: u32, total: u32, phase: T) where T: AsRef<str> {
     println!(
@@ -32,114 +105,1127 @@ fn print_phase<T>(current: u32, total: u32, phase: T) where T: AsRef<str> {
     );
 }
 
+/// The entries installed on one side, without deps on entries only installed on the other, such
+/// as the other half of a split project.
+fn side_entries(entries: &[NixMod], on_side: fn(&Side) -> bool) -> Vec<NixMod> {
+    let elsewhere: HashSet<&str> = entries.iter().filter(|m| !on_side(&m.side)).map(|m| m.slug.as_str()).collect();
+    entries.iter()
+        .filter(|m| on_side(&m.side))
+        .map(|m| NixMod { deps: m.deps.iter().filter(|d| !elsewhere.contains(d.as_str())).cloned().collect(), ..m.clone() })
+        .collect()
+}
+
+fn write_nix_manifest<'a, I>(nix_manifest_path: &Path, provenance: Option<&Provenance>, version: &str,
+                            java: Option<&JavaRequirement>, loader: Option<&LoaderInfo>, mods: I) -> Result<()>
+    where I: Iterator<Item = &'a NixMod> {
+    // The Java requirement goes with the loader, as both are about running the server.
+    let formatted_loader = java.map(|j| format!("{}\n", j)).into_iter()
+        .chain(loader.map(|l| format!("{}\n", l)))
+        .collect::<String>();
+    let formatted_provenance = provenance.map(|p| p.to_string()).unwrap_or_default();
+    let mut out = AtomicFile::create(nix_manifest_path)?;
+    // Grouped mods go after the ungrouped ones, each group under a comment saying which it is.
+    let mut mods: Vec<&NixMod> = mods.collect();
+    mods.sort_by(|a, b| a.group.cmp(&b.group));
+    let mut mods = mods.into_iter().peekable();
+    if mods.peek().is_none() {
+        write!(out, "{provenance}{{\n    \"version\" = {version};\n    \"imports\" = [];\n{loader}    \"mods\" = {{}};\n}}",
+               provenance = formatted_provenance, version = nix::string(version), loader = formatted_loader)?;
+        return out.commit();
+    }
+    write!(out, r#"{provenance}{{
+    "version" = {version};
+    "imports" = [];
+{loader}    "mods" = {{
+    "#, provenance = formatted_provenance, version = nix::string(version), loader = formatted_loader)?;
+    // Written one at a time, as huge packs would otherwise need the whole file in memory at once.
+    let mut group = None;
+    for (i, nix_mod) in mods.enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        if nix_mod.group.is_some() && nix_mod.group != group {
+            group = nix_mod.group.clone();
+            let header = group.as_deref().unwrap_or_default().replace('\n', " ");
+            if i > 0 {
+                writeln!(out, "\n    # {}", header)?;
+            } else {
+                write!(out, "# {}\n    ", header)?;
+            }
+        }
+        write!(out, "{}", nix_mod)?;
+    }
+    write!(out, "\n    }};\n}}")?;
+    out.commit()
+}
+
+/// Where `--versions` puts the output for one version, e.g. `mods-1.20.1.nix` for `mods.nix`.
+fn versioned_path(path: &Path, version: &str) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, version, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, version),
+    };
+    path.with_file_name(file_name)
+}
+
 // All those 'apps littered everywhere are there to tell Rust that all of these structs live as
 // long as the app does, i.e. until the end of main.
 struct App<'app> {
     commandline: &'app Commandline,
     downloader: &'app Downloader<'app>,
-    _database: &'app Database,
+    database: &'app dyn Cache,
+    status: StatusFile,
+    policy: PolicyEnforcer,
+    pool: rayon::ThreadPool,
+    download_pool: rayon::ThreadPool,
+    // With --deadline, mods left out of the outputs and why.
+    unfinished: Mutex<Vec<(String, String)>>,
+    // Told about every mod, besides the status file.
+    observer: Option<&'app dyn ProgressObserver>,
+    // For --metrics-file.
+    metrics: Metrics,
+    // For --max-download-bytes.
+    budget: DownloadBudget,
+    // The `lint` config section.
+    lint: LintConfig,
+    // The `filename_rules` config section.
+    filename_rules: CompiledRules,
+}
+
+/// A mod whose file is known, but not yet downloaded.
+struct ResolvedMod {
+    yaml_mod: YamlMod,
+    // None for direct-URL mods.
+    curse: Option<CurseResolution>,
+}
+
+struct CurseResolution {
+    project_id: u32,
+    mod_file: CurseModFile,
+    addon_info: AddonInfo,
+}
+
+/// What an earlier run left behind to spare `resolve_manifest` work, and what to change about the manifest.
+#[derive(Default)]
+struct Resolution<'a> {
+    // From --verify-existing, by normalized download URL.
+    recorded: Option<&'a HashMap<String, CurseModFileInfo>>,
+    // The last lock written, to carry unchanged mods forward from and check for downgrades against.
+    previous: Option<Lockfile>,
+    // From --full, resolving every mod again rather than carrying any forward.
+    full: bool,
+    // From --allow-downgrade.
+    allow_downgrade: bool,
+    // From --versions, the game version to resolve for instead of the manifest's.
+    version: Option<&'a str>,
+    // From --rich-metadata.
+    rich_metadata: bool,
+}
+
+/// What a manifest is being resolved for.
+struct Target<'a> {
+    version: &'a VersionFilter,
+    loader: Option<String>,
+    hashes: &'a [HashAlgorithm],
+    // From --verify-existing, by normalized download URL.
+    recorded: Option<&'a HashMap<String, CurseModFileInfo>>,
+    // Whether to leave out mods that fail instead of failing the run, like --deadline does.
+    keep_going: bool,
+    // Whether to record project icons, for --rich-metadata.
+    rich_metadata: bool,
+}
+
+impl Target<'_> {
+    /// What an earlier output recorded for a file, if it has every hash asked for.
+    fn recorded_file_info(&self, download_url: &str) -> Result<Option<CurseModFileInfo>> {
+        let recorded = match self.recorded {
+            Some(recorded) => recorded,
+            None => return Ok(None),
+        };
+        Ok(recorded.get(&naming::normalize_download_url(download_url)?)
+            .filter(|info| self.hashes.iter().all(|a| info.hashes.contains_key(a)))
+            .cloned()
+            .inspect(|_| log::debug!("Reusing recorded hashes for {}", download_url)))
+    }
 }
 
 impl<'app> App<'app> {
-    fn new(commandline: &'app Commandline, database: &'app Database, downloader: &'app Downloader<'app>) -> Self {
-        App { commandline, _database: database, downloader }
+    fn new(commandline: &'app Commandline, config: &'app Config, database: &'app dyn Cache, downloader: &'app Downloader<'app>,
+           observer: Option<&'app dyn ProgressObserver>) -> Result<Self> {
+        let status = StatusFile::new(commandline.status_file.clone());
+        let metrics = Metrics::new(commandline.metrics_file.clone());
+        // A dedicated pool, so that --threads only limits our own work.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(commandline.threads.unwrap_or(0))
+            .build()
+            .context("While creating thread pool")?;
+        let download_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(commandline.download_threads.unwrap_or(2))
+            .build()
+            .context("While creating download thread pool")?;
+        let policy = PolicyEnforcer::new(config.policy.clone());
+        let budget = DownloadBudget::new(commandline.max_download_bytes.map(|bytes| bytes.0));
+        let filename_rules = CompiledRules::new(&config.filename_rules)?;
+        Ok(App { commandline, database, downloader, status, policy, pool, download_pool, unfinished: Mutex::new(Vec::new()), observer, metrics,
+                 budget, lint: config.lint.clone(), filename_rules })
     }
 
     fn main(&self) -> Result<()> {
-        match self.commandline.mode {
-            Mode::Yaml => self.generate_nix_from_yaml(&self.commandline.input_file, &self.commandline.output_file)
+        let result = self.pool.install(|| self.run_mode());
+        // Failed runs cost quota too.
+        let usage = self.downloader.usage();
+        quota::log_usage(&usage);
+        quota::log_key_usage(&self.downloader.key_usage());
+        quota::log_handshakes(&self.downloader.handshakes());
+        self.status.set_api_usage(usage.clone());
+        let unfinished = self.unfinished.lock().unwrap();
+        self.metrics.write(result.is_ok() && unfinished.is_empty(), usage);
+        result?;
+        if interrupt::interrupted() {
+            log::warn!("Interrupted; what resolved was written out, and the next run carries pinned mods forward from the lockfile");
+        }
+        if !unfinished.is_empty() {
+            let reasons: Vec<String> = unfinished.iter().map(|(name, reason)| format!("{}: {}", name, reason)).collect();
+            anyhow::bail!("{} mods were left out of the output:\n  {}", unfinished.len(), reasons.join("\n  "));
+        }
+        self.status.finish();
+        Ok(())
+    }
+
+    fn run_mode(&self) -> Result<()> {
+        match &self.commandline.mode {
+            Mode::Yaml(options) => self.generate_nix_from_yaml(options)
                 .context("While generating nix from yaml")?,
-            Mode::Curse => self.generate_yaml_from_curse(&self.commandline.input_file, &self.commandline.output_file)
-                .context("While generating yaml from curse")?
+            Mode::Curse { input_file, output_file } =>
+                self.generate_yaml_from_curse(input_file, &self.output_path(output_file.as_deref(), "manifest.yaml")?)
+                    .context("While generating yaml from curse")?,
+            Mode::ExportCurse { input_file, output_file } =>
+                self.generate_curse_from_yaml(input_file, &self.output_path(output_file.as_deref(), "manifest.json")?)
+                    .context("While generating curse from yaml")?,
+            Mode::Export(ExportCommand::Csv { input_file, output_file }) =>
+                self.export_csv(input_file, &self.output_path(output_file.as_deref(), "modlist.csv")?)
+                    .context("While exporting csv")?,
+            Mode::Export(ExportCommand::Fetchlist { input_file, output_file, json }) => {
+                let default_name = if *json { "fetchlist.json" } else { "fetchlist.txt" };
+                self.export_fetchlist(input_file, &self.output_path(output_file.as_deref(), default_name)?, *json)
+                    .context("While exporting fetch list")?
+            }
+            Mode::Cache(CacheCommand::Refresh { manifest, .. }) => self.refresh_cache(manifest)
+                .context("While refreshing cache")?,
+            Mode::Cache(CacheCommand::Export { archive, for_manifest }) => self.export_cache(archive, for_manifest.as_deref())
+                .context("While exporting cache")?,
+            Mode::Cache(CacheCommand::Invalidate { pattern }) => {
+                let removed = self.database.invalidate_matching(pattern)?;
+                log::info!("Removed {} cache entries matching {}", removed, pattern);
+            }
+            Mode::Cache(CacheCommand::RevalidateFiles { sample }) => self.revalidate_files(*sample)
+                .context("While revalidating cached files")?,
+            Mode::Cache(CacheCommand::Import { archive }) => {
+                let imported = cache_archive::import(self.database, archive)
+                    .context(format!("While importing cache from {:?}", archive))?;
+                log::info!("Imported {} cache entries", imported);
+            }
+            Mode::Remove { manifest, name, remove_orphans } => self.remove_mod(manifest, name, *remove_orphans)
+                .context(format!("While removing {}", name))?,
+            Mode::CheckServer { mods_dir, manifest } => self.check_server(mods_dir, manifest)
+                .context("While checking server")?,
+            Mode::Lint { manifest, branch } => self.lint_manifest(manifest, branch.as_deref())
+                .context(format!("While linting {:?}", manifest))?,
+            Mode::LintNix { manifest } => lint_nix_manifest(manifest)
+                .context(format!("While linting {:?}", manifest))?,
+            Mode::ImportServer { source, output_file, game_version } =>
+                self.import_server(source, output_file, game_version.as_deref())
+                    .context(format!("While importing mods from {}", source))?,
+            Mode::Outdated { manifest } => self.list_outdated(manifest)
+                .context("While looking for updates")?,
+            Mode::Pin { manifest } => self.pin_mods(manifest)
+                .context(format!("While pinning mods in {:?}", manifest))?,
+            Mode::Unpin { manifest, names } => self.unpin_mods(manifest, names)
+                .context(format!("While unpinning mods in {:?}", manifest))?,
+            Mode::WhyFailed { manifest, name } => self.why_failed(manifest, name)
+                .context(format!("While resolving {} again", name))?,
+            Mode::Changelog { from, to, manifest } => self.print_changelog(manifest, from, to.as_deref())
+                .context("While writing changelog")?,
+            Mode::Fmt { manifest, check } => self.format_manifest(manifest, *check)
+                .context(format!("While formatting {:?}", manifest))?,
+            Mode::Schema => println!("{}", serde_json::to_string_pretty(&YamlManifest::schema())?),
+            Mode::ImportList { input_file, output_file, game_version } =>
+                self.import_list(input_file, output_file, game_version.as_deref())
+                    .context("While importing mod list")?,
+        }
+        Ok(())
+    }
+
+    /// The output given on the command line, or the conventional name in `--output-dir`.
+    fn output_path(&self, explicit: Option<&Path>, conventional: &str) -> Result<PathBuf> {
+        match (explicit, &self.commandline.output_dir) {
+            (Some(path), _) => Ok(path.to_path_buf()),
+            (None, Some(dir)) => {
+                std::fs::create_dir_all(dir).context(format!("While creating {:?}", dir))?;
+                Ok(dir.join(conventional))
+            }
+            (None, None) => anyhow::bail!("No output file given; name one, or pass --output-dir"),
+        }
+    }
+
+    /// Outputs only written into `--output-dir`, as they have no option of their own.
+    fn conventional_output(&self, conventional: &str) -> Option<PathBuf> {
+        self.commandline.output_dir.as_ref().map(|dir| dir.join(conventional))
+    }
+
+    fn print_phase<T>(&self, current: u32, total: u32, phase: T) where T: AsRef<str> {
+        print_phase(current, total, &phase);
+        self.status.set_phase(phase);
+    }
+
+    fn generate_nix_from_yaml(&self, options: &YamlOptions) -> Result<()> {
+        if options.fetch_list.is_some() && options.src_prefix.is_none() {
+            anyhow::bail!("--fetch-list lists what to put in the --src-prefix mirror, so it needs one");
+        }
+        if options.icon_dir.is_some() && !options.rich_metadata {
+            anyhow::bail!("--icon-dir downloads the icons --rich-metadata records, so it needs it");
+        }
+        if options.print_merge {
+            let yaml_manifest = YamlManifest::recursive_load_from_file(&options.input_file)?;
+            for (name, sources) in yaml_manifest.overridden() {
+                println!("{}: {}", name, describe_precedence(sources));
+            }
+        }
+        if options.versions.is_empty() {
+            return self.generate_nix_for_version(options, None);
+        }
+        for version in &options.versions {
+            log::info!("Resolving for Minecraft {}", version);
+            self.generate_nix_for_version(options, Some(version))
+                .context(format!("While resolving for Minecraft {}", version))?;
+        }
+        let unfinished = self.unfinished.lock().unwrap();
+        for version in &options.versions {
+            let suffix = format!(" ({})", version);
+            let missing: Vec<&str> = unfinished.iter().filter_map(|(name, _)| name.strip_suffix(&suffix)).collect();
+            if missing.is_empty() {
+                log::info!("Minecraft {}: every mod resolved", version);
+            } else {
+                log::warn!("Minecraft {}: left out {}", version, missing.join(", "));
+            }
+        }
+        Ok(())
+    }
+
+    /// With `version`, every output goes to a path named after it, as `--versions` does.
+    fn generate_nix_for_version(&self, options: &YamlOptions, version: Option<&str>) -> Result<()> {
+        let path = |path: &Path| match version {
+            Some(version) => versioned_path(path, version),
+            None => path.to_path_buf(),
+        };
+        let recorded = match options.verify_existing {
+            Some(ref existing) => {
+                let recorded = lockfile::recorded_files(&path(existing))?;
+                log::info!("Reusing hashes recorded in {:?} for files that haven't changed", path(existing));
+                Some(recorded)
+            }
+            None => None,
+        };
+        let output_file = path(&self.output_path(options.output_file.as_deref(), "manifest.nix")?);
+        let lock_file = options.lock_file.clone().or_else(|| self.conventional_output("manifest.lock.json"));
+        let previous = match lock_file {
+            Some(ref lock_file) => Lockfile::read(&path(lock_file)).unwrap_or_else(|e| {
+                log::warn!("Resolving every mod, as the lockfile can't be used: {:#}", e);
+                None
+            }),
+            None => None,
+        };
+        let resolution = Resolution {
+            recorded: recorded.as_ref(),
+            previous,
+            full: options.full,
+            allow_downgrade: options.allow_downgrade,
+            version,
+            rich_metadata: options.rich_metadata,
+        };
+        let (yaml_manifest, mod_entries) = self.resolve_manifest(&options.input_file, &options.hashes.0, resolution)?;
+        let loader = if options.resolve_loader {
+            Some(self.resolve_loader(&yaml_manifest, &options.hashes.0)?)
+        } else {
+            None
+        };
+
+        if let Some(sample) = options.verify_prefetch {
+            self.verify_prefetch(&mod_entries, sample)?;
+        }
+
+        self.print_phase(4, 4, "Writing out manifest");
+        let provenance = if options.no_provenance {
+            None
+        } else {
+            Some(Provenance::for_input(&options.input_file)?)
+        };
+        let mut mirror = options.src_prefix.as_deref().map(|prefix| Mirror::new(prefix, options.mirror_key));
+        let (nix_loader, nix_entries) = match mirror {
+            Some(ref mut mirror) => (loader.as_ref().map(|l| mirror.loader(l)).transpose()?,
+                                     mod_entries.iter().map(|m| mirror.mod_entry(m)).collect::<Result<Vec<_>>>()?),
+            None => (loader.clone(), mod_entries.clone()),
+        };
+        let (nix_loader, nix_entries) = if options.base32_sha256 {
+            (nix_loader.map(|l| -> Result<LoaderInfo> { Ok(LoaderInfo { hashes: hashes::with_base32_sha256(&l.hashes)?, ..l }) }).transpose()?,
+             nix_entries.into_iter()
+                 .map(|m| -> Result<NixMod> { Ok(NixMod { hashes: hashes::with_base32_sha256(&m.hashes)?, ..m }) })
+                 .collect::<Result<Vec<_>>>()?)
+        } else {
+            (nix_loader, nix_entries)
+        };
+        let previous_output = summary::read_previous(&output_file);
+        let java = java::requirement(&yaml_manifest.version, yaml_manifest.loader_name().as_deref());
+        write_nix_manifest(&output_file, provenance.as_ref(), &yaml_manifest.version, Some(&java), nix_loader.as_ref(), nix_entries.iter())?;
+        match previous_output.map(|previous| Summary::new(&previous, &nix_entries)) {
+            Some(summary) if summary.is_empty() => log::info!("No mods changed in {:?}", output_file),
+            Some(summary) => log::info!("Changes to {:?}: {}", output_file, summary),
+            None => (),
+        }
+        // The split manifests reuse the same resolution, so they cost no extra requests.
+        if let Some(ref client_output) = options.client_output {
+            write_nix_manifest(&path(client_output), provenance.as_ref(), &yaml_manifest.version, Some(&java), nix_loader.as_ref(),
+                               side_entries(&nix_entries, Side::on_client).iter())?;
+        }
+        if let Some(ref server_output) = options.server_output {
+            write_nix_manifest(&path(server_output), provenance.as_ref(), &yaml_manifest.version, Some(&java), nix_loader.as_ref(),
+                               side_entries(&nix_entries, Side::on_server).iter())?;
+        }
+        if let (Some(ref mirror), Some(ref fetch_list)) = (mirror, &options.fetch_list) {
+            mirror.write_fetch_list(&path(fetch_list))?;
+        }
+        if let Some(ref icon_dir) = options.icon_dir {
+            self.download_icons(icon_dir, &mod_entries)?;
+        }
+        if let Some(modlist) = self.conventional_output("modlist.md") {
+            inventory::write_markdown(&path(&modlist), &yaml_manifest.version, &mod_entries)?;
+        }
+        if let Some(ref lock_file) = lock_file {
+            let inputs = LockInputs {
+                rich_metadata: options.rich_metadata,
+                filename_rules: self.filename_rules.rules().to_vec(),
+                ..LockInputs::new(&yaml_manifest, &options.hashes.0)?
+            };
+            Lockfile { version: yaml_manifest.version, loader, inputs: Some(inputs), mods: mod_entries }.write(&path(lock_file))?;
+        }
+        Ok(())
+    }
+
+    /// Downloads `sample` of the files again, evenly spread over the manifest, and checks the recorded
+    /// sha256 against what `nix-prefetch-url` would compute, so a bad cache entry can't reach a build.
+    fn verify_prefetch(&self, mod_entries: &[NixMod], sample: usize) -> Result<()> {
+        if sample == 0 || mod_entries.is_empty() {
+            return Ok(());
+        }
+        let step = (mod_entries.len() / sample).max(1);
+        let sampled: Vec<&NixMod> = mod_entries.iter().step_by(step).take(sample).collect();
+        log::info!("Checking {} files against nix-prefetch-url's hashing", sampled.len());
+        let mismatched: Vec<String> = sampled.par_iter().filter_map(|nix_mod| {
+            let check = || -> Result<Option<String>> {
+                let recorded = nix_mod.hashes.get(&HashAlgorithm::Sha256)
+                    .context("Checking against nix-prefetch-url needs sha256 in --hashes")?;
+                let recorded = hashes::hex_to_nix_base32(recorded)?;
+                let prefetched = self.downloader.prefetch(&nix_mod.src)?;
+                Ok(if prefetched == recorded { None } else { Some(format!("{} (recorded {}, downloaded {})", nix_mod.slug, recorded, prefetched)) })
+            };
+            check().unwrap_or_else(|e| Some(format!("{} ({:#})", nix_mod.slug, e)))
+        }).collect();
+        if !mismatched.is_empty() {
+            anyhow::bail!("The recorded sha256 doesn't match a fresh download for {}", mismatched.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Saves each mod's icon as `<mod>.<extension>`, skipping icons already there.
+    fn download_icons(&self, icon_dir: &Path, mod_entries: &[NixMod]) -> Result<()> {
+        std::fs::create_dir_all(icon_dir).context(format!("While creating {:?}", icon_dir))?;
+        let failed: Vec<String> = mod_entries.par_iter().filter_map(|nix_mod| {
+            let url = nix_mod.icon.as_ref()?;
+            let path = icon_dir.join(naming::icon_filename(&nix_mod.slug, url));
+            if path.exists() {
+                return None;
+            }
+            self.downloader.download_icon(url, &path)
+                .map_err(|e| log::warn!("Could not download the icon for {}: {:#}", nix_mod.slug, e))
+                .err().map(|_| nix_mod.slug.clone())
+        }).collect();
+        if !failed.is_empty() {
+            anyhow::bail!("Could not download icons for {}", failed.join(", "));
         }
         Ok(())
     }
 
-    fn generate_nix_from_yaml(&self, yaml_manifest_path: &Path, nix_manifest_path: &Path) -> Result<()> {
-        print_phase(1, 3, "Loading manifest");
+    /// Resolves a single mod the way `yaml` would, for a closer look at why it fails. Run with
+    /// trace logging and the cache bypassed, so every request it makes shows up in the log.
+    fn why_failed(&self, yaml_manifest_path: &Path, name: &str) -> Result<()> {
         let yaml_manifest = YamlManifest::recursive_load_from_file(yaml_manifest_path)?;
+        if yaml_manifest.disabled.iter().any(|d| d == name) {
+            anyhow::bail!("{} is disabled in {:?}, so it isn't resolved at all", name, yaml_manifest_path);
+        }
+        let yaml_mod = yaml_manifest.mods.iter().find(|m| m.name == name).cloned()
+            .context(format!("{} isn't in {:?}", name, yaml_manifest_path))?;
+        let version_filter = yaml_manifest.version_filter()?;
+        let target = Target {
+            version: &version_filter,
+            loader: yaml_manifest.loader_name(),
+            hashes: &[HashAlgorithm::Md5, HashAlgorithm::Sha256],
+            recorded: None,
+            keep_going: false,
+            rich_metadata: false,
+        };
+        log::info!("Resolving {} for Minecraft {}: {:?}", name, yaml_manifest.version, yaml_mod);
+        let _trace = trace::enter(name);
+        let resolved = self.resolve_mod_metadata(yaml_mod, &target).context("Resolving mod")?;
+        let nix_mod = self.finish_nix_mod_entry(resolved, &target).context("Downloading mod")?;
+        log::info!("{} resolved to {} this time, from {}", name, nix_mod.filename, nix_mod.src);
+        Ok(())
+    }
+
+    fn export_csv(&self, yaml_manifest_path: &Path, csv_path: &Path) -> Result<()> {
+        let (_, mod_entries) = self.resolve_manifest(yaml_manifest_path, &[HashAlgorithm::Sha256], Resolution::default())?;
+        self.print_phase(4, 4, "Writing out inventory");
+        inventory::write_csv(csv_path, &mod_entries)
+    }
+
+    fn export_fetchlist(&self, yaml_manifest_path: &Path, fetchlist_path: &Path, json: bool) -> Result<()> {
+        let (_, mod_entries) = self.resolve_manifest(yaml_manifest_path, &[HashAlgorithm::Sha256], Resolution::default())?;
+        self.print_phase(4, 4, "Writing out fetch list");
+        if json {
+            inventory::write_fetchlist_json(fetchlist_path, &mod_entries)
+        } else {
+            inventory::write_aria2_input(fetchlist_path, &mod_entries)
+        }
+    }
+
+    /// Runs the first three phases: loading, checking and resolving every mod in the manifest.
+    /// Pinned mods that are unchanged since the previous lock are carried forward instead.
+    fn resolve_manifest(&self, yaml_manifest_path: &Path, hashes: &[HashAlgorithm],
+                        resolution: Resolution) -> Result<(YamlManifest, Vec<NixMod>)> {
+        self.print_phase(1, 4, "Loading manifest");
+        let mut yaml_manifest = YamlManifest::recursive_load_for_version(yaml_manifest_path, resolution.version)?;
+        if resolution.version.is_some() {
+            // The manifest's version group is for its own version.
+            yaml_manifest.game_version_type_id = None;
+        }
+        let yaml_manifest = self.rename_numeric_names(yaml_manifest_path, yaml_manifest)?;
+        yaml_manifest.validate()?;
         log::info!("Found {} mods from manifest", yaml_manifest.mods.len());
+        if yaml_manifest.mods.is_empty() {
+            log::warn!("{:?} lists no mods, so the output will have none", yaml_manifest_path);
+        }
+        if !yaml_manifest.disabled.is_empty() {
+            log::info!("Skipping {} disabled mods: {}", yaml_manifest.disabled.len(), yaml_manifest.disabled.join(", "));
+            self.status.set_disabled(yaml_manifest.disabled.clone());
+        }
 
         //print_phase(2, 4, format!("Fetching list of every mod for version {}", yaml_manifest.version));
         //let slug_map = self.downloader.request_mod_listing(&yaml_manifest.version)?; // map of slug -> numeric ID for every mod on Curse
 
-        print_phase(2, 3, format!("Fetching details for {} mods", yaml_manifest.mods.len()));
-        let mut mod_entries = self.generate_nix_mod_entries(yaml_manifest.mods, &yaml_manifest.version)?;
-        mod_entries.sort_unstable_by_key(|m| m.slug.clone());
+        // Carrying mods forward consumes the lock, so what it had is kept apart to check for downgrades.
+        let (locked, mut carried) = match resolution.previous {
+            Some(lock) if resolution.full => (Some(lock), BTreeMap::new()),
+            Some(lock) => {
+                let locked = Lockfile { version: lock.version.clone(), loader: None, inputs: None, mods: lock.mods.clone() };
+                let inputs = LockInputs {
+                    rich_metadata: resolution.rich_metadata,
+                    filename_rules: self.filename_rules.rules().to_vec(),
+                    ..LockInputs::new(&yaml_manifest, hashes)?
+                };
+                (Some(locked), lock.unchanged_mods(&yaml_manifest.version, &inputs))
+            }
+            None => (None, BTreeMap::new()),
+        };
+        // Unpinned mods may have a newer file by now, so those are always resolved, unless held back.
+        carried.retain(|name, _| yaml_manifest.mods.iter().any(|m| &m.name == name && (m.is_pinned() || m.is_held())));
+        for yaml_mod in yaml_manifest.mods.iter().filter(|m| m.is_held() && !m.is_pinned() && !carried.contains_key(&m.name)) {
+            log::info!("{} is held back, but has no file in the lockfile to hold it at, so it gets the newest", yaml_mod.name);
+        }
+        let to_resolve: Vec<YamlMod> = yaml_manifest.mods.iter().filter(|m| !carried.contains_key(&m.name)).cloned().collect();
+        if !carried.is_empty() {
+            log::info!("Carrying {} unchanged mods forward from the lockfile", carried.len());
+        }
 
-        print_phase(3, 3, "Writing out manifest");
-        let formatted_mods = mod_entries.into_iter().map(|m| m.to_string()).collect::<Vec<_>>().join("\n");
-        write!(BufWriter::new(File::create(nix_manifest_path)?),
-               r#"{{
-    "version" = "{version}";
-    "imports" = [];
-    "mods" = {{
-    {mods}
-    }};
-}}"#, version = yaml_manifest.version, mods = formatted_mods)?;
+        self.print_phase(2, 4, format!("Checking availability of {} mods", to_resolve.len()));
+        self.check_availability(&to_resolve)?;
+        // Nothing has resolved yet, so there's nothing worth writing out.
+        interrupt::check()?;
+        self.policy.enforce()?;
+
+        self.print_phase(3, 4, format!("Fetching details for {} mods", to_resolve.len()));
+        let version_filter = yaml_manifest.version_filter()?;
+        let target = Target {
+            version: &version_filter,
+            loader: yaml_manifest.loader_name(),
+            hashes,
+            recorded: resolution.recorded,
+            keep_going: resolution.version.is_some(),
+            rich_metadata: resolution.rich_metadata,
+        };
+        let already_unfinished = self.unfinished.lock().unwrap().len();
+        let resolved_at = std::time::SystemTime::now();
+        let mut mod_entries = self.generate_nix_mod_entries(to_resolve, &target)?;
+        for nix_mod in mod_entries.iter_mut() {
+            if let Some(yaml_mod) = yaml_manifest.mods.iter().find(|m| m.name == nix_mod.slug) {
+                let sources = yaml_manifest.sources.get(&yaml_mod.name);
+                nix_mod.provenance = Some(EntryProvenance::new(yaml_mod, sources, yaml_manifest_path, resolved_at));
+            }
+        }
+        if let Some(version) = resolution.version {
+            for (name, _) in self.unfinished.lock().unwrap().iter_mut().skip(already_unfinished) {
+                name.push_str(&format!(" ({})", version));
+            }
+        }
+        // Pinned mods got the file they asked for, even an older one.
+        let floating = mod_entries.iter().filter(|m| yaml_manifest.mods.iter().any(|y| y.name == m.slug && y.is_floating()));
+        if let Some(locked) = locked {
+            self.check_downgrades(locked.downgrades(&yaml_manifest.version, floating), resolution.allow_downgrade)?;
+        }
+        mod_entries.extend(carried.into_values());
+        if yaml_manifest.auto_deps && self.unfinished.lock().unwrap().len() == already_unfinished {
+            self.add_auto_deps(&mut mod_entries, &target)?;
+        }
+        // Sort so the output doesn't depend on which worker finished first.
+        mod_entries.sort_by(|a, b| a.slug.cmp(&b.slug));
+        if self.unfinished.lock().unwrap().len() == already_unfinished {
+            self.check_dependencies(&mod_entries);
+        } else {
+            log::warn!("Not checking dependencies, as some mods are missing");
+        }
+        self.check_distribution(&mod_entries);
+        self.report_held_back(&yaml_manifest, &version_filter, &mod_entries);
+        naming::check_filename_collisions(&mod_entries)?;
+        self.policy.enforce()?;
+        Ok((yaml_manifest, mod_entries))
+    }
+
+    /// Gives mods that have their project ID as their name the project's slug instead,
+    /// and with `--fix`, makes the same change to the manifest file.
+    fn rename_numeric_names(&self, yaml_manifest_path: &Path, mut yaml_manifest: YamlManifest) -> Result<YamlManifest> {
+        let mut renamed = BTreeMap::new();
+        for yaml_mod in yaml_manifest.mods.iter_mut() {
+            if let Some(id) = yaml_mod.project_id_in_name() {
+                let info = self.downloader.request_addon_info(id)
+                    .context(format!("Looking up project {}, given as the name of a mod", id))?;
+                renamed.insert(yaml_mod.name.clone(), (info.slug.clone(), id));
+                yaml_mod.name = info.slug;
+                yaml_mod.id = Some(id);
+            }
+        }
+        if renamed.is_empty() {
+            return Ok(yaml_manifest);
+        }
+        if !self.commandline.fix {
+            for (name, (slug, _)) in &renamed {
+                log::warn!("{} is a project ID, not a name; treating it as `name: {}` and `id: {}`. Run with --fix to update the manifest",
+                           name, slug, name);
+            }
+            return Ok(yaml_manifest);
+        }
+        let mut editable = YamlManifest::load_for_editing(yaml_manifest_path)?;
+        for yaml_mod in editable.mods.iter_mut() {
+            if let Some((slug, id)) = renamed.remove(&yaml_mod.name) {
+                log::info!("Renaming {} to {}", yaml_mod.name, slug);
+                yaml_mod.name = slug;
+                yaml_mod.id = Some(id);
+            }
+        }
+        for name in renamed.keys() {
+            log::warn!("{} is a project ID, not a name, but comes from an import; fix it there", name);
+        }
+        editable.save(yaml_manifest_path)?;
+        Ok(yaml_manifest)
+    }
+
+    /// Looks up every project before anything gets downloaded, so that missing projects are
+    /// reported in seconds rather than after all the hashing. This also warms the cache.
+    fn check_availability(&self, mod_list: &[YamlMod]) -> Result<()> {
+        let problems: Vec<String> = mod_list.par_iter().filter(|m| !m.is_direct()).filter_map(|yaml_mod| {
+            if interrupt::interrupted() {
+                return None;
+            }
+            if !yaml_mod.ids.is_empty() {
+                // Some of them may well be gone, which is why there are several.
+                let found = yaml_mod.ids.iter().any(|&id| self.downloader.request_addon_info(id).is_ok());
+                return if found { None } else { Some(format!("{}: none of its ids were found", yaml_mod.name)) };
+            }
+            let addon_info = yaml_mod.id
+                .or_else(|| yaml_mod.project_id_in_name())
+                .map(Ok)
+                .unwrap_or_else(|| self.downloader.search_id_with_slug(&yaml_mod.name))
+                .and_then(|id| self.downloader.request_addon_info(id));
+            match addon_info {
+                Err(e) => Some(format!("{}: not found ({:#})", yaml_mod.name, e)),
+                Ok(info) if info.is_available == Some(false) => Some(format!("{}: project {} is unavailable", yaml_mod.name, info.id)),
+                Ok(info) => {
+                    // Split halves are named after their entry, not their project.
+                    if !slugs::same(&info.slug, &yaml_mod.name) && yaml_mod.split_from.is_none() {
+                        self.policy.report(ErrorClass::StaleSlug,
+                                           format!("{} has been renamed to {} (project {})", yaml_mod.name, info.slug, info.id));
+                    }
+                    None
+                }
+            }
+        }).collect();
+
+        if !problems.is_empty() {
+            anyhow::bail!("{} mods could not be resolved:\n  {}", problems.len(), problems.join("\n  "));
+        }
+        Ok(())
+    }
+
+    fn resolve_loader(&self, yaml_manifest: &YamlManifest, hashes: &[HashAlgorithm]) -> Result<LoaderInfo> {
+        let (name, version) = loader::parse_loader(yaml_manifest.loader.as_deref()
+            .context("--resolve-loader needs a loader in the manifest")?)?;
+        let url = loader::installer_url(&yaml_manifest.version, &name, &version)?;
+        log::info!("Fetching {} {} installer from {}", name, version, url);
+        let info = self.downloader.request_mod_file_info(&url, hashes)
+            .context(format!("Fetching installer for {} {}", name, version))?;
+        Ok(LoaderInfo {
+            name,
+            version,
+            src: info.download_url,
+            size: info.size,
+            hashes: info.hashes.into_iter().filter(|(a, _)| hashes.contains(a)).collect(),
+        })
+    }
+
+    fn check_dependencies(&self, mod_entries: &[NixMod]) {
+        let ids: HashSet<u32> = mod_entries.iter().map(|m| m.id).collect();
+        for nix_mod in mod_entries {
+            for dependency in nix_mod.dependency_ids.iter().filter(|id| !ids.contains(id)) {
+                self.policy.report(ErrorClass::MissingDependency,
+                                   format!("{} requires project {}, which is not in the manifest", nix_mod.slug, dependency));
+            }
+        }
+        for duplicate in jarjar::find_embedded_duplicates(mod_entries) {
+            self.policy.report(ErrorClass::EmbeddedLibrary,
+                               format!("{} already embeds {}; consider removing {} from the manifest",
+                                       duplicate.container, duplicate.library, duplicate.library));
+        }
+        for conflict in modmeta::find_version_conflicts(mod_entries) {
+            self.policy.report(ErrorClass::VersionConflict, conflict.to_string());
+        }
+    }
+
+    /// Resolves the required dependencies no entry provides as entries of their own, then theirs in turn.
+    fn add_auto_deps(&self, mod_entries: &mut Vec<NixMod>, target: &Target) -> Result<()> {
+        let mut tried = HashSet::new();
+        loop {
+            let ids: HashSet<u32> = mod_entries.iter().map(|m| m.id).collect();
+            let mut required_by: BTreeMap<u32, Vec<&str>> = BTreeMap::new();
+            for nix_mod in mod_entries.iter() {
+                for &dependency in nix_mod.dependency_ids.iter().filter(|id| !ids.contains(id) && !tried.contains(*id)) {
+                    required_by.entry(dependency).or_default().push(&nix_mod.slug);
+                }
+            }
+            if required_by.is_empty() {
+                return Ok(());
+            }
+            let mut to_resolve = Vec::new();
+            for (id, dependents) in required_by {
+                tried.insert(id);
+                let info = self.downloader.request_addon_info(id)
+                    .context(format!("Looking up project {}, which {} require", id, dependents.join(", ")))?;
+                if mod_entries.iter().any(|m| slugs::same(&m.slug, &info.slug)) {
+                    log::warn!("Not adding {} (project {}), as another mod in the manifest has that name", info.slug, id);
+                    continue;
+                }
+                log::info!("Adding {} (project {}), which {} require", info.slug, id, dependents.join(", "));
+                to_resolve.push(YamlMod::with_id(&info.slug, id));
+            }
+            let added = self.generate_nix_mod_entries(to_resolve, target)?;
+            for nix_mod in mod_entries.iter_mut() {
+                let new_deps: Vec<String> = added.iter()
+                    .filter(|a| nix_mod.dependency_ids.contains(&a.id))
+                    .map(|a| a.slug.clone())
+                    .collect();
+                nix_mod.deps.extend(new_deps);
+            }
+            let provenance = EntryProvenance::auto_dep(std::time::SystemTime::now());
+            mod_entries.extend(added.into_iter().map(|nix_mod| NixMod { auto: true, provenance: Some(provenance.clone()), ..nix_mod }));
+        }
+    }
+
+    /// Stops the run when mods resolved to older files than the lockfile has, unless
+    /// `--allow-downgrade` says that's expected. Either way, the status file lists them.
+    fn check_downgrades(&self, downgrades: Vec<Downgrade>, allow: bool) -> Result<()> {
+        if downgrades.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<String> = downgrades.iter().map(Downgrade::to_string).collect();
+        let message = format!("{} mods resolved to older files than the lockfile has:\n  {}", downgrades.len(), lines.join("\n  "));
+        self.status.set_downgraded(downgrades);
+        if !allow {
+            anyhow::bail!("{}\nRun with --allow-downgrade to accept them", message);
+        }
+        log::warn!("{}", message);
         Ok(())
     }
 
-    fn generate_nix_mod_entries(&self, mod_list: Vec<YamlMod>, version: &str) -> Result<Vec<NixMod>> {
+    /// Lists the held mods that have newer files, and how far behind they are. Only for information,
+    /// so failing to look is no reason to fail the run.
+    fn report_held_back(&self, yaml_manifest: &YamlManifest, version: &VersionFilter, mod_entries: &[NixMod]) {
+        let held: Vec<&NixMod> = mod_entries.iter()
+            .filter(|m| m.file_id != 0 && yaml_manifest.mods.iter().any(|y| y.name == m.slug && y.is_held()))
+            .collect();
+        if held.is_empty() {
+            return;
+        }
+        let loader = yaml_manifest.loader_name();
+        let updates: Vec<outdated::Update> = self.pool.install(|| held.par_iter().filter_map(|nix_mod| {
+            let result = self.downloader.request_mod_file(nix_mod.id, nix_mod.file_id).and_then(|pinned| {
+                let files = self.downloader.request_mod_files(nix_mod.id, version)?;
+                let newest = outdated::newest_file(files, loader.as_deref()).context("No files for the version")?;
+                Ok(outdated::Update { name: nix_mod.slug.clone(), pinned, newest })
+            });
+            result.map_err(|e| log::warn!("Could not check whether held back {} has newer files: {:#}", nix_mod.slug, e)).ok()
+        }).collect());
+        let behind = outdated::by_staleness(updates);
+        if behind.is_empty() {
+            log::info!("{} held back mods are up to date", held.len());
+            return;
+        }
+        let lines: Vec<String> = behind.iter()
+            .map(|u| format!("{}: {} -> {}, {} days behind", u.name, u.pinned.file_name, u.newest.file_name, u.days_behind()))
+            .collect();
+        log::info!("Holding back {} of {} held mods:\n  {}", behind.len(), held.len(), lines.join("\n  "));
+    }
+
+    /// Redistributing these, e.g. in a server tarball, goes against their authors' terms.
+    fn check_distribution(&self, mod_entries: &[NixMod]) {
+        for nix_mod in mod_entries.iter().filter(|m| m.allow_distribution == Some(false)) {
+            self.policy.report(ErrorClass::DistributionDisallowed,
+                               format!("{}'s author doesn't allow third-party distribution, so {} mustn't be redistributed",
+                                       nix_mod.slug, nix_mod.filename));
+        }
+    }
+
+    fn observers(&self) -> impl Iterator<Item = &dyn ProgressObserver> {
+        std::iter::once(&self.status as &dyn ProgressObserver)
+            .chain(std::iter::once(&self.metrics as &dyn ProgressObserver))
+            .chain(self.observer)
+    }
+
+    /// Resolves mods in two stages: metadata lookups on the main pool, then downloads on the
+    /// smaller download pool, so neither the API nor the CDN gets hammered. Every file is known
+    /// before the first download, so `--max-download-bytes` stops the run before it pulls anything.
+    fn generate_nix_mod_entries(&self, mod_list: Vec<YamlMod>, target: &Target) -> Result<Vec<NixMod>> {
+        if mod_list.is_empty() {
+            return Ok(Vec::new());
+        }
 
         let progress = ProgressBar::new(mod_list.len() as u64)
             .with_style(ProgressStyle::default_bar()
                 .template("{bar:30} {pos}/{len} {msg}"));
-        let updater = progress.downgrade();
+        self.status.add_pending(mod_list.len() as u32);
 
-        mod_list.into_par_iter().progress_with(progress).map(|yaml_mod| {
-            updater.upgrade().unwrap().set_message(&format!("Processing mod: {}", yaml_mod.name));
+        let resolved: Vec<(String, Result<ResolvedMod>)> = mod_list.into_par_iter().map(|yaml_mod| {
+            let name = yaml_mod.name.clone();
+            let _trace = trace::enter(&name);
+            for observer in self.observers() {
+                observer.mod_started(&name);
+            }
+            let resolved = interrupt::check().map_err(anyhow::Error::from)
+                .and_then(|_| self.resolve_mod_metadata(yaml_mod, target))
+                .and_then(|resolved| self.plan_download(&resolved, target).map(|_| resolved))
+                .context(format!("[{}] Resolving mod", name));
+            (name, resolved)
+        }).collect();
 
-            let project_id = match yaml_mod.id {
-                Some(id) => id,
-                None => self.downloader.search_id_with_slug(&yaml_mod.name)?
-            };
-            let addon_info = self.downloader.request_addon_info(project_id)?;
+        if let Some(plan) = self.budget.describe(self.downloader.bandwidth()) {
+            log::info!("{}", plan);
+        }
+        match self.budget.unsized_files() {
+            0 => (),
+            count => log::warn!("{} files to download have no known size, so the total is only a lower bound", count),
+        }
+        if let Err(over) = self.budget.check() {
+            progress.finish_and_clear();
+            return Err(over.into());
+        }
 
-            let get_all_files = |project_id: u32| -> Result<Vec<CurseModFile>> {
-                self.downloader.request_mod_files(project_id, version)
-                    .context(format!("Fetching files for project id {}", project_id))
-            };
+        let results: Vec<(String, Result<NixMod>)> = self.download_pool.install(|| {
+            resolved.into_par_iter().map(|(name, resolved)| {
+                let result = resolved.and_then(|resolved| {
+                    interrupt::check()?;
+                    let _trace = trace::enter(&name);
+                    progress.set_message(&format!("Downloading mod: {}", name));
+                    self.finish_nix_mod_entry(resolved, target).context(format!("[{}] Downloading mod", name))
+                });
+                for observer in self.observers() {
+                    match result {
+                        Ok(_) => observer.mod_resolved(&name),
+                        Err(ref e) => observer.mod_failed(&name, e),
+                    }
+                }
+                progress.inc(1);
+                (name, result)
+            }).collect()
+        });
+        progress.finish_and_clear();
+        if self.commandline.deadline.is_none() && !target.keep_going && !interrupt::interrupted() {
+            let missing: Vec<&NoFilesForVersion> = results.iter()
+                .filter_map(|(_, result)| result.as_ref().err()?.downcast_ref::<NoFilesForVersion>())
+                .collect();
+            let other_failures = results.iter().filter(|(_, result)| result.is_err()).count() - missing.len();
+            if !missing.is_empty() && other_failures == 0 {
+                anyhow::bail!("{}", game_version::report_missing_files(&missing));
+            } else if !missing.is_empty() {
+                log::error!("{}", game_version::report_missing_files(&missing));
+            }
+            return results.into_iter().map(|(_, result)| result).collect();
+        }
+
+        // Keep going past failures, so whatever did resolve is still written out.
+        let mut entries = Vec::with_capacity(results.len());
+        let mut unfinished = self.unfinished.lock().unwrap();
+        for (name, result) in results {
+            match result {
+                Ok(entry) => entries.push(entry),
+                Err(e) if deadline::is_exceeded(&e) => unfinished.push((name, "not finished before the deadline".to_string())),
+                Err(e) if interrupt::is_interrupted(&e) => unfinished.push((name, "not started before the interrupt".to_string())),
+                Err(e) => unfinished.push((name, format!("{:#}", e))),
+            }
+        }
+        if !unfinished.is_empty() {
+            log::warn!("Leaving out {} mods that didn't resolve", unfinished.len());
+        }
+        Ok(entries)
+    }
+
+    fn resolve_mod_metadata(&self, yaml_mod: YamlMod, target: &Target) -> Result<ResolvedMod> {
+        if yaml_mod.is_direct() {
+            return Ok(ResolvedMod { yaml_mod, curse: None });
+        }
+        let (project_id, mod_file) = self.resolve_mod_file(&yaml_mod, target.version)?;
+        let addon_info = self.downloader.request_addon_info(project_id)?;
+
+        if let Some(ref loader) = target.loader {
+            let file_loaders: Vec<String> = mod_file.game_version.iter()
+                .map(|v| v.to_lowercase())
+                .filter(|v| KNOWN_LOADERS.contains(&v.as_str()))
+                .collect();
+            if !file_loaders.is_empty() && !file_loaders.contains(loader) {
+                self.policy.report(ErrorClass::WrongLoader,
+                                   format!("{}: {} is for {}, not {}", yaml_mod.name, mod_file.file_name, file_loaders.join("/"), loader));
+            }
+        }
+        Ok(ResolvedMod { yaml_mod, curse: Some(CurseResolution { project_id, mod_file, addon_info }) })
+    }
+
+    /// Counts the mod's file against `--max-download-bytes`, unless it won't need downloading.
+    fn plan_download(&self, resolved: &ResolvedMod, target: &Target) -> Result<()> {
+        let (url, size) = match resolved.curse {
+            Some(ref curse) => (curse.mod_file.download_url.as_str(), curse.mod_file.file_length),
+            None => match resolved.yaml_mod.files.as_ref().and_then(|f| f.first()) {
+                Some(YamlModFile { src: Some(ref src), size, .. }) => (src.as_str(), *size),
+                _ => return Ok(()),
+            },
+        };
+        if target.recorded_file_info(url)?.is_some() || self.downloader.is_file_cached(url)? {
+            return Ok(());
+        }
+        self.budget.plan(size);
+        Ok(())
+    }
+
+    fn finish_nix_mod_entry(&self, resolved: ResolvedMod, target: &Target) -> Result<NixMod> {
+        let (yaml_mod, CurseResolution { project_id, mod_file, addon_info }) = match resolved {
+            ResolvedMod { yaml_mod, curse: Some(curse) } => (yaml_mod, curse),
+            ResolvedMod { yaml_mod, curse: None } => return self.finish_direct_nix_mod_entry(yaml_mod, target),
+        };
 
-            let get_newest_file = |project_id: u32| -> Result<CurseModFile> {
-                let mut files = get_all_files(project_id)?;
-                files.sort_unstable_by_key(|f| f.file_date.clone());
-                Ok(files.last().context(format!("Did not get at least one file for {:?}", yaml_mod))?.clone())
+        let CurseModFileInfo { hashes: all_hashes, size, download_url, embedded_jars, metadata, .. } =
+            match target.recorded_file_info(&mod_file.download_url)? {
+                Some(info) => info,
+                None => self.downloader.request_mod_file_info(&mod_file.download_url, target.hashes)?,
             };
+        let expected_md5 = yaml_mod.files.as_ref().and_then(|f| f.first()).and_then(|f| f.md5.as_ref());
+        if let (Some(expected), Some(actual)) = (expected_md5, all_hashes.get(&HashAlgorithm::Md5)) {
+            if expected != actual {
+                self.policy.report(ErrorClass::HashMismatch,
+                                   format!("{}: expected md5 {}, but {} has {}", yaml_mod.name, expected, mod_file.file_name, actual));
+            }
+        }
+
+        let nix_mod = NixMod::new(&yaml_mod.name, &addon_info.name, project_id)
+            .file_id(mod_file.id)
+            .flags_from(&yaml_mod)
+            .page(&addon_info.links.website_url)
+            .categories(addon_info.categories.iter().map(|c| c.slug.clone()).collect())
+            .file(&naming::sanitize_filename(&mod_file.file_name, &self.filename_rules), &naming::normalize_download_url(&download_url)?, size)
+            .hashes(all_hashes.into_iter().filter(|(a, _)| target.hashes.contains(a)).collect());
+        let nix_mod = match addon_info.logo {
+            Some(ref logo) if target.rich_metadata => nix_mod.icon(&logo.thumbnail_url),
+            _ => nix_mod,
+        };
+        Ok(NixMod {
+            allow_distribution: addon_info.allow_mod_distribution,
+            fingerprint: mod_file.file_fingerprint,
+            embedded_jars,
+            metadata,
+            embedded_ids: mod_file.dependencies.iter()
+                .filter(|d| d.relation_type == CurseFileDependency::EMBEDDED_LIBRARY)
+                .map(|d| d.mod_id)
+                .collect(),
+            dependency_ids: mod_file.dependencies.iter()
+                .filter(|d| d.relation_type == CurseFileDependency::REQUIRED_DEPENDENCY)
+                .map(|d| d.mod_id)
+                .collect(),
+            game_versions: mod_file.game_version.clone(),
+            ..nix_mod
+        })
+    }
 
-            // Get a specific file if one was specified, otherwise the newest.
-            let mod_file: CurseModFile = if let Some(ref file) = yaml_mod.files {
-                if let Some(id) = file[0].id {
-                    self.downloader.request_mod_file(project_id, id)
-                        .context(format!("Looking for specific file in {:?}", yaml_mod))?
-                } else {
-                    get_newest_file(project_id)?
+    fn finish_direct_nix_mod_entry(&self, yaml_mod: YamlMod, target: &Target) -> Result<NixMod> {
+        let file = yaml_mod.files.as_ref().and_then(|f| f.first()).context("Direct mods have a file")?;
+        let src = file.src.as_ref().context("Direct mods have a src")?;
+        let mut known_hashes = file.hashes.clone();
+        if let Some(ref md5) = file.md5 {
+            known_hashes.insert(HashAlgorithm::Md5, md5.clone());
+        }
+        // Only download if the manifest doesn't already say everything we need.
+        let (hashes, size) = match file.size {
+            Some(size) if target.hashes.iter().all(|a| known_hashes.contains_key(a)) => (known_hashes, size),
+            _ => {
+                let info = match target.recorded_file_info(src)? {
+                    Some(info) => info,
+                    None => self.downloader.request_mod_file_info(src, target.hashes)?,
+                };
+                for (algorithm, expected) in &known_hashes {
+                    match info.hashes.get(algorithm) {
+                        Some(actual) if actual != expected => self.policy.report(ErrorClass::HashMismatch,
+                            format!("{}: expected {} {}, but {} has {}", yaml_mod.name, algorithm, expected, src, actual)),
+                        _ => (),
+                    }
                 }
+                (info.hashes, info.size)
+            }
+        };
+        let filename = match file.name {
+            Some(ref name) => name.clone(),
+            None => urlencoding::decode(src.rsplit('/').next().unwrap_or(src))?,
+        };
+        Ok(NixMod::new(&yaml_mod.name, &yaml_mod.name, 0)
+            .flags_from(&yaml_mod)
+            .file(&naming::sanitize_filename(&filename, &self.filename_rules), &naming::normalize_download_url(src)?, size)
+            .hashes(hashes.into_iter().filter(|(a, _)| target.hashes.contains(a)).collect()))
+    }
+
+    /// Finds the project ID and the file to use for a mod: the pinned one if specified,
+    /// otherwise the newest file for the given version.
+    fn resolve_mod_file(&self, yaml_mod: &YamlMod, version: &VersionFilter) -> Result<(u32, CurseModFile)> {
+        if !yaml_mod.ids.is_empty() {
+            return self.resolve_moved_mod_file(yaml_mod, version);
+        }
+        let project_id = match yaml_mod.id.or_else(|| yaml_mod.project_id_in_name()) {
+            Some(id) => id,
+            None => self.downloader.search_id_with_slug(&yaml_mod.name)?
+        };
+
+        let get_all_files = |project_id: u32| -> Result<Vec<CurseModFile>> {
+            self.downloader.request_mod_files(project_id, version)
+                .context(format!("Fetching files for project id {}", project_id))
+        };
+
+        let get_newest_file = |project_id: u32| -> Result<CurseModFile> {
+            // The project's index usually names the newest file, sparing the paginated listing.
+            let indexed = self.downloader.request_file_indexes(project_id)
+                .map_err(|e| log::debug!("Couldn't look up {} to find its newest file: {:#}", yaml_mod.name, e))
+                .ok()
+                .and_then(|indexes| version.newest_indexed(&indexes));
+            if let Some(file_id) = indexed {
+                match self.downloader.request_mod_file(project_id, file_id) {
+                    Ok(file) => return Ok(file),
+                    Err(e) => log::debug!("{}: indexed file {} failed ({:#}), listing every file", yaml_mod.name, file_id, e),
+                }
+            }
+            let mut files = get_all_files(project_id)?;
+            files.sort_unstable_by_key(|f| f.file_date.clone());
+            match files.pop() {
+                Some(file) => Ok(file),
+                None => {
+                    let info = self.downloader.request_addon_info(project_id)
+                        .map_err(|e| log::debug!("Couldn't look up {} to see which versions it has: {:#}", yaml_mod.name, e))
+                        .ok();
+                    let indexes = info.as_ref().map(|i| i.latest_files_indexes.as_slice());
+                    Err(NoFilesForVersion::new(&yaml_mod.name, version, indexes).into())
+                }
+            }
+        };
+
+        // Get a specific file if one was specified, otherwise the newest.
+        let mod_file: CurseModFile = if let Some(file) = yaml_mod.files.as_ref().and_then(|f| f.first()) {
+            if let Some(id) = file.id {
+                self.downloader.request_mod_file(project_id, id)
+                    .context(format!("Looking for specific file in {:?}", yaml_mod))?
             } else {
                 get_newest_file(project_id)?
-            };
+            }
+        } else {
+            get_newest_file(project_id)?
+        };
+        Ok((project_id, mod_file))
+    }
 
-            let CurseModFileInfo { md5, sha256, size, download_url} = self.downloader.request_mod_file_info(&mod_file.download_url)?;
-            // Fix filenames and URLs
-            let fixed_filename = mod_file.file_name.replace("(", "").replace(")", "");
-            let fixed_src = download_url.replace("+", "%2B").replace(" ", "+");
-            Ok(NixMod {
-                slug: yaml_mod.name.clone(),
-                title: addon_info.name,
-                id: project_id,
-                side: yaml_mod.side.unwrap_or(Side::Both),
+    /// Tries each of a mod's `ids` in turn, moving on only when a project has no files for the
+    /// version. Other failures, like network errors, stop the search, so they can't end up
+    /// silently picking an older project.
+    fn resolve_moved_mod_file(&self, yaml_mod: &YamlMod, version: &VersionFilter) -> Result<(u32, CurseModFile)> {
+        let mut missing = None;
+        for &project_id in &yaml_mod.ids {
+            let single = YamlMod { id: Some(project_id), ids: vec![], ..yaml_mod.clone() };
+            match self.resolve_mod_file(&single, version) {
+                Ok(resolved) => {
+                    if project_id != yaml_mod.ids[0] {
+                        log::info!("{}: using project {}, as earlier ones have no files for the version", yaml_mod.name, project_id);
+                    }
+                    return Ok(resolved);
+                }
+                Err(e) if e.downcast_ref::<NoFilesForVersion>().is_some() => {
+                    log::debug!("{}: project {} has no files for the version", yaml_mod.name, project_id);
+                    missing = Some(e);
+                }
+                Err(e) => return Err(e.context(format!("Trying project {} for {}", project_id, yaml_mod.name))),
+            }
+        }
+        let ids: Vec<String> = yaml_mod.ids.iter().map(u32::to_string).collect();
+        Err(missing.expect("ids isn't empty").context(format!("None of projects {} have files for the version", ids.join(", "))))
+    }
+
+    fn generate_curse_from_yaml(&self, yaml_manifest_path: &Path, curse_manifest_path: &Path) -> Result<()> {
+        let yaml_manifest = YamlManifest::recursive_load_from_file(yaml_manifest_path)?;
+        yaml_manifest.validate()?;
+        log::info!("Found {} mods from manifest", yaml_manifest.mods.len());
+
+        for yaml_mod in yaml_manifest.mods.iter().filter(|m| m.is_direct()) {
+            log::warn!("Leaving out {}, as Curse manifests can't hold direct downloads", yaml_mod.name);
+        }
+        let version_filter = yaml_manifest.version_filter()?;
+        let mut files = yaml_manifest.mods.par_iter().filter(|m| !m.is_direct()).map(|yaml_mod| {
+            let (project_id, mod_file) = self.resolve_mod_file(yaml_mod, &version_filter)?;
+            Ok(ModFile {
+                project_id,
+                file_id: mod_file.id,
                 required: yaml_mod.required.unwrap_or(true),
-                default: yaml_mod.default.unwrap_or(true),
-                deps: vec![],
-                filename: fixed_filename.clone(),
-                encoded: fixed_filename,
-                md5,
-                sha256,
-                size,
-                src: fixed_src,
-                page: addon_info.links.website_url,
             })
-       }).collect::<Result<Vec<NixMod>, _>>()
+        }).collect::<Result<Vec<_>>>()?;
+        files.sort_by_key(|f| f.project_id);
+
+        log::info!("Writing manifest...");
+        let mut out = AtomicFile::create(curse_manifest_path)?;
+        serde_json::to_writer_pretty(&mut out, &CurseManifest {
+            minecraft: MinecraftVersion {
+                version: yaml_manifest.version,
+                mod_loaders: yaml_manifest.loader.into_iter().map(|id| ModLoader { id, primary: true }).collect(),
+            },
+            files,
+        })?;
+        out.commit()?;
+        log::info!("Successfully wrote manifest!");
+
+        Ok(())
     }
 
     fn generate_yaml_from_curse(&self, curse_manifest_path: &Path, yaml_manifest_path: &Path) -> Result<()> {
@@ -153,60 +1239,599 @@ impl<'app> App<'app> {
             self.generate_yaml_mod_entry(m)
         }).collect::<Result<Vec<_>, _>>()?;
         mod_entries.sort_unstable_by_key(|d| d.name.clone());
+        let loader = curse_manifest.minecraft.mod_loaders.iter()
+            .find(|l| l.primary)
+            .or_else(|| curse_manifest.minecraft.mod_loaders.first())
+            .map(|l| l.id.clone());
 
         log::info!("Writing manifest...");
-        serde_yaml::to_writer(&File::create(yaml_manifest_path)?,
+        let mut out = AtomicFile::create(yaml_manifest_path)?;
+        serde_yaml::to_writer(&mut out,
                               &YamlManifest {
                                   version: curse_manifest.minecraft.version,
+                                  loader,
+                                  game_version_type_id: None,
+                                  vars: BTreeMap::new(),
                                   imports: vec![],
                                   mods: mod_entries,
+                                  auto_deps: false,
+                                  disabled: vec![],
+                                  sources: BTreeMap::new(),
                               })?;
+        out.commit()?;
         log::info!("Successfully wrote manifest!");
 
         Ok(())
    }
 
+    fn refresh_cache(&self, yaml_manifest_path: &Path) -> Result<()> {
+        let yaml_manifest = YamlManifest::recursive_load_from_file(yaml_manifest_path)?;
+        log::info!("Refreshing cache for {} mods", yaml_manifest.mods.len());
+        let version_filter = yaml_manifest.version_filter()?;
+        // One mod at a time; this is supposed to be slow and gentle.
+        let mut failures = 0;
+        for yaml_mod in yaml_manifest.mods.iter().filter(|m| !m.is_direct()) {
+            let result = self.resolve_mod_file(yaml_mod, &version_filter)
+                .and_then(|(project_id, _)| self.downloader.request_addon_info(project_id));
+            if let Err(e) = result {
+                log::warn!("Could not refresh {}: {:#}", yaml_mod.name, e);
+                failures += 1;
+            }
+        }
+        log::info!("Refreshed {} mods, {} failed", yaml_manifest.mods.len() - failures, failures);
+
+        Ok(())
+    }
+
+    fn import_list(&self, list_path: &Path, yaml_manifest_path: &Path, game_version: Option<&str>) -> Result<()> {
+        log::info!("Reading mod list...");
+        let list = std::fs::read_to_string(list_path)
+            .context(format!("While reading {:?}", list_path))?;
+        let listed = list.lines()
+            .map(import::parse_list_entry)
+            .filter_map(Result::transpose)
+            .collect::<Result<Vec<_>>>()?;
+        log::info!("Found {} mods in list", listed.len());
+
+        let mut manifest = if yaml_manifest_path.exists() {
+            YamlManifest::load_for_editing(yaml_manifest_path)?
+        } else {
+            YamlManifest::new(game_version.context("--game-version is required when creating a new manifest")?)
+        };
+
+        // An import list can contain the same mod twice, e.g. as slug and URL.
+        let mut new_slugs: Vec<String> = Vec::new();
+        for slug in listed.iter().map(|s| slugs::normalize(s)) {
+            if !new_slugs.contains(&slug) && !manifest.mods.iter().any(|m| slugs::normalize(&m.name) == slug) {
+                new_slugs.push(slug);
+            }
+        }
+        log::info!("Resolving {} new mods...", new_slugs.len());
+        let mut new_mods = new_slugs.par_iter().map(|slug| {
+            let id = self.downloader.search_id_with_slug(slug)?;
+            Ok(YamlMod::with_id(slug, id))
+        }).collect::<Result<Vec<_>>>()?;
+        manifest.mods.append(&mut new_mods);
+
+        log::info!("Writing manifest...");
+        manifest.save(yaml_manifest_path)?;
+        log::info!("Successfully wrote manifest!");
+
+        Ok(())
+    }
+
+    fn print_changelog(&self, yaml_manifest_path: &Path, from: &str, to: Option<&str>) -> Result<()> {
+        let old = changelog::read_revision(yaml_manifest_path, from)?;
+        let new = match to {
+            Some(to) => changelog::read_revision(yaml_manifest_path, to)?,
+            None => changelog::parse(&std::fs::read_to_string(yaml_manifest_path)
+                .context(format!("While reading {:?}", yaml_manifest_path))?)?,
+        };
+        let changes = changelog::diff(&old, &new);
+        log::info!("{} mods changed, looking up their files...", changes.len());
+        print!("{}", changelog::render(&changes, |yaml_mod| self.describe_pin(yaml_mod))?);
+        Ok(())
+    }
+
+    fn describe_pin(&self, yaml_mod: &YamlMod) -> Result<changelog::Described> {
+        let file = yaml_mod.files.as_ref().and_then(|files| files.first());
+        if let Some(src) = file.and_then(|f| f.src.as_ref()) {
+            let file_name = file.and_then(|f| f.name.clone())
+                .unwrap_or_else(|| src.rsplit('/').next().unwrap_or(src).to_string());
+            return Ok(changelog::Described { title: yaml_mod.name.clone(), file: file_name, link: Some(src.clone()) });
+        }
+        let project_id = match yaml_mod.id.or(yaml_mod.client_id).or_else(|| yaml_mod.ids.first().copied())
+            .or_else(|| yaml_mod.project_id_in_name()) {
+            Some(project_id) => project_id,
+            None => self.downloader.search_id_with_slug(&yaml_mod.name)?,
+        };
+        let addon_info = self.downloader.request_addon_info(project_id)?;
+        Ok(match file.and_then(|f| f.id) {
+            Some(file_id) => {
+                let mod_file = self.downloader.request_mod_file(project_id, file_id)?;
+                changelog::Described {
+                    title: addon_info.name,
+                    file: mod_file.display_name.unwrap_or(mod_file.file_name),
+                    // The file page shows its changelog.
+                    link: Some(format!("{}/files/{}", addon_info.links.website_url, file_id)),
+                }
+            }
+            None => changelog::Described { title: addon_info.name, file: "newest file".to_string(), link: Some(addon_info.links.website_url) },
+        })
+    }
+
+    fn format_manifest(&self, yaml_manifest_path: &Path, check: bool) -> Result<()> {
+        let source = std::fs::read_to_string(yaml_manifest_path)
+            .context(format!("While reading {:?}", yaml_manifest_path))?;
+        let formatted = formatting::format_manifest(&source)?;
+        if formatted == source {
+            log::info!("{} is already formatted", yaml_manifest_path.display());
+        } else if check {
+            anyhow::bail!("{} is not formatted; run fmt on it", yaml_manifest_path.display());
+        } else {
+            output::write(yaml_manifest_path, formatted)?;
+            log::info!("Formatted {}", yaml_manifest_path.display());
+        }
+        Ok(())
+    }
+
+    /// Runs the offline rules, then looks up every mod given an `id` to check it's the project the name says.
+    fn lint_manifest(&self, yaml_manifest_path: &Path, branch: Option<&str>) -> Result<()> {
+        let source = std::fs::read_to_string(yaml_manifest_path)
+            .context(format!("While reading {:?}", yaml_manifest_path))?;
+        let document: serde_yaml::Value = serde_yaml::from_str(&source)
+            .context(format!("While parsing YAML from {:?}", yaml_manifest_path))?;
+        let branch = branch.map(str::to_string).or_else(|| lint::current_branch(yaml_manifest_path));
+        let mut findings = lint::lint(&document, &self.lint, branch.as_deref())?;
+
+        if self.lint.id_slug_mismatch != Action::Ignore {
+            let yaml_manifest = lint::expand(&document)?;
+            let mismatched: Vec<Finding> = lint::mods_with_ids(&yaml_manifest).par_iter().filter_map(|&(yaml_mod, id)| {
+                match self.downloader.request_addon_info(id) {
+                    Ok(info) => lint::id_slug_mismatch(yaml_mod, id, &info.slug),
+                    Err(e) => {
+                        log::warn!("Could not look up {} by its id {}: {:#}", yaml_mod.name, id, e);
+                        None
+                    }
+                }
+            }).collect();
+            findings.extend(mismatched);
+        }
+
+        let count = findings.len();
+        lint::report(findings, &self.lint)?;
+        if count == 0 {
+            log::info!("{:?} looks fine", yaml_manifest_path);
+        }
+        Ok(())
+    }
+
+    fn import_server(&self, source: &str, yaml_manifest_path: &Path, game_version: Option<&str>) -> Result<()> {
+        let jars = server_import::read_jars(source)?;
+        log::info!("Found {} jars, looking them up by fingerprint...", jars.len());
+        let fingerprints: Vec<u32> = jars.iter().map(|(_, contents)| fingerprint::curse_fingerprint(contents)).collect();
+        let matches = self.downloader.request_fingerprint_matches(&fingerprints)?;
+
+        let mut manifest = if yaml_manifest_path.exists() {
+            YamlManifest::load_for_editing(yaml_manifest_path)?
+        } else {
+            YamlManifest::new(game_version.context("--game-version is required when creating a new manifest")?)
+        };
+        let new_mods = jars.par_iter().zip(fingerprints.par_iter()).filter_map(|((name, _), fingerprint)| {
+            let found = matches.exact_matches.iter().find(|m| m.file.file_fingerprint == *fingerprint);
+            let found = match found {
+                Some(found) => found,
+                None => {
+                    log::warn!("{} is not on CurseForge; add it by hand, e.g. with a src: URL", name);
+                    return None;
+                }
+            };
+            let result = self.downloader.request_addon_info(found.id)
+                .map(|info| YamlMod::with_files(&info.slug, found.id, YamlModFile::with_id(found.file.id)));
+            result.map_err(|e| log::warn!("Could not look up project {} for {}: {:#}", found.id, name, e)).ok()
+        }).collect::<Vec<_>>();
+        log::info!("Matched {} of {} jars", new_mods.len(), jars.len());
+        for new_mod in new_mods {
+            if manifest.mods.iter().any(|m| m.name == new_mod.name) {
+                log::info!("{} is already in the manifest", new_mod.name);
+            } else {
+                manifest.mods.push(new_mod);
+            }
+        }
+
+        log::info!("Writing manifest...");
+        manifest.save(yaml_manifest_path)?;
+        log::info!("Successfully wrote manifest!");
+        Ok(())
+    }
+
+    fn export_cache(&self, archive_path: &Path, for_manifest: Option<&Path>) -> Result<()> {
+        let only = match for_manifest {
+            Some(yaml_manifest_path) => {
+                // Resolving goes through the cache, which records exactly the entries it needs.
+                let yaml_manifest = YamlManifest::recursive_load_from_file(yaml_manifest_path)?;
+                let version_filter = yaml_manifest.version_filter()?;
+                let target = Target { version: &version_filter, loader: yaml_manifest.loader_name(), hashes: &[], recorded: None, keep_going: false,
+                                      rich_metadata: false };
+                self.generate_nix_mod_entries(yaml_manifest.mods.clone(), &target)?;
+                Some(self.downloader.touched_urls())
+            }
+            None => None,
+        };
+        let exported = cache_archive::export(self.database, archive_path, only.as_ref())?;
+        log::info!("Exported {} cache entries to {:?}", exported, archive_path);
+        Ok(())
+    }
+
+    /// File details are cached forever, as files hardly ever change. When one is re-uploaded,
+    /// this finds out with a HEAD request and drops the entry so the next run downloads it again.
+    fn revalidate_files(&self, sample: Option<usize>) -> Result<()> {
+        let mut files: Vec<(String, CurseModFileInfo, std::time::SystemTime)> = self.database.rows()?.into_iter()
+            // API responses don't parse as file details.
+            .filter_map(|row| Some((row.url().to_string(), serde_json::from_str(row.result()).ok()?, row.downloaded())))
+            .collect();
+        files.sort_by_key(|(_, _, downloaded)| *downloaded);
+        let total = files.len();
+        files.truncate(sample.unwrap_or(total));
+        log::info!("Revalidating {} of {} cached files", files.len(), total);
+        let results: Vec<Result<bool>> = self.download_pool.install(|| files.par_iter().map(|(url, cached, _)| {
+            let matches = self.downloader.revalidate_file(cached).context(format!("While checking {}", url))?;
+            if matches {
+                // Marks it as checked, so the next --sample moves on to other files.
+                self.database.put(url, &serde_json::to_string(cached)?)?;
+            } else {
+                self.database.invalidate(url)?;
+            }
+            Ok(matches)
+        }).collect());
+        let evicted = results.iter().filter(|r| matches!(r, Ok(false))).count();
+        let failures: Vec<String> = results.into_iter().filter_map(Result::err).map(|e| format!("{:#}", e)).collect();
+        log::info!("Dropped {} changed files from the cache; {} are unchanged", evicted, files.len() - evicted - failures.len());
+        if !failures.is_empty() {
+            anyhow::bail!("{} files couldn't be checked:\n  {}", failures.len(), failures.join("\n  "));
+        }
+        Ok(())
+    }
+
+    fn remove_mod(&self, yaml_manifest_path: &Path, name: &str, remove_orphans: bool) -> Result<()> {
+        // Only the given file is edited, but dependencies can come from anywhere in the pack.
+        let mut manifest = YamlManifest::load_for_editing(yaml_manifest_path)?;
+        if !manifest.mods.iter().any(|m| m.name == name) {
+            anyhow::bail!("{} is not in {:?}; it may come from an import", name, yaml_manifest_path);
+        }
+        let full_manifest = YamlManifest::recursive_load_from_file(yaml_manifest_path)?;
+        let version_filter = full_manifest.version_filter()?;
+        log::info!("Resolving dependencies of {} mods...", full_manifest.mods.len());
+        let mods: Vec<ModDependencies> = full_manifest.mods.par_iter().filter(|m| !m.is_direct()).filter_map(|yaml_mod| {
+            match self.resolve_mod_file(yaml_mod, &version_filter) {
+                Ok((id, mod_file)) => Some(ModDependencies {
+                    name: yaml_mod.name.clone(),
+                    id,
+                    dependencies: mod_file.dependencies.iter()
+                        .filter(|d| d.relation_type == CurseFileDependency::REQUIRED_DEPENDENCY)
+                        .map(|d| d.mod_id)
+                        .collect(),
+                }),
+                Err(e) => {
+                    log::warn!("Could not resolve {}, ignoring its dependencies: {:#}", yaml_mod.name, e);
+                    None
+                }
+            }
+        }).collect();
+
+        let plan = remove::plan_removal(&mods, name);
+        for dependent in &plan.dependents {
+            log::warn!("{} requires {}, which is being removed", dependent, name);
+        }
+        let mut removed = vec![name.to_string()];
+        for orphan in &plan.orphans {
+            if !remove_orphans {
+                log::info!("{} was only required by {}; --remove-orphans would remove it too", orphan, name);
+            } else if manifest.mods.iter().any(|m| &m.name == orphan) {
+                log::info!("Also removing {}, which was only required by {}", orphan, name);
+                removed.push(orphan.clone());
+            } else {
+                log::warn!("{} was only required by {}, but comes from an import", orphan, name);
+            }
+        }
+        manifest.mods.retain(|m| !removed.contains(&m.name));
+
+        log::info!("Writing manifest...");
+        manifest.save(yaml_manifest_path)?;
+        log::info!("Removed {}", removed.join(", "));
+        Ok(())
+    }
+
+    fn pin_mods(&self, yaml_manifest_path: &Path) -> Result<()> {
+        let mut manifest = YamlManifest::load_for_editing(yaml_manifest_path)?;
+        // Resolved with variables expanded, as they would be when generating. Own entries are
+        // matched up by the names they end up with, listed in the same order.
+        let full_manifest = YamlManifest::recursive_load_from_file(yaml_manifest_path)?;
+        let own_names = YamlManifest::load_own_names(yaml_manifest_path)?;
+        let version_filter = full_manifest.version_filter()?;
+        let floating: Vec<&YamlMod> = full_manifest.mods.iter()
+            .filter(|m| m.is_floating() && m.split_from.is_none() && !m.is_held())
+            .filter(|m| own_names.contains(&m.name))
+            .collect();
+        for (own, name) in manifest.mods.iter().zip(&own_names).filter(|(m, _)| m.is_floating()) {
+            if own.is_held() {
+                // The newest file is the one a hold keeps it from, and the held file is only in the lock.
+                log::info!("Not pinning {}, as it's held back; take its `hold` out to pin it", own.name);
+            } else if own.client_id.is_some() || own.server_id.is_some() {
+                log::warn!("Not pinning {}, as one pin can't cover its separate client and server projects", own.name);
+            } else if !floating.iter().any(|m| &m.name == name) {
+                log::warn!("Not pinning {}, as it doesn't float once loaded; it may be disabled or inherit a pin", own.name);
+            }
+        }
+        log::info!("Pinning {} floating mods...", floating.len());
+        let results: Vec<(String, Result<(u32, CurseModFile)>)> = floating.par_iter()
+            .map(|yaml_mod| (yaml_mod.name.clone(), self.resolve_mod_file(yaml_mod, &version_filter)))
+            .collect();
+
+        let mut failures = vec![];
+        let mut pinned = 0;
+        for (name, result) in results {
+            match result {
+                Ok((project_id, mod_file)) => {
+                    let own = own_names.iter().position(|own| *own == name).unwrap();
+                    let entry = &mut manifest.mods[own];
+                    log::info!("Pinned {} to {} (file {})", name, mod_file.file_name, mod_file.id);
+                    // The pinned file is from one of the projects, so that's the one to keep.
+                    if !entry.ids.is_empty() {
+                        log::info!("Replacing {}'s ids with project {}, which the file is from", name, project_id);
+                        entry.ids.clear();
+                    }
+                    entry.id.get_or_insert(project_id);
+                    entry.pin(mod_file.id);
+                    pinned += 1;
+                }
+                Err(e) => failures.push(format!("{}: {:#}", name, e)),
+            }
+        }
+
+        if pinned > 0 {
+            log::info!("Writing manifest...");
+            manifest.save(yaml_manifest_path)?;
+        }
+        if !failures.is_empty() {
+            anyhow::bail!("{} mods could not be pinned and still float:\n  {}", failures.len(), failures.join("\n  "));
+        }
+        log::info!("Pinned {} mods", pinned);
+        Ok(())
+    }
+
+    fn unpin_mods(&self, yaml_manifest_path: &Path, names: &[String]) -> Result<()> {
+        let mut manifest = YamlManifest::load_for_editing(yaml_manifest_path)?;
+        let missing: Vec<&str> = names.iter().filter(|n| !manifest.mods.iter().any(|m| &m.name == *n)).map(String::as_str).collect();
+        if !missing.is_empty() {
+            anyhow::bail!("{} not in {:?}; they may come from an import", missing.join(", "), yaml_manifest_path);
+        }
+        let locked: Vec<&str> = manifest.mods.iter()
+            .filter(|m| m.is_locked() && names.contains(&m.name))
+            .map(|m| m.name.as_str())
+            .collect();
+        if !locked.is_empty() {
+            anyhow::bail!("Refusing to unpin {}, which are locked; remove their `locked: true` first", locked.join(", "));
+        }
+
+        let mut unpinned = vec![];
+        for yaml_mod in manifest.mods.iter_mut().filter(|m| names.is_empty() || names.contains(&m.name)) {
+            if yaml_mod.is_locked() {
+                log::info!("Keeping {} pinned, as it's locked", yaml_mod.name);
+            } else if yaml_mod.is_held() && names.is_empty() {
+                log::info!("Keeping {} pinned, as it's held back; name it to unpin it anyway", yaml_mod.name);
+            } else if yaml_mod.is_direct() {
+                log::warn!("Not unpinning {}, as direct downloads have no newest file", yaml_mod.name);
+            } else if yaml_mod.unpin() {
+                unpinned.push(yaml_mod.name.clone());
+            }
+        }
+        if unpinned.is_empty() {
+            log::info!("No pins to take out");
+            return Ok(());
+        }
+        log::info!("Writing manifest...");
+        manifest.save(yaml_manifest_path)?;
+        log::info!("Unpinned {}", unpinned.join(", "));
+        Ok(())
+    }
+
+    fn check_server(&self, mods_dir: &Path, nix_manifest_path: &Path) -> Result<()> {
+        let source = std::fs::read_to_string(nix_manifest_path)
+            .context(format!("While reading {:?}", nix_manifest_path))?;
+        let manifest = nix::parse(&source)
+            .context(format!("While parsing {:?}", nix_manifest_path))?;
+        let drift = server_check::check_mods_dir(&manifest, mods_dir)?;
+        for missing in &drift.missing {
+            log::warn!("Missing: {}", missing);
+        }
+        for extra in &drift.extra {
+            log::warn!("Not in manifest: {}", extra);
+        }
+        for mismatched in &drift.mismatched {
+            log::warn!("Mismatched: {}", mismatched);
+        }
+        if !drift.is_empty() {
+            anyhow::bail!("{:?} has drifted: {} missing, {} extra and {} mismatched files",
+                          mods_dir, drift.missing.len(), drift.extra.len(), drift.mismatched.len());
+        }
+        log::info!("{:?} matches the manifest", mods_dir);
+        Ok(())
+    }
+
+    fn list_outdated(&self, yaml_manifest_path: &Path) -> Result<()> {
+        let yaml_manifest = YamlManifest::recursive_load_from_file(yaml_manifest_path)?;
+        let version_filter = yaml_manifest.version_filter()?;
+        let loader = yaml_manifest.loader_name();
+        // Unpinned mods always get the newest file, so only pins can fall behind.
+        let pinned: Vec<&YamlMod> = yaml_manifest.mods.iter()
+            .filter(|m| m.files.as_ref().and_then(|f| f.first()).is_some_and(|f| f.id.is_some()))
+            .collect();
+        log::info!("Checking {} pinned mods for updates...", pinned.len());
+        let updates: Vec<outdated::Update> = pinned.par_iter().filter_map(|yaml_mod| {
+            let result = self.resolve_mod_file(yaml_mod, &version_filter).and_then(|(project_id, pinned)| {
+                let files = self.downloader.request_mod_files(project_id, &version_filter)?;
+                let newest = outdated::newest_file(files, loader.as_deref())
+                    .context(format!("No files for {} {}", version_filter, loader.as_deref().unwrap_or("")))?;
+                Ok(outdated::Update { name: yaml_mod.name.clone(), pinned, newest })
+            });
+            result.map_err(|e| log::warn!("Could not check {}: {:#}", yaml_mod.name, e)).ok()
+        }).collect();
+
+        let outdated = outdated::by_staleness(updates);
+        for update in &outdated {
+            let held = pinned.iter().any(|m| m.name == update.name && m.is_held());
+            println!("{:<32} {} ({}) -> {} ({}), {} days behind{}",
+                     update.name,
+                     update.pinned.file_name, update.pinned.file_date.get(..10).unwrap_or(&update.pinned.file_date),
+                     update.newest.file_name, update.newest.file_date.get(..10).unwrap_or(&update.newest.file_date),
+                     update.days_behind(),
+                     if held { ", held back" } else { "" });
+        }
+        log::info!("{} of {} pinned mods have updates", outdated.len(), pinned.len());
+        Ok(())
+    }
+
     fn generate_yaml_mod_entry(&self, mod_info: &ModFile) -> Result<YamlMod> {
         log::info!("Fetching data for file {} in project {}", mod_info.file_id, mod_info.project_id);
         let addon_info = self.downloader.request_addon_info(mod_info.project_id)?;
-        Ok(YamlMod::with_files(&addon_info.slug, mod_info.project_id, YamlModFile::with_id(mod_info.file_id)))
+        Ok(YamlMod::from_curse_file(&addon_info.slug, mod_info))
+    }
+}
+
+/// Doesn't need the App, as nothing is looked up.
+fn lint_nix_manifest(nix_manifest_path: &Path) -> Result<()> {
+    let source = std::fs::read_to_string(nix_manifest_path)
+        .context(format!("While reading {:?}", nix_manifest_path))?;
+    let manifest = nix::parse(&source).context("It isn't in the format cursetool writes")?;
+    let problems = lint_nix::lint(&manifest);
+    for problem in &problems {
+        log::warn!("{}", problem);
+    }
+    if !problems.is_empty() {
+        anyhow::bail!("Found {} problems", problems.len());
     }
+    log::info!("{:?} looks fine", nix_manifest_path);
+    Ok(())
 }
 
 fn main() -> Result<()> {
-    TermLogger::init(LevelFilter::Info, Config::default(), TerminalMode::Mixed)?;
+    let mut commandline = parse_commandline();
+    let why_failed = matches!(commandline.mode, Mode::WhyFailed { .. });
+    trace::init_logger(if why_failed { LevelFilter::Trace } else { LevelFilter::Info })?;
 
-    let commandline = parse_commandline();
-    let database = Database::from_filesystem()?;
-    let downloader = Downloader::new(&database);
+    output::keep_backups(commandline.backup);
+    interrupt::install();
+    let mut config = Config::load(commandline.config.as_deref())?;
+    model::limit_import_depth(config.max_import_depth);
+    if let Some(ref hosts) = commandline.allow_hosts {
+        config.allow_hosts = Some(hosts.clone());
+    }
+    if let Some(roots) = commandline.tls_roots {
+        config.tls_roots = roots;
+    }
+    let database = database::open(config.cache_backend, config.cache_retention())?;
+    let mut downloader = Downloader::new(&*database, &config)?
+        .max_download_rate(commandline.max_download_rate.map_or(0, |rate| rate.0))
+        .local_jar_dirs(commandline.local_jar_dirs.clone())
+        .fault_injector(commandline.fault_inject.take())
+        .deadline(Deadline::after(commandline.deadline.map(|deadline| deadline.0)))
+        .interactive(commandline.interactive)
+        .bypass_cache(why_failed);
+    if let Mode::Cache(CacheCommand::Refresh { within, requests_per_minute, .. }) = commandline.mode {
+        downloader = downloader
+            .refresh_margin(within.0)
+            .request_interval(Duration::from_secs(60) / requests_per_minute.max(1));
+    }
 
-    let app = App::new(&commandline, &database, &downloader);
+    let app = App::new(&commandline, &config, &*database, &downloader, None)?;
 
     app.main()
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
-
     use super::*;
+    use crate::database::Database;
+    use crate::mirror::MirrorKey;
 
-    fn with_app<F, X>(mode: Mode, input_path: PathBuf, output_path: PathBuf, f: F) -> Result<X>
+    fn with_app<F, X>(mode: Mode, f: F) -> Result<X>
         where F: FnOnce(App) -> Result<X> {
-        TermLogger::init(LevelFilter::Debug, Config::default(), TerminalMode::Mixed)?;
+        with_observed_app(mode, None, f)
+    }
+
+    fn with_observed_app<F, X>(mode: Mode, observer: Option<&dyn ProgressObserver>, f: F) -> Result<X>
+        where F: FnOnce(App) -> Result<X> {
+        // Only the first test to get here can set the logger.
+        let _ = trace::init_logger(LevelFilter::Debug);
 
         let commandline = Commandline {
             mode,
-            input_file: input_path,
-            output_file: output_path,
+            config: None,
+            threads: None,
+            download_threads: None,
+            max_download_rate: None,
+            max_download_bytes: None,
+            status_file: None,
+            metrics_file: None,
+            output_dir: None,
+            tls_roots: None,
+            backup: false,
+            local_jar_dirs: vec![],
+            allow_hosts: None,
+            deadline: None,
+            fix: false,
+            interactive: false,
+            fault_inject: None,
         };
         let database = Database::for_tests()?;
-        let downloader = Downloader::new(&database);
-        let app = App::new(&commandline, &database, &downloader);
+        let config = Config::default();
+        let downloader = Downloader::new(&database, &config)?;
+        let app = App::new(&commandline, &config, &database, &downloader, observer)?;
         f(app)
     }
 
+    /// Options for a `yaml` run writing only `output_file`, which tests change what they need of.
+    fn yaml_options(input_file: PathBuf, output_file: PathBuf) -> YamlOptions {
+        YamlOptions {
+            input_file,
+            output_file: Some(output_file),
+            client_output: None,
+            server_output: None,
+            hashes: "sha256".parse().unwrap(),
+            lock_file: None,
+            resolve_loader: false,
+            no_provenance: true,
+            verify_existing: None,
+            full: false,
+            allow_downgrade: false,
+            versions: vec![],
+            src_prefix: None,
+            mirror_key: MirrorKey::Sha256,
+            fetch_list: None,
+            rich_metadata: false,
+            icon_dir: None,
+            print_merge: false,
+            base32_sha256: false,
+            verify_prefetch: None,
+        }
+    }
+
     #[test]
+    fn leaves_other_side_deps_out_of_side_outputs() {
+        let entries = vec![
+            NixMod::new("fabric-api-client", "Fabric API", 1).side(Side::Client),
+            NixMod::new("fabric-api-server", "Fabric API", 2).side(Side::Server),
+            NixMod::new("rei", "REI", 3).deps(vec!["fabric-api-client".to_string(), "fabric-api-server".to_string()]),
+        ];
+        let client = side_entries(&entries, Side::on_client);
+        assert_eq!(client.iter().map(|m| m.slug.as_str()).collect::<Vec<_>>(), vec!["fabric-api-client", "rei"]);
+        assert_eq!(client[1].deps, vec!["fabric-api-client"]);
+        assert_eq!(side_entries(&entries, Side::on_server)[1].deps, vec!["fabric-api-server"]);
+    }
+
+    #[test]
+    #[allow(clippy::get_first)]
     fn can_generate_yaml() -> Result<()> {
         let dir = tempfile::tempdir()?;
         let manifest_path = dir.path().join("manifest.json");
@@ -214,7 +1839,7 @@ mod tests {
 
         write_simple_manifest(File::create(&manifest_path)?)?;
 
-        with_app(Mode::Curse, manifest_path, output_path.clone(), |app| { app.main() })?;
+        with_app(Mode::Curse { input_file: manifest_path, output_file: Some(output_path.clone()) }, |app| { app.main() })?;
 
         let generated_manifest: YamlManifest = serde_yaml::from_reader(&File::open(output_path)?)?;
         assert_eq!(generated_manifest.version, "1.12.2".to_string(), "Version is incorrect");
@@ -223,14 +1848,172 @@ mod tests {
         assert_eq!(generated_manifest.mods.get(0).unwrap().name, "iron-chests", "Iron Chests should be present");
         assert_eq!(generated_manifest.mods.get(1).unwrap().name, "jei", "JEI should be present");
         assert_eq!(generated_manifest.mods.get(0).unwrap().files.as_ref().unwrap()[0].id.unwrap(), 2747935, "File ID should be set");
+        assert!(generated_manifest.mods.first().unwrap().required.is_none(), "Required mods shouldn't spell it out");
+
+        Ok(())
+    }
 
+    #[test]
+    fn streams_nix_manifest() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("manifest.nix");
+        let mods = [NixMod::new("a", "A", 1), NixMod::new("b", "B", 2)];
+        write_nix_manifest(&path, None, "1.12.2", None, None, mods.iter())?;
+
+        let expected = format!("{{\n    \"version\" = \"1.12.2\";\n    \"imports\" = [];\n    \"mods\" = {{\n    {}\n{}\n    }};\n}}",
+                               mods[0], mods[1]);
+        assert_eq!(std::fs::read_to_string(&path)?, expected);
+        let parsed = nix::parse(&expected)?;
+        assert_eq!(parsed["mods"]["b"]["id"], 2);
+
+        let grouped = [NixMod { group: Some("performance".to_string()), ..NixMod::new("a", "A", 1) }, NixMod::new("b", "B", 2)];
+        write_nix_manifest(&path, None, "1.12.2", None, None, grouped.iter())?;
+        let written = std::fs::read_to_string(&path)?;
+        assert!(written.contains(&format!("{}\n\n    # performance\n{}", grouped[1], grouped[0])), "{}", written);
+        assert_eq!(nix::parse(&written)?["mods"]["a"]["group"], "performance");
+
+        Ok(())
+    }
+
+    #[test]
+    fn generates_empty_nix_from_empty_manifest() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input_file = dir.path().join("manifest.yaml");
+        let output_file = dir.path().join("manifest.nix");
+        let lock_file = dir.path().join("manifest.lock.json");
+        std::fs::write(&input_file, "# Mods go here\nversion: 1.12.2\nmods:\n")?;
+
+        with_app(Mode::Yaml(YamlOptions {
+            hashes: "md5,sha256".parse()?,
+            lock_file: Some(lock_file.clone()),
+            ..yaml_options(input_file, output_file.clone())
+        }), |app| app.main())?;
+
+        let nix = std::fs::read_to_string(&output_file)?;
+        assert_eq!(nix, "{\n    \"version\" = \"1.12.2\";\n    \"imports\" = [];\n    \"java\" = {\n        \"major\" = 8;\n    };\n    \"mods\" = {};\n}");
+        assert_eq!(nix::parse(&nix)?["mods"], serde_json::json!({}));
+        assert_eq!(nix::parse(&nix)?["java"]["major"], 8);
+        assert!(Lockfile::read(&lock_file)?.unwrap().mods.is_empty());
+
+        std::fs::write(dir.path().join("blank.yaml"), "# Nothing yet\n")?;
+        let error = YamlManifest::recursive_load_from_file(&dir.path().join("blank.yaml")).unwrap_err();
+        assert!(error.to_string().contains("needs at least a version"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn writes_one_output_per_version() -> Result<()> {
+        assert_eq!(versioned_path(Path::new("out/mods.lock.json"), "1.20.1"), Path::new("out/mods.lock-1.20.1.json"));
+        assert_eq!(versioned_path(Path::new("mods"), "1.19.2"), Path::new("mods-1.19.2"));
+
+        let dir = tempfile::tempdir()?;
+        let input_file = dir.path().join("manifest.yaml");
+        std::fs::write(&input_file, "version: 1.12.2\n")?;
+        with_app(Mode::Yaml(YamlOptions {
+            versions: vec!["1.19.2".to_string(), "1.20.1".to_string()],
+            ..yaml_options(input_file, dir.path().join("mods.nix"))
+        }), |app| app.main())?;
+        for version in ["1.19.2", "1.20.1"] {
+            let nix = nix::parse(&std::fs::read_to_string(dir.path().join(format!("mods-{}.nix", version)))?)?;
+            assert_eq!(nix["version"], version);
+        }
+        assert!(!dir.path().join("mods.nix").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn unpins_all_but_locked_mods() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let manifest = dir.path().join("manifest.yaml");
+        std::fs::write(&manifest, "version: 1.12.2\nmods:\n\
+                                   - name: fragile\n  locked: true\n  files:\n  - id: 1\n\
+                                   - name: jei\n  files:\n  - id: 2\n")?;
+        let unpin = |names: Vec<String>| with_app(Mode::Unpin { manifest: manifest.clone(), names }, |app| app.main());
+        assert!(unpin(vec!["fragile".to_string()]).is_err(), "Locked mods can't be unpinned by name");
+        unpin(vec![])?;
+        let mods = YamlManifest::load_for_editing(&manifest)?.mods;
+        assert!(!mods[0].is_floating(), "Locked mods keep their pins");
+        assert!(mods[1].is_floating());
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_comments_when_unpinning() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let manifest = dir.path().join("manifest.yaml");
+        std::fs::write(&manifest, "# yaml-language-server: $schema=manifest.schema.json\nversion: 1.12.2\nmods:\n\
+                                   # Recipes\n- name: jei\n  files:\n  - id: 2\n- name: waila  # Tooltips\n")?;
+        with_app(Mode::Unpin { manifest: manifest.clone(), names: vec![] }, |app| app.main())?;
+        assert_eq!(std::fs::read_to_string(&manifest)?,
+                   "# yaml-language-server: $schema=manifest.schema.json\nversion: 1.12.2\nmods:\n# Recipes\n- name: jei\n- name: waila  # Tooltips\n");
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_held_mods_floating_when_pinning() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let manifest = dir.path().join("manifest.yaml");
+        std::fs::write(&manifest, "version: 1.12.2\nmods:\n- name: jei\n  hold: true\n- name: ae2\n  files:\n  - id: 2\n")?;
+        with_app(Mode::Pin { manifest: manifest.clone() }, |app| app.main())?;
+        let mods = YamlManifest::load_for_editing(&manifest)?.mods;
+        assert!(mods[0].is_floating(), "Held mods aren't pinned to the newest file");
+        Ok(())
+    }
+
+    #[test]
+    fn replays_one_mod_from_the_manifest() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let manifest = dir.path().join("manifest.yaml");
+        std::fs::write(&manifest, "version: 1.12.2\nmods:\n\
+                                   - name: known\n  files:\n  - src: https://example.org/known.jar\n    size: 1\n    md5: abc\n    hashes:\n      sha256: def\n\
+                                   - name: off\n  enabled: false\n")?;
+        let why_failed = |name: &str| with_app(Mode::WhyFailed { manifest: manifest.clone(), name: name.to_string() }, |app| app.main());
+        why_failed("known")?;
+        let error = format!("{:#}", why_failed("off").unwrap_err());
+        assert!(error.contains("off is disabled"), "{}", error);
+        let error = format!("{:#}", why_failed("jei").unwrap_err());
+        assert!(error.contains("jei isn't in"), "{}", error);
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver(Mutex<Vec<String>>);
+
+    impl ProgressObserver for RecordingObserver {
+        fn mod_started(&self, name: &str) {
+            self.0.lock().unwrap().push(format!("started {}", name));
+        }
+
+        fn mod_resolved(&self, name: &str) {
+            self.0.lock().unwrap().push(format!("resolved {}", name));
+        }
+
+        fn mod_failed(&self, name: &str, _error: &anyhow::Error) {
+            self.0.lock().unwrap().push(format!("failed {}", name));
+        }
+    }
+
+    #[test]
+    fn reports_progress_per_mod() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input_file = dir.path().join("manifest.yaml");
+        std::fs::write(&input_file, "version: 1.12.2\nmods:\n\
+                                     - name: known\n  files:\n  - src: https://example.org/known.jar\n    size: 1\n    hashes:\n      sha256: abc\n\
+                                     - name: broken\n  files:\n  - src: https://example.invalid/broken.jar\n")?;
+        let observer = RecordingObserver::default();
+        let result = with_observed_app(Mode::Yaml(yaml_options(input_file, dir.path().join("mods.nix"))), Some(&observer), |app| app.main());
+        assert!(result.is_err(), "broken.jar can't be downloaded");
+        let mut events = observer.0.into_inner().unwrap();
+        events.sort();
+        assert_eq!(events, vec!["failed broken", "resolved known", "started broken", "started known"]);
         Ok(())
     }
 
     fn write_simple_manifest(file: File) -> Result<()> {
         serde_json::to_writer(file, &CurseManifest {
             minecraft: MinecraftVersion {
-               version: "1.12.2".to_string()
+               version: "1.12.2".to_string(),
+               mod_loaders: vec![]
             },
             files: vec![
                 // JEI 4.16.1.302
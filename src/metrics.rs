@@ -0,0 +1,132 @@
+//! Run metrics in the Prometheus textfile format, for node_exporter's textfile collector to
+//! pick up after scheduled runs.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::output::AtomicFile;
+use crate::progress::ProgressObserver;
+use crate::quota::EndpointUsage;
+
+/// Counts mods as they resolve, to be written out once the run is over.
+pub struct Metrics {
+    path: Option<PathBuf>,
+    started: Instant,
+    resolved: AtomicU32,
+    failed: AtomicU32,
+}
+
+/// How a run went, as written to the textfile.
+#[derive(Debug, PartialEq)]
+pub struct RunSummary {
+    pub duration: Duration,
+    pub success: bool,
+    pub resolved: u32,
+    pub failed: u32,
+    pub usage: BTreeMap<String, EndpointUsage>,
+}
+
+impl Metrics {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Metrics { path, started: Instant::now(), resolved: AtomicU32::new(0), failed: AtomicU32::new(0) }
+    }
+
+    pub fn summary(&self, success: bool, usage: BTreeMap<String, EndpointUsage>) -> RunSummary {
+        RunSummary {
+            duration: self.started.elapsed(),
+            success,
+            resolved: self.resolved.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            usage,
+        }
+    }
+
+    /// Writes the textfile, if one was asked for. Like the status file, failing to doesn't fail the run.
+    pub fn write(&self, success: bool, usage: BTreeMap<String, EndpointUsage>) {
+        if let Some(ref path) = self.path {
+            if let Err(e) = write_textfile(path, &self.summary(success, usage)) {
+                log::warn!("Could not write metrics to {:?}: {:#}", path, e);
+            }
+        }
+    }
+}
+
+impl ProgressObserver for Metrics {
+    fn mod_resolved(&self, _name: &str) {
+        self.resolved.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn mod_failed(&self, _name: &str, _error: &anyhow::Error) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn write_textfile(path: &Path, summary: &RunSummary) -> Result<()> {
+    // Written atomically, as the collector may read it at any moment, and never backed up, as it
+    // would read a `.bak` in its directory too.
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(render(summary, SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()).as_bytes())
+        .context(format!("While writing {:?}", path))?;
+    file.commit_without_backup()
+}
+
+fn metric(out: &mut String, name: &str, help: &str, samples: &[(String, String)]) {
+    let _ = writeln!(out, "# HELP cursetool_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE cursetool_{} gauge", name);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "cursetool_{}{} {}", name, labels, value);
+    }
+}
+
+/// Escapes a label value as the exposition format wants.
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub fn render(summary: &RunSummary, finished_at: u64) -> String {
+    let mut out = String::new();
+    let single = |value: String| vec![(String::new(), value)];
+    metric(&mut out, "last_run_timestamp_seconds", "When the last run finished, in seconds since the epoch.", &single(finished_at.to_string()));
+    metric(&mut out, "last_run_duration_seconds", "How long the last run took.", &single(format!("{:.3}", summary.duration.as_secs_f64())));
+    metric(&mut out, "last_run_success", "Whether the last run succeeded.", &single((summary.success as u8).to_string()));
+    metric(&mut out, "mods_resolved", "Mods resolved in the last run.", &single(summary.resolved.to_string()));
+    metric(&mut out, "mods_failed", "Mods that failed to resolve in the last run.", &single(summary.failed.to_string()));
+    let per_endpoint = |f: fn(&EndpointUsage) -> u32| summary.usage.iter()
+        .map(|(endpoint, usage)| (format!("{{endpoint=\"{}\"}}", label(endpoint)), f(usage).to_string()))
+        .collect::<Vec<_>>();
+    metric(&mut out, "requests", "Network requests made in the last run, per endpoint.", &per_endpoint(|u| u.requests));
+    metric(&mut out, "cache_hits", "Requests answered from the cache in the last run, per endpoint.", &per_endpoint(|u| u.cache_hits));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_textfile_format() {
+        let metrics = Metrics::new(None);
+        metrics.mod_resolved("jei");
+        metrics.mod_resolved("waystones");
+        metrics.mod_failed("broken", &anyhow::anyhow!("No files"));
+        let usage = vec![("v1/mods/{id}".to_string(), EndpointUsage { requests: 3, cache_hits: 5 })].into_iter().collect();
+        let summary = RunSummary { duration: Duration::from_millis(12_345), ..metrics.summary(false, usage) };
+        let rendered = render(&summary, 1700000000);
+        for line in &["cursetool_last_run_timestamp_seconds 1700000000",
+                      "cursetool_last_run_duration_seconds 12.345",
+                      "cursetool_last_run_success 0",
+                      "cursetool_mods_resolved 2",
+                      "cursetool_mods_failed 1",
+                      "# TYPE cursetool_requests gauge",
+                      "cursetool_requests{endpoint=\"v1/mods/{id}\"} 3",
+                      "cursetool_cache_hits{endpoint=\"v1/mods/{id}\"} 5"] {
+            assert!(rendered.lines().any(|l| l == *line), "Missing {:?} in\n{}", line, rendered);
+        }
+    }
+}
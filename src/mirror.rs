@@ -0,0 +1,121 @@
+//! Pointing outputs at an internal mirror, for builds without access to the CDN.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+use crate::hashes::HashAlgorithm;
+use crate::loader::LoaderInfo;
+use crate::model::NixMod;
+use crate::output::AtomicFile;
+
+/// How files are laid out on the mirror.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MirrorKey {
+    /// `<sha256>/<filename>`, so different files with the same name can't collide.
+    Sha256,
+    /// `<filename>`, as CurseForge names it.
+    Filename,
+}
+
+impl FromStr for MirrorKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha256" => Ok(MirrorKey::Sha256),
+            "filename" => Ok(MirrorKey::Filename),
+            other => anyhow::bail!("Unknown mirror layout {}, expected sha256 or filename", other),
+        }
+    }
+}
+
+/// Rewrites `src` attributes into the mirror, remembering where each file came from.
+pub struct Mirror {
+    prefix: String,
+    key: MirrorKey,
+    // Path on the mirror to upstream URL, for the fetch list.
+    fetched: BTreeMap<String, String>,
+}
+
+impl Mirror {
+    pub fn new(prefix: &str, key: MirrorKey) -> Self {
+        let prefix = if prefix.ends_with('/') { prefix.to_string() } else { format!("{}/", prefix) };
+        Mirror { prefix, key, fetched: BTreeMap::new() }
+    }
+
+    /// Where the file lives on the mirror, relative to the prefix.
+    fn path(&self, encoded_filename: &str, hashes: &BTreeMap<HashAlgorithm, String>) -> Result<String> {
+        match self.key {
+            MirrorKey::Filename => Ok(encoded_filename.to_string()),
+            MirrorKey::Sha256 => {
+                let sha256 = hashes.get(&HashAlgorithm::Sha256)
+                    .context(format!("Keying the mirror by sha256 needs sha256 in --hashes, which {} lacks", encoded_filename))?;
+                Ok(format!("{}/{}", sha256, encoded_filename))
+            }
+        }
+    }
+
+    fn rewrite(&mut self, src: &str, encoded_filename: &str, hashes: &BTreeMap<HashAlgorithm, String>) -> Result<String> {
+        let path = self.path(encoded_filename, hashes)?;
+        match self.fetched.get(&path) {
+            Some(upstream) if upstream != src => anyhow::bail!("{} and {} would both be mirrored as {}", upstream, src, path),
+            _ => (),
+        }
+        self.fetched.insert(path.clone(), src.to_string());
+        Ok(format!("{}{}", self.prefix, path))
+    }
+
+    pub fn mod_entry(&mut self, nix_mod: &NixMod) -> Result<NixMod> {
+        let src = self.rewrite(&nix_mod.src, &nix_mod.encoded, &nix_mod.hashes)
+            .context(format!("While mirroring {}", nix_mod.slug))?;
+        Ok(NixMod { src, ..nix_mod.clone() })
+    }
+
+    pub fn loader(&mut self, loader: &LoaderInfo) -> Result<LoaderInfo> {
+        let filename = loader.src.rsplit('/').next().unwrap_or(&loader.src);
+        let src = self.rewrite(&loader.src, filename, &loader.hashes)
+            .context(format!("While mirroring the {} installer", loader.name))?;
+        Ok(LoaderInfo { src, ..loader.clone() })
+    }
+
+    /// Writes one `<upstream url>\t<path on the mirror>` line per file, for the job that fills the mirror.
+    pub fn write_fetch_list(&self, path: &Path) -> Result<()> {
+        let mut out = AtomicFile::create(path)?;
+        for (mirrored, upstream) in &self.fetched {
+            writeln!(out, "{}\t{}", upstream, mirrored)?;
+        }
+        out.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_sources_into_the_mirror() -> Result<()> {
+        let jei = NixMod::new("jei", "JEI", 238222)
+            .file("jei 1.12.2.jar", "https://media.forgecdn.net/files/3043/174/jei%201.12.2.jar", 1234)
+            .hash(HashAlgorithm::Sha256, "abc");
+        let mut mirror = Mirror::new("https://mirror.internal/mods", MirrorKey::Sha256);
+        assert_eq!(mirror.mod_entry(&jei)?.src, "https://mirror.internal/mods/abc/jei%201.12.2.jar");
+
+        let dir = tempfile::tempdir()?;
+        let fetch_list = dir.path().join("fetch.tsv");
+        mirror.write_fetch_list(&fetch_list)?;
+        assert_eq!(std::fs::read_to_string(&fetch_list)?,
+                   "https://media.forgecdn.net/files/3043/174/jei%201.12.2.jar\tabc/jei%201.12.2.jar\n");
+
+        let mut by_name = Mirror::new("https://mirror.internal/mods/", MirrorKey::Filename);
+        assert_eq!(by_name.mod_entry(&jei)?.src, "https://mirror.internal/mods/jei%201.12.2.jar");
+        let clash = NixMod::new("jei-fork", "JEI", 1)
+            .file("jei 1.12.2.jar", "https://media.forgecdn.net/files/1/2/jei%201.12.2.jar", 1);
+        assert!(by_name.mod_entry(&clash).is_err(), "Different files can't share a name on the mirror");
+        assert!(mirror.mod_entry(&NixMod::new("bare", "Bare", 1)).is_err(), "sha256 is needed to key by it");
+        Ok(())
+    }
+}
@@ -1,28 +1,40 @@
-use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use schemars::schema::RootSchema;
+use serde::{Serialize, Deserialize, Deserializer};
 use serde_json::json;
-use std::collections::{HashSet, HashMap};
-use std::path::Path;
+use std::collections::{BTreeMap, HashSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use anyhow::{Result, Context};
 use std::fs::File;
+use std::io::Write;
+
+use crate::game_version::VersionFilter;
+use crate::editing;
+use crate::hashes::HashAlgorithm;
+use crate::import;
+use crate::modmeta::ModMetadata;
+use crate::modrinth;
+use crate::naming;
+use crate::nix;
+use crate::output::AtomicFile;
+use crate::provenance::EntryProvenance;
+use crate::slugs;
+use crate::vars;
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct CurseWrapper<T> {
-    pub data: T,
-    pub pagination: Option<Pagination>
-}
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Pagination {
-    pub index: u32,
-    #[serde(rename = "pageSize")]
-    pub page_size: u32,
-    #[serde(rename = "resultCount")]
-    pub result_count: u32,
-    #[serde(rename = "totalCount")]
-    pub total_count: u32,
+pub struct MinecraftVersion {
+    pub version: String,
+    #[serde(rename = "modLoaders")]
+    #[serde(default)]
+    pub mod_loaders: Vec<ModLoader>,
 }
 #[derive(Serialize, Deserialize, Debug)]
-pub struct MinecraftVersion {
-    pub version: String
+pub struct ModLoader {
+    // E.g. forge-14.23.5.2860
+    pub id: String,
+    #[serde(default)]
+    pub primary: bool,
 }
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ModFile {
@@ -37,20 +49,7 @@ pub struct CurseManifest {
     pub minecraft: MinecraftVersion,
     pub files: Vec<ModFile>
 }
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AddonLinks {
-    #[serde(rename = "websiteUrl")]
-    pub website_url: String
-}
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AddonInfo {
-    pub name: String,
-    pub slug: String,
-    pub id: u32,
-    pub links: AddonLinks
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
 pub struct YamlModFile {
     #[serde(skip_serializing_if="Option::is_none")]
     pub name: Option<String>,
@@ -64,177 +63,868 @@ pub struct YamlModFile {
     #[serde(skip_serializing_if="Option::is_none")]
     pub src: Option<String>,
     #[serde(skip_serializing_if="Option::is_none")]
-    pub md5: Option<String>
+    pub md5: Option<String>,
+    /// Known hashes of a direct-URL file, checked against the download if one is needed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hashes: BTreeMap<HashAlgorithm, String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub size: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
 pub struct YamlMod {
     pub name: String,
     #[serde(skip_serializing_if="Option::is_none")]
     pub id: Option<u32>,
+    /// Projects to try in order instead of `id`, for mods that moved to a new project. The
+    /// first with files for the manifest's version is used.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ids: Vec<u32>,
+    /// For mods published as separate client and server projects; expanded into one entry each.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub client_id: Option<u32>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub server_id: Option<u32>,
+    /// The entry this one was split from, if it came from a `client_id`/`server_id` pair.
+    #[serde(skip)]
+    pub split_from: Option<String>,
     #[serde(skip_serializing_if="Option::is_none")]
     pub side: Option<Side>,
     #[serde(skip_serializing_if="Option::is_none")]
     pub required: Option<bool>,
     #[serde(skip_serializing_if="Option::is_none")]
     pub default: Option<bool>,
+    /// Names of other mods in the manifest this one needs, passed through to the nix `deps`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deps: Vec<String>,
+    /// Disabled mods keep their pins in the manifest, but are left out of everything generated from it.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Locked mods must stay pinned to a file; `unpin` refuses them.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub locked: Option<bool>,
+    /// Held mods keep the file they have, pinned or in the lockfile, however much newer ones there are.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub hold: Option<bool>,
+    /// A label for related mods, e.g. `performance`. Grouped mods are listed together in the nix output.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub group: Option<String>,
+    /// Takes whatever this entry leaves out from the imported entry it overrides, instead of
+    /// replacing that entry wholesale. `files` and `deps` are taken or replaced as a whole.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub inherit: Option<bool>,
     #[serde(skip_serializing_if="Option::is_none")]
     pub files: Option<Vec<YamlModFile>>
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct YamlManifest {
     pub version: String,
-    #[serde(default)]
+    /// The mod loader, optionally with its version, e.g. `forge` or `forge-14.23.5.2860`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loader: Option<String>,
+    /// CurseForge's ID for the version group, e.g. `Minecraft 1.21`. Needed to match weekly snapshots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_version_type_id: Option<u32>,
+    /// Substituted for `${name}` in the manifest's strings when it's loaded.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, String>,
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub imports: Vec<String>,
-    #[serde(default)]
-    pub mods: Vec<YamlMod>
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub mods: Vec<YamlMod>,
+    /// Adds the required dependencies `mods` leaves out as entries of their own, marked `auto`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_deps: bool,
+    /// Names of the `enabled: false` mods taken out of `mods` while loading.
+    #[serde(skip)]
+    pub disabled: Vec<String>,
+    /// Every manifest that lists each mod, by the name it has there, in order of precedence.
+    #[serde(skip)]
+    pub sources: BTreeMap<String, Vec<MergeSource>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A manifest that lists a mod, and whether its entry inherits from the ones below it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeSource {
+    pub path: PathBuf,
+    pub inherit: bool,
+}
+
+/// Which manifest's entry was used, e.g. `pack.yaml, inheriting from base.yaml, over other.yaml`.
+pub fn describe_precedence(sources: &[MergeSource]) -> String {
+    let mut used = 1;
+    while used < sources.len() && sources[used - 1].inherit {
+        used += 1;
+    }
+    let mut description = sources.first().map(|s| s.path.display().to_string()).unwrap_or_default();
+    for source in &sources[1..used] {
+        description.push_str(&format!(", inheriting from {}", source.path.display()));
+    }
+    if used < sources.len() {
+        let overridden: Vec<String> = sources[used..].iter().map(|s| s.path.display().to_string()).collect();
+        description.push_str(&format!(", over {}", overridden.join(", ")));
+    }
+    description
+}
+
+pub const DEFAULT_MAX_IMPORT_DEPTH: usize = 16;
+
+static MAX_IMPORT_DEPTH: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_IMPORT_DEPTH);
+
+/// How many levels of imports manifests loaded from now on may have.
+pub fn limit_import_depth(depth: usize) {
+    MAX_IMPORT_DEPTH.store(depth, Ordering::Relaxed);
+}
+
+/// The manifests that led to `path`, for errors, e.g. `pack.yaml -> base.yaml -> libs.yaml`.
+fn import_chain(importers: &[PathBuf], path: &Path) -> String {
+    importers.iter().map(|p| p.as_path()).chain(std::iter::once(path))
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// Lets `mods:` with nothing under it mean no mods, as in a freshly started manifest.
+fn null_as_empty<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+    where D: Deserializer<'de>, T: Deserialize<'de> {
+    Ok(Option::<Vec<T>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CurseModFileInfo {
-    pub md5: String,
-    pub sha256: String,
+    // Older cache entries only have md5 and sha256.
+    #[serde(flatten)]
+    pub hashes: BTreeMap<HashAlgorithm, String>,
     pub size: u64,
     pub download_url: String,
+    // Older cache entries predate jar inspection.
+    #[serde(default)]
+    pub embedded_jars: Vec<String>,
+    #[serde(default)]
+    pub metadata: ModMetadata,
+    /// The CDN's ETag for the file, to notice re-uploads. Older cache entries predate it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
 }
 
 impl YamlManifest {
     pub(crate) fn recursive_load_from_file(manifest_path: &Path) -> Result<Self> {
-        log::info!("Reading manifest file {}...", manifest_path.display());
+        Self::recursive_load_for_version(manifest_path, None)
+    }
+
+    /// Like `recursive_load_from_file`, but with `version` standing in for every file's own
+    /// version, `${version}` included, as `--versions` needs.
+    pub(crate) fn recursive_load_for_version(manifest_path: &Path, version: Option<&str>) -> Result<Self> {
+        // Split projects are expanded only once everything is merged, so that entries
+        // still override imported ones by their original name.
+        Ok(Self::load_imported(manifest_path, &[], version)?.without_inherit_flags().with_normalized_deps().expand_split_projects()?.pin_file_page_urls()?.without_disabled())
+    }
+
+    /// Mods listed by more than one manifest, with every manifest listing them, winner first.
+    pub fn overridden(&self) -> impl Iterator<Item = (&String, &Vec<MergeSource>)> {
+        self.sources.iter().filter(|(_, sources)| sources.len() > 1)
+    }
+
+    /// Entries still marked `inherit` once everything is merged had nothing to inherit from.
+    fn without_inherit_flags(mut self) -> Self {
+        for a_mod in &mut self.mods {
+            if a_mod.inherit.take() == Some(true) {
+                log::warn!("{} has `inherit: true`, but no import has an entry for it to inherit from", a_mod.name);
+            }
+        }
+        self
+    }
+
+    /// Puts names looked up by slug into the form Curse uses. Done to each file before merging,
+    /// so that `JEI` still overrides an imported `jei`.
+    fn with_normalized_slugs(mut self) -> Result<Self> {
+        for a_mod in self.mods.iter_mut().filter(|m| m.searches_by_slug()) {
+            let slug = slugs::normalize(&a_mod.name);
+            if slug != a_mod.name {
+                log::warn!("Slug {:?} normalized to {}", a_mod.name, slug);
+                a_mod.name = slug;
+            }
+        }
+        let mut seen = HashSet::new();
+        if let Some(clash) = self.mods.iter().find(|m| !seen.insert(&m.name)) {
+            anyhow::bail!("{} is listed more than once after normalizing slugs", clash.name);
+        }
+        Ok(self)
+    }
+
+    /// Points deps spelled like a slug before normalizing at the entry they name, from whichever file.
+    fn with_normalized_deps(mut self) -> Self {
+        let names: HashSet<String> = self.mods.iter().map(|m| m.name.clone()).collect();
+        for dep in self.mods.iter_mut().flat_map(|m| m.deps.iter_mut()) {
+            let slug = slugs::normalize(dep);
+            if !names.contains(dep.as_str()) && names.contains(&slug) {
+                *dep = slug;
+            }
+        }
+        self
+    }
+
+    fn without_disabled(mut self) -> Self {
+        let (enabled, disabled): (Vec<YamlMod>, Vec<YamlMod>) = self.mods.into_iter().partition(|m| m.enabled != Some(false));
+        self.mods = enabled;
+        self.disabled.extend(disabled.into_iter().map(|m| m.name));
+        self.disabled.sort();
+        self
+    }
+
+    /// JSON Schema for manifest files, for editors and external validators.
+    pub fn schema() -> RootSchema {
+        schemars::schema_for!(YamlManifest)
+    }
+
+    /// Loads just this file, without imports or variable expansion, so it can be written back as it was.
+    pub fn load_for_editing(manifest_path: &Path) -> Result<Self> {
         let manifest_file = File::open(manifest_path)
             .context(format!("While opening {:?}", manifest_path))?;
-        let base_manifest: YamlManifest = serde_yaml::from_reader(manifest_file)
-            .context(format!("While parsing YAML from {:?}", manifest_path))?;
+        serde_yaml::from_reader(manifest_file)
+            .context(format!("While parsing YAML from {:?}", manifest_path))
+    }
 
+    /// Writes a manifest loaded for editing back. Only what changed since is rewritten, keeping
+    /// comments and unknown keys; new files are written whole.
+    pub fn save(&self, manifest_path: &Path) -> Result<()> {
+        let edited = match std::fs::read_to_string(manifest_path) {
+            Ok(source) => {
+                let original: YamlManifest = serde_yaml::from_str(&source)
+                    .context(format!("While parsing YAML from {:?}", manifest_path))?;
+                let edited = editing::apply_edits(&source, &original, self)?;
+                if edited.is_none() {
+                    log::warn!("Rewriting {:?} whole, which drops its comments, as it couldn't be edited in place", manifest_path);
+                }
+                edited
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context(format!("While reading {:?}", manifest_path)),
+        };
+        let mut manifest_file = AtomicFile::create(manifest_path)?;
+        match edited {
+            Some(edited) => manifest_file.write_all(edited.as_bytes())
+                .context(format!("While writing YAML to {:?}", manifest_path))?,
+            None => serde_yaml::to_writer(&mut manifest_file, self)
+                .context(format!("While writing YAML to {:?}", manifest_path))?,
+        }
+        manifest_file.commit()
+    }
+
+    /// Loads a manifest imported through `importers`, outermost first. Every error names the
+    /// whole chain, rather than nesting one context per level.
+    fn load_imported(manifest_path: &Path, importers: &[PathBuf], version: Option<&str>) -> Result<Self> {
+        let chain = import_chain(importers, manifest_path);
+        let identity = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if importers.iter().any(|importer| identity(importer) == identity(manifest_path)) {
+            anyhow::bail!("Manifests import each other in a cycle: {}", chain);
+        }
+        let max_depth = MAX_IMPORT_DEPTH.load(Ordering::Relaxed);
+        if importers.len() > max_depth {
+            anyhow::bail!("Imports nest more than {} deep, raise max_import_depth in cursetool.yaml if that's intended: {}",
+                          max_depth, chain);
+        }
+        log::info!("Reading manifest file {}...", manifest_path.display());
+        let base_manifest = Self::load_own(manifest_path, &chain, version)?.listed_in(manifest_path);
+
+        let importers: Vec<PathBuf> = importers.iter().cloned().chain(std::iter::once(manifest_path.to_path_buf())).collect();
         let mut imported_manifests: Vec<YamlManifest> = Vec::new();
         for import in &base_manifest.imports {
-            let relative_path = manifest_path.parent().expect("Base manifest has no parent").join(&import);
-            imported_manifests.push(Self::recursive_load_from_file(&relative_path)
-                .context(format!("While importing yaml file {}", import))?);
+            let relative_path = manifest_path.parent().expect("Base manifest has no parent").join(import);
+            if modrinth::is_index(import) {
+                imported_manifests.push(modrinth::load_index(&relative_path)
+                    .context(format!("While importing Modrinth index {}", import_chain(&importers, &relative_path)))?
+                    .listed_in(&relative_path));
+                continue;
+            }
+            imported_manifests.push(Self::load_imported(&relative_path, &importers, version)?);
         }
         Ok(base_manifest.merge(imported_manifests))
     }
+
+    /// Reads one file on its own, with `version` standing in for its own if given.
+    fn load_own(manifest_path: &Path, chain: &str, version: Option<&str>) -> Result<Self> {
+        let source = std::fs::read_to_string(manifest_path)
+            .context(format!("While opening {}", chain))?;
+        if source.lines().all(|l| l.trim().is_empty() || l.trim_start().starts_with('#')) {
+            anyhow::bail!("{} is empty; a manifest needs at least a version, e.g. `version: 1.12.2`", chain);
+        }
+        let mut document: serde_yaml::Value = serde_yaml::from_str(&source)
+            .context(format!("While parsing YAML from {}", chain))?;
+        if let (Some(version), serde_yaml::Value::Mapping(mapping)) = (version, &mut document) {
+            mapping.insert("version".into(), version.into());
+        }
+        let document = vars::expand_manifest(document)
+            .context(format!("While expanding variables in {}", chain))?;
+        let base_manifest: YamlManifest = serde_yaml::from_value(document)
+            .context(format!("While parsing YAML from {}", chain))?;
+        base_manifest.with_normalized_slugs()
+            .context(format!("While reading {}", chain))
+    }
+
+    /// The names this file's own entries end up with, in the order they're listed: variables
+    /// expanded and slugs normalized, as in the fully loaded manifest.
+    pub(crate) fn load_own_names(manifest_path: &Path) -> Result<Vec<String>> {
+        let manifest = Self::load_own(manifest_path, &import_chain(&[], manifest_path), None)?;
+        Ok(manifest.mods.into_iter().map(|m| m.name).collect())
+    }
+
+    /// Records this file as where its own mods come from.
+    fn listed_in(mut self, manifest_path: &Path) -> Self {
+        self.sources = self.mods.iter()
+            .map(|m| (m.name.clone(), vec![MergeSource { path: manifest_path.to_path_buf(), inherit: m.inherit == Some(true) }]))
+            .collect();
+        self
+    }
+
+    /// Replaces each entry with a `client_id`/`server_id` pair by `<name>-client` and `<name>-server`.
+    fn expand_split_projects(mut self) -> Result<Self> {
+        let mut mods = Vec::with_capacity(self.mods.len());
+        for a_mod in self.mods {
+            let (client_id, server_id) = match (a_mod.client_id, a_mod.server_id) {
+                (None, None) => {
+                    mods.push(a_mod);
+                    continue;
+                }
+                (Some(client_id), Some(server_id)) => (client_id, server_id),
+                _ => anyhow::bail!("{} needs both client_id and server_id", a_mod.name),
+            };
+            if a_mod.id.is_some() || !a_mod.ids.is_empty() || a_mod.side.is_some() || a_mod.files.is_some() {
+                anyhow::bail!("{} has client_id/server_id, so it can't also set id, ids, side or files", a_mod.name);
+            }
+            for (suffix, id, side) in [("client", client_id, Side::Client), ("server", server_id, Side::Server)] {
+                mods.push(YamlMod {
+                    name: format!("{}-{}", a_mod.name, suffix),
+                    id: Some(id),
+                    client_id: None,
+                    server_id: None,
+                    split_from: Some(a_mod.name.clone()),
+                    side: Some(side),
+                    ..a_mod.clone()
+                });
+            }
+        }
+        // Depending on a split project means depending on the halves installed where the dependent is.
+        let split: BTreeMap<String, Vec<(String, Side)>> = mods.iter()
+            .filter_map(|m| Some((m.split_from.clone()?, (m.name.clone(), m.side.unwrap_or_default()))))
+            .fold(BTreeMap::new(), |mut split, (from, half)| {
+                split.entry(from).or_insert_with(Vec::new).push(half);
+                split
+            });
+        for a_mod in &mut mods {
+            let side = a_mod.side.unwrap_or_default();
+            a_mod.deps = a_mod.deps.iter()
+                .flat_map(|dep| match split.get(dep) {
+                    Some(halves) => halves.iter()
+                        .filter(|(_, half)| (side.on_client() && half.on_client()) || (side.on_server() && half.on_server()))
+                        .map(|(name, _)| name.clone())
+                        .collect(),
+                    None => vec![dep.clone()],
+                })
+                .collect();
+        }
+        self.mods = mods;
+        Ok(self)
+    }
+
+    /// Turns `filePageUrl`s into file IDs, so they pin the file just like `id` does.
+    fn pin_file_page_urls(mut self) -> Result<Self> {
+        for a_mod in &mut self.mods {
+            let file = match a_mod.files.as_mut().and_then(|f| f.first_mut()) {
+                Some(file) => file,
+                None => continue,
+            };
+            let url = match file.file_page_url {
+                Some(ref url) => url.clone(),
+                None => continue,
+            };
+            let (slug, file_id) = import::parse_file_page_url(&url).context(format!("In the file for {}", a_mod.name))?;
+            if file.id.is_some_and(|id| id != file_id) {
+                anyhow::bail!("{} has file id {}, but its filePageUrl points at file {}", a_mod.name, file.id.unwrap_or_default(), file_id);
+            }
+            // Without an id, the project is found by searching for the name.
+            if a_mod.id.is_none() && slug != a_mod.name {
+                anyhow::bail!("{}'s filePageUrl is for project {}; rename the mod or give its id", a_mod.name, slug);
+            }
+            file.id = Some(file_id);
+        }
+        Ok(self)
+    }
+
+    /// Catches manifest mistakes that would otherwise be silently ignored during resolution.
+    pub fn validate(&self) -> Result<()> {
+        for a_mod in &self.mods {
+            if let Some(ref files) = a_mod.files {
+                if files.len() > 1 {
+                    anyhow::bail!("{} lists {} files, but only one file per mod is supported", a_mod.name, files.len());
+                }
+            }
+            if !a_mod.ids.is_empty() {
+                if a_mod.id.is_some() {
+                    anyhow::bail!("{} has both id and ids; keep only one", a_mod.name);
+                }
+                if !a_mod.is_floating() {
+                    anyhow::bail!("{} pins a file, which belongs to one project, so it needs id rather than ids", a_mod.name);
+                }
+            }
+        }
+        let unpinned: Vec<&str> = self.mods.iter().filter(|m| m.is_locked() && m.is_floating()).map(|m| m.name.as_str()).collect();
+        if !unpinned.is_empty() {
+            anyhow::bail!("{} are locked, but have no file id to stay on; pin them", unpinned.join(", "));
+        }
+        let names: HashSet<&str> = self.mods.iter().map(|m| m.name.as_str()).collect();
+        let problems: Vec<String> = self.mods.iter()
+            .flat_map(|m| m.deps.iter().filter(|dep| !names.contains(dep.as_str())).map(move |dep| {
+                if self.disabled.contains(dep) {
+                    format!("{} depends on {}, which is disabled", m.name, dep)
+                } else {
+                    format!("{} depends on {}, which isn't in the manifest", m.name, dep)
+                }
+            }))
+            .collect();
+        if !problems.is_empty() {
+            anyhow::bail!("{} dependencies can't be met:\n  {}", problems.len(), problems.join("\n  "));
+        }
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum Side {
     Client,
     Server,
+    #[default]
     Both
 }
 
+impl Side {
+    pub fn on_client(&self) -> bool {
+        matches!(self, Side::Client | Side::Both)
+    }
+
+    pub fn on_server(&self) -> bool {
+        matches!(self, Side::Server | Side::Both)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct NixMod {
     pub slug: String,
     pub title: String,
     pub id: u32,
+    #[serde(default)]
+    pub file_id: u32,
     pub side: Side,
     pub required: bool,
     pub default: bool,
     pub deps: Vec<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
     pub filename: String,
     pub encoded: String,
     pub page: String,
     pub src: String,
     pub size: u64,
-    pub md5: String,
-    pub sha256: String
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-pub struct CurseModFile {
-    pub id: u32,
-    #[serde(rename = "fileName")]
-    pub file_name: String,
-    #[serde(rename = "fileDate")]
-    pub file_date: String,
-    #[serde(rename = "downloadUrl")]
-    pub download_url: String,
-    #[serde(rename = "gameVersions")]
-    pub game_version: Vec<String>
+    #[serde(flatten)]
+    pub hashes: BTreeMap<HashAlgorithm, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub embedded_jars: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub embedded_ids: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependency_ids: Vec<u32>,
+    /// The selected file's game version and loader tags, exactly as CurseForge lists them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub game_versions: Vec<String>,
+    #[serde(default, skip_serializing_if = "ModMetadata::is_empty")]
+    pub metadata: ModMetadata,
+    /// The project's icon, with `--rich-metadata`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Added by `auto_deps` rather than listed in the manifest.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto: bool,
+    /// Where the entry came from and how its file was chosen; only recorded in the lockfile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<EntryProvenance>,
+    /// CurseForge's fingerprint of the file, for quick checks of a mods folder.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<u32>,
+    /// Whether the project's author allows third-party distribution, where CurseForge says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_distribution: Option<bool>,
 }
 
 impl std::fmt::Display for NixMod {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f,
-r#"    "{slug}" = {{
-        "title" = "{title}";
-        "name" = "{slug}";
+r#"    {slug} = {{
+        "title" = {title};
+        "name" = {slug};
         "id" = {id};
         "side" = "{side}";
         "required" = {required};
         "default" = {default};
-        "deps" = [];
-        "filename" = "{filename}";
-        "encoded" = "{encoded}";
-        "page" = "{page}";
-        "src" = "{src}";
+        "deps" = [{deps}];
+        "categories" = [{categories}];
+{auto}{group}        "filename" = {filename};
+        "encoded" = {encoded};
+        "page" = {page};
+{icon}{distribution}        "src" = {src};
         "type" = "remote";
-        "md5" = "{md5}";
-        "sha256" = "{sha256}";
+{hashes}
         "size" = {size};
-    }};"#,
-            title = self.title,
-            slug = self.slug,
+{fingerprint}    }};"#,
+            title = nix::string(&self.title),
+            slug = nix::string(&self.slug),
             id = self.id,
             side = json!(self.side).as_str().unwrap(),
             required = self.required,
             default = self.default,
-            filename = self.filename,
-            encoded = self.encoded,
-            page = self.page,
-            src = self.src,
-            md5 = self.md5,
-            sha256 = self.sha256,
-            size = self.size)
+            deps = nix::string_list(&self.deps),
+            categories = nix::string_list(&self.categories),
+            auto = if self.auto { "        \"auto\" = true;\n" } else { "" },
+            group = self.group.as_ref().map(|group| format!("        \"group\" = {};\n", nix::string(group))).unwrap_or_default(),
+            filename = nix::string(&self.filename),
+            encoded = nix::string(&self.encoded),
+            page = nix::string(&self.page),
+            icon = self.icon.as_ref().map(|icon| format!("        \"icon\" = {};\n", nix::string(icon))).unwrap_or_default(),
+            // Only when disallowed, so consumers packing files know to leave them out.
+            distribution = if self.allow_distribution == Some(false) { "        \"distributable\" = false;\n" } else { "" },
+            src = nix::string(&self.src),
+            hashes = nix::hash_lines(&self.hashes),
+            size = self.size,
+            fingerprint = self.fingerprint.map(|f| format!("        \"fingerprint\" = {};\n", f)).unwrap_or_default())
     }
 }
 
 impl YamlModFile {
     pub fn with_id(id: u32) -> YamlModFile {
         YamlModFile {
-            name: None,
             id: Some(id),
-            maturity: None,
-            file_page_url: None,
-            src: None,
-            md5: None
+            ..Default::default()
+        }
+    }
+}
+
+impl YamlMod {
+    /// Mods whose file has a `src` are downloaded from there, without consulting Curse.
+    pub fn is_direct(&self) -> bool {
+        self.id.is_none() && self.files.as_ref().and_then(|f| f.first()).is_some_and(|f| f.src.is_some())
+    }
+
+    /// Whether the entry's name is all there is to find its project by.
+    pub fn searches_by_slug(&self) -> bool {
+        self.id.is_none() && self.ids.is_empty() && self.client_id.is_none() && self.server_id.is_none() && !self.is_direct()
+    }
+
+    /// Whether the entry names its file, rather than taking the newest one for the version.
+    pub fn is_pinned(&self) -> bool {
+        self.files.as_ref().is_some_and(|f| !f.is_empty())
+    }
+
+    /// Whether the entry takes whatever file is newest, having no file ID to pin it.
+    pub fn is_floating(&self) -> bool {
+        !self.is_direct() && self.files.as_ref().and_then(|f| f.first()).is_none_or(|f| f.id.is_none())
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked == Some(true)
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.hold == Some(true)
+    }
+
+    /// Names the file to use, keeping other choices about it like `maturity`.
+    pub fn pin(&mut self, file_id: u32) {
+        match self.files.as_mut().and_then(|f| f.first_mut()) {
+            Some(file) => file.id = Some(file_id),
+            None => self.files = Some(vec![YamlModFile::with_id(file_id)]),
+        }
+    }
+
+    /// Takes out whatever names a specific file, so the newest one is used again. Other choices
+    /// about the file, like `maturity`, stay. Returns whether there was a pin to take out.
+    pub fn unpin(&mut self) -> bool {
+        let file = match self.files.as_mut().and_then(|f| f.first_mut()) {
+            Some(file) if file.src.is_none() => file,
+            _ => return false,
+        };
+        let pinned = file.id.is_some() || file.file_page_url.is_some();
+        file.id = None;
+        file.file_page_url = None;
+        file.name = None;
+        file.md5 = None;
+        if file.maturity.is_none() && file.hashes.is_empty() && file.size.is_none() {
+            self.files = None;
+        }
+        pinned
+    }
+
+    /// Fills in what this entry leaves out from the one it overrides. Whether the result inherits
+    /// further is up to `parent`, so chains of `inherit` entries keep merging down the imports.
+    pub fn inherit_from(self, parent: &YamlMod) -> YamlMod {
+        let parent = parent.clone();
+        YamlMod {
+            name: self.name,
+            id: self.id.or(parent.id),
+            ids: if self.ids.is_empty() { parent.ids } else { self.ids },
+            client_id: self.client_id.or(parent.client_id),
+            server_id: self.server_id.or(parent.server_id),
+            split_from: self.split_from.or(parent.split_from),
+            side: self.side.or(parent.side),
+            required: self.required.or(parent.required),
+            default: self.default.or(parent.default),
+            deps: if self.deps.is_empty() { parent.deps } else { self.deps },
+            enabled: self.enabled.or(parent.enabled),
+            locked: self.locked.or(parent.locked),
+            hold: self.hold.or(parent.hold),
+            group: self.group.or(parent.group),
+            inherit: parent.inherit,
+            files: self.files.or(parent.files),
+        }
+    }
+
+    /// A common mistake is to put the project ID in `name:`, where no slug search could match it.
+    pub fn project_id_in_name(&self) -> Option<u32> {
+        if self.id.is_some() || !self.ids.is_empty() || self.name.is_empty() || !self.name.chars().all(|c| c.is_ascii_digit()) {
+            return None;
         }
+        self.name.parse().ok()
     }
 }
 
 impl YamlMod {
+    pub fn with_name(name: &str) -> YamlMod {
+        YamlMod {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_id(name: &str, id: u32) -> YamlMod {
+        YamlMod {
+            name: name.to_owned(),
+            id: Some(id),
+            ..Default::default()
+        }
+    }
+
+    /// An entry pinning a file from a Curse manifest. Optional files stay optional and,
+    /// like in the Curse launcher, are left out unless the player opts in.
+    pub fn from_curse_file(slug: &str, mod_file: &ModFile) -> YamlMod {
+        let yaml_mod = YamlMod::with_files(slug, mod_file.project_id, YamlModFile::with_id(mod_file.file_id));
+        // Required is the default, so only optional mods need it spelled out.
+        if mod_file.required { yaml_mod } else { yaml_mod.required(false).default(false) }
+    }
+
     pub fn with_files(name: &str, id: u32, file: YamlModFile) -> YamlMod {
         YamlMod {
             name: name.to_owned(),
             id: Some(id),
-            side: None,
-            required: None,
-            default: None,
-            files: Some(vec![file])
+            files: Some(vec![file]),
+            ..Default::default()
+        }
+    }
+
+    pub fn side(mut self, side: Side) -> Self {
+        self.side = Some(side);
+        self
+    }
+
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = Some(required);
+        self
+    }
+
+    pub fn default(mut self, default: bool) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    pub fn file(mut self, file: YamlModFile) -> Self {
+        self.files.get_or_insert_with(Vec::new).push(file);
+        self
+    }
+}
+
+impl YamlManifest {
+    pub fn new(version: &str) -> YamlManifest {
+        YamlManifest {
+            version: version.to_owned(),
+            loader: None,
+            game_version_type_id: None,
+            vars: BTreeMap::new(),
+            imports: vec![],
+            mods: vec![],
+            auto_deps: false,
+            disabled: vec![],
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// How to ask CurseForge for files matching this manifest's version.
+    pub fn version_filter(&self) -> Result<VersionFilter> {
+        Ok(VersionFilter::new(&self.version, self.game_version_type_id)?.for_loader(self.loader_name().as_deref()))
+    }
+
+    /// The loader's name without its version, lowercased.
+    pub fn loader_name(&self) -> Option<String> {
+        self.loader.as_ref().map(|l| l.split('-').next().unwrap_or(l).to_lowercase())
+    }
+
+    #[cfg(test)]
+    pub fn import(mut self, path: &str) -> Self {
+        self.imports.push(path.to_owned());
+        self
+    }
+
+    pub fn with_mod(mut self, a_mod: YamlMod) -> Self {
+        self.mods.push(a_mod);
+        self
+    }
+}
+
+impl NixMod {
+    /// Starts a mod that is required, enabled by default and installed on both sides.
+    /// Everything else has to be filled in through the setters below.
+    pub fn new(slug: &str, title: &str, id: u32) -> NixMod {
+        NixMod {
+            slug: slug.to_owned(),
+            title: title.to_owned(),
+            id,
+            required: true,
+            default: true,
+            ..Default::default()
         }
     }
+
+    pub fn side(mut self, side: Side) -> Self {
+        self.side = side;
+        self
+    }
+
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    /// Takes the side, `required` and `default` flags from a manifest entry, which are all optional
+    /// there, along with its deps and group.
+    pub fn flags_from(self, yaml_mod: &YamlMod) -> Self {
+        let nix_mod = self.side(yaml_mod.side.unwrap_or_default())
+            .required(yaml_mod.required.unwrap_or(true))
+            .default(yaml_mod.default.unwrap_or(true))
+            .deps(yaml_mod.deps.clone());
+        NixMod { group: yaml_mod.group.clone(), ..nix_mod }
+    }
+
+    pub fn default(mut self, default: bool) -> Self {
+        self.default = default;
+        self
+    }
+
+    pub fn deps(mut self, deps: Vec<String>) -> Self {
+        self.deps = deps;
+        self
+    }
+
+    pub fn categories(mut self, categories: Vec<String>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    pub fn file_id(mut self, file_id: u32) -> Self {
+        self.file_id = file_id;
+        self
+    }
+
+    pub fn page(mut self, page: &str) -> Self {
+        self.page = page.to_owned();
+        self
+    }
+
+    pub fn icon(mut self, icon: &str) -> Self {
+        self.icon = Some(icon.to_owned());
+        self
+    }
+
+    /// The filename is made safe, but applying `filename_rules` is up to the caller.
+    pub fn file(mut self, filename: &str, src: &str, size: u64) -> Self {
+        self.filename = naming::safe_filename(filename);
+        self.encoded = naming::encode_path_segment(&self.filename);
+        self.src = src.to_owned();
+        self.size = size;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn hash(mut self, algorithm: HashAlgorithm, hash: &str) -> Self {
+        self.hashes.insert(algorithm, hash.to_owned());
+        self
+    }
+
+    pub fn hashes(mut self, hashes: BTreeMap<HashAlgorithm, String>) -> Self {
+        self.hashes = hashes;
+        self
+    }
 }
 
 impl YamlManifest {
+    /// Combines this manifest with its already merged imports. Precedence is depth-first: this
+    /// manifest's entries win, then those of each import in the order listed, where an import's
+    /// own imports come right after it and before the next import. Mods keep the order they're
+    /// first listed in.
     pub fn merge(&self, others: Vec<YamlManifest>) -> YamlManifest {
-        let mut mod_list = HashMap::new();
-        let mut imports: HashSet<&String> = HashSet::new();
+        let mut mod_list: Vec<YamlMod> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut imports: Vec<String> = Vec::new();
+        let mut sources = self.sources.clone();
         for a_mod in &self.mods {
-            mod_list.entry(&a_mod.name).or_insert(a_mod);
+            if !positions.contains_key(&a_mod.name) {
+                positions.insert(a_mod.name.clone(), mod_list.len());
+                mod_list.push(a_mod.clone());
+            }
         }
         for other in &others {
-            imports.extend(other.imports.iter());
+            for import in &other.imports {
+                if !imports.contains(import) {
+                    imports.push(import.clone());
+                }
+            }
+            for (name, other_sources) in &other.sources {
+                sources.entry(name.clone()).or_default().extend(other_sources.iter().cloned());
+            }
             for a_mod in &other.mods {
-                mod_list.entry(&a_mod.name).or_insert(a_mod);
+                match positions.get(&a_mod.name) {
+                    None => {
+                        positions.insert(a_mod.name.clone(), mod_list.len());
+                        mod_list.push(a_mod.clone());
+                    }
+                    Some(&i) if mod_list[i].inherit == Some(true) => {
+                        let inheriting = mod_list[i].clone();
+                        mod_list[i] = inheriting.inherit_from(a_mod);
+                    }
+                    Some(_) => (),
+                }
             }
         }
 
         YamlManifest {
             version: self.version.clone(),
-            imports: imports.into_iter().cloned().collect(),
-            mods: mod_list.values().map(|&s| s.clone()).collect(),
+            loader: self.loader.clone(),
+            game_version_type_id: self.game_version_type_id,
+            vars: self.vars.clone(),
+            imports,
+            mods: mod_list,
+            auto_deps: self.auto_deps,
+            disabled: vec![],
+            sources,
         }
     }
 }
@@ -245,6 +935,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::needless_borrowed_reference, clippy::needless_borrow)]
     fn can_merge_manifests() -> Result<()> {
         let dir = tempfile::tempdir()?;
         let a_manifest_path = dir.path().join("a.yaml");
@@ -272,35 +963,388 @@ mod tests {
         Ok(())
     }
 
-    impl YamlMod {
-        fn with_id(name: &str, id: u32) -> YamlMod {
-            YamlMod {
-                name: name.to_owned(),
-                id: Some(id),
-                side: None,
-                required: None,
-                default: None,
-                files: None
-            }
-        }
+    #[test]
+    fn inherits_left_out_fields_from_imports() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("base.yaml"), "version: 1.12.2\nmods:\n\
+                                                       - name: jei\n  id: 238222\n  required: false\n  files:\n  - id: 3043174\n\
+                                                       - name: waystones\n  id: 245755\n  side: both\n")?;
+        std::fs::write(dir.path().join("pack.yaml"), "version: 1.12.2\nimports: [base.yaml]\nmods:\n\
+                                                       - name: jei\n  side: client\n  inherit: true\n\
+                                                       - name: waystones\n  side: client\n\
+                                                       - name: lonely\n  inherit: true\n")?;
+        let manifest = YamlManifest::recursive_load_from_file(&dir.path().join("pack.yaml"))?;
+        let by_name = |name: &str| manifest.mods.iter().find(|m| m.name == name).unwrap();
+        let jei = by_name("jei");
+        assert_eq!((jei.id, jei.side, jei.required), (Some(238222), Some(Side::Client), Some(false)));
+        assert_eq!(jei.files.as_ref().unwrap()[0].id, Some(3043174), "The pin is inherited");
+        assert_eq!(by_name("waystones").id, None, "Without inherit, the entry replaces the imported one");
+        assert_eq!(by_name("lonely").inherit, None);
+        let (name, sources) = manifest.overridden().next().unwrap();
+        assert_eq!(name, "jei");
+        assert_eq!(describe_precedence(sources), format!("{}, inheriting from {}",
+                                                         dir.path().join("pack.yaml").display(), dir.path().join("base.yaml").display()));
+        Ok(())
+    }
 
-        fn with_name(name: &str) -> YamlMod {
-            YamlMod {
-                name: name.to_owned(),
-                id: None,
-                side: None,
-                required: None,
-                default: None,
-                files: None
-            }
+    #[test]
+    fn names_the_import_chain_in_errors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let write = |name: &str, contents: &str| std::fs::write(dir.path().join(name), contents);
+        write("pack.yaml", "version: 1.12.2\nimports: [base.yaml]\n")?;
+        write("base.yaml", "version: 1.12.2\nimports: [libs.yaml]\n")?;
+        write("libs.yaml", "version: 1.12.2\nmods: [oops\n")?;
+        let error = format!("{:#}", YamlManifest::recursive_load_from_file(&dir.path().join("pack.yaml")).unwrap_err());
+        let chain = ["pack.yaml", "base.yaml", "libs.yaml"].iter()
+            .map(|name| dir.path().join(name).display().to_string()).collect::<Vec<_>>().join(" -> ");
+        assert!(error.starts_with(&format!("While parsing YAML from {}:", chain)), "{}", error);
+
+        write("libs.yaml", "version: 1.12.2\nimports: [pack.yaml]\n")?;
+        let error = format!("{:#}", YamlManifest::recursive_load_from_file(&dir.path().join("pack.yaml")).unwrap_err());
+        assert!(error.contains("cycle") && error.ends_with(&format!("libs.yaml -> {}", dir.path().join("pack.yaml").display())),
+                "{}", error);
+
+        for level in 0..=DEFAULT_MAX_IMPORT_DEPTH + 1 {
+            write(&format!("{}.yaml", level), &format!("version: 1.12.2\nimports: [{}.yaml]\n", level + 1))?;
         }
+        write(&format!("{}.yaml", DEFAULT_MAX_IMPORT_DEPTH + 2), "version: 1.12.2\n")?;
+        let error = format!("{:#}", YamlManifest::recursive_load_from_file(&dir.path().join("0.yaml")).unwrap_err());
+        assert!(error.starts_with("Imports nest more than 16 deep"), "{}", error);
+        assert!(YamlManifest::recursive_load_from_file(&dir.path().join("2.yaml")).is_ok(), "The limit itself is allowed");
+        Ok(())
+    }
+
+    #[test]
+    fn normalizes_slugs_on_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("pack.yaml");
+        std::fs::write(&path, "version: 1.12.2\nmods:\n\
+                               - name: JEI \n\
+                               - name: iron_chests\n  deps: [JEI ]\n\
+                               - name: My_Fork\n  id: 1\n")?;
+        let manifest = YamlManifest::recursive_load_from_file(&path)?;
+        let names: Vec<&str> = manifest.mods.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["jei", "iron-chests", "My_Fork"], "Names with an id aren't searched for, so they're kept");
+        assert_eq!(manifest.mods[1].deps, vec!["jei"]);
+
+        std::fs::write(&path, "version: 1.12.2\nmods:\n- name: jei\n- name: JEI\n")?;
+        assert!(YamlManifest::recursive_load_from_file(&path).is_err(), "Both entries are the same mod");
+        Ok(())
+    }
+
+    #[test]
+    fn takes_ids_only_for_floating_mods() -> Result<()> {
+        let parse = |mods: &str| serde_yaml::from_str::<YamlManifest>(&format!("version: 1.12.2\nmods:\n{}", mods));
+        let moved = parse("- name: jei\n  ids: [12345, 67890]\n")?;
+        moved.validate()?;
+        assert_eq!(moved.mods[0].ids, vec![12345, 67890]);
+        assert!(moved.mods[0].is_floating());
+        assert!(parse("- name: jei\n  id: 1\n  ids: [2, 3]\n")?.validate().is_err(), "id and ids contradict each other");
+        assert!(parse("- name: jei\n  ids: [2, 3]\n  files:\n  - id: 4\n")?.validate().is_err(), "A file is from one project");
+        assert_eq!(YamlMod { ids: vec![2], ..YamlMod::with_name("238222") }.project_id_in_name(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn merges_imports_depth_first() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let write = |name: &str, source: &str| std::fs::write(dir.path().join(name), source);
+        write("pack.yaml", "version: 1.12.2\nimports: [b.yaml, c.yaml]\nmods:\n- name: zoo\n")?;
+        write("b.yaml", "version: 1.12.2\nimports: [d.yaml]\nmods:\n- name: x\n  id: 2\n")?;
+        write("c.yaml", "version: 1.12.2\nmods:\n- name: x\n  id: 3\n- name: y\n  id: 3\n- name: alpha\n")?;
+        write("d.yaml", "version: 1.12.2\nmods:\n- name: y\n  id: 4\n")?;
+        let manifest = YamlManifest::recursive_load_from_file(&dir.path().join("pack.yaml"))?;
+        let ids: Vec<(&str, Option<u32>)> = manifest.mods.iter().map(|m| (m.name.as_str(), m.id)).collect();
+        assert_eq!(ids, vec![("zoo", None), ("x", Some(2)), ("y", Some(4)), ("alpha", None)],
+                   "An import's own imports come before the next import");
+        let overridden: Vec<(&str, String)> = manifest.overridden().map(|(name, s)| (name.as_str(), describe_precedence(s))).collect();
+        assert_eq!(overridden.len(), 2);
+        assert!(overridden[1].1.ends_with(&format!("d.yaml, over {}", dir.path().join("c.yaml").display())), "{:?}", overridden);
+        Ok(())
+    }
+
+    #[test]
+    fn expands_the_version_resolved_for() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let write = |name: &str, source: &str| std::fs::write(dir.path().join(name), source);
+        write("pack.yaml", "version: 1.19.2\nimports: [base.yaml]\nmods:\n- name: jei\n  files:\n  - name: jei-${version}.jar\n")?;
+        write("base.yaml", "version: 1.19.2\nmods:\n- name: waila\n  files:\n  - name: waila-${version}.jar\n")?;
+        let manifest = YamlManifest::recursive_load_for_version(&dir.path().join("pack.yaml"), Some("1.20.1"))?;
+        assert_eq!(manifest.version, "1.20.1");
+        let files: Vec<Option<&str>> = manifest.mods.iter()
+            .map(|m| m.files.as_ref().and_then(|f| f[0].name.as_deref()))
+            .collect();
+        assert_eq!(files, vec![Some("jei-1.20.1.jar"), Some("waila-1.20.1.jar")], "Imports get the version too");
+        Ok(())
+    }
+
+    #[test]
+    fn overrides_imports_spelled_differently() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let write = |name: &str, source: &str| std::fs::write(dir.path().join(name), source);
+        write("pack.yaml", "version: 1.12.2\nimports: [base.yaml]\nmods:\n- name: JEI\n  side: client\n")?;
+        write("base.yaml", "version: 1.12.2\nmods:\n- name: jei\n- name: waila\n  deps: [JEI]\n")?;
+        let manifest = YamlManifest::recursive_load_from_file(&dir.path().join("pack.yaml"))?;
+        let names: Vec<&str> = manifest.mods.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["jei", "waila"]);
+        assert_eq!(manifest.mods[0].side, Some(Side::Client), "The override wins");
+        assert_eq!(manifest.mods[1].deps, vec!["jei"]);
+
+        write("pack.yaml", "version: 1.12.2\nmods:\n- name: JEI\n- name: jei\n")?;
+        assert!(YamlManifest::recursive_load_from_file(&dir.path().join("pack.yaml")).is_err(), "Clashes within a file still fail");
+        Ok(())
+    }
+
+    #[test]
+    fn records_game_version_tags_outside_nix() -> Result<()> {
+        let nix_mod = NixMod {
+            game_versions: vec!["1.12.2".to_string(), "Forge".to_string()],
+            ..NixMod::new("jei", "JEI", 238222)
+        };
+        let json = serde_json::to_value(&nix_mod)?;
+        assert_eq!(json["game_versions"], json!(["1.12.2", "Forge"]));
+        assert!(!nix_mod.to_string().contains("Forge"), "The nix output doesn't need them");
+        let untagged = serde_json::to_value(NixMod::new("jei", "JEI", 238222))?;
+        assert!(untagged.get("game_versions").is_none(), "Direct downloads have no tags");
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_optional_curse_files_optional() -> Result<()> {
+        let optional = YamlMod::from_curse_file("jei", &ModFile { project_id: 238222, file_id: 3043174, required: false });
+        let parsed: YamlMod = serde_yaml::from_str(&serde_yaml::to_string(&optional)?)?;
+        assert_eq!((parsed.required, parsed.default), (Some(false), Some(false)));
+        let nix_mod = NixMod::new("jei", "JEI", 238222).flags_from(&parsed);
+        assert!(!nix_mod.required && !nix_mod.default, "Optional files should be neither required nor default");
+        assert!(nix_mod.to_string().contains("\"required\" = false;\n        \"default\" = false;"));
+
+        let required = YamlMod::from_curse_file("jei", &ModFile { project_id: 238222, file_id: 3043174, required: true });
+        assert_eq!((required.required, required.default), (None, None), "Required mods shouldn't spell it out");
+        let nix_mod = NixMod::new("jei", "JEI", 238222).flags_from(&required);
+        assert!(nix_mod.required && nix_mod.default);
+        Ok(())
+    }
+
+    #[test]
+    fn passes_manual_deps_through() -> Result<()> {
+        let manifest: YamlManifest = serde_yaml::from_str("version: 1.18.2\nmods:\n- name: rei\n  deps: [cloth-config, fabric-api]\n- name: cloth-config\n- name: fabric-api\n  client_id: 1\n  server_id: 2\n- name: zoomify\n  side: client\n  deps: [fabric-api]\n")?;
+        let manifest = manifest.expand_split_projects()?;
+        manifest.validate()?;
+        let rei = manifest.mods.iter().find(|m| m.name == "rei").unwrap();
+        assert_eq!(rei.deps, vec!["cloth-config", "fabric-api-client", "fabric-api-server"]);
+        let zoomify = manifest.mods.iter().find(|m| m.name == "zoomify").unwrap();
+        assert_eq!(zoomify.deps, vec!["fabric-api-client"], "Client mods only need the client half");
+        assert!(NixMod::new("rei", "REI", 310111).flags_from(rei).to_string()
+                .contains(r#""deps" = ["cloth-config" "fabric-api-client" "fabric-api-server"];"#));
+
+        let broken: YamlManifest = serde_yaml::from_str("version: 1.18.2\nmods:\n- name: rei\n  deps: [cloth]\n")?;
+        let error = broken.validate().unwrap_err().to_string();
+        assert!(error.contains("rei depends on cloth, which isn't in the manifest"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn schema_describes_manifest_fields() -> Result<()> {
+        let schema = serde_json::to_value(YamlManifest::schema())?;
+        assert_eq!(schema["required"], json!(["version"]));
+        assert!(schema["properties"]["mods"].is_object());
+        assert!(schema["properties"].get("disabled").is_none(), "Skipped fields aren't part of the manifest");
+        assert!(schema["definitions"]["YamlModFile"]["properties"]["filePageUrl"].is_object());
+        assert_eq!(schema["definitions"]["Side"]["enum"], json!(["client", "server", "both"]));
+        Ok(())
+    }
+
+    #[test]
+    fn builders_round_trip_through_serde() -> Result<()> {
+        let manifest = YamlManifest::new("1.12.2")
+            .import("base.yaml")
+            .with_mod(YamlMod::with_name("jei").side(Side::Client).required(false))
+            .with_mod(YamlMod::with_id("iron-chests", 228756).file(YamlModFile::with_id(2747935)));
+        let parsed: YamlManifest = serde_yaml::from_str(&serde_yaml::to_string(&manifest)?)?;
+        assert_eq!(parsed.imports, vec!["base.yaml"]);
+        assert_eq!(parsed.mods[0].side, Some(Side::Client));
+        assert_eq!(parsed.mods[0].required, Some(false));
+        assert_eq!(parsed.mods[1].files.as_ref().unwrap()[0].id, Some(2747935));
+
+        let nix_mod = NixMod::new("jei", "Just Enough Items", 238222)
+            .side(Side::Client)
+            .categories(vec!["library-api".to_string(), "utility-qol".to_string()])
+            .file("jei  1.12.2?.jar", "https://media.forgecdn.net/files/3043/174/jei.jar", 1234)
+            .hash(HashAlgorithm::Md5, "abc")
+            .hash(HashAlgorithm::Sha256, "def");
+        let parsed: NixMod = serde_json::from_str(&serde_json::to_string(&nix_mod)?)?;
+        assert_eq!(parsed.filename, "jei 1.12.2_.jar");
+        assert_eq!(parsed.encoded, "jei%201.12.2_.jar");
+        assert_eq!(parsed.side, Side::Client);
+        assert_eq!(parsed.hashes[&HashAlgorithm::Sha256], "def");
+        assert!(parsed.required && parsed.default, "Should be required and default");
+        assert_eq!(parsed.to_string(), nix_mod.to_string());
+        assert!(parsed.to_string().contains(r#""categories" = ["library-api" "utility-qol"];"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn expands_split_projects() -> Result<()> {
+        let manifest: YamlManifest = serde_yaml::from_str(
+            "version: 1.12.2\nmods:\n- name: foo\n  client_id: 1\n  server_id: 2\n  required: false\n- name: jei\n")?;
+        let manifest = manifest.expand_split_projects()?;
+        let names: Vec<_> = manifest.mods.iter().map(|m| (m.name.as_str(), m.id, m.side)).collect();
+        assert_eq!(names, vec![("foo-client", Some(1), Some(Side::Client)),
+                               ("foo-server", Some(2), Some(Side::Server)),
+                               ("jei", None, None)]);
+        assert_eq!(manifest.mods[1].required, Some(false));
+        assert_eq!(manifest.mods[1].split_from.as_deref(), Some("foo"));
+
+        let half: YamlManifest = serde_yaml::from_str("version: 1.12.2\nmods:\n- name: foo\n  client_id: 1\n")?;
+        assert!(half.expand_split_projects().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn emits_icons_only_when_recorded() {
+        let plain = NixMod::new("jei", "JEI", 238222).page("https://www.curseforge.com/minecraft/mc-mods/jei");
+        assert!(!plain.to_string().contains("icon"));
+        let rendered = plain.icon("https://media.forgecdn.net/avatars/thumbnails/29/69/256/256/jei.png").to_string();
+        assert!(rendered.contains("\"page\" = \"https://www.curseforge.com/minecraft/mc-mods/jei\";\n        \
+                                   \"icon\" = \"https://media.forgecdn.net/avatars/thumbnails/29/69/256/256/jei.png\";\n        \"src\""),
+                "{}", rendered);
+    }
+
+    #[test]
+    fn marks_mods_that_may_not_be_distributed() -> Result<()> {
+        let allowed = NixMod { allow_distribution: Some(true), ..NixMod::new("jei", "JEI", 238222) };
+        assert!(!allowed.to_string().contains("distributable"));
+        let disallowed = NixMod { allow_distribution: Some(false), ..allowed };
+        assert!(disallowed.to_string().contains("\n        \"distributable\" = false;\n"));
+        let locked: NixMod = serde_json::from_str(&serde_json::to_string(&disallowed)?)?;
+        assert_eq!(locked.allow_distribution, Some(false), "The lockfile records it for audits");
+        Ok(())
+    }
+
+    #[test]
+    fn marks_automatic_dependencies() -> Result<()> {
+        let manifest: YamlManifest = serde_yaml::from_str("version: 1.20.1\nauto_deps: true\nmods: [{name: sodium}]\n")?;
+        assert!(manifest.auto_deps);
+        let listed = NixMod::new("fabric-api", "Fabric API", 306612);
+        assert!(!listed.to_string().contains("auto"));
+        let added = NixMod { auto: true, ..listed };
+        assert!(added.to_string().contains("\"categories\" = [];\n        \"auto\" = true;\n"), "{}", added);
+        Ok(())
+    }
+
+    #[test]
+    fn quotes_awkward_slugs() {
+        let rendered = NixMod::new("1st-mod", "First", 1).to_string();
+        assert!(rendered.starts_with(r#"    "1st-mod" = {"#), "{}", rendered);
+        let rendered = NixMod::new("mod.with.dots", "Say \"Hi\"", 2).to_string();
+        assert!(rendered.starts_with(r#"    "mod.with.dots" = {"#), "{}", rendered);
+        assert!(rendered.contains(r#""title" = "Say \"Hi\"";"#), "{}", rendered);
+    }
+
+    #[test]
+    fn pins_file_page_urls() -> Result<()> {
+        let manifest: YamlManifest = serde_yaml::from_str("version: 1.12.2\nmods:\n- name: jei\n  files:\n  \
+            - filePageUrl: https://www.curseforge.com/minecraft/mc-mods/jei/files/3043174\n")?;
+        let manifest = manifest.pin_file_page_urls()?;
+        assert_eq!(manifest.mods[0].files.as_ref().and_then(|f| f[0].id), Some(3043174));
+
+        let renamed: YamlManifest = serde_yaml::from_str("version: 1.12.2\nmods:\n- name: hei\n  files:\n  \
+            - filePageUrl: https://www.curseforge.com/minecraft/mc-mods/jei/files/3043174\n")?;
+        assert!(renamed.pin_file_page_urls().is_err(), "The URL's project should match the name");
+
+        Ok(())
+    }
+
+    #[test]
+    fn tells_floating_mods_from_pinned() {
+        assert!(YamlMod::with_name("jei").is_floating());
+        assert!(!YamlMod::with_files("jei", 1, YamlModFile::with_id(2)).is_floating());
+        assert!(YamlMod::with_files("jei", 1, YamlModFile { maturity: Some("beta".to_string()), ..Default::default() }).is_floating());
+        let direct = YamlModFile { src: Some("https://example.org/a.jar".to_string()), ..Default::default() };
+        assert!(!YamlMod { name: "a".to_string(), files: Some(vec![direct]), ..Default::default() }.is_floating());
+    }
+
+    #[test]
+    fn pins_only_the_file_choice() -> Result<()> {
+        let mut beta = YamlMod { files: Some(vec![YamlModFile { maturity: Some("beta".to_string()), ..Default::default() }]), ..YamlMod::with_id("beta", 1) };
+        beta.pin(2);
+        let file = &beta.files.unwrap()[0];
+        assert_eq!((file.id, file.maturity.as_deref()), (Some(2), Some("beta")));
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("pack.yaml");
+        std::fs::write(&path, "version: 1.12.2\nvars:\n  chests: ironchest\nmods:\n- name: JEI\n- name: ${chests}\n- name: Kept\n  id: 3\n")?;
+        assert_eq!(YamlManifest::load_own_names(&path)?, vec!["jei", "ironchest", "Kept"], "Names are as when generating");
+        Ok(())
+    }
+
+    #[test]
+    fn unpins_only_the_file_choice() -> Result<()> {
+        let mut jei = YamlMod::with_files("jei", 238222, YamlModFile::with_id(2));
+        assert!(jei.unpin());
+        assert!(jei.files.is_none() && jei.id == Some(238222), "The project ID isn't a pin");
+        assert!(!jei.unpin());
+
+        let mut beta = YamlMod::with_files("beta", 1, YamlModFile { id: Some(2), maturity: Some("beta".to_string()), ..Default::default() });
+        assert!(beta.unpin());
+        assert_eq!(beta.files.unwrap()[0].maturity.as_deref(), Some("beta"));
+
+        let manifest: YamlManifest = serde_yaml::from_str("version: 1.12.2\nmods:\n- name: fragile\n  locked: true\n")?;
+        assert!(manifest.validate().is_err(), "Locked mods can't float");
+        Ok(())
+    }
+
+    #[test]
+    fn recognizes_project_ids_as_names() {
+        assert_eq!(YamlMod::with_name("238222").project_id_in_name(), Some(238222));
+        assert_eq!(YamlMod::with_id("238222", 1).project_id_in_name(), None, "An explicit id wins");
+        assert_eq!(YamlMod::with_name("jei").project_id_in_name(), None);
+        assert_eq!(YamlMod::with_name("99999999999").project_id_in_name(), None, "Too large for a project ID");
+    }
+
+    #[test]
+    fn leaves_out_disabled_mods() -> Result<()> {
+        let manifest: YamlManifest = serde_yaml::from_str(
+            "version: 1.12.2\nmods:\n- name: jei\n- name: foo\n  enabled: false\n  files:\n  - id: 1\n- name: bar\n  enabled: true\n")?;
+        let manifest = manifest.without_disabled();
+        let names: Vec<_> = manifest.mods.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["jei", "bar"]);
+        assert_eq!(manifest.disabled, vec!["foo"]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_multiple_files() {
+        let manifest = YamlManifest::new("1.12.2")
+            .with_mod(YamlMod::with_files("jei", 238222, YamlModFile::with_id(1)).file(YamlModFile::with_id(2)));
+        assert!(manifest.validate().is_err(), "Should reject more than one file");
+        assert!(YamlManifest::new("1.12.2").with_mod(YamlMod::with_name("jei")).validate().is_ok());
+    }
+
+    #[test]
+    fn parses_cached_file_info_without_extra_hashes() -> Result<()> {
+        let info: CurseModFileInfo = serde_json::from_str(
+            r#"{"md5":"abc","sha256":"def","size":3,"download_url":"https://media.forgecdn.net/files/1/2/a.jar"}"#)?;
+        assert_eq!(info.hashes.len(), 2);
+        assert_eq!(info.hashes[&HashAlgorithm::Sha256], "def");
+        assert!(info.embedded_jars.is_empty());
+
+        Ok(())
     }
 
     fn write_yaml_manifest(file: &File, imports: Vec<String>, mods: Vec<YamlMod>) -> Result<()> {
         serde_yaml::to_writer(file, &YamlManifest {
             version: "1.12.2".to_string(),
+            loader: None,
+            game_version_type_id: None,
+            vars: BTreeMap::new(),
             imports,
-            mods
+            mods,
+            auto_deps: false,
+            disabled: vec![],
+            sources: BTreeMap::new(),
         })?;
 
         Ok(())
@@ -0,0 +1,240 @@
+//! Mod IDs, versions and version constraints declared inside mod jars, and the conflicts
+//! between them that would otherwise only show up when the game refuses to start.
+
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::model::NixMod;
+use crate::versions::{self, RangeSyntax};
+
+// Forge and NeoForge respectively.
+const MODS_TOML_PATHS: &[&str] = &["META-INF/mods.toml", "META-INF/neoforge.mods.toml"];
+const FABRIC_JSON_PATH: &str = "fabric.mod.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ModMetadata {
+    /// Mod IDs the jar provides, with their versions.
+    #[serde(default)]
+    pub provides: BTreeMap<String, String>,
+    #[serde(default)]
+    pub requires: Vec<Requirement>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Requirement {
+    pub id: String,
+    pub range: String,
+    pub syntax: RangeSyntax,
+}
+
+impl ModMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.provides.is_empty() && self.requires.is_empty()
+    }
+}
+
+/// Reads whichever of `mods.toml`, `neoforge.mods.toml` and `fabric.mod.json` the jar has.
+pub fn read_mod_metadata(jar: &[u8]) -> Result<ModMetadata> {
+    let mut archive = ZipArchive::new(Cursor::new(jar))?;
+    let mut read = |path: &str| -> Option<String> {
+        let mut contents = String::new();
+        archive.by_name(path).ok()?.read_to_string(&mut contents).ok()?;
+        Some(contents)
+    };
+    let jar_version = read("META-INF/MANIFEST.MF")
+        .and_then(|manifest| manifest.lines()
+            .find_map(|l| l.strip_prefix("Implementation-Version:").map(|v| v.trim().to_string())));
+
+    let mut metadata = ModMetadata::default();
+    for path in MODS_TOML_PATHS {
+        if let Some(toml) = read(path) {
+            parse_mods_toml(&toml, jar_version.as_deref(), &mut metadata).context(format!("Parsing {}", path))?;
+        }
+    }
+    if let Some(json) = read(FABRIC_JSON_PATH) {
+        parse_fabric_json(&json, &mut metadata).context(format!("Parsing {}", FABRIC_JSON_PATH))?;
+    }
+    Ok(metadata)
+}
+
+fn parse_mods_toml(source: &str, jar_version: Option<&str>, metadata: &mut ModMetadata) -> Result<()> {
+    let toml: toml::Value = toml::from_str(source)?;
+    for entry in toml.get("mods").and_then(|m| m.as_array()).into_iter().flatten() {
+        let id = match entry.get("modId").and_then(|i| i.as_str()) {
+            Some(id) => id,
+            None => continue,
+        };
+        let version = match entry.get("version").and_then(|v| v.as_str()) {
+            Some("${file.jarVersion}") => jar_version,
+            other => other,
+        };
+        // Without a version there's nothing to check requirements against.
+        if let Some(version) = version {
+            metadata.provides.insert(id.to_string(), version.to_string());
+        }
+    }
+    let dependencies = toml.get("dependencies").and_then(|d| d.as_table());
+    for dependency in dependencies.into_iter().flat_map(|d| d.values()).filter_map(|d| d.as_array()).flatten() {
+        // Forge says `mandatory = true`, NeoForge says `type = "required"`.
+        let mandatory = dependency.get("mandatory").and_then(|m| m.as_bool())
+            .or_else(|| dependency.get("type").and_then(|t| t.as_str()).map(|t| t.eq_ignore_ascii_case("required")))
+            .unwrap_or(true);
+        if let (true, Some(id), Some(range)) = (mandatory,
+                                                dependency.get("modId").and_then(|i| i.as_str()),
+                                                dependency.get("versionRange").and_then(|r| r.as_str())) {
+            metadata.requires.push(Requirement { id: id.to_string(), range: range.to_string(), syntax: RangeSyntax::Maven });
+        }
+    }
+    Ok(())
+}
+
+fn parse_fabric_json(source: &str, metadata: &mut ModMetadata) -> Result<()> {
+    let json: serde_json::Value = serde_json::from_str(source)?;
+    if let (Some(id), Some(version)) = (json["id"].as_str(), json["version"].as_str()) {
+        metadata.provides.insert(id.to_string(), version.to_string());
+    }
+    for (id, range) in json["depends"].as_object().into_iter().flatten() {
+        // A list of ranges means any of them will do.
+        let range = match range {
+            serde_json::Value::String(range) => range.clone(),
+            serde_json::Value::Array(ranges) => ranges.iter().filter_map(|r| r.as_str()).collect::<Vec<_>>().join(" || "),
+            _ => continue,
+        };
+        metadata.requires.push(Requirement { id: id.clone(), range, syntax: RangeSyntax::Fabric });
+    }
+    Ok(())
+}
+
+/// A library whose version in the manifest doesn't satisfy everything that requires it.
+#[derive(Debug, PartialEq)]
+pub struct VersionConflict {
+    pub library: String,
+    pub provider: String,
+    pub version: String,
+    /// Every mod requiring the library, with its constraint and whether the version satisfies it.
+    pub requirers: Vec<(String, String, bool)>,
+}
+
+/// Checks every requirement on a mod ID that some manifest entry provides. Requirements on
+/// mods that aren't in the manifest, such as the loader itself, are left alone.
+pub fn find_version_conflicts(mods: &[NixMod]) -> Vec<VersionConflict> {
+    let mut conflicts = Vec::new();
+    for provider in mods {
+        for (library, version) in &provider.metadata.provides {
+            let requirers: Vec<(String, String, bool)> = mods.iter()
+                .flat_map(|m| m.metadata.requires.iter().map(move |r| (m, r)))
+                .filter(|(m, r)| &r.id == library && m.slug != provider.slug)
+                .map(|(m, r)| {
+                    // Constraints we can't parse aren't worth failing a build over.
+                    let satisfied = versions::satisfies(r.syntax, &r.range, version).unwrap_or_else(|| {
+                        log::debug!("{}: can't understand version range {:?} for {}", m.slug, r.range, library);
+                        true
+                    });
+                    (m.slug.clone(), r.range.clone(), satisfied)
+                })
+                .collect();
+            if requirers.iter().any(|(_, _, satisfied)| !satisfied) {
+                conflicts.push(VersionConflict {
+                    library: library.clone(),
+                    provider: provider.slug.clone(),
+                    version: version.clone(),
+                    requirers,
+                });
+            }
+        }
+    }
+    conflicts
+}
+
+impl std::fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let requirers: Vec<String> = self.requirers.iter()
+            .map(|(slug, range, satisfied)| format!("{} requires {}{}", slug, range, if *satisfied { "" } else { " (not satisfied)" }))
+            .collect();
+        write!(f, "{} {} (from {}) conflicts with its dependents: {}",
+               self.library, self.version, self.provider, requirers.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    fn jar(files: &[(&str, &str)]) -> Result<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            writer.start_file(*name, FileOptions::default())?;
+            writer.write_all(contents.as_bytes())?;
+        }
+        Ok(writer.finish()?.into_inner())
+    }
+
+    #[test]
+    fn reads_forge_and_fabric_metadata() -> Result<()> {
+        let forge = jar(&[
+            ("META-INF/MANIFEST.MF", "Manifest-Version: 1.0\nImplementation-Version: 2.1.0\n"),
+            ("META-INF/mods.toml", r#"
+modLoader = "javafml"
+[[mods]]
+modId = "examplemod"
+version = "${file.jarVersion}"
+[[dependencies.examplemod]]
+modId = "forge"
+mandatory = true
+versionRange = "[47,)"
+[[dependencies.examplemod]]
+modId = "jei"
+mandatory = false
+versionRange = "[15,)"
+"#),
+        ])?;
+        let metadata = read_mod_metadata(&forge)?;
+        assert_eq!(metadata.provides.get("examplemod").map(String::as_str), Some("2.1.0"));
+        assert_eq!(metadata.requires, vec![
+            Requirement { id: "forge".to_string(), range: "[47,)".to_string(), syntax: RangeSyntax::Maven }]);
+
+        let fabric = jar(&[("fabric.mod.json", r#"{"id": "other", "version": "1.0.0", "depends": {"cloth-config": [">=6", "~5.3"]}}"#)])?;
+        let metadata = read_mod_metadata(&fabric)?;
+        assert_eq!(metadata.provides.get("other").map(String::as_str), Some("1.0.0"));
+        assert_eq!(metadata.requires[0].range, ">=6 || ~5.3");
+
+        assert!(read_mod_metadata(&jar(&[])?)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn reports_unsatisfied_requirers() {
+        let requiring = |slug: &str, range: &str| NixMod {
+            metadata: ModMetadata {
+                provides: BTreeMap::new(),
+                requires: vec![Requirement { id: "cloth_config".to_string(), range: range.to_string(), syntax: RangeSyntax::Maven }],
+            },
+            ..NixMod::new(slug, slug, 0)
+        };
+        let library = NixMod {
+            metadata: ModMetadata {
+                provides: vec![("cloth_config".to_string(), "11.1.106".to_string())].into_iter().collect(),
+                requires: vec![],
+            },
+            ..NixMod::new("cloth-config", "Cloth Config", 1)
+        };
+
+        let fine = vec![library.clone(), requiring("a", "[11,)")];
+        assert!(find_version_conflicts(&fine).is_empty());
+
+        let conflicting = vec![library, requiring("a", "[11,)"), requiring("b", "[8,9)")];
+        let conflicts = find_version_conflicts(&conflicting);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].to_string(),
+                   "cloth_config 11.1.106 (from cloth-config) conflicts with its dependents: a requires [11,), b requires [8,9) (not satisfied)");
+    }
+}
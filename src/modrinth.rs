@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::hashes::HashAlgorithm;
+use crate::model::{Side, YamlManifest, YamlMod, YamlModFile};
+
+/// The `modrinth.index.json` at the root of a `.mrpack`.
+#[derive(Deserialize, Debug)]
+pub struct ModrinthIndex {
+    pub files: Vec<ModrinthFile>,
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ModrinthFile {
+    pub path: String,
+    pub hashes: BTreeMap<String, String>,
+    #[serde(default)]
+    pub env: Option<ModrinthEnv>,
+    pub downloads: Vec<String>,
+    #[serde(rename = "fileSize")]
+    pub file_size: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ModrinthEnv {
+    pub client: String,
+    pub server: String,
+}
+
+pub fn is_index(import: &str) -> bool {
+    import.ends_with(".json")
+}
+
+/// Converts the mods in a Modrinth index to direct-URL entries, which need no Curse lookups.
+pub fn load_index(index_path: &Path) -> Result<YamlManifest> {
+    log::info!("Reading Modrinth index {}...", index_path.display());
+    let index: ModrinthIndex = serde_json::from_reader(File::open(index_path)
+        .context(format!("While opening {:?}", index_path))?)
+        .context(format!("While parsing Modrinth index from {:?}", index_path))?;
+    let version = index.dependencies.get("minecraft")
+        .context(format!("{:?} doesn't name a Minecraft version", index_path))?;
+
+    let mut manifest = YamlManifest::new(version);
+    for file in index.files {
+        let filename = match file.path.strip_prefix("mods/") {
+            Some(filename) => filename.to_string(),
+            None => {
+                log::info!("Skipping {} from Modrinth index, as it isn't a mod", file.path);
+                continue;
+            }
+        };
+        let src = file.downloads.first()
+            .context(format!("{} has no download URL", file.path))?;
+        let (side, required) = match file.env {
+            None => (Side::Both, true),
+            Some(ModrinthEnv { client, server }) => {
+                let side = match (client.as_str(), server.as_str()) {
+                    ("unsupported", _) => Side::Server,
+                    (_, "unsupported") => Side::Client,
+                    _ => Side::Both,
+                };
+                (side, client != "optional" && server != "optional")
+            }
+        };
+        // Modrinth also provides sha1, but that's not something we emit.
+        let hashes = file.hashes.get("sha512")
+            .map(|h| (HashAlgorithm::Sha512, h.clone()))
+            .into_iter()
+            .collect();
+        let name = filename.trim_end_matches(".jar").to_lowercase();
+        let mod_file = YamlModFile {
+            name: Some(filename),
+            src: Some(src.clone()),
+            size: Some(file.file_size),
+            hashes,
+            ..Default::default()
+        };
+        let yaml_mod = YamlMod::with_name(&name).side(side).file(mod_file);
+        manifest = manifest.with_mod(if required { yaml_mod } else { yaml_mod.required(false) });
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_index_to_direct_mods() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("modrinth.index.json");
+        std::fs::write(&path, r#"{
+            "formatVersion": 1, "game": "minecraft", "versionId": "1.0", "name": "Pack",
+            "files": [
+                {"path": "mods/sodium-fabric-0.5.3.jar", "hashes": {"sha1": "aa", "sha512": "bb"},
+                 "env": {"client": "required", "server": "unsupported"},
+                 "downloads": ["https://cdn.modrinth.com/data/AANobbMI/versions/1/sodium-fabric-0.5.3.jar"], "fileSize": 1024},
+                {"path": "config/sodium.json", "hashes": {"sha512": "cc"}, "downloads": ["https://example.org/c"], "fileSize": 1}
+            ],
+            "dependencies": {"minecraft": "1.20.1", "fabric-loader": "0.14.22"}
+        }"#)?;
+
+        let manifest = load_index(&path)?;
+        assert_eq!(manifest.version, "1.20.1");
+        assert_eq!(manifest.mods.len(), 1, "Only mods should be imported");
+        let sodium = &manifest.mods[0];
+        assert_eq!(sodium.name, "sodium-fabric-0.5.3");
+        assert_eq!(sodium.side, Some(Side::Client));
+        assert!(sodium.is_direct());
+        assert_eq!(sodium.files.as_ref().unwrap()[0].hashes[&HashAlgorithm::Sha512], "bb");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::model::NixMod;
+
+// Reserved on at least one common filesystem, or a path separator.
+const UNSAFE_FILENAME_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// A replacement made to filenames before they're made safe, from `filename_rules` in the config.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FilenameRule {
+    /// A regular expression, replaced wherever it matches.
+    pub pattern: String,
+    /// What to replace it with, in which `$1` and so on are the pattern's groups.
+    #[serde(default)]
+    pub replace: String,
+}
+
+/// Dropping parentheses, as cursetool always has.
+pub fn default_filename_rules() -> Vec<FilenameRule> {
+    vec![FilenameRule { pattern: "[()]".to_string(), replace: String::new() }]
+}
+
+/// The `filename_rules` from the config, ready to apply. They're kept as written too, so a lock
+/// can tell whether they've changed since it was written.
+pub struct CompiledRules {
+    rules: Vec<FilenameRule>,
+    compiled: Vec<(Regex, String)>,
+}
+
+impl CompiledRules {
+    pub fn new(rules: &[FilenameRule]) -> Result<Self> {
+        let compiled = rules.iter()
+            .map(|rule| Ok((Regex::new(&rule.pattern).context(format!("While compiling filename rule {:?}", rule.pattern))?,
+                            rule.replace.clone())))
+            .collect::<Result<_>>()?;
+        Ok(CompiledRules { rules: rules.to_vec(), compiled })
+    }
+
+    pub fn rules(&self) -> &[FilenameRule] {
+        &self.rules
+    }
+}
+
+impl Default for CompiledRules {
+    fn default() -> Self {
+        CompiledRules::new(&default_filename_rules()).expect("The default filename rules compile")
+    }
+}
+
+/// Produces a filesystem-safe version of a mod's filename.
+///
+/// The `filename_rules` are applied first, by default dropping parentheses. Then the result is
+/// made safe as by `safe_filename`.
+pub fn sanitize_filename(filename: &str, rules: &CompiledRules) -> String {
+    let mut replaced = filename.to_string();
+    for (pattern, replacement) in &rules.compiled {
+        replaced = pattern.replace_all(&replaced, replacement.as_str()).into_owned();
+    }
+    safe_filename(&replaced)
+}
+
+/// Makes a name safe to save a file under: reserved and control characters become `_`, and
+/// runs of whitespace collapse into a single space. Non-ASCII characters are left alone.
+pub fn safe_filename(name: &str) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    let mut last_was_space = false;
+    for c in name.trim().chars() {
+        if c.is_whitespace() {
+            if !last_was_space {
+                sanitized.push(' ');
+            }
+            last_was_space = true;
+            continue;
+        }
+        last_was_space = false;
+        if c.is_control() || UNSAFE_FILENAME_CHARS.contains(&c) {
+            sanitized.push('_');
+        } else {
+            sanitized.push(c);
+        }
+    }
+    sanitized
+}
+
+/// Fails if two mods installed on the same side would be saved under the same filename, which
+/// is compared case-insensitively as some filesystems are.
+pub fn check_filename_collisions(mod_entries: &[NixMod]) -> Result<()> {
+    let mut by_filename: BTreeMap<String, Vec<&NixMod>> = BTreeMap::new();
+    for nix_mod in mod_entries {
+        by_filename.entry(nix_mod.filename.to_lowercase()).or_default().push(nix_mod);
+    }
+    let mut collisions = Vec::new();
+    for same_name in by_filename.values() {
+        for (i, a) in same_name.iter().enumerate() {
+            for b in &same_name[i + 1..] {
+                if (a.side.on_client() && b.side.on_client()) || (a.side.on_server() && b.side.on_server()) {
+                    collisions.push(format!("{} and {} would both be saved as {}", a.slug, b.slug, b.filename));
+                }
+            }
+        }
+    }
+    if !collisions.is_empty() {
+        anyhow::bail!("Filenames collide, which filename_rules in cursetool.yaml may be to blame for:\n  {}",
+                      collisions.join("\n  "));
+    }
+    Ok(())
+}
+
+/// Percent-encodes everything outside of the RFC 3986 unreserved set.
+///
+/// Input that is already (partially) encoded is decoded first, so this is idempotent.
+pub fn encode_path_segment(segment: &str) -> String {
+    let decoded = urlencoding::decode(segment).unwrap_or_else(|_| segment.to_string());
+    urlencoding::encode(&decoded)
+}
+
+/// Rewrites a download URL so that every path segment is consistently encoded.
+///
+/// CurseForge hands out URLs that are sometimes encoded and sometimes not, and
+/// requires `+` to be encoded even though the URL spec doesn't.
+pub fn normalize_download_url(download_url: &str) -> Result<String> {
+    let url = Url::parse(download_url)
+        .context(format!("Parsing download URL {}", download_url))?;
+    let segments: Vec<String> = url.path_segments()
+        .context(format!("Download URL {} has no path", download_url))?
+        .map(encode_path_segment)
+        .collect();
+    // Build the URL by hand; Url would otherwise re-encode our percent signs.
+    let mut fixed_url = format!("{}://{}", url.scheme(),
+                                url.host_str().context(format!("Download URL {} has no host", download_url))?);
+    if let Some(port) = url.port() {
+        fixed_url.push_str(&format!(":{}", port));
+    }
+    for segment in segments {
+        fixed_url.push('/');
+        fixed_url.push_str(&segment);
+    }
+    if let Some(query) = url.query() {
+        fixed_url.push('?');
+        fixed_url.push_str(query);
+    }
+    Ok(fixed_url)
+}
+
+/// Where `--icon-dir` saves a mod's icon: its name, with the extension the icon URL has.
+pub fn icon_filename(slug: &str, icon_url: &str) -> String {
+    let last_segment = icon_url.split(['?', '#']).next().unwrap_or_default().rsplit('/').next().unwrap_or_default();
+    let extension = match last_segment.rsplit_once('.') {
+        Some((_, extension)) if !extension.is_empty() && extension.len() <= 4 && extension.chars().all(|c| c.is_ascii_alphanumeric()) =>
+            extension.to_ascii_lowercase(),
+        _ => "png".to_string(),
+    };
+    format!("{}.{}", safe_filename(slug), extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Side;
+
+    #[test]
+    fn sanitizes_filenames() {
+        let rules = CompiledRules::default();
+        assert_eq!(sanitize_filename("Iron Chests (1.12.2).jar", &rules), "Iron Chests 1.12.2.jar");
+        assert_eq!(sanitize_filename("a  b\tc.jar", &rules), "a b c.jar");
+        assert_eq!(sanitize_filename("§6Fancy: Mod?.jar", &rules), "§6Fancy_ Mod_.jar");
+        assert_eq!(sanitize_filename("模组-1.0.jar", &rules), "模组-1.0.jar");
+        assert_eq!(safe_filename("Iron Chests (1.12.2).jar"), "Iron Chests (1.12.2).jar");
+    }
+
+    #[test]
+    fn applies_configured_rules() -> Result<()> {
+        let keep_versions = CompiledRules::new(&[FilenameRule { pattern: r"\((\d[^)]*)\)".to_string(), replace: "[$1]".to_string() }])?;
+        assert_eq!(sanitize_filename("Iron Chests (1.12.2).jar", &keep_versions), "Iron Chests [1.12.2].jar");
+        assert_eq!(sanitize_filename("Chisel (Fork)?.jar", &CompiledRules::new(&[])?), "Chisel (Fork)_.jar");
+        assert!(CompiledRules::new(&[FilenameRule { pattern: "(".to_string(), replace: String::new() }]).is_err());
+
+        let jar = |slug: &str, filename: &str, side: Side| NixMod { filename: filename.to_string(), side, ..NixMod::new(slug, slug, 1) };
+        assert!(check_filename_collisions(&[jar("a", "Mod 1.jar", Side::Client), jar("b", "mod 1.jar", Side::Server)]).is_ok(),
+                "Client and server mods are never installed together");
+        let error = check_filename_collisions(&[jar("a", "Mod 1.jar", Side::Both), jar("b", "mod 1.jar", Side::Server)]).unwrap_err();
+        assert!(error.to_string().contains("a and b would both be saved as mod 1.jar"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn encodes_segments_idempotently() {
+        assert_eq!(encode_path_segment("a b+c.jar"), "a%20b%2Bc.jar");
+        assert_eq!(encode_path_segment("a%20b%2Bc.jar"), "a%20b%2Bc.jar");
+        assert_eq!(encode_path_segment("§.jar"), "%C2%A7.jar");
+        assert_eq!(encode_path_segment("a  b.jar"), "a%20%20b.jar");
+    }
+
+    #[test]
+    fn names_icons_after_mods() {
+        assert_eq!(icon_filename("jei", "https://media.forgecdn.net/avatars/thumbnails/29/69/256/256/635596230345639462.PNG"), "jei.png");
+        assert_eq!(icon_filename("jei", "https://media.forgecdn.net/avatars/29/69/logo.webp?width=64"), "jei.webp");
+        assert_eq!(icon_filename("odd/name", "https://example.org/icon"), "odd_name.png");
+    }
+
+    #[test]
+    fn normalizes_download_urls() -> Result<()> {
+        assert_eq!(normalize_download_url("https://media.forgecdn.net/files/2747/935/ironchest-1.12.2+7.0.72.jar")?,
+                   "https://media.forgecdn.net/files/2747/935/ironchest-1.12.2%2B7.0.72.jar");
+        assert_eq!(normalize_download_url("https://media.forgecdn.net/files/1/2/My%20Mod%20(1).jar")?,
+                   "https://media.forgecdn.net/files/1/2/My%20Mod%20%281%29.jar");
+        assert_eq!(normalize_download_url("https://media.forgecdn.net/files/1/2/模组.jar")?,
+                   "https://media.forgecdn.net/files/1/2/%E6%A8%A1%E7%BB%84.jar");
+        Ok(())
+    }
+}
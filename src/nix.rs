@@ -0,0 +1,221 @@
+//! Helpers for rendering values as Nix source.
+
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+
+use crate::hashes::HashAlgorithm;
+
+/// Renders a double-quoted Nix string. Also used for attribute names, so that slugs
+/// starting with a digit or containing dots stay a single attribute.
+pub fn string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            // Would otherwise start an interpolation.
+            '$' if chars.peek() == Some(&'{') => quoted.push_str("\\$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub fn string_list(list: &[String]) -> String {
+    list.iter().map(|s| string(s)).collect::<Vec<_>>().join(" ")
+}
+
+/// One `"<algorithm>" = "<hash>";` line per hash, at attribute set member indentation.
+pub fn hash_lines(hashes: &BTreeMap<HashAlgorithm, String>) -> String {
+    hashes.iter()
+        .map(|(algorithm, hash)| format!("        {} = {};", string(&algorithm.to_string()), string(hash)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Parses the subset of Nix that cursetool writes: attribute sets, lists, strings,
+/// integers, booleans and comments. Anything fancier is rejected.
+pub fn parse(source: &str) -> Result<Value> {
+    let mut parser = Parser { chars: source.char_indices().peekable(), source };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if let Some(&(position, _)) = parser.chars.peek() {
+        anyhow::bail!("Unexpected trailing input at {}", parser.location(position));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    source: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn location(&self, position: usize) -> String {
+        let line = self.source[..position].matches('\n').count() + 1;
+        format!("line {}", line)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&(_, c)) = self.chars.peek() {
+            if c == '#' {
+                for (_, c) in self.chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            } else if c.is_whitespace() {
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((position, c)) => anyhow::bail!("Expected {:?} but found {:?} at {}", expected, c, self.location(position)),
+            None => anyhow::bail!("Expected {:?} but found the end of input", expected),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        let &(position, c) = self.chars.peek().context("Unexpected end of input")?;
+        match c {
+            '{' => self.attribute_set(),
+            '[' => self.list(),
+            '"' => self.string().map(Value::String),
+            c if c.is_ascii_digit() || c == '-' => {
+                let word = self.word();
+                let number: i64 = word.parse()
+                    .context(format!("Invalid number {:?} at {}", word, self.location(position)))?;
+                Ok(Value::from(number))
+            }
+            _ => match self.word().as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                "null" => Ok(Value::Null),
+                other => anyhow::bail!("Unsupported expression {:?} at {}", other, self.location(position)),
+            }
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(&(_, c)) = self.chars.peek() {
+            if c.is_alphanumeric() || matches!(c, '_' | '-' | '\'' | '.') {
+                word.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        word
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next().context("Unterminated string")? {
+                (_, '"') => return Ok(string),
+                (_, '\\') => match self.chars.next().context("Unterminated string")?.1 {
+                    'n' => string.push('\n'),
+                    'r' => string.push('\r'),
+                    't' => string.push('\t'),
+                    c => string.push(c),
+                },
+                (position, '$') if matches!(self.chars.peek(), Some((_, '{'))) =>
+                    anyhow::bail!("Interpolation is not supported at {}", self.location(position)),
+                (_, c) => string.push(c),
+            }
+        }
+    }
+
+    fn attribute_set(&mut self) -> Result<Value> {
+        self.expect('{')?;
+        let mut attributes = Map::new();
+        loop {
+            self.skip_whitespace();
+            let position = self.chars.peek().map_or(self.source.len(), |&(position, _)| position);
+            let name = match self.chars.peek() {
+                Some((_, '}')) => {
+                    self.chars.next();
+                    return Ok(Value::Object(attributes));
+                }
+                Some((_, '"')) => self.string()?,
+                _ => self.word(),
+            };
+            if name.is_empty() {
+                anyhow::bail!("Expected an attribute name");
+            }
+            self.expect('=')?;
+            let value = self.value()?;
+            self.expect(';')?;
+            // Nix refuses these too, so they'd only hide an entry.
+            if attributes.contains_key(&name) {
+                anyhow::bail!("Attribute {:?} is defined twice, again at {}", name, self.location(position));
+            }
+            attributes.insert(name, value);
+        }
+    }
+
+    fn list(&mut self) -> Result<Value> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if let Some((_, ']')) = self.chars.peek() {
+                self.chars.next();
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("jei"), r#""jei""#);
+        assert_eq!(string(r#"say "hi" \o/"#), r#""say \"hi\" \\o/""#);
+        assert_eq!(string("${pkgs.evil}"), r#""\${pkgs.evil}""#);
+        assert_eq!(string("$5 mod"), r#""$5 mod""#);
+    }
+
+    #[test]
+    fn parses_rendered_manifests() -> Result<()> {
+        let nix_mod = crate::model::NixMod::new("1st.mod", "Say \"Hi\"", 7)
+            .categories(vec!["library-api".to_string()])
+            .file("a b.jar", "https://media.forgecdn.net/files/1/2/a%20b.jar", 3)
+            .hash(HashAlgorithm::Sha256, "def");
+        let source = format!("# Generated\n{{\n    \"version\" = \"1.12.2\";\n    \"mods\" = {{\n{}\n    }};\n}}", nix_mod);
+        let parsed = parse(&source)?;
+        let entry = &parsed["mods"]["1st.mod"];
+        assert_eq!(entry["title"], "Say \"Hi\"");
+        assert_eq!(entry["id"], 7);
+        assert_eq!(entry["required"], true);
+        assert_eq!(entry["categories"][0], "library-api");
+        assert_eq!(entry["sha256"], "def");
+        assert!(parse("{ a = import ./foo.nix; }").is_err());
+        assert!(format!("{:#}", parse("{\n  a = 1;\n  \"a\" = 2;\n}").unwrap_err()).contains("defined twice, again at line 3"));
+
+        Ok(())
+    }
+}
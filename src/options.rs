@@ -1,31 +1,292 @@
 use std::path::PathBuf;
 
 use structopt::StructOpt;
-use structopt::clap::arg_enum;
+
+use crate::database::Database;
+use crate::faults::FaultInjector;
+use crate::hashes::HashList;
+use crate::mirror::MirrorKey;
+use crate::pinning::TlsRoots;
+use crate::units::{ByteSize, HumanDuration};
 
 #[derive(Debug, StructOpt)]
 #[structopt(about = "Rust implementation of Cursetool")]
 pub struct Commandline {
-    #[structopt(help = "Whether to convert Curse manifest files to yaml, or yaml to nix.")]
+    #[structopt(subcommand)]
     pub mode: Mode,
-    #[structopt(help = "Path to input file.\n\
-                    Should be a json file in curse mode,\n\
-                    and a yaml file in yaml mode")]
+    #[structopt(long, help = "Path to a config file. Defaults to cursetool.yaml, if present.")]
+    pub config: Option<PathBuf>,
+    #[structopt(long, help = "Number of mods to resolve in parallel. Defaults to the number of CPUs.")]
+    pub threads: Option<usize>,
+    #[structopt(long, help = "Number of files to download and hash in parallel. Defaults to 2.")]
+    pub download_threads: Option<usize>,
+    #[structopt(long, help = "Limit on the combined download rate per second, e.g. 2MB or 500KiB. Plain numbers are bytes.")]
+    pub max_download_rate: Option<ByteSize>,
+    #[structopt(long, help = "Fail before downloading more than this in total, e.g. 2GB, so an update that unexpectedly \
+                              pulls in far more than usual stops early. Cached files don't count.")]
+    pub max_download_bytes: Option<ByteSize>,
+    #[structopt(long, help = "Path to a JSON file that is kept updated with the progress of the run.")]
+    pub status_file: Option<PathBuf>,
+    #[structopt(long, help = "Write metrics of the run to this file in the Prometheus textfile format, e.g. for \
+                              node_exporter's textfile collector.")]
+    pub metrics_file: Option<PathBuf>,
+    #[structopt(long, help = "Keep the previous version of each output file that gets replaced, as <name>.bak.")]
+    pub backup: bool,
+    #[structopt(long, use_delimiter = true,
+                help = "Comma-separated directories with copies of mod jars, e.g. a server's mods folder. \
+                        Used to compute hashes missing from the cache instead of downloading again.")]
+    pub local_jar_dirs: Vec<PathBuf>,
+    #[structopt(long, use_delimiter = true,
+                help = "Comma-separated hosts that may be contacted. Requests and redirects to anything else fail.")]
+    pub allow_hosts: Option<Vec<String>>,
+    #[structopt(long, help = "Which certificate authorities to trust: bundled (the default), system or both. \
+                              Use system behind TLS-intercepting proxies whose CA is installed in the OS.")]
+    pub tls_roots: Option<TlsRoots>,
+    #[structopt(long, help = "Write outputs not given explicitly into this directory, under conventional names: \
+                              manifest.nix, manifest.lock.json and modlist.md for yaml, manifest.yaml for curse, \
+                              manifest.json for export-curse, modlist.csv for export csv and fetchlist.txt for export fetchlist.")]
+    pub output_dir: Option<PathBuf>,
+    #[structopt(long, help = "Give up on mods that aren't resolved this long after starting, e.g. 30m or 1h30m. \
+                              Plain numbers are seconds. The rest are still written out, but the run fails.")]
+    pub deadline: Option<HumanDuration>,
+    #[structopt(long, help = "Correct fixable mistakes in the input manifest, such as project IDs given as names.")]
+    pub fix: bool,
+    #[structopt(long, help = "Ask which project is meant when a slug search has no clear winner, instead of failing.")]
+    pub interactive: bool,
+    #[structopt(long, hidden = true,
+                help = "Fail this share of network requests, as p=<probability>[,seed=<number>]. For testing wrappers.")]
+    pub fault_inject: Option<FaultInjector>,
+}
+
+// Parsed once per run, so the size of the yaml options doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, StructOpt)]
+pub enum Mode {
+    #[structopt(about = "Convert a Curse manifest file to yaml")]
+    Curse {
+        #[structopt(help = "Path to input file. Should be a json file.")]
+        input_file: PathBuf,
+        #[structopt(help = "Path to output file. Will dump yaml data. Defaults to manifest.yaml in --output-dir.")]
+        output_file: Option<PathBuf>,
+    },
+    #[structopt(about = "Convert a yaml manifest to nix")]
+    Yaml(YamlOptions),
+    #[structopt(about = "Convert a yaml manifest back to a Curse manifest file")]
+    ExportCurse {
+        #[structopt(help = "Path to input file. Should be a yaml file.")]
+        input_file: PathBuf,
+        #[structopt(help = "Path to output file. Will dump Curse manifest json. Defaults to manifest.json in --output-dir.")]
+        output_file: Option<PathBuf>,
+    },
+    #[structopt(about = "Export a yaml manifest to other formats")]
+    Export(ExportCommand),
+    #[structopt(about = "Maintain the API response cache")]
+    Cache(CacheCommand),
+    #[structopt(about = "Remove a mod from a yaml manifest, reporting mods that depend on it")]
+    Remove {
+        #[structopt(help = "Path to the yaml manifest.")]
+        manifest: PathBuf,
+        #[structopt(help = "Name of the mod to remove.")]
+        name: String,
+        #[structopt(long, help = "Also remove mods that were only required by the removed one.")]
+        remove_orphans: bool,
+    },
+    #[structopt(about = "Compare a server's mods folder with a nix manifest, without changing anything")]
+    CheckServer {
+        #[structopt(long, help = "The server's mods folder.")]
+        mods_dir: PathBuf,
+        #[structopt(help = "Path to a nix manifest generated by cursetool.")]
+        manifest: PathBuf,
+    },
+    #[structopt(about = "Check a yaml manifest for likely mistakes, such as duplicate names, misspelled fields or unpinned mods on a release branch")]
+    Lint {
+        #[structopt(help = "Path to the yaml manifest. Imports are linted on their own.")]
+        manifest: PathBuf,
+        #[structopt(long, help = "The git branch the manifest is on, for CI checkouts without one. Defaults to the checkout's branch.")]
+        branch: Option<String>,
+    },
+    #[structopt(about = "Check a generated nix manifest for broken hand edits, such as bad URLs, hashes or sides")]
+    LintNix {
+        #[structopt(help = "Path to a nix manifest generated by cursetool.")]
+        manifest: PathBuf,
+    },
+    #[structopt(about = "Create or extend a yaml manifest from an existing server's mod jars, matched by fingerprint")]
+    ImportServer {
+        #[structopt(help = "The server's mods folder, either local or as [user@]host:path to read it over ssh.")]
+        source: String,
+        #[structopt(help = "Path to the yaml manifest. Mods are merged into it if it already exists.")]
+        output_file: PathBuf,
+        #[structopt(long, help = "Minecraft version to use when creating a new manifest.")]
+        game_version: Option<String>,
+    },
+    #[structopt(about = "List pinned mods that have newer files available, without changing anything")]
+    Outdated {
+        #[structopt(help = "Path to the yaml manifest.")]
+        manifest: PathBuf,
+    },
+    #[structopt(about = "Pin every mod without a file ID in a yaml manifest to its current newest file")]
+    Pin {
+        #[structopt(help = "Path to the yaml manifest, which is rewritten in place. Imported files are left alone.")]
+        manifest: PathBuf,
+    },
+    #[structopt(about = "Take the file pins out of mods in a yaml manifest, so they get the newest file again")]
+    Unpin {
+        #[structopt(help = "Path to the yaml manifest, which is rewritten in place.")]
+        manifest: PathBuf,
+        #[structopt(help = "Names of the mods to unpin. Defaults to every mod that isn't locked.")]
+        names: Vec<String>,
+    },
+    #[structopt(about = "Resolve one mod again with every request and response logged and the cache bypassed, to see why it failed")]
+    WhyFailed {
+        #[structopt(help = "Path to the yaml manifest.")]
+        manifest: PathBuf,
+        #[structopt(help = "Name of the mod to resolve, as the failed run reported it.")]
+        name: String,
+    },
+    #[structopt(about = "Summarize how the mods in a yaml manifest changed between two git revisions, as Markdown")]
+    Changelog {
+        #[structopt(long, default_value = "HEAD", help = "The older revision.")]
+        from: String,
+        #[structopt(long, help = "The newer revision. Defaults to the manifest as it is on disk.")]
+        to: Option<String>,
+        #[structopt(help = "Path to the yaml manifest, inside a git checkout.")]
+        manifest: PathBuf,
+    },
+    #[structopt(about = "Sort a yaml manifest's mods and normalize its layout, keeping comments")]
+    Fmt {
+        #[structopt(help = "Path to the yaml manifest, which is rewritten in place.")]
+        manifest: PathBuf,
+        #[structopt(long, help = "Only check the formatting, failing if the manifest would change. For CI.")]
+        check: bool,
+    },
+    #[structopt(about = "Print a JSON Schema for yaml manifests, e.g. for editor completion")]
+    Schema,
+    #[structopt(about = "Add every slug or project URL in a plain text list to a yaml manifest")]
+    ImportList {
+        #[structopt(help = "Path to a text file with one slug or project URL per line.")]
+        input_file: PathBuf,
+        #[structopt(help = "Path to the yaml manifest. Mods are merged into it if it already exists.")]
+        output_file: PathBuf,
+        #[structopt(long, help = "Minecraft version to use when creating a new manifest.")]
+        game_version: Option<String>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub struct YamlOptions {
+    #[structopt(help = "Path to input file. Should be a yaml file.")]
     pub input_file: PathBuf,
-    #[structopt(help = "Path to output file.\n\
-                    Will dump yaml data in curse mode,\n\
-                    and nix data in yaml mode.")]
-    pub output_file: PathBuf,
+    #[structopt(help = "Path to output file. Will dump nix data. Defaults to manifest.nix in --output-dir.")]
+    pub output_file: Option<PathBuf>,
+    #[structopt(long, help = "Also write a nix manifest with only the client-side mods.")]
+    pub client_output: Option<PathBuf>,
+    #[structopt(long, help = "Also write a nix manifest with only the server-side mods.")]
+    pub server_output: Option<PathBuf>,
+    #[structopt(long, default_value = "md5,sha256",
+                help = "Comma-separated hashes to emit. Supports md5, sha256, sha512 and blake3.")]
+    pub hashes: HashList,
+    #[structopt(long, help = "Also write the resolved mods to a JSON lockfile. Defaults to manifest.lock.json in --output-dir.")]
+    pub lock_file: Option<PathBuf>,
+    #[structopt(long, help = "Resolve the installer for the manifest's Forge/NeoForge loader and include it.")]
+    pub resolve_loader: bool,
+    #[structopt(long, help = "Leave out the header recording how the output was generated, so it only changes with the mods.")]
+    pub no_provenance: bool,
+    #[structopt(long, help = "Reuse the sizes and hashes recorded in this earlier output (nix or .json lockfile) for files whose pins haven't changed, instead of downloading them again.")]
+    pub verify_existing: Option<PathBuf>,
+    #[structopt(long, help = "Resolve every mod again, instead of carrying pinned mods that haven't changed forward from --lock-file.")]
+    pub full: bool,
+    #[structopt(long, help = "Carry on when mods resolve to older files than --lock-file has, as when an author deletes their newest file.")]
+    pub allow_downgrade: bool,
+    #[structopt(long, use_delimiter = true,
+                help = "Comma-separated Minecraft versions to resolve the manifest for instead of its own, \
+                        writing each output with the version in its name, e.g. mods-1.20.1.nix.")]
+    pub versions: Vec<String>,
+    #[structopt(long, help = "Point every src in the nix output into this mirror, e.g. https://mirror.internal/mods/. \
+                              The lockfile keeps the upstream URLs.")]
+    pub src_prefix: Option<String>,
+    #[structopt(long, default_value = "sha256", help = "How files are laid out in the --src-prefix mirror: sha256 or filename.")]
+    pub mirror_key: MirrorKey,
+    #[structopt(long, help = "With --src-prefix, also write the upstream URL and mirror path of every file, tab-separated.")]
+    pub fetch_list: Option<PathBuf>,
+    #[structopt(long, help = "Also record each project's icon URL in the nix output and lockfile, e.g. for launcher UIs.")]
+    pub rich_metadata: bool,
+    #[structopt(long, help = "With --rich-metadata, also download every icon into this directory, named after the mod.")]
+    pub icon_dir: Option<PathBuf>,
+    #[structopt(long, help = "Print which manifest's entry is used for each mod that several manifests list.")]
+    pub print_merge: bool,
+    #[structopt(long, help = "Write sha256 hashes in the nix output in Nix's base32, as nix-prefetch-url prints them, instead of hex. \
+                              The lockfile keeps hex.")]
+    pub base32_sha256: bool,
+    #[structopt(long, help = "Download this many files again, spread over the manifest, and check that hashing them as \
+                              nix-prefetch-url does gives the recorded sha256.")]
+    pub verify_prefetch: Option<usize>,
 }
 
-arg_enum! {
-    #[derive(Debug)]
-    pub enum Mode {
-        Curse,
-        Yaml,
-    }
+#[derive(Debug, StructOpt)]
+pub enum ExportCommand {
+    #[structopt(about = "Write an inventory of the resolved mods as CSV, e.g. for pack documentation")]
+    Csv {
+        #[structopt(help = "Path to input file. Should be a yaml file.")]
+        input_file: PathBuf,
+        #[structopt(help = "Path to output file. Will dump CSV. Defaults to modlist.csv in --output-dir.")]
+        output_file: Option<PathBuf>,
+    },
+    #[structopt(about = "Write every file's URL, sha256, size and filename, for aria2c -i or a mirroring script")]
+    Fetchlist {
+        #[structopt(help = "Path to input file. Should be a yaml file.")]
+        input_file: PathBuf,
+        #[structopt(help = "Path to output file. Defaults to fetchlist.txt, or fetchlist.json with --json, in --output-dir.")]
+        output_file: Option<PathBuf>,
+        #[structopt(long, help = "Write a JSON list instead of an aria2c input file.")]
+        json: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CacheCommand {
+    #[structopt(about = "Re-fetch metadata that is about to expire for every mod in a manifest. Meant for cron.")]
+    Refresh {
+        #[structopt(help = "Path to the yaml manifest.")]
+        manifest: PathBuf,
+        #[structopt(long, default_value = "6h", help = "Refresh entries expiring within this long, e.g. 6h or 1d.")]
+        within: HumanDuration,
+        #[structopt(long, default_value = "30", help = "Maximum number of API requests per minute.")]
+        requests_per_minute: u32,
+    },
+    #[structopt(about = "Write the cache to a .tar.zst archive, e.g. to share it between CI runners")]
+    Export {
+        #[structopt(help = "Path to the archive to write.")]
+        archive: PathBuf,
+        #[structopt(long = "for", help = "Only include entries used to resolve this yaml manifest.")]
+        for_manifest: Option<PathBuf>,
+    },
+    #[structopt(about = "Delete cache entries whose URL matches a pattern, e.g. after caching a bad response")]
+    Invalidate {
+        #[structopt(help = "URL to delete, where * matches anything, e.g. 'https://api.curseforge.com/v1/mods/238222*'.")]
+        pattern: String,
+    },
+    #[structopt(about = "Check cached file details against the CDN, dropping those for files that were re-uploaded")]
+    RevalidateFiles {
+        #[structopt(long, help = "Only check this many files, least recently checked first. Defaults to all of them.")]
+        sample: Option<usize>,
+    },
+    #[structopt(about = "Merge a cache archive into the cache, keeping the newer copy of each entry")]
+    Import {
+        #[structopt(help = "Path to the archive to read.")]
+        archive: PathBuf,
+    },
+}
+
+/// What `--version` and `-V` print.
+fn long_version() -> String {
+    let cache_dir = Database::cache_dir()
+        .map(|d| d.display().to_string())
+        .unwrap_or_else(|e| format!("unavailable ({:#})", e));
+    format!("{}\ncommit: {}\ncache: {}", env!("CARGO_PKG_VERSION"), env!("CURSETOOL_GIT_COMMIT"), cache_dir)
 }
 
 pub fn parse_commandline() -> Commandline {
-    Commandline::from_args()
-}
\ No newline at end of file
+    // clap wants the version to outlive the parser, which only runs once anyway.
+    let long_version: &'static str = Box::leak(long_version().into_boxed_str());
+    Commandline::from_clap(&Commandline::clap().version(long_version).long_version(long_version).get_matches())
+}
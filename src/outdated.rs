@@ -0,0 +1,95 @@
+use std::cmp::Reverse;
+
+use crate::curse_api::CurseModFile;
+use crate::KNOWN_LOADERS;
+
+/// A pinned mod, the file it's pinned to and the newest file it could be updated to.
+pub struct Update {
+    pub name: String,
+    pub pinned: CurseModFile,
+    pub newest: CurseModFile,
+}
+
+impl Update {
+    /// How many days older the pinned file is than the newest one.
+    pub fn days_behind(&self) -> i64 {
+        match (days_since_epoch(&self.newest.file_date), days_since_epoch(&self.pinned.file_date)) {
+            (Some(newest), Some(pinned)) => newest - pinned,
+            _ => 0,
+        }
+    }
+
+    pub fn is_outdated(&self) -> bool {
+        self.newest.id != self.pinned.id && self.newest.file_date > self.pinned.file_date
+    }
+}
+
+/// The newest file, leaving out files that are explicitly tagged for another loader.
+pub fn newest_file(files: Vec<CurseModFile>, loader: Option<&str>) -> Option<CurseModFile> {
+    files.into_iter()
+        .filter(|f| {
+            let file_loaders: Vec<String> = f.game_version.iter()
+                .map(|v| v.to_lowercase())
+                .filter(|v| KNOWN_LOADERS.contains(&v.as_str()))
+                .collect();
+            loader.is_none_or(|l| file_loaders.is_empty() || file_loaders.iter().any(|f| f == l))
+        })
+        .max_by(|a, b| a.file_date.cmp(&b.file_date))
+}
+
+/// Sorts the most outdated mods first, leaving out those that are up to date.
+pub fn by_staleness(updates: Vec<Update>) -> Vec<Update> {
+    let mut outdated: Vec<Update> = updates.into_iter().filter(Update::is_outdated).collect();
+    outdated.sort_by_key(|u| (Reverse(u.days_behind()), u.name.clone()));
+    outdated
+}
+
+/// Parses the date part of an ISO 8601 timestamp such as `2021-03-01T12:34:56.123Z`.
+fn days_since_epoch(timestamp: &str) -> Option<i64> {
+    let mut parts = timestamp.get(..10)?.split('-').map(|p| p.parse::<i64>());
+    let (year, month, day) = (parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?);
+    // Howard Hinnant's days_from_civil.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(id: u32, date: &str, versions: &[&str]) -> CurseModFile {
+        CurseModFile {
+            id,
+            file_name: format!("mod-{}.jar", id),
+            display_name: None,
+            file_date: date.to_string(),
+            download_url: String::new(),
+            game_version: versions.iter().map(|v| v.to_string()).collect(),
+            dependencies: vec![],
+            file_length: None,
+            file_fingerprint: None,
+        }
+    }
+
+    #[test]
+    fn sorts_by_staleness() {
+        let files = vec![file(1, "2021-01-01T00:00:00Z", &["1.16.5"]),
+                         file(2, "2021-03-01T00:00:00Z", &["1.16.5", "Forge"]),
+                         file(3, "2021-06-01T00:00:00Z", &["1.16.5", "Fabric"])];
+        let newest = newest_file(files.clone(), Some("forge")).unwrap();
+        assert_eq!(newest.id, 2, "Files for other loaders aren't updates");
+
+        let updates = vec![
+            Update { name: "a".to_string(), pinned: files[1].clone(), newest: newest.clone() },
+            Update { name: "b".to_string(), pinned: file(4, "2020-12-01T00:00:00Z", &[]), newest: newest.clone() },
+            Update { name: "c".to_string(), pinned: files[0].clone(), newest },
+        ];
+        let outdated = by_staleness(updates);
+        let summary: Vec<(&str, i64)> = outdated.iter().map(|u| (u.name.as_str(), u.days_behind())).collect();
+        assert_eq!(summary, vec![("b", 90), ("c", 59)]);
+    }
+}
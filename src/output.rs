@@ -0,0 +1,119 @@
+//! Writing output files so a crash or failure mid-write never leaves a truncated file behind.
+//!
+//! Everything is written to a temporary file next to the destination, which is renamed over it
+//! only once complete. With `--backup`, the file being replaced is kept as `<name>.bak`.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result};
+
+static KEEP_BACKUPS: AtomicBool = AtomicBool::new(false);
+
+/// Whether outputs replaced from now on keep a `.bak` of what they replace.
+pub fn keep_backups(keep: bool) {
+    KEEP_BACKUPS.store(keep, Ordering::Relaxed);
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// An output being written. Nothing at the destination changes until `commit`; dropping it
+/// without committing throws the partial file away.
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        // In the destination directory, as renames across filesystems aren't atomic.
+        let tmp_path = with_suffix(path, &format!(".{}.tmp", std::process::id()));
+        let file = File::create(&tmp_path)
+            .context(format!("While creating {:?}", tmp_path))?;
+        Ok(AtomicFile { path: path.to_path_buf(), tmp_path, writer: Some(BufWriter::new(file)) })
+    }
+
+    pub fn commit(self) -> Result<()> {
+        self.commit_keeping_backup(KEEP_BACKUPS.load(Ordering::Relaxed))
+    }
+
+    /// Commits without a `.bak` even with `--backup`, for files rewritten all through a run
+    /// that something else watches.
+    pub fn commit_without_backup(self) -> Result<()> {
+        self.commit_keeping_backup(false)
+    }
+
+    fn commit_keeping_backup(mut self, backup: bool) -> Result<()> {
+        let file = self.writer.take().unwrap().into_inner()
+            .map_err(|e| e.into_error())
+            .context(format!("While writing {:?}", self.path))?;
+        file.sync_all().context(format!("While writing {:?}", self.path))?;
+        if backup && self.path.exists() {
+            let backup_path = with_suffix(&self.path, ".bak");
+            // Copied rather than moved, so the destination exists throughout.
+            fs::copy(&self.path, &backup_path)
+                .context(format!("While backing up {:?} to {:?}", self.path, backup_path))?;
+        }
+        fs::rename(&self.tmp_path, &self.path)
+            .context(format!("While renaming {:?} to {:?}", self.tmp_path, self.path))?;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.writer.as_mut().unwrap().write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+/// Writes a whole file at once, like `fs::write`.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(contents.as_ref()).context(format!("While writing {:?}", path))?;
+    file.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_only_complete_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("mods.nix");
+        fs::write(&path, "old")?;
+
+        let mut abandoned = AtomicFile::create(&path)?;
+        abandoned.write_all(b"half")?;
+        drop(abandoned);
+        assert_eq!(fs::read_to_string(&path)?, "old", "Unfinished writes leave the output alone");
+
+        let mut file = AtomicFile::create(&path)?;
+        file.write_all(b"new")?;
+        file.commit_keeping_backup(true)?;
+        assert_eq!(fs::read_to_string(&path)?, "new");
+        assert_eq!(fs::read_to_string(dir.path().join("mods.nix.bak"))?, "old");
+        assert_eq!(fs::read_dir(dir.path())?.count(), 2, "No temporary files are left behind");
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use rustls::{Certificate, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError, WebPKIVerifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::quota::HandshakeCounter;
+
+/// Verifies certificates as usual, then additionally requires hosts with pins to
+/// present a certificate whose public key matches one of them.
+struct PinningVerifier {
+    inner: WebPKIVerifier,
+    // Host to SHA-256 hashes of acceptable SubjectPublicKeyInfos.
+    pins: BTreeMap<String, Vec<Vec<u8>>>,
+    // Certificates are only verified on full handshakes, which makes this a count of cold connections.
+    handshakes: Arc<HandshakeCounter>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(&self, roots: &RootCertStore, presented_certs: &[Certificate],
+                          dns_name: webpki::DNSNameRef, ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let verified = self.inner.verify_server_cert(roots, presented_certs, dns_name, ocsp_response)?;
+        let host: &str = dns_name.into();
+        self.handshakes.record(host);
+        let pins = match self.pins.get(host) {
+            Some(pins) => pins,
+            None => return Ok(verified),
+        };
+        // Pinning any certificate in the chain is allowed, so pins can name an intermediate.
+        let matched = presented_certs.iter()
+            .filter_map(|c| spki_hash(&c.0))
+            .any(|hash| pins.contains(&hash));
+        if matched {
+            Ok(verified)
+        } else {
+            Err(TLSError::General(format!("Certificate for {} matches none of its pinned public keys", host)))
+        }
+    }
+}
+
+/// Which certificate authorities to trust.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsRoots {
+    /// The Mozilla roots built into the binary, which work the same everywhere.
+    #[default]
+    Bundled,
+    /// The operating system's store, which is where TLS-intercepting proxies install their CA.
+    System,
+    Both,
+}
+
+impl FromStr for TlsRoots {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bundled" => Ok(TlsRoots::Bundled),
+            "system" => Ok(TlsRoots::System),
+            "both" => Ok(TlsRoots::Both),
+            other => anyhow::bail!("Unknown certificate store {}, expected bundled, system or both", other),
+        }
+    }
+}
+
+/// Where distributions keep their CA bundle, after whatever the environment points at.
+fn system_bundles() -> Vec<PathBuf> {
+    let from_env = ["SSL_CERT_FILE", "NIX_SSL_CERT_FILE"].iter().filter_map(std::env::var_os).map(PathBuf::from);
+    let known = [
+        "/etc/ssl/certs/ca-certificates.crt",
+        "/etc/pki/tls/certs/ca-bundle.crt",
+        "/etc/ssl/ca-bundle.pem",
+        "/etc/ssl/cert.pem",
+    ].iter().map(PathBuf::from);
+    from_env.chain(known).collect()
+}
+
+/// Adds the certificates from the first of `bundles` that exists, returning how many were added.
+fn add_system_roots(store: &mut RootCertStore, bundles: &[PathBuf]) -> Result<usize> {
+    let path = bundles.iter().find(|p| p.is_file())
+        .context("No system certificate store found; point SSL_CERT_FILE at a PEM bundle")?;
+    let file = File::open(path).context(format!("While opening {:?}", path))?;
+    let (valid, invalid) = store.add_pem_file(&mut BufReader::new(file))
+        .map_err(|()| anyhow::anyhow!("{:?} isn't a PEM certificate bundle", path))?;
+    if invalid > 0 {
+        log::warn!("Skipped {} unusable certificates in {:?}", invalid, path);
+    }
+    log::info!("Trusting {} certificates from {:?}", valid, path);
+    Ok(valid)
+}
+
+/// Builds a TLS config enforcing the given pins, as `sha256/<base64>` per host, and counting handshakes.
+pub fn tls_config(pins: &BTreeMap<String, Vec<String>>, roots: TlsRoots, handshakes: Arc<HandshakeCounter>) -> Result<rustls::ClientConfig> {
+    let pins = pins.iter()
+        .map(|(host, pins)| {
+            let hashes = pins.iter()
+                .map(|p| parse_pin(p).context(format!("Parsing TLS pin {:?} for {}", p, host)))
+                .collect::<Result<Vec<_>>>()?;
+            Ok((host.to_lowercase(), hashes))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    let mut config = rustls::ClientConfig::new();
+    if roots != TlsRoots::System {
+        config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    }
+    if roots != TlsRoots::Bundled {
+        add_system_roots(&mut config.root_store, &system_bundles())?;
+    }
+    // reqwest only offers HTTP/2 if the TLS config does.
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config.dangerous().set_certificate_verifier(Arc::new(PinningVerifier { inner: WebPKIVerifier::new(), pins, handshakes }));
+    Ok(config)
+}
+
+/// What a failed handshake most likely means, for errors that would otherwise just say "invalid certificate".
+fn explain_tls_error(error: &TLSError) -> String {
+    match error {
+        TLSError::WebPKIError(webpki::Error::UnknownIssuer) =>
+            "The server's certificate isn't signed by a trusted authority. Behind a TLS-intercepting proxy, \
+             install its CA in the system certificate store and pass --tls-roots system (or both)".to_string(),
+        TLSError::WebPKIError(webpki::Error::CertExpired) | TLSError::WebPKIError(webpki::Error::CertNotValidYet) =>
+            "The server's certificate has expired or isn't valid yet; check the system clock".to_string(),
+        TLSError::WebPKIError(webpki::Error::CertNotValidForName) =>
+            "The server's certificate is for another host, so something else, like a proxy, is answering".to_string(),
+        other => format!("TLS handshake failed: {}", other),
+    }
+}
+
+/// Adds an explanation to request errors caused by certificate problems.
+pub fn explain(error: reqwest::Error) -> anyhow::Error {
+    let mut cause: Option<&(dyn std::error::Error + 'static)> = Some(&error);
+    let mut explanation = None;
+    while let Some(current) = cause {
+        // io::Error's source skips the error it wraps, so that has to be looked at separately.
+        let wrapped = current.downcast_ref::<io::Error>().and_then(|e| e.get_ref()).map(|e| e as &(dyn std::error::Error + 'static));
+        if let Some(tls) = current.downcast_ref::<TLSError>().or_else(|| wrapped.and_then(|e| e.downcast_ref::<TLSError>())) {
+            explanation = Some(explain_tls_error(tls));
+            break;
+        }
+        cause = current.source();
+    }
+    match explanation {
+        Some(explanation) => anyhow::Error::new(error).context(explanation),
+        None => error.into(),
+    }
+}
+
+fn parse_pin(pin: &str) -> Result<Vec<u8>> {
+    let encoded = pin.strip_prefix("sha256/").unwrap_or(pin);
+    let hash = base64::decode(encoded)?;
+    anyhow::ensure!(hash.len() == 32, "Expected a SHA-256 hash, got {} bytes", hash.len());
+    Ok(hash)
+}
+
+fn spki_hash(cert_der: &[u8]) -> Option<Vec<u8>> {
+    Some(Sha256::digest(subject_public_key_info(cert_der)?).to_vec())
+}
+
+/// Splits one DER element off the front, returning it whole, its contents, and what follows.
+fn der_element(data: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let first = *data.get(1)? as usize;
+    let (length, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let bytes = first & 0x7f;
+        if bytes == 0 || bytes > 4 {
+            return None;
+        }
+        let length = data.get(2..2 + bytes)?.iter().fold(0, |acc, b| (acc << 8) | *b as usize);
+        (length, 2 + bytes)
+    };
+    let end = header.checked_add(length)?;
+    Some((data.get(..end)?, data.get(header..end)?, data.get(end..)?))
+}
+
+/// Finds the SubjectPublicKeyInfo in an X.509 certificate.
+fn subject_public_key_info(cert_der: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_element(cert_der)?;
+    let (_, tbs, _) = der_element(certificate)?;
+    let mut rest = tbs;
+    // The version is an optional explicitly tagged field.
+    if rest.first() == Some(&0xa0) {
+        rest = der_element(rest)?.2;
+    }
+    // Skip serial number, signature algorithm, issuer, validity and subject.
+    for _ in 0..5 {
+        rest = der_element(rest)?.2;
+    }
+    der_element(rest).map(|(whole, _, _)| whole)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_certificate_public_key() -> Result<()> {
+        // Generated with openssl; the pin is what `openssl pkey -pubin -outform der | openssl dgst -sha256` gives.
+        let cert = include_bytes!("../fixtures/tls/cert.der");
+        let pin = parse_pin("sha256/Y1W1nbWbe6AwLY9qMwgLxaec2C7Rhl2VhQ1w/QOV6E4=")?;
+        assert_eq!(spki_hash(cert), Some(pin));
+        assert_eq!(spki_hash(&cert[..100]), None, "Truncated certificates must not match");
+        assert!(parse_pin("sha256/AAAA").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn loads_system_roots_and_explains_failures() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let bundle = dir.path().join("ca-bundle.crt");
+        let encoded = base64::encode(&include_bytes!("../fixtures/tls/cert.der")[..]);
+        std::fs::write(&bundle, format!("-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n", encoded))?;
+        let mut store = RootCertStore::empty();
+        assert_eq!(add_system_roots(&mut store, &[dir.path().join("missing.crt"), bundle])?, 1);
+        assert!(add_system_roots(&mut store, &[]).is_err());
+        assert_eq!("both".parse::<TlsRoots>()?, TlsRoots::Both);
+
+        let message = explain_tls_error(&TLSError::WebPKIError(webpki::Error::UnknownIssuer));
+        assert!(message.contains("--tls-roots system"), "{}", message);
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Ignore,
+    Warn,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    MissingDependency,
+    HashMismatch,
+    WrongLoader,
+    StaleSlug,
+    EmbeddedLibrary,
+    VersionConflict,
+    DistributionDisallowed,
+}
+
+/// How strictly each class of problem is treated. Configured in the `policy` config section.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Policy {
+    pub missing_dependency: Action,
+    pub hash_mismatch: Action,
+    pub wrong_loader: Action,
+    pub stale_slug: Action,
+    pub embedded_library: Action,
+    pub version_conflict: Action,
+    pub distribution_disallowed: Action,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            missing_dependency: Action::Warn,
+            hash_mismatch: Action::Error,
+            wrong_loader: Action::Error,
+            stale_slug: Action::Warn,
+            embedded_library: Action::Warn,
+            version_conflict: Action::Warn,
+            distribution_disallowed: Action::Warn,
+        }
+    }
+}
+
+impl Policy {
+    pub fn action(&self, class: ErrorClass) -> Action {
+        match class {
+            ErrorClass::MissingDependency => self.missing_dependency,
+            ErrorClass::HashMismatch => self.hash_mismatch,
+            ErrorClass::WrongLoader => self.wrong_loader,
+            ErrorClass::StaleSlug => self.stale_slug,
+            ErrorClass::EmbeddedLibrary => self.embedded_library,
+            ErrorClass::VersionConflict => self.version_conflict,
+            ErrorClass::DistributionDisallowed => self.distribution_disallowed,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Violation {
+    pub class: ErrorClass,
+    pub message: String,
+}
+
+/// Collects problems from anywhere in the resolution pipeline, and decides what to do
+/// about them in one place.
+pub struct PolicyEnforcer {
+    policy: Policy,
+    violations: Mutex<Vec<Violation>>,
+}
+
+impl PolicyEnforcer {
+    pub fn new(policy: Policy) -> Self {
+        PolicyEnforcer { policy, violations: Mutex::new(Vec::new()) }
+    }
+
+    pub fn report<T>(&self, class: ErrorClass, message: T) where T: Into<String> {
+        self.violations.lock().unwrap().push(Violation { class, message: message.into() });
+    }
+
+    /// Logs everything reported so far, failing if any of it is configured as an error.
+    pub fn enforce(&self) -> Result<()> {
+        let violations: Vec<Violation> = self.violations.lock().unwrap().drain(..).collect();
+        let mut errors = Vec::new();
+        for violation in violations {
+            match self.policy.action(violation.class) {
+                Action::Ignore => log::debug!("Ignoring {:?}: {}", violation.class, violation.message),
+                Action::Warn => log::warn!("{}", violation.message),
+                Action::Error => errors.push(violation.message),
+            }
+        }
+        if !errors.is_empty() {
+            anyhow::bail!("{} problems are configured as errors:\n  {}", errors.len(), errors.join("\n  "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_only_on_error_classes() -> Result<()> {
+        let policy: Policy = serde_yaml::from_str("stale_slug: error\nhash_mismatch: ignore\n")?;
+        let enforcer = PolicyEnforcer::new(policy);
+
+        enforcer.report(ErrorClass::HashMismatch, "jei has the wrong md5");
+        enforcer.report(ErrorClass::MissingDependency, "jei requires something");
+        assert!(enforcer.enforce().is_ok(), "Ignored and warned classes shouldn't fail");
+
+        enforcer.report(ErrorClass::StaleSlug, "jei was renamed");
+        assert!(enforcer.enforce().is_err(), "Error classes should fail");
+        assert!(enforcer.enforce().is_ok(), "Violations should only be enforced once");
+
+        Ok(())
+    }
+}
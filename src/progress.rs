@@ -0,0 +1,12 @@
+//! Per-mod progress events from resolution, for whatever wants to follow along, such as a
+//! frontend driving the resolver instead of reading its console output.
+
+/// Told about each mod as it goes through resolution. Called from worker threads, and in no
+/// particular order across mods.
+pub trait ProgressObserver: Send + Sync {
+    /// The mod's metadata is about to be looked up.
+    fn mod_started(&self, _name: &str) {}
+    /// The mod's entry is complete, file hashes and all.
+    fn mod_resolved(&self, _name: &str) {}
+    fn mod_failed(&self, _name: &str, _error: &anyhow::Error) {}
+}
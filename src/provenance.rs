@@ -0,0 +1,155 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::model::{self, MergeSource, YamlMod};
+
+/// Where a generated file came from, written as a comment block at its top.
+pub struct Provenance {
+    pub tool_version: String,
+    pub input: String,
+    pub input_sha256: String,
+    pub generated_at: SystemTime,
+    pub arguments: Vec<String>,
+}
+
+impl Provenance {
+    pub fn for_input(input_path: &Path) -> Result<Self> {
+        let input = std::fs::read(input_path).context(format!("While hashing {:?}", input_path))?;
+        Ok(Provenance {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            input: input_path.display().to_string(),
+            input_sha256: format!("{:x}", Sha256::digest(&input)),
+            generated_at: SystemTime::now(),
+            arguments: std::env::args().collect(),
+        })
+    }
+}
+
+/// Why a lock entry has the file it has, so audits can tell long after the run.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EntryProvenance {
+    /// The manifest whose entry was used, and any it inherited from. None for `auto_deps` entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<String>,
+    pub pin: PinSource,
+    /// When the file was picked, in RFC 3339. Entries carried forward from a lock keep theirs.
+    pub resolved_at: String,
+}
+
+/// How the file was chosen.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PinSource {
+    /// The manifest names the file by ID.
+    FileId,
+    /// The manifest gives the file's URL.
+    Url,
+    /// The newest file of a project the manifest names by ID.
+    Newest,
+    /// The newest file of the project found by searching for the entry's name.
+    NameMatch,
+    /// The newest file of a required dependency added by `auto_deps`.
+    AutoDep,
+}
+
+impl PinSource {
+    pub fn of(yaml_mod: &YamlMod) -> Self {
+        if yaml_mod.is_direct() {
+            PinSource::Url
+        } else if yaml_mod.files.as_ref().and_then(|f| f.first()).is_some_and(|f| f.id.is_some()) {
+            PinSource::FileId
+        } else if yaml_mod.searches_by_slug() {
+            PinSource::NameMatch
+        } else {
+            PinSource::Newest
+        }
+    }
+}
+
+impl EntryProvenance {
+    /// For an entry listed in `sources`, or in `manifest_path` when there's no record of which manifests list it.
+    pub fn new(yaml_mod: &YamlMod, sources: Option<&Vec<MergeSource>>, manifest_path: &Path, resolved_at: SystemTime) -> Self {
+        let manifest = match sources {
+            Some(sources) if !sources.is_empty() => model::describe_precedence(sources),
+            _ => manifest_path.display().to_string(),
+        };
+        EntryProvenance { manifest: Some(manifest), pin: PinSource::of(yaml_mod), resolved_at: rfc3339(resolved_at) }
+    }
+
+    pub fn auto_dep(resolved_at: SystemTime) -> Self {
+        EntryProvenance { manifest: None, pin: PinSource::AutoDep, resolved_at: rfc3339(resolved_at) }
+    }
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp, e.g. `2024-04-01T12:00:00Z`.
+fn rfc3339(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let (days, second_of_day) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year, month, day, second_of_day / 3600, second_of_day / 60 % 60, second_of_day % 60)
+}
+
+impl std::fmt::Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Comments, so the generated attrset itself is unaffected.
+        let arguments: Vec<String> = self.arguments.iter().map(|a| a.replace('\n', " ")).collect();
+        writeln!(f, "# Generated by cursetool-rs {}", self.tool_version)?;
+        writeln!(f, "# Input: {} (sha256 {})", self.input.replace('\n', " "), self.input_sha256)?;
+        writeln!(f, "# Resolved at: {}", rfc3339(self.generated_at))?;
+        writeln!(f, "# Command: {}", arguments.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn formats_header() {
+        let provenance = Provenance {
+            tool_version: "0.1.0".to_string(),
+            input: "manifest/e30.yml".to_string(),
+            input_sha256: "abc".to_string(),
+            generated_at: UNIX_EPOCH + Duration::from_secs(1_709_294_400 + 3661),
+            arguments: vec!["cursetool-rs".to_string(), "yaml".to_string(), "manifest/e30.yml".to_string()],
+        };
+        assert_eq!(provenance.to_string(), "# Generated by cursetool-rs 0.1.0\n\
+                                            # Input: manifest/e30.yml (sha256 abc)\n\
+                                            # Resolved at: 2024-03-01T13:01:01Z\n\
+                                            # Command: cursetool-rs yaml manifest/e30.yml\n");
+    }
+
+    #[test]
+    fn records_how_entries_were_pinned() {
+        let at = UNIX_EPOCH + Duration::from_secs(1_709_294_400);
+        let searched = EntryProvenance::new(&YamlMod::with_name("jei"), None, Path::new("pack.yaml"), at);
+        assert_eq!(searched, EntryProvenance {
+            manifest: Some("pack.yaml".to_string()),
+            pin: PinSource::NameMatch,
+            resolved_at: "2024-03-01T12:00:00Z".to_string(),
+        });
+        let sources = vec![MergeSource { path: "base.yaml".into(), inherit: false }];
+        let pinned = YamlMod::with_files("jei", 238222, crate::model::YamlModFile::with_id(3043174));
+        let pinned = EntryProvenance::new(&pinned, Some(&sources), Path::new("pack.yaml"), at);
+        assert_eq!((pinned.manifest.as_deref(), pinned.pin), (Some("base.yaml"), PinSource::FileId));
+        assert_eq!(PinSource::of(&YamlMod::with_id("jei", 238222)), PinSource::Newest);
+        assert_eq!(serde_json::to_value(EntryProvenance::auto_dep(at)).unwrap(),
+                   serde_json::json!({ "pin": "auto-dep", "resolved_at": "2024-03-01T12:00:00Z" }));
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::api_keys::KeyUsage;
+
+/// Requests that went out to the network versus ones answered from the cache.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct EndpointUsage {
+    pub requests: u32,
+    pub cache_hits: u32,
+}
+
+/// Counts requests per endpoint, so heavy users can see how much API quota a run costs.
+#[derive(Default)]
+pub struct QuotaCounter {
+    usage: Mutex<BTreeMap<String, EndpointUsage>>,
+}
+
+impl QuotaCounter {
+    pub fn record(&self, endpoint: String, from_network: bool) {
+        let mut usage = self.usage.lock().unwrap();
+        let entry = usage.entry(endpoint).or_default();
+        if from_network {
+            entry.requests += 1;
+        } else {
+            entry.cache_hits += 1;
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, EndpointUsage> {
+        self.usage.lock().unwrap().clone()
+    }
+}
+
+/// Groups API URLs by endpoint, replacing IDs in the path with `{id}`.
+pub fn endpoint_of(url: &Url) -> String {
+    url.path_segments()
+        .map(|segments| segments
+            .map(|s| if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) { "{id}" } else { s })
+            .collect::<Vec<_>>()
+            .join("/"))
+        .unwrap_or_default()
+}
+
+/// TLS handshakes per host. With working keep-alive, this stays near the number of threads.
+#[derive(Default)]
+pub struct HandshakeCounter {
+    handshakes: Mutex<BTreeMap<String, u32>>,
+}
+
+impl HandshakeCounter {
+    pub fn record(&self, host: &str) {
+        *self.handshakes.lock().unwrap().entry(host.to_string()).or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, u32> {
+        self.handshakes.lock().unwrap().clone()
+    }
+}
+
+pub fn log_handshakes(handshakes: &BTreeMap<String, u32>) {
+    for (host, count) in handshakes {
+        log::info!("  {}: {} TLS handshakes", host, count);
+    }
+}
+
+pub fn log_key_usage(usage: &BTreeMap<String, KeyUsage>) {
+    for (key, u) in usage {
+        log::info!("  {}: {} requests, {} refused", key, u.requests, u.refused);
+    }
+}
+
+pub fn log_usage(usage: &BTreeMap<String, EndpointUsage>) {
+    let requests: u32 = usage.values().map(|u| u.requests).sum();
+    let cache_hits: u32 = usage.values().map(|u| u.cache_hits).sum();
+    log::info!("Made {} network requests, answered {} from cache", requests, cache_hits);
+    for (endpoint, u) in usage {
+        log::info!("  {}: {} requests, {} cached", endpoint, u.requests, u.cache_hits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_requests_by_endpoint() {
+        let counter = QuotaCounter::default();
+        for url in &["https://api.curseforge.com/v1/mods/224476/files/2433202",
+                     "https://api.curseforge.com/v1/mods/238222/files/3043174?gameVersion=1.12.2"] {
+            counter.record(endpoint_of(&Url::parse(url).unwrap()), true);
+        }
+        counter.record(endpoint_of(&Url::parse("https://api.curseforge.com/v1/mods/search?slug=jei").unwrap()), false);
+
+        let usage = counter.snapshot();
+        assert_eq!(usage["v1/mods/{id}/files/{id}"], EndpointUsage { requests: 2, cache_hits: 0 });
+        assert_eq!(usage["v1/mods/search"], EndpointUsage { requests: 0, cache_hits: 1 });
+    }
+
+    #[test]
+    fn counts_handshakes_per_host() {
+        let counter = HandshakeCounter::default();
+        counter.record("media.forgecdn.net");
+        counter.record("media.forgecdn.net");
+        counter.record("api.curseforge.com");
+        let handshakes = counter.snapshot();
+        assert_eq!(handshakes["media.forgecdn.net"], 2);
+        assert_eq!(handshakes["api.curseforge.com"], 1);
+    }
+}
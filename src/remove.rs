@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+/// A manifest entry with the projects its resolved file requires.
+pub struct ModDependencies {
+    pub name: String,
+    pub id: u32,
+    pub dependencies: Vec<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RemovalPlan {
+    /// Mods that still require the removed one.
+    pub dependents: Vec<String>,
+    /// Mods only there for the removed one, e.g. libraries, including their own orphaned dependencies.
+    pub orphans: Vec<String>,
+}
+
+pub fn plan_removal(mods: &[ModDependencies], name: &str) -> RemovalPlan {
+    let removed_id = mods.iter().find(|m| m.name == name).map(|m| m.id);
+    let dependents = mods.iter()
+        .filter(|m| m.name != name && removed_id.is_some_and(|id| m.dependencies.contains(&id)))
+        .map(|m| m.name.clone())
+        .collect();
+
+    let mut removed: HashSet<&str> = HashSet::new();
+    removed.insert(name);
+    let mut orphans = Vec::new();
+    loop {
+        let required_by_removed: HashSet<u32> = mods.iter()
+            .filter(|m| removed.contains(m.name.as_str()))
+            .flat_map(|m| m.dependencies.iter().copied())
+            .collect();
+        let required_by_kept: HashSet<u32> = mods.iter()
+            .filter(|m| !removed.contains(m.name.as_str()))
+            .flat_map(|m| m.dependencies.iter().copied())
+            .collect();
+        let new_orphans: Vec<&ModDependencies> = mods.iter()
+            .filter(|m| !removed.contains(m.name.as_str()))
+            .filter(|m| required_by_removed.contains(&m.id) && !required_by_kept.contains(&m.id))
+            .collect();
+        if new_orphans.is_empty() {
+            break;
+        }
+        for orphan in new_orphans {
+            removed.insert(&orphan.name);
+            orphans.push(orphan.name.clone());
+        }
+    }
+    RemovalPlan { dependents, orphans }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, id: u32, dependencies: Vec<u32>) -> ModDependencies {
+        ModDependencies { name: name.to_string(), id, dependencies }
+    }
+
+    #[test]
+    fn finds_dependents_and_orphans() {
+        let mods = vec![
+            entry("big-mod", 1, vec![10, 11]),
+            entry("other-mod", 2, vec![11]),
+            entry("its-library", 10, vec![20]),
+            entry("shared-library", 11, vec![]),
+            entry("library-library", 20, vec![]),
+            entry("addon", 3, vec![1]),
+        ];
+        let plan = plan_removal(&mods, "big-mod");
+        assert_eq!(plan.dependents, vec!["addon"]);
+        assert_eq!(plan.orphans, vec!["its-library", "library-library"]);
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::fingerprint;
+use crate::hashes::{HashAlgorithm, MultiHasher};
+
+/// How a mods folder differs from a resolved manifest.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Drift {
+    /// Server-side mods from the manifest with no file on disk.
+    pub missing: Vec<String>,
+    /// Jars on disk that aren't in the manifest.
+    pub extra: Vec<String>,
+    /// Files present under the expected name, but with other contents.
+    pub mismatched: Vec<String>,
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+// Strongest first.
+const PREFERRED_HASHES: &[HashAlgorithm] = &[HashAlgorithm::Sha512, HashAlgorithm::Blake3, HashAlgorithm::Sha256, HashAlgorithm::Md5];
+
+/// Compares the jars in `mods_dir` with the `mods` of a manifest parsed by `nix::parse`.
+pub fn check_mods_dir(manifest: &Value, mods_dir: &Path) -> Result<Drift> {
+    let mods = manifest["mods"].as_object().context("Manifest has no mods attribute set")?;
+    let mut on_disk = BTreeSet::new();
+    for entry in std::fs::read_dir(mods_dir).context(format!("While listing {:?}", mods_dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_file() && name.ends_with(".jar") {
+            on_disk.insert(name);
+        }
+    }
+
+    let mut drift = Drift::default();
+    let mut expected = BTreeSet::new();
+    for (slug, entry) in mods {
+        if entry["side"] == "client" {
+            continue;
+        }
+        let filename = entry["filename"].as_str().context(format!("{} has no filename", slug))?;
+        expected.insert(filename.to_string());
+        if !on_disk.contains(filename) {
+            drift.missing.push(format!("{} ({})", slug, filename));
+            continue;
+        }
+        // Cheaper than hashing, and enough to tell a different file.
+        if let Some(expected) = entry["fingerprint"].as_u64() {
+            let path = mods_dir.join(filename);
+            let actual = fingerprint::curse_fingerprint(&std::fs::read(&path).context(format!("While reading {:?}", path))?);
+            if actual as u64 != expected {
+                drift.mismatched.push(format!("{} ({}: expected fingerprint {}, found {})", slug, filename, expected, actual));
+            }
+            continue;
+        }
+        let wanted = PREFERRED_HASHES.iter()
+            .find_map(|a| entry[a.name()].as_str().map(|h| (*a, h)));
+        if let Some((algorithm, hash)) = wanted {
+            let actual = hash_file(&mods_dir.join(filename))?;
+            if actual[&algorithm] != hash {
+                drift.mismatched.push(format!("{} ({}: expected {} {}, found {})", slug, filename, algorithm, hash, actual[&algorithm]));
+            }
+        }
+    }
+    drift.extra = on_disk.difference(&expected).cloned().collect();
+    Ok(drift)
+}
+
+fn hash_file(path: &Path) -> Result<BTreeMap<HashAlgorithm, String>> {
+    let mut hasher = MultiHasher::new();
+    io::copy(&mut File::open(path).context(format!("While opening {:?}", path))?, &mut hasher)
+        .context(format!("While reading {:?}", path))?;
+    Ok(hasher.finish().1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_missing_extra_and_mismatched() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("good.jar"), b"abc")?;
+        std::fs::write(dir.path().join("changed.jar"), b"abd")?;
+        std::fs::write(dir.path().join("stray.jar"), b"")?;
+        std::fs::write(dir.path().join("notes.txt"), b"")?;
+        let abc_md5 = "900150983cd24fb0d6963f7d28e17f72";
+        let manifest = serde_json::json!({
+            "mods": {
+                "good": { "side": "both", "filename": "good.jar", "md5": abc_md5 },
+                "changed": { "side": "server", "filename": "changed.jar", "md5": abc_md5 },
+                "gone": { "side": "both", "filename": "gone.jar", "md5": abc_md5 },
+                "client-only": { "side": "client", "filename": "client.jar", "md5": abc_md5 },
+            }
+        });
+
+        let drift = check_mods_dir(&manifest, dir.path())?;
+        assert_eq!(drift.missing, vec!["gone (gone.jar)"]);
+        assert_eq!(drift.extra, vec!["stray.jar"]);
+        assert_eq!(drift.mismatched.len(), 1);
+        assert!(drift.mismatched[0].starts_with("changed (changed.jar"));
+
+        let abc = fingerprint::curse_fingerprint(b"abc");
+        let fingerprinted = serde_json::json!({
+            "mods": {
+                "good": { "side": "both", "filename": "good.jar", "fingerprint": abc, "md5": "not checked" },
+                "changed": { "side": "both", "filename": "changed.jar", "fingerprint": abc },
+            }
+        });
+        let drift = check_mods_dir(&fingerprinted, dir.path())?;
+        assert_eq!(drift.mismatched, vec![format!("changed (changed.jar: expected fingerprint {}, found {})",
+                                                  abc, fingerprint::curse_fingerprint(b"abd"))]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Reading the mod jars of an existing server, either from a local folder or over SSH.
+
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+/// Every `.jar` directly in the source, with its contents. The source is a local directory,
+/// or `[user@]host:path` to fetch them through `ssh` and `tar`.
+pub fn read_jars(source: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let local = Path::new(source);
+    if local.is_dir() {
+        return read_local_jars(local);
+    }
+    match source.split_once(':') {
+        // ssh would take a host starting with `-` for an option.
+        Some((host, _)) if host.starts_with('-') => anyhow::bail!("{} is not a host name", host),
+        Some((host, path)) if !host.is_empty() && !host.contains('/') => read_remote_jars(host, path),
+        _ => anyhow::bail!("{} is neither a directory nor a [user@]host:path", source),
+    }
+}
+
+fn read_local_jars(dir: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut jars = Vec::new();
+    for entry in std::fs::read_dir(dir).context(format!("While listing {:?}", dir))? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if name.ends_with(".jar") && path.is_file() => name.to_string(),
+            _ => continue,
+        };
+        jars.push((name, std::fs::read(&path).context(format!("While reading {:?}", path))?));
+    }
+    jars.sort();
+    Ok(jars)
+}
+
+fn read_remote_jars(host: &str, path: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    log::info!("Fetching jars from {}:{} over ssh", host, path);
+    let mut child = Command::new("ssh")
+        .arg("--")
+        .arg(host)
+        .arg(format!("cd {} && tar -cf - -- *.jar", remote_path(path)))
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("While running ssh")?;
+    let mut jars = Vec::new();
+    {
+        let stdout = child.stdout.take().context("ssh has no stdout")?;
+        let mut archive = tar::Archive::new(stdout);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            jars.push((name, contents));
+        }
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        anyhow::bail!("Fetching jars from {}:{} failed with {}", host, path, status);
+    }
+    jars.sort();
+    Ok(jars)
+}
+
+/// Quotes a path for the remote shell, leaving a leading `~/` outside the quotes so it still
+/// means the remote user's home.
+fn remote_path(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("~/{}", shell_quote(rest)),
+        None if path == "~" => path.to_string(),
+        None => shell_quote(path),
+    }
+}
+
+/// Quotes a string for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_local_jars() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("b.jar"), b"b")?;
+        std::fs::write(dir.path().join("a.jar"), b"a")?;
+        std::fs::write(dir.path().join("a.jar.disabled"), b"x")?;
+        let jars = read_jars(dir.path().to_str().unwrap())?;
+        assert_eq!(jars, vec![("a.jar".to_string(), b"a".to_vec()), ("b.jar".to_string(), b"b".to_vec())]);
+
+        assert_eq!(shell_quote("/srv/it's/mods"), r"'/srv/it'\''s/mods'");
+        assert_eq!(remote_path("~/mods"), "~/'mods'");
+        assert_eq!(remote_path("/srv/~mods"), "'/srv/~mods'");
+        assert!(read_jars("not/a/dir").is_err());
+        let error = read_jars("-oProxyCommand=touch pwned:mods").unwrap_err();
+        assert!(error.to_string().contains("not a host name"), "{}", error);
+        Ok(())
+    }
+}
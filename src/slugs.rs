@@ -0,0 +1,165 @@
+//! Picking the right project out of slug search results.
+//!
+//! Search results aren't always in a useful order; forks and renamed projects can come first.
+//! Only exact slug matches count, the most downloaded of those wins and then the newest. When
+//! that still leaves a choice, a `Disambiguator` makes it.
+//!
+//! Slugs are compared canonically, so `JEI` or `iron_chests` match `jei` and `iron-chests`.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+
+use crate::curse_api::AddonInfo;
+
+/// Chooses between search results the policy couldn't tell apart.
+pub trait Disambiguator: Send + Sync {
+    fn choose(&self, slug: &str, candidates: &[&AddonInfo]) -> Result<u32>;
+}
+
+/// Fails instead of guessing, listing the candidates so one can be pinned with `id:`.
+pub struct Strict;
+
+impl Disambiguator for Strict {
+    fn choose(&self, slug: &str, candidates: &[&AddonInfo]) -> Result<u32> {
+        anyhow::bail!("Slug {} is ambiguous; set id: to one of {}, or run with --interactive",
+                      slug, candidates.iter().map(|c| describe(c)).collect::<Vec<_>>().join(", "))
+    }
+}
+
+/// Asks on the terminal, remembering each answer for the rest of the run.
+#[derive(Default)]
+pub struct Prompt {
+    // Held while asking, so parallel lookups take turns at the terminal.
+    choices: Mutex<HashMap<String, u32>>,
+}
+
+impl Disambiguator for Prompt {
+    fn choose(&self, slug: &str, candidates: &[&AddonInfo]) -> Result<u32> {
+        let mut choices = self.choices.lock().unwrap();
+        if let Some(&id) = choices.get(slug) {
+            return Ok(id);
+        }
+        let id = ask(slug, candidates, &mut io::stdin().lock(), &mut io::stderr())?;
+        choices.insert(slug.to_string(), id);
+        Ok(id)
+    }
+}
+
+fn describe(candidate: &AddonInfo) -> String {
+    format!("{} ({}, id {}, {} downloads)", candidate.name, candidate.slug, candidate.id,
+            candidate.download_count.map(|d| format!("{:.0}", d)).unwrap_or_else(|| "unknown".to_string()))
+}
+
+fn ask<R: BufRead, W: Write>(slug: &str, candidates: &[&AddonInfo], input: &mut R, output: &mut W) -> Result<u32> {
+    writeln!(output, "Which project is {} meant to be?", slug)?;
+    for (i, candidate) in candidates.iter().enumerate() {
+        writeln!(output, "  {}) {}", i + 1, describe(candidate))?;
+    }
+    loop {
+        write!(output, "Pick 1-{}: ", candidates.len())?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            anyhow::bail!("No project picked for slug {}", slug);
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(n) if (1..=candidates.len()).contains(&n) => return Ok(candidates[n - 1].id),
+            _ => writeln!(output, "{:?} isn't one of the choices", answer.trim())?,
+        }
+    }
+}
+
+/// The form Curse gives slugs in: lowercase, with hyphens for underscores and spaces.
+pub fn normalize(slug: &str) -> String {
+    slug.trim().to_lowercase().chars()
+        .map(|c| if c == '_' || c.is_whitespace() { '-' } else { c })
+        .collect()
+}
+
+/// Whether two slugs name the same project.
+pub fn same(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+/// The project a slug search means, by the policy above.
+pub fn pick(slug: &str, results: &[AddonInfo], disambiguator: &dyn Disambiguator) -> Result<u32> {
+    let mut exact: Vec<&AddonInfo> = results.iter().filter(|r| same(&r.slug, slug)).collect();
+    if exact.is_empty() {
+        let closest: Vec<&AddonInfo> = results.iter().collect();
+        if closest.is_empty() {
+            anyhow::bail!("No mods found with slug {}", slug);
+        }
+        return disambiguator.choose(slug, &closest)
+            .context(format!("No project has the slug {} exactly", slug));
+    }
+    let rank = |r: &AddonInfo| (r.download_count.unwrap_or(0.0), r.date_released.clone().unwrap_or_default());
+    exact.sort_by(|a, b| rank(b).partial_cmp(&rank(a)).unwrap_or(std::cmp::Ordering::Equal));
+    let best = rank(exact[0]);
+    let tied: Vec<&AddonInfo> = exact.iter().copied().filter(|r| rank(r) == best).collect();
+    match tied.as_slice() {
+        [only] => Ok(only.id),
+        _ => disambiguator.choose(slug, &tied),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curse_api::AddonLinks;
+
+    fn project(slug: &str, id: u32, downloads: f64, released: &str) -> AddonInfo {
+        AddonInfo {
+            name: slug.to_uppercase(),
+            slug: slug.to_string(),
+            id,
+            links: AddonLinks { website_url: String::new(), wiki_url: None, issues_url: None, source_url: None },
+            status: None,
+            is_available: None,
+            categories: vec![],
+            authors: vec![],
+            latest_files_indexes: vec![],
+            download_count: Some(downloads),
+            date_released: Some(released.to_string()),
+            logo: None,
+            allow_mod_distribution: None,
+        }
+    }
+
+    #[test]
+    fn prefers_exact_popular_new_projects() -> Result<()> {
+        let results = [
+            project("jei-fork", 1, 9e9, "2024-01-01"),
+            project("jei", 2, 1e6, "2020-01-01"),
+            project("jei", 3, 2e6, "2019-01-01"),
+        ];
+        assert_eq!(pick("jei", &results, &Strict)?, 3, "Only exact slugs count, most downloaded first");
+        let tied = [project("jei", 2, 1e6, "2020-01-01"), project("jei", 4, 1e6, "2021-01-01")];
+        assert_eq!(pick("jei", &tied, &Strict)?, 4, "Newest breaks ties");
+
+        let error = pick("jei", &[project("jei-fork", 1, 9e9, "2024-01-01")], &Strict).unwrap_err();
+        assert!(format!("{:#}", error).contains("set id: to one of JEI-FORK (jei-fork, id 1"), "{:#}", error);
+        assert!(pick("jei", &[], &Strict).is_err());
+
+        let candidates: Vec<&AddonInfo> = results.iter().collect();
+        let mut output = Vec::new();
+        let id = ask("jei", &candidates, &mut "7\n2\n".as_bytes(), &mut output)?;
+        assert_eq!(id, 2);
+        assert!(String::from_utf8(output)?.contains("\"7\" isn't one of the choices"));
+        assert!(ask("jei", &candidates, &mut "".as_bytes(), &mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compares_slugs_canonically() -> Result<()> {
+        assert_eq!(normalize(" Iron_Chests "), "iron-chests");
+        assert_eq!(normalize("Ännoying Mod"), "ännoying-mod", "Non-ASCII letters are lowercased too");
+        assert!(same("JEI", "jei"));
+        assert!(!same("jei", "jei-fork"));
+        let results = [project("jei-fork", 1, 9e9, "2024-01-01"), project("jei", 2, 1e6, "2020-01-01")];
+        assert_eq!(pick("JEI", &results, &Strict)?, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::lockfile::Downgrade;
+use crate::output::AtomicFile;
+use crate::progress::ProgressObserver;
+use crate::quota::EndpointUsage;
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Status {
+    pub phase: String,
+    pub resolved: u32,
+    pub pending: u32,
+    pub failed: u32,
+    pub finished: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub api_usage: BTreeMap<String, EndpointUsage>,
+    /// Mods left out with `enabled: false`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled: Vec<String>,
+    /// Mods whose newest file is older than the one in the lockfile.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub downgraded: Vec<Downgrade>,
+}
+
+/// Machine-readable progress, rewritten atomically whenever it changes so that
+/// external dashboards never observe a half-written file.
+pub struct StatusFile {
+    path: Option<PathBuf>,
+    status: Mutex<Status>,
+}
+
+impl StatusFile {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        StatusFile { path, status: Mutex::new(Status::default()) }
+    }
+
+    pub fn set_phase<T>(&self, phase: T) where T: AsRef<str> {
+        self.update(|s| s.phase = phase.as_ref().to_string());
+    }
+
+    pub fn add_pending(&self, count: u32) {
+        self.update(|s| s.pending += count);
+    }
+
+    pub fn resolved(&self) {
+        self.update(|s| {
+            s.resolved += 1;
+            s.pending = s.pending.saturating_sub(1);
+        });
+    }
+
+    pub fn failed(&self) {
+        self.update(|s| {
+            s.failed += 1;
+            s.pending = s.pending.saturating_sub(1);
+        });
+    }
+
+    pub fn set_api_usage(&self, usage: BTreeMap<String, EndpointUsage>) {
+        self.update(|s| s.api_usage = usage);
+    }
+
+    pub fn set_disabled(&self, disabled: Vec<String>) {
+        self.update(|s| s.disabled = disabled);
+    }
+
+    pub fn set_downgraded(&self, downgraded: Vec<Downgrade>) {
+        self.update(|s| s.downgraded = downgraded);
+    }
+
+    pub fn finish(&self) {
+        self.update(|s| s.finished = true);
+    }
+
+    fn update<F>(&self, f: F) where F: FnOnce(&mut Status) {
+        let mut status = self.status.lock().unwrap();
+        f(&mut status);
+        if let Some(ref path) = self.path {
+            // A broken status file shouldn't take down the whole run.
+            if let Err(e) = write_atomically(path, &status) {
+                log::warn!("Could not write status file {:?}: {:#}", path, e);
+            }
+        }
+    }
+}
+
+impl ProgressObserver for StatusFile {
+    fn mod_resolved(&self, _name: &str) {
+        self.resolved();
+    }
+
+    fn mod_failed(&self, _name: &str, _error: &anyhow::Error) {
+        self.failed();
+    }
+}
+
+fn write_atomically(path: &Path, status: &Status) -> Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    serde_json::to_writer(&mut file, status).context(format!("While writing {:?}", path))?;
+    file.commit_without_backup()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_counts_and_phase() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("status.json");
+        let status_file = StatusFile::new(Some(path.clone()));
+
+        status_file.set_phase("Fetching details");
+        status_file.add_pending(3);
+        status_file.resolved();
+        status_file.failed();
+
+        let written: Status = serde_json::from_reader(std::fs::File::open(&path)?)?;
+        assert_eq!(written.phase, "Fetching details");
+        assert_eq!(written.resolved, 1);
+        assert_eq!(written.failed, 1);
+        assert_eq!(written.pending, 1);
+        assert!(!written.finished, "Should not be finished yet");
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1, "Temporary files should be renamed away");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+//! What regenerating a nix manifest changed, compared to the file it replaces.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::model::NixMod;
+use crate::nix;
+
+/// The bits of an entry that tell whether it changed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pin {
+    filename: String,
+    src: String,
+}
+
+/// The mods in an existing nix manifest, by name. None if there's no usable one.
+pub fn read_previous(path: &Path) -> Option<BTreeMap<String, Pin>> {
+    let source = std::fs::read_to_string(path).ok()?;
+    let manifest = match nix::parse(&source) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            log::debug!("Not summarizing changes, as {:?} doesn't parse: {:#}", path, e);
+            return None;
+        }
+    };
+    Some(manifest["mods"].as_object()?.iter().map(|(name, entry)| (name.clone(), Pin {
+        filename: entry["filename"].as_str().unwrap_or_default().to_string(),
+        src: entry["src"].as_str().unwrap_or_default().to_string(),
+    })).collect())
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    added: Vec<(String, String)>,
+    updated: Vec<(String, String, String)>,
+    removed: Vec<String>,
+}
+
+impl Summary {
+    pub fn new(previous: &BTreeMap<String, Pin>, mods: &[NixMod]) -> Self {
+        let mut summary = Summary::default();
+        for nix_mod in mods {
+            match previous.get(&nix_mod.slug) {
+                None => summary.added.push((nix_mod.slug.clone(), nix_mod.filename.clone())),
+                Some(pin) if pin.filename != nix_mod.filename || pin.src != nix_mod.src =>
+                    summary.updated.push((nix_mod.slug.clone(), pin.filename.clone(), nix_mod.filename.clone())),
+                Some(_) => (),
+            }
+        }
+        summary.removed = previous.keys().filter(|name| !mods.iter().any(|m| &m.slug == *name)).cloned().collect();
+        summary
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Counts on the first line, then one line per mod.
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} added, {} updated, {} removed", self.added.len(), self.updated.len(), self.removed.len())?;
+        for (name, filename) in &self.added {
+            write!(f, "\n  + {} ({})", name, filename)?;
+        }
+        for (name, from, to) in &self.updated {
+            write!(f, "\n  ~ {}: {} -> {}", name, from, to)?;
+        }
+        for name in &self.removed {
+            write!(f, "\n  - {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_added_updated_and_removed_mods() {
+        let pin = |filename: &str| Pin { filename: filename.to_string(), src: format!("https://example.org/{}", filename) };
+        let previous: BTreeMap<String, Pin> = vec![("jei".to_string(), pin("jei-1.jar")), ("gone".to_string(), pin("gone.jar")),
+                                                   ("same".to_string(), pin("same.jar"))].into_iter().collect();
+        let mods = [
+            NixMod::new("jei", "JEI", 1).file("jei-2.jar", "https://example.org/jei-2.jar", 1),
+            NixMod::new("new", "New", 2).file("new.jar", "https://example.org/new.jar", 1),
+            NixMod::new("same", "Same", 3).file("same.jar", "https://example.org/same.jar", 1),
+        ];
+        let summary = Summary::new(&previous, &mods);
+        assert_eq!(summary.to_string(), "1 added, 1 updated, 1 removed\n  + new (new.jar)\n  ~ jei: jei-1.jar -> jei-2.jar\n  - gone");
+        assert_eq!(Summary::new(&previous, &[]).removed.len(), 3);
+        assert!(Summary::new(&BTreeMap::new(), &[]).is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits the combined rate of all downloads sharing it.
+pub struct Throttle {
+    // Zero means unlimited.
+    bytes_per_second: u64,
+    // When the current window started, and how much has been let through since.
+    window: Mutex<(Instant, u64)>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Throttle { bytes_per_second, window: Mutex::new((Instant::now(), 0)) }
+    }
+
+    /// The rate let through, if there's a limit.
+    pub fn limit(&self) -> Option<u64> {
+        Some(self.bytes_per_second).filter(|&rate| rate > 0)
+    }
+
+    /// Accounts for `bytes` having been transferred, sleeping until that's within the limit.
+    pub fn consume(&self, bytes: u64) {
+        if self.bytes_per_second == 0 {
+            return;
+        }
+        let wait = {
+            let mut window = self.window.lock().unwrap();
+            let allowed = Duration::from_secs_f64(window.1 as f64 / self.bytes_per_second as f64);
+            // After an idle stretch, start over instead of allowing a burst to catch up.
+            if window.0.elapsed() > allowed + Duration::from_secs(1) {
+                *window = (Instant::now(), 0);
+            }
+            window.1 += bytes;
+            let due = Duration::from_secs_f64(window.1 as f64 / self.bytes_per_second as f64);
+            due.saturating_sub(window.0.elapsed())
+        };
+        std::thread::sleep(wait);
+    }
+}
+
+pub struct ThrottledWriter<'a, W> {
+    pub inner: W,
+    pub throttle: &'a Throttle,
+}
+
+impl<'a, W: Write> Write for ThrottledWriter<'a, W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(data)?;
+        self.throttle.consume(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_rate() {
+        let throttle = Throttle::new(10_000);
+        let start = Instant::now();
+        throttle.consume(500);
+        throttle.consume(500);
+        assert!(start.elapsed() >= Duration::from_millis(90), "Took only {:?}", start.elapsed());
+
+        let unlimited = Throttle::new(0);
+        let start = Instant::now();
+        unlimited.consume(1 << 30);
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,87 @@
+//! Per-mod trace IDs, so that log lines from parallel workers can be told apart.
+
+use std::cell::RefCell;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use simplelog::{TermLogger, TerminalMode};
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Restores the previous trace ID when dropped.
+pub struct TraceGuard {
+    previous: Option<String>,
+}
+
+/// Tags everything logged on this thread with the given ID until the guard is dropped.
+pub fn enter(id: &str) -> TraceGuard {
+    let previous = CURRENT.with(|current| current.replace(Some(id.to_string())));
+    TraceGuard { previous }
+}
+
+pub fn current() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Prefixes messages with the current trace ID before handing them on.
+struct TracingLogger<L> {
+    inner: L,
+}
+
+impl<L> Log for TracingLogger<L> where L: Log {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        match current() {
+            Some(id) => self.inner.log(&Record::builder()
+                .args(format_args!("[{}] {}", id, record.args()))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build()),
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+pub fn init_logger(level: LevelFilter) -> Result<(), log::SetLoggerError> {
+    // Logs stay on stderr so that commands printing results, like `schema`, can be piped.
+    let inner = TermLogger::new(level, simplelog::Config::default(), TerminalMode::Stderr);
+    log::set_boxed_logger(Box::new(TracingLogger { inner }))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nests_trace_ids() {
+        assert_eq!(current(), None);
+        {
+            let _outer = enter("jei");
+            {
+                let _inner = enter("jei-addon");
+                assert_eq!(current().as_deref(), Some("jei-addon"));
+            }
+            assert_eq!(current().as_deref(), Some("jei"));
+        }
+        assert_eq!(current(), None);
+    }
+}
@@ -0,0 +1,187 @@
+//! Human-friendly durations and sizes for command line options, such as `36h` or `1.5GB`.
+//! Plain numbers still mean seconds and bytes, as they always have.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A duration such as `90s`, `30m`, `36h`, `1.5d`, `2w` or `1h30m`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HumanDuration(pub Duration);
+
+/// A size in bytes such as `500KiB` or `1.5GB`. KB, MB, GB and TB are powers of 1000; KiB, MiB,
+/// GiB and TiB powers of 1024.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+/// Splits a leading decimal number off `s`.
+fn number(s: &str) -> Option<(f64, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let value = s[..end].parse().ok()?;
+    Some((value, &s[end..]))
+}
+
+impl FromStr for HumanDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid duration {:?}; expected e.g. 90s, 30m, 36h or 1h30m", s);
+        let trimmed = s.trim();
+        if let Ok(seconds) = trimmed.parse::<u64>() {
+            return Ok(HumanDuration(Duration::from_secs(seconds)));
+        }
+        if trimmed.is_empty() {
+            return Err(invalid());
+        }
+        let mut rest = trimmed;
+        let mut seconds = 0.0;
+        while !rest.is_empty() {
+            let (value, after) = number(rest).ok_or_else(invalid)?;
+            let unit_end = after.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(after.len());
+            let scale = match after[..unit_end].trim() {
+                "ms" => 0.001,
+                "s" => 1.0,
+                "m" => 60.0,
+                "h" => 3600.0,
+                "d" => 86400.0,
+                "w" => 86400.0 * 7.0,
+                _ => return Err(invalid()),
+            };
+            seconds += value * scale;
+            rest = after[unit_end..].trim_start();
+        }
+        Duration::try_from_secs_f64(seconds).map(HumanDuration).map_err(|_| invalid())
+    }
+}
+
+/// Read from config files as either a number of seconds or a string such as `36h`.
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Seconds(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Seconds(seconds) => Ok(HumanDuration(Duration::from_secs(seconds))),
+            Raw::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid size {:?}; expected e.g. 4096, 500KiB or 1.5GB", s);
+        let (value, unit) = number(s.trim()).ok_or_else(invalid)?;
+        let scale: u64 = match unit.trim().to_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1000,
+            "m" | "mb" => 1000_u64.pow(2),
+            "g" | "gb" => 1000_u64.pow(3),
+            "t" | "tb" => 1000_u64.pow(4),
+            "kib" => 1 << 10,
+            "mib" => 1 << 20,
+            "gib" => 1 << 30,
+            "tib" => 1 << 40,
+            _ => return Err(invalid()),
+        };
+        Ok(ByteSize((value * scale as f64).round() as u64))
+    }
+}
+
+/// Like `1.5GB`, in the largest unit that keeps the number at least 1.
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let unit = ["TB", "GB", "MB", "KB"].iter().zip((1..=4).rev())
+            .map(|(unit, power)| (unit, 1000_u64.pow(power)))
+            .find(|&(_, scale)| self.0 >= scale);
+        match unit {
+            Some((unit, scale)) => write!(f, "{:.1}{}", self.0 as f64 / scale as f64, unit),
+            None => write!(f, "{}B", self.0),
+        }
+    }
+}
+
+/// Like `1h30m`, to the second.
+impl std::fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut left = self.0.as_secs_f64().round() as u64;
+        if left == 0 {
+            return write!(f, "0s");
+        }
+        for (unit, seconds) in &[("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+            if left >= *seconds {
+                write!(f, "{}{}", left / seconds, unit)?;
+                left %= seconds;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() -> Result<()> {
+        let seconds = |s: &str| -> Result<f64> { Ok(s.parse::<HumanDuration>()?.0.as_secs_f64()) };
+        assert_eq!(seconds("1800")?, 1800.0, "Plain numbers are seconds");
+        assert_eq!(seconds("90s")?, 90.0);
+        assert_eq!(seconds("30m")?, 1800.0);
+        assert_eq!(seconds("36h")?, 129600.0);
+        assert_eq!(seconds("1.5d")?, 129600.0);
+        assert_eq!(seconds("2w")?, 1209600.0);
+        assert_eq!(seconds("1h30m")?, 5400.0);
+        assert_eq!(seconds(" 1h 30m ")?, 5400.0);
+        assert_eq!(seconds("250ms")?, 0.25);
+        for invalid in &["", "h", "36x", "1.2.3h", "-5m", "1h30", "99999999999999999999s"] {
+            assert!(invalid.parse::<HumanDuration>().is_err(), "{:?} should be rejected", invalid);
+        }
+        assert_eq!(HumanDuration(Duration::from_secs(5400)).to_string(), "1h30m");
+        assert_eq!(HumanDuration(Duration::from_secs(90061)).to_string(), "1d1h1m1s");
+        assert_eq!(HumanDuration(Duration::from_millis(400)).to_string(), "0s");
+        Ok(())
+    }
+
+    #[test]
+    fn reads_durations_from_config() -> Result<()> {
+        let durations: Vec<HumanDuration> = serde_yaml::from_str("[600, 36h, '1h30m']")?;
+        assert_eq!(durations, vec![HumanDuration(Duration::from_secs(600)), HumanDuration(Duration::from_secs(129600)),
+                                   HumanDuration(Duration::from_secs(5400))]);
+        assert_eq!(serde_yaml::to_string(&durations[1])?.trim_start_matches("---").trim(), "1d12h");
+        assert!(serde_yaml::from_str::<HumanDuration>("36x").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parses_sizes() -> Result<()> {
+        let bytes = |s: &str| -> Result<u64> { Ok(s.parse::<ByteSize>()?.0) };
+        assert_eq!(bytes("4096")?, 4096, "Plain numbers are bytes");
+        assert_eq!(bytes("10B")?, 10);
+        assert_eq!(bytes("500KB")?, 500_000);
+        assert_eq!(bytes("500KiB")?, 512_000);
+        assert_eq!(bytes("1.5GB")?, 1_500_000_000);
+        assert_eq!(bytes("1.5 GiB")?, 1_610_612_736);
+        assert_eq!(bytes("2mb")?, 2_000_000, "Units are case-insensitive");
+        assert_eq!(bytes("1TiB")?, 1 << 40);
+        for invalid in &["", "GB", "1.5XB", "-1MB", "1..5MB"] {
+            assert!(invalid.parse::<ByteSize>().is_err(), "{:?} should be rejected", invalid);
+        }
+        assert_eq!(ByteSize(999).to_string(), "999B");
+        assert_eq!(ByteSize(1_500_000_000).to_string(), "1.5GB");
+        assert_eq!(ByteSize(340_250_000).to_string(), "340.2MB");
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+
+/// Expands `${name}` references in every string of a manifest document.
+///
+/// Variables come from the manifest's `vars:` block, plus `version` for the
+/// manifest's Minecraft version. Variables may refer to those defined before them.
+pub fn expand_manifest(mut document: Value) -> Result<Value> {
+    let mut vars = BTreeMap::new();
+    if let Some(version) = document.get("version").and_then(scalar_string) {
+        vars.insert("version".to_string(), version);
+    }
+    if let Some(Value::Mapping(defined)) = document.get("vars") {
+        for (name, value) in defined {
+            let name = name.as_str().context("Variable names must be strings")?.to_string();
+            let value = scalar_string(value).context(format!("Variable {} must be a plain value", name))?;
+            let value = substitute(&value, &vars).context(format!("While expanding variable {}", name))?;
+            vars.insert(name, value);
+        }
+    }
+    expand_value(&mut document, &vars)?;
+    Ok(document)
+}
+
+fn scalar_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn expand_value(value: &mut Value, vars: &BTreeMap<String, String>) -> Result<()> {
+    match value {
+        Value::String(s) => *s = substitute(s, vars)?,
+        Value::Sequence(items) => {
+            for item in items {
+                expand_value(item, vars)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                expand_value(item, vars)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn substitute(input: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+        let end = rest[start..].find('}')
+            .context(format!("Unterminated variable reference in {:?}", input))?;
+        let name = &rest[start + 2..start + end];
+        let value = vars.get(name)
+            .context(format!("Unknown variable ${{{}}} in {:?}", name, input))?;
+        output.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_version_and_custom_vars() -> Result<()> {
+        let document: Value = serde_yaml::from_str(r#"
+version: 1.12.2
+vars:
+  jei: 4.16.1.302
+  jei_file: jei_${version}-${jei}.jar
+mods:
+- name: jei
+  files:
+  - name: ${jei_file}
+"#)?;
+        let expanded = expand_manifest(document)?;
+        assert_eq!(expanded["mods"][0]["files"][0]["name"].as_str(), Some("jei_1.12.2-4.16.1.302.jar"));
+
+        let unknown: Value = serde_yaml::from_str("version: 1.12.2\nmods:\n- name: ${nope}\n")?;
+        assert!(expand_manifest(unknown).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Version comparison and the two version range syntaxes found in mod jars:
+//! Maven ranges from Forge's `mods.toml` and semver-style predicates from `fabric.mod.json`.
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RangeSyntax {
+    Maven,
+    Fabric,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Number(u64),
+    Text(String),
+}
+
+fn parts(version: &str) -> Vec<Part> {
+    // Build metadata doesn't take part in comparisons.
+    let version = version.split('+').next().unwrap_or(version);
+    let mut parts = Vec::new();
+    for piece in version.split(['.', '-', '_']) {
+        let mut number = String::new();
+        let mut text = String::new();
+        for c in piece.chars() {
+            if c.is_ascii_digit() && text.is_empty() {
+                number.push(c);
+            } else {
+                text.push(c);
+            }
+        }
+        if !number.is_empty() {
+            parts.push(Part::Number(number.parse().unwrap_or(u64::MAX)));
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text.to_lowercase()));
+        }
+    }
+    parts
+}
+
+/// Compares versions part by part, numbers numerically. A trailing text part such as
+/// `beta` sorts before the bare version, so `1.0-beta` < `1.0` < `1.0.1`.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let (a, b) = (parts(a), parts(b));
+    for i in 0..a.len().max(b.len()) {
+        let ordering = match (a.get(i), b.get(i)) {
+            (Some(Part::Number(x)), Some(Part::Number(y))) => x.cmp(y),
+            (Some(Part::Text(x)), Some(Part::Text(y))) => x.cmp(y),
+            (Some(Part::Number(_)), Some(Part::Text(_))) => Ordering::Greater,
+            (Some(Part::Text(_)), Some(Part::Number(_))) => Ordering::Less,
+            (Some(Part::Number(x)), None) => if *x == 0 { Ordering::Equal } else { Ordering::Greater },
+            (None, Some(Part::Number(y))) => if *y == 0 { Ordering::Equal } else { Ordering::Less },
+            (Some(Part::Text(_)), None) => Ordering::Less,
+            (None, Some(Part::Text(_))) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Whether `version` satisfies `range`, or None if the range can't be understood.
+pub fn satisfies(syntax: RangeSyntax, range: &str, version: &str) -> Option<bool> {
+    match syntax {
+        RangeSyntax::Maven => maven_satisfies(range, version),
+        RangeSyntax::Fabric => fabric_satisfies(range, version),
+    }
+}
+
+/// E.g. `[1.2,)`, `[1.2,2.0)`, `[1.0]` or several of those separated by commas, any of which
+/// may match. A bare version is only a recommendation and matches anything.
+fn maven_satisfies(range: &str, version: &str) -> Option<bool> {
+    let range = range.trim();
+    if range.is_empty() || range == "*" || !range.starts_with(['[', '(']) {
+        return Some(true);
+    }
+    let mut rest = range;
+    let mut any = false;
+    while !rest.is_empty() {
+        let end = rest.find([']', ')'])?;
+        let (interval, tail) = rest.split_at(end + 1);
+        any |= interval_satisfies(interval, version)?;
+        rest = tail.trim_start_matches(',').trim();
+    }
+    Some(any)
+}
+
+fn interval_satisfies(interval: &str, version: &str) -> Option<bool> {
+    let (lower_inclusive, inner) = match (interval.strip_prefix('['), interval.strip_prefix('(')) {
+        (Some(inner), _) => (true, inner),
+        (None, Some(inner)) => (false, inner),
+        (None, None) => return None,
+    };
+    let (upper_inclusive, inner) = match (inner.strip_suffix(']'), inner.strip_suffix(')')) {
+        (Some(inner), _) => (true, inner),
+        (None, Some(inner)) => (false, inner),
+        (None, None) => return None,
+    };
+    if inner.trim().is_empty() {
+        return None;
+    }
+    let (lower, upper) = match inner.split_once(',') {
+        Some((lower, upper)) => (lower.trim(), upper.trim()),
+        // `[1.0]` means exactly that version.
+        None => (inner.trim(), inner.trim()),
+    };
+    let above = lower.is_empty() || match compare(version, lower) {
+        Ordering::Greater => true,
+        Ordering::Equal => lower_inclusive,
+        Ordering::Less => false,
+    };
+    let below = upper.is_empty() || match compare(version, upper) {
+        Ordering::Less => true,
+        Ordering::Equal => upper_inclusive,
+        Ordering::Greater => false,
+    };
+    Some(above && below)
+}
+
+/// E.g. `>=1.2.0 <2`, `~1.2`, `^1.2`, `1.2.x` or `*`; all space-separated predicates must match.
+/// Alternatives are separated by `||`.
+fn fabric_satisfies(range: &str, version: &str) -> Option<bool> {
+    let mut any = false;
+    for alternative in range.split("||") {
+        let mut all = true;
+        for predicate in alternative.split_whitespace() {
+            all &= fabric_predicate_satisfies(predicate, version)?;
+        }
+        any |= all;
+    }
+    Some(any)
+}
+
+fn fabric_predicate_satisfies(predicate: &str, version: &str) -> Option<bool> {
+    if predicate == "*" {
+        return Some(true);
+    }
+    for (operator, test) in [(">=", &[Ordering::Greater, Ordering::Equal][..]), ("<=", &[Ordering::Less, Ordering::Equal][..]),
+                             (">", &[Ordering::Greater][..]), ("<", &[Ordering::Less][..]), ("=", &[Ordering::Equal][..])] {
+        if let Some(bound) = predicate.strip_prefix(operator) {
+            return Some(test.contains(&compare(version, bound)));
+        }
+    }
+    let numbers = |v: &str| -> Vec<String> { v.split(['.', '-', '+']).map(str::to_string).collect() };
+    if let Some(bound) = predicate.strip_prefix('~') {
+        // Same major and minor version, at least the bound.
+        let prefix: Vec<String> = numbers(bound).into_iter().take(2).collect();
+        return Some(compare(version, bound) != Ordering::Less && numbers(version).starts_with(&prefix));
+    }
+    if let Some(bound) = predicate.strip_prefix('^') {
+        // Same major version, at least the bound.
+        let prefix: Vec<String> = numbers(bound).into_iter().take(1).collect();
+        return Some(compare(version, bound) != Ordering::Less && numbers(version).starts_with(&prefix));
+    }
+    if predicate.contains(['x', 'X', '*']) {
+        let prefix: Vec<String> = numbers(predicate).into_iter()
+            .take_while(|p| !matches!(p.as_str(), "x" | "X" | "*"))
+            .collect();
+        return Some(numbers(version).starts_with(&prefix));
+    }
+    Some(compare(version, predicate) == Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions() {
+        assert_eq!(compare("1.2.10", "1.2.9"), Ordering::Greater);
+        assert_eq!(compare("1.0", "1.0.0"), Ordering::Equal);
+        assert_eq!(compare("1.0-beta", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0+build.5", "1.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn checks_ranges() {
+        assert_eq!(satisfies(RangeSyntax::Maven, "[1.2,)", "1.10"), Some(true));
+        assert_eq!(satisfies(RangeSyntax::Maven, "[1.2,2.0)", "2.0"), Some(false));
+        assert_eq!(satisfies(RangeSyntax::Maven, "[1.0,1.1),[2.0,)", "2.5"), Some(true));
+        assert_eq!(satisfies(RangeSyntax::Maven, "[1.0]", "1.0.1"), Some(false));
+        assert_eq!(satisfies(RangeSyntax::Maven, "1.0", "5.0"), Some(true));
+        for broken in &["[1.0],]", "[1.0],é]", "[]"] {
+            assert_eq!(satisfies(RangeSyntax::Maven, broken, "1.0"), None, "{:?} can't be understood", broken);
+        }
+        assert_eq!(satisfies(RangeSyntax::Fabric, ">=0.5.0 <0.6", "0.5.3"), Some(true));
+        assert_eq!(satisfies(RangeSyntax::Fabric, "~1.2.0", "1.3.0"), Some(false));
+        assert_eq!(satisfies(RangeSyntax::Fabric, "^1.2.0", "1.9.0"), Some(true));
+        assert_eq!(satisfies(RangeSyntax::Fabric, "1.20.x", "1.20.1"), Some(true));
+        assert_eq!(satisfies(RangeSyntax::Fabric, ">=6 || ~5.3", "5.3.2"), Some(true));
+    }
+}
@@ -0,0 +1,33 @@
+//! Matching text against patterns in which `*` stands for anything, as cache URLs and branch
+//! names are given on the command line and in the config.
+
+/// Whether `text` matches `pattern`, in which `*` matches anything.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else { return false };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else { return rest.is_empty() };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_anything_for_stars() {
+        assert!(matches("*forgecdn*", "https://media.forgecdn.net/files/1/2/a.jar"));
+        assert!(matches("https://*/a.jar", "https://media.forgecdn.net/files/1/2/a.jar"));
+        assert!(!matches("*.jar", "https://media.forgecdn.net/files/1/2/a.zip"));
+        assert!(!matches("a*a", "a"));
+        assert!(matches("release/*", "release/1.2"));
+        assert!(!matches("release", "release/1.2"));
+    }
+}